@@ -0,0 +1,254 @@
+//! Measure the latency of common operations on freshly mounted file systems.
+//!
+//! To compare a change against the code it replaces, run the benchmark on the old code with
+//! `cargo bench --bench latency -- --save-baseline <name>`, and then on the new code with
+//! `cargo bench --bench latency -- --baseline <name>`.  The second run adds each benchmark's
+//! change in best time to the first table.
+use std::{
+    collections::HashMap,
+    env,
+    fs,
+    path::{Path, PathBuf},
+    time::{Duration, Instant},
+};
+
+use function_name::named;
+
+#[allow(dead_code)]
+mod util;
+use util::{mount, read_files, unmount, Bench, Image};
+
+/// How many times to run each benchmark.  Each run uses a freshly mounted file system, so the
+/// kernel's caches are always cold.
+const ITERATIONS: usize = 10;
+
+const BENCHES: &[Bench] = &[
+    Bench::new("readdir-block", Image::Golden4K, readdir_block),
+    Bench::new("readdir-leaf", Image::Golden4K, readdir_leaf),
+    Bench::new("readdir-node3", Image::Golden1K, readdir_node3),
+    Bench::new("readdir-btree3", Image::Golden1K, readdir_btree3),
+    Bench::new("read-fragmented", Image::Golden4K, read_fragmented),
+    Bench::new("read-sequential", Image::Golden4K, read_sequential),
+    Bench::new("read-btree", Image::Golden4K, read_btree),
+    Bench::new("lookup-random", Image::Golden1K, lookup_random),
+    Bench::new("readdir-btree3", Image::Golden1K, readdir_btree3).mmap("readdir-btree3-mmap"),
+    Bench::new("read-fragmented", Image::Golden4K, read_fragmented).mmap("read-fragmented-mmap"),
+    Bench::new("lookup-random", Image::Golden1K, lookup_random).mmap("lookup-random-mmap"),
+];
+
+/// Benchmarks that run twice on each mount: once with cold caches, and again with warm ones.
+/// xfs-fuse grants entries and attributes an infinite TTL, so repeating the same lookups would be
+/// answered entirely from the kernel's caches.  Instead, the warm pass looks up different names
+/// in the same directory, which the kernel has never seen but whose directory xfs-fuse has already
+/// read.
+const COLD_WARM_BENCHES: &[(Bench, fn(&Path) -> u64)] = &[(
+    Bench::new("lookup-node", Image::GoldenV4, lookup_node_even),
+    lookup_node_odd,
+)];
+
+fn readdir(path: &Path) -> u64 {
+    fs::read_dir(path).unwrap().map(|e| e.unwrap()).count() as u64
+}
+
+/// List the block directory
+fn readdir_block(mountpoint: &Path) -> u64 {
+    readdir(&mountpoint.join("block"))
+}
+
+/// List the leaf directory
+fn readdir_leaf(mountpoint: &Path) -> u64 {
+    readdir(&mountpoint.join("leaf"))
+}
+
+/// List the node3 directory
+fn readdir_node3(mountpoint: &Path) -> u64 {
+    readdir(&mountpoint.join("node3"))
+}
+
+/// List the btree3 directory
+fn readdir_btree3(mountpoint: &Path) -> u64 {
+    readdir(&mountpoint.join("btree3"))
+}
+
+/// Stat files in the btree3 directory, in a scattered order
+fn lookup_random(mountpoint: &Path) -> u64 {
+    const NFILES: u64 = 131072;
+    const NLOOKUPS: u64 = 4096;
+    let d = mountpoint.join("btree3");
+    for k in 0..NLOOKUPS {
+        // An odd multiplier modulo a power of 2 visits each file at most once
+        let i = (k * 40503) % NFILES;
+        fs::symlink_metadata(d.join(format!("frame{:_<242}{:08}", "", i))).unwrap();
+    }
+    NLOOKUPS
+}
+
+/// Stat every other file in the node directory, starting with `first`
+fn lookup_node(mountpoint: &Path, first: u64) -> u64 {
+    const NFILES: u64 = 512;
+    let d = mountpoint.join("node");
+    for i in (first..NFILES).step_by(2) {
+        fs::symlink_metadata(d.join(format!("frame{:06}", i))).unwrap();
+    }
+    NFILES / 2
+}
+
+/// Stat the even-numbered files in the node directory
+fn lookup_node_even(mountpoint: &Path) -> u64 {
+    lookup_node(mountpoint, 0)
+}
+
+/// Stat the odd-numbered files in the node directory
+fn lookup_node_odd(mountpoint: &Path) -> u64 {
+    lookup_node(mountpoint, 1)
+}
+
+/// Read several fragmented files
+fn read_fragmented(mountpoint: &Path) -> u64 {
+    read_files(
+        mountpoint,
+        &[
+            "four_extents.txt",
+            "btree2.txt",
+            "btree2.4.txt",
+            "btree3.txt",
+        ],
+    )
+}
+
+/// Sequentially read files whose data forks are B+trees
+fn read_btree(mountpoint: &Path) -> u64 {
+    read_files(mountpoint, &["btree2.4.txt", "btree3.txt"])
+}
+
+/// Read a file that is stored in a single extent
+fn read_sequential(mountpoint: &Path) -> u64 {
+    read_files(mountpoint, &["large_extent.txt"])
+}
+
+/// Where `--save-baseline` stores the best time of each benchmark
+fn baseline_path(name: &str) -> PathBuf {
+    Path::new(env!("CARGO_TARGET_TMPDIR")).join(format!("latency-{}.baseline", name))
+}
+
+/// Read a baseline saved by `--save-baseline`.  Each line holds a benchmark's name and its best
+/// time in nanoseconds.
+fn load_baseline(name: &str) -> HashMap<String, Duration> {
+    let path = baseline_path(name);
+    let contents = fs::read_to_string(&path)
+        .unwrap_or_else(|e| panic!("Cannot read baseline {}: {}", path.display(), e));
+    contents
+        .lines()
+        .map(|line| {
+            let (bench, nanos) = line.split_once(' ').unwrap();
+            (
+                bench.to_owned(),
+                Duration::from_nanos(nanos.parse().unwrap()),
+            )
+        })
+        .collect()
+}
+
+/// Format the change in a benchmark's best time relative to its baseline
+fn change(best: Duration, old: Option<&Duration>) -> String {
+    match old {
+        Some(old) => format!(
+            "{:+.1}%",
+            100.0 * (best.as_secs_f64() / old.as_secs_f64() - 1.0)
+        ),
+        None => "n/a".to_owned(),
+    }
+}
+
+#[named]
+fn main() {
+    require_fusefs!();
+
+    let mut save_baseline = None;
+    let mut baseline = None;
+    // Other arguments, like the --bench that cargo bench passes, are ignored
+    let mut args = env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--save-baseline" => save_baseline = Some(args.next().expect("Missing baseline name")),
+            "--baseline" => {
+                baseline = Some(load_baseline(&args.next().expect("Missing baseline name")))
+            }
+            _ => (),
+        }
+    }
+
+    print!(
+        "{:^20} {:^12} {:^16} {:^16}",
+        "Benchmark", "Items", "Best time", "Time per item"
+    );
+    if baseline.is_some() {
+        print!(" {:^8}", "Change");
+    }
+    println!();
+    print!("{:=^20} {:=^12} {:=^16} {:=^16}", "", "", "", "");
+    if baseline.is_some() {
+        print!(" {:=^8}", "");
+    }
+    println!();
+
+    let mut results = String::new();
+    for bench in BENCHES {
+        let mut best = Duration::MAX;
+        let mut items = 0;
+        for _ in 0..ITERATIONS {
+            let (d, mut child) = mount(bench.image(), bench.args);
+
+            let start = Instant::now();
+            items = bench.run(d.path());
+            best = best.min(start.elapsed());
+
+            unmount(d.path());
+            child.wait().unwrap();
+        }
+        let per_item = best / u32::try_from(items.max(1)).unwrap();
+        print!(
+            "{:20} {:12} {:>16?} {:>16?}",
+            bench.name, items, best, per_item
+        );
+        if let Some(baseline) = baseline.as_ref() {
+            print!(" {:>8}", change(best, baseline.get(bench.name)));
+        }
+        println!();
+        results.push_str(&format!("{} {}\n", bench.name, best.as_nanos()));
+    }
+    if let Some(name) = save_baseline {
+        fs::write(baseline_path(&name), results).unwrap();
+    }
+
+    println!();
+    println!(
+        "{:^20} {:^12} {:^16} {:^16} {:^8}",
+        "Benchmark", "Items", "Cold time", "Warm time", "Speedup"
+    );
+    println!("{:=^20} {:=^12} {:=^16} {:=^16} {:=^8}", "", "", "", "", "");
+
+    for (bench, warm_f) in COLD_WARM_BENCHES {
+        let mut cold = Duration::MAX;
+        let mut warm = Duration::MAX;
+        let mut items = 0;
+        for _ in 0..ITERATIONS {
+            let (d, mut child) = mount(bench.image(), bench.args);
+
+            let start = Instant::now();
+            items = bench.run(d.path());
+            cold = cold.min(start.elapsed());
+            let start = Instant::now();
+            warm_f(d.path());
+            warm = warm.min(start.elapsed());
+
+            unmount(d.path());
+            child.wait().unwrap();
+        }
+        let speedup = cold.as_secs_f64() / warm.as_secs_f64();
+        println!(
+            "{:20} {:12} {:>16?} {:>16?} {:>7.1}x",
+            bench.name, items, cold, warm, speedup
+        );
+    }
+}
@@ -0,0 +1,117 @@
+//! Compare allocating a fresh buffer for each read request, as `File::read` does, with reusing a
+//! single buffer across requests, as the server's read method does.  Both go through
+//! `Volume::read_into`, the server's own read path, and the benchmark counts the heap allocations
+//! that each makes during a large sequential read.  Unlike the other benchmarks this one doesn't
+//! mount anything, so it needs no privileges.
+use std::{
+    alloc::{GlobalAlloc, Layout, System},
+    path::Path,
+    sync::atomic::{AtomicUsize, Ordering},
+    time::{Duration, Instant},
+};
+
+#[path = "../tests/util.rs"]
+#[allow(dead_code)]
+#[allow(unused_macros)]
+mod util;
+use util::GOLDEN4K;
+
+// Most of the file system isn't needed here, nor are its unit tests.
+#[path = "../src/libxfuse/mod.rs"]
+#[allow(unused)]
+mod libxfuse;
+use libxfuse::volume::{Volume, VolumeOptions};
+
+/// Counts every allocation made by the process, and the bytes allocated.
+struct CountingAlloc;
+
+static ALLOCS: AtomicUsize = AtomicUsize::new(0);
+static ALLOC_BYTES: AtomicUsize = AtomicUsize::new(0);
+
+unsafe impl GlobalAlloc for CountingAlloc {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCS.fetch_add(1, Ordering::Relaxed);
+        ALLOC_BYTES.fetch_add(layout.size(), Ordering::Relaxed);
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+}
+
+#[global_allocator]
+static GLOBAL: CountingAlloc = CountingAlloc;
+
+/// The file to read.  At 8 MiB, it spans many extents.
+const PATH: &str = "files/btree2.4.txt";
+/// The size of each read request, as FUSE would issue by default.
+const REQSIZE: u32 = 128 * 1024;
+/// How many times to read the whole file with each method.
+const ITERATIONS: usize = 10;
+
+struct Stats {
+    allocs: usize,
+    bytes:  usize,
+    best:   Duration,
+}
+
+/// Read the whole file `ITERATIONS` times with `f`, which returns the number of bytes read at the
+/// given offset.
+fn measure<F: FnMut(i64) -> usize>(size: u64, mut f: F) -> Stats {
+    let allocs = ALLOCS.load(Ordering::Relaxed);
+    let bytes = ALLOC_BYTES.load(Ordering::Relaxed);
+    let mut best = Duration::MAX;
+    for _ in 0..ITERATIONS {
+        let start = Instant::now();
+        let mut total = 0;
+        for offset in (0..size).step_by(REQSIZE as usize) {
+            total += f(offset as i64);
+        }
+        best = best.min(start.elapsed());
+        assert_eq!(total as u64, size);
+    }
+    Stats {
+        allocs: (ALLOCS.load(Ordering::Relaxed) - allocs) / ITERATIONS,
+        bytes: (ALLOC_BYTES.load(Ordering::Relaxed) - bytes) / ITERATIONS,
+        best,
+    }
+}
+
+fn main() {
+    let img: &Path = &GOLDEN4K;
+    let mut volume = Volume::from(img, VolumeOptions::default()).unwrap();
+    let ino = volume.lookup_path(Path::new(PATH), false).unwrap();
+    let mut buf = vec![0u8; REQSIZE as usize];
+    // Find the file's size by reading it once, which also opens its inode.
+    let mut size = 0u64;
+    loop {
+        let len = volume.read_into(ino, size as i64, &mut buf).unwrap();
+        if len == 0 {
+            break;
+        }
+        size += len as u64;
+    }
+
+    let read = measure(size, |offset| {
+        let mut v = vec![0u8; REQSIZE as usize];
+        let len = volume.read_into(ino, offset, &mut v).unwrap();
+        v.truncate(len);
+        v.len()
+    });
+    let read_into = measure(size, |offset| {
+        volume.read_into(ino, offset, &mut buf).unwrap()
+    });
+
+    println!(
+        "{:^12} {:^16} {:^16} {:^16}",
+        "Method", "Allocations", "Bytes", "Best time"
+    );
+    println!("{:=^12} {:=^16} {:=^16} {:=^16}", "", "", "", "");
+    for (name, stats) in [("read", read), ("read_into", read_into)] {
+        println!(
+            "{:12} {:16} {:16} {:>16?}",
+            name, stats.allocs, stats.bytes, stats.best
+        );
+    }
+}
@@ -1,24 +1,20 @@
 use std::{
     convert::TryFrom,
-    ffi::{OsStr, OsString},
+    ffi::OsStr,
     fs::File,
-    io::{self, Read},
-    os::unix::ffi::{OsStrExt, OsStringExt},
+    io,
+    os::unix::ffi::OsStrExt,
     path::{Path, PathBuf},
     process::Command,
     str::FromStr,
-    thread::sleep,
-    time::Duration,
 };
 
-use assert_cmd::cargo::CommandCargoExt;
 use function_name::named;
-use tempfile::tempdir;
 use xattr::FileExt;
 
-#[path = "../tests/util.rs"]
+#[allow(dead_code)]
 mod util;
-use util::{waitfor, GOLDEN1K, GOLDEN4K};
+use util::{mount, read_files, unmount, Bench, Image};
 
 pub struct Gnop {
     path: PathBuf,
@@ -72,40 +68,6 @@ impl Drop for Gnop {
     }
 }
 
-#[derive(Clone, Copy, Debug, Eq, PartialEq)]
-enum Image {
-    Golden1K,
-    Golden4K,
-}
-
-struct Bench {
-    /// Name of the benchmark
-    name:  &'static str,
-    /// The disk image to use
-    image: Image,
-    /// The benchmark's function.  The argument is the path to the mounted file
-    /// sytem.  The return value is the number of "useful" bytes the benchmark
-    /// read.  An ideal file system would never read anything else.
-    f:     fn(&Path) -> u64,
-}
-
-impl Bench {
-    const fn new(name: &'static str, image: Image, f: fn(&Path) -> u64) -> Self {
-        Self { name, image, f }
-    }
-
-    fn image(&self) -> &Path {
-        match self.image {
-            Image::Golden1K => GOLDEN1K.as_path(),
-            Image::Golden4K => GOLDEN4K.as_path(),
-        }
-    }
-
-    fn run(&self, path: &Path) -> u64 {
-        (self.f)(path)
-    }
-}
-
 const BENCHES: &[Bench] = &[
     Bench::new("metadata-sf", Image::Golden4K, stat_sf),
     Bench::new("metadata-block", Image::Golden4K, stat_block),
@@ -175,18 +137,6 @@ fn stat_btree3(mountpoint: &Path) -> u64 {
     stat_files(&mountpoint.join("btree3"), 512)
 }
 
-fn read_files(mountpoint: &Path, files: &[&'static str]) -> u64 {
-    let mut user_data = 0;
-    let mut buf = Vec::new();
-    for file in files {
-        buf.truncate(0);
-        let mut f = File::open(mountpoint.join("files").join(file)).unwrap();
-        f.read_to_end(&mut buf).unwrap();
-        user_data += u64::try_from(buf.len()).unwrap();
-    }
-    user_data
-}
-
 /// Read all fragmented dense files in the 4k golden image, sequentially
 fn read_fragmented_1k(mountpoint: &Path) -> u64 {
     read_files(
@@ -278,48 +228,14 @@ fn main() {
     for bench in BENCHES {
         let md = mdconfig::Builder::vnode(bench.image()).create().unwrap();
         let gnop = Gnop::new(md.path()).unwrap();
-        let d = tempdir().unwrap();
-
-        let mut child = Command::cargo_bin("xfs-fuse")
-            .unwrap()
-            .arg(gnop.as_path())
-            .arg(d.path())
-            .spawn()
-            .unwrap();
-
-        waitfor(Duration::from_secs(5), || {
-            let s = nix::sys::statfs::statfs(d.path()).unwrap();
-            s.filesystem_type_name() == "fusefs.xfs"
-        })
-        .unwrap();
+        let (d, mut child) = mount(gnop.as_path(), bench.args);
 
         // start_bytes excludes whatever was necessary to mount the file system.
         let start_bytes = gnop.read_bytes();
 
         let useful_bytes = bench.run(d.path());
 
-        loop {
-            let cmd = Command::new("umount").arg(d.path()).output();
-            match cmd {
-                Err(e) => {
-                    panic!("Executing umount failed: {}", e);
-                }
-                Ok(output) => {
-                    let errmsg = OsString::from_vec(output.stderr).into_string().unwrap();
-                    if output.status.success() {
-                        break;
-                    } else if errmsg.contains("not a file system root directory") {
-                        // The daemon probably crashed.
-                        break;
-                    } else if errmsg.contains("Device busy") {
-                        println!("{}", errmsg);
-                    } else {
-                        panic!("{}", errmsg);
-                    }
-                }
-            }
-            sleep(Duration::from_millis(50));
-        }
+        unmount(d.path());
 
         let end_bytes = gnop.read_bytes();
         let total_bytes = end_bytes - start_bytes;
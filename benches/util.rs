@@ -0,0 +1,136 @@
+//! Harness shared by the benchmarks that mount an image
+use std::{
+    convert::TryFrom,
+    ffi::OsString,
+    fs::File,
+    io::Read,
+    os::unix::ffi::OsStringExt,
+    path::Path,
+    process::{Child, Command},
+    thread::sleep,
+    time::Duration,
+};
+
+use assert_cmd::cargo::CommandCargoExt;
+use tempfile::{tempdir, TempDir};
+
+#[path = "../tests/util.rs"]
+#[allow(dead_code)]
+mod test_util;
+pub use test_util::{waitfor, GOLDEN1K, GOLDEN4K, GOLDENV4};
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Image {
+    Golden1K,
+    Golden4K,
+    GoldenV4,
+}
+
+impl Image {
+    pub fn path(&self) -> &'static Path {
+        match self {
+            Image::Golden1K => GOLDEN1K.as_path(),
+            Image::Golden4K => GOLDEN4K.as_path(),
+            Image::GoldenV4 => GOLDENV4.as_path(),
+        }
+    }
+}
+
+pub struct Bench {
+    /// Name of the benchmark
+    pub name:  &'static str,
+    /// The disk image to use
+    pub image: Image,
+    /// The benchmark's function.  The argument is the path to the mounted file
+    /// sytem.  The return value is the amount of useful work that it did, such
+    /// as the number of directory entries or bytes that it processed.
+    pub f:     fn(&Path) -> u64,
+    /// Extra arguments for xfs-fuse
+    pub args:  &'static [&'static str],
+}
+
+impl Bench {
+    pub const fn new(name: &'static str, image: Image, f: fn(&Path) -> u64) -> Self {
+        Self {
+            name,
+            image,
+            f,
+            args: &[],
+        }
+    }
+
+    /// The same benchmark, but reading the image through a memory mapping
+    pub const fn mmap(self, name: &'static str) -> Self {
+        Self {
+            name,
+            args: &["--mmap"],
+            ..self
+        }
+    }
+
+    pub fn image(&self) -> &Path {
+        self.image.path()
+    }
+
+    pub fn run(&self, path: &Path) -> u64 {
+        (self.f)(path)
+    }
+}
+
+/// Read each of the given files in the image's `files` directory, and return the total number of
+/// bytes read.
+pub fn read_files(mountpoint: &Path, files: &[&'static str]) -> u64 {
+    let mut user_data = 0;
+    let mut buf = Vec::new();
+    for file in files {
+        buf.truncate(0);
+        let mut f = File::open(mountpoint.join("files").join(file)).unwrap();
+        f.read_to_end(&mut buf).unwrap();
+        user_data += u64::try_from(buf.len()).unwrap();
+    }
+    user_data
+}
+
+/// Mount `dev` with the given extra arguments, and return the mountpoint along with the daemon
+pub fn mount(dev: &Path, args: &[&str]) -> (TempDir, Child) {
+    let d = tempdir().unwrap();
+    let child = Command::cargo_bin("xfs-fuse")
+        .unwrap()
+        .args(args)
+        .arg(dev)
+        .arg(d.path())
+        .spawn()
+        .unwrap();
+
+    waitfor(Duration::from_secs(5), || {
+        let s = nix::sys::statfs::statfs(d.path()).unwrap();
+        s.filesystem_type_name() == "fusefs.xfs"
+    })
+    .unwrap();
+    (d, child)
+}
+
+pub fn unmount(d: &Path) {
+    loop {
+        let cmd = Command::new("umount").arg(d).output();
+        match cmd {
+            Err(e) => {
+                panic!("Executing umount failed: {}", e);
+            }
+            Ok(output) => {
+                let errmsg = OsString::from_vec(output.stderr).into_string().unwrap();
+                if output.status.success() {
+                    break;
+                } else if errmsg.contains("not a file system root directory") {
+                    // The daemon probably crashed.
+                    break;
+                } else if errmsg.contains("Device busy") {
+                    println!("{}", errmsg);
+                } else {
+                    panic!("{}", errmsg);
+                }
+            }
+        }
+        sleep(Duration::from_millis(50));
+    }
+}
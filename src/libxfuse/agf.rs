@@ -0,0 +1,229 @@
+/*
+ * BSD 2-Clause License
+ *
+ * Copyright (c) 2021, Khaled Emara
+ * All rights reserved.
+ *
+ * Redistribution and use in source and binary forms, with or without
+ * modification, are permitted provided that the following conditions are met:
+ *
+ * 1. Redistributions of source code must retain the above copyright notice, this
+ *    list of conditions and the following disclaimer.
+ *
+ * 2. Redistributions in binary form must reproduce the above copyright notice,
+ *    this list of conditions and the following disclaimer in the documentation
+ *    and/or other materials provided with the distribution.
+ *
+ * THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS IS"
+ * AND ANY EXPRESS OR IMPLIED WARRANTIES, INCLUDING, BUT NOT LIMITED TO, THE
+ * IMPLIED WARRANTIES OF MERCHANTABILITY AND FITNESS FOR A PARTICULAR PURPOSE ARE
+ * DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT HOLDER OR CONTRIBUTORS BE LIABLE
+ * FOR ANY DIRECT, INDIRECT, INCIDENTAL, SPECIAL, EXEMPLARY, OR CONSEQUENTIAL
+ * DAMAGES (INCLUDING, BUT NOT LIMITED TO, PROCUREMENT OF SUBSTITUTE GOODS OR
+ * SERVICES; LOSS OF USE, DATA, OR PROFITS; OR BUSINESS INTERRUPTION) HOWEVER
+ * CAUSED AND ON ANY THEORY OF LIABILITY, WHETHER IN CONTRACT, STRICT LIABILITY,
+ * OR TORT (INCLUDING NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE USE
+ * OF THIS SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+ */
+use std::{
+    io::{BufRead, Seek, SeekFrom},
+    ops::Add,
+};
+
+use bincode::{de::Decoder, error::DecodeError, impl_borrow_decode, Decode};
+
+use super::{agi::Agi, definitions::*, error::XfsError, sb::Sb, utils::decode_from};
+
+/// The AG free space information header.  We only need its free space counters.
+#[derive(Debug)]
+pub struct Agf {
+    // agf_magicnum: u32,
+    // agf_versionnum: u32,
+    // agf_seqno: XfsAgnumber,
+    // agf_length: XfsAgblock,
+    // agf_roots: [XfsAgblock; 3],
+    // agf_levels: [u32; 3],
+    // agf_flfirst: u32,
+    // agf_fllast: u32,
+    /// Number of blocks on the AG free list
+    pub agf_flcount:   u32,
+    /// Number of free blocks in the AG
+    pub agf_freeblks:  XfsExtlen,
+    // agf_longest: XfsExtlen,
+    /// Number of blocks used by the free space B+trees, not counting their roots.  Only
+    /// maintained on file systems with lazy superblock counters.
+    pub agf_btreeblks: u32,
+}
+
+impl<Ctx> Decode<Ctx> for Agf {
+    fn decode<D: Decoder>(decoder: &mut D) -> Result<Self, DecodeError> {
+        let agf_magicnum: u32 = Decode::decode(decoder)?;
+        if agf_magicnum != XFS_AGF_MAGIC {
+            return Err(DecodeError::Other("AGF magic number is invalid"));
+        }
+        let _agf_versionnum: u32 = Decode::decode(decoder)?;
+        let _agf_seqno: XfsAgnumber = Decode::decode(decoder)?;
+        let _agf_length: XfsAgblock = Decode::decode(decoder)?;
+        let _agf_roots: [XfsAgblock; 3] = Decode::decode(decoder)?;
+        let _agf_levels: [u32; 3] = Decode::decode(decoder)?;
+        let _agf_flfirst: u32 = Decode::decode(decoder)?;
+        let _agf_fllast: u32 = Decode::decode(decoder)?;
+        let agf_flcount = Decode::decode(decoder)?;
+        let agf_freeblks = Decode::decode(decoder)?;
+        let _agf_longest: XfsExtlen = Decode::decode(decoder)?;
+        let agf_btreeblks = Decode::decode(decoder)?;
+
+        Ok(Agf {
+            agf_flcount,
+            agf_freeblks,
+            agf_btreeblks,
+        })
+    }
+}
+impl_borrow_decode!(Agf);
+
+impl Agf {
+    pub fn from<R: BufRead + Seek + bincode::de::read::Reader>(
+        buf_reader: &mut R,
+        superblock: &Sb,
+        agno: XfsAgnumber,
+    ) -> Result<Agf, XfsError> {
+        buf_reader.seek(SeekFrom::Start(superblock.agf_offset(agno)))?;
+        decode_from(buf_reader.by_ref())
+            .map_err(|e| XfsError::Corrupt(format!("AGF of AG {}: {}", agno, e)))
+    }
+}
+
+/// Free space and inode counters, as kept in the superblock
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct Counters {
+    pub fdblocks: u64,
+    pub icount:   u64,
+    pub ifree:    u64,
+}
+
+impl Counters {
+    /// The superblock's own copies of the counters
+    pub fn from_superblock(superblock: &Sb) -> Counters {
+        Counters {
+            fdblocks: superblock.sb_fdblocks,
+            icount:   superblock.sb_icount,
+            ifree:    superblock.sb_ifree,
+        }
+    }
+
+    /// The free space and inode counters of a single AG, from its headers.
+    ///
+    /// On file systems with lazy superblock counters, their sums over every AG are the
+    /// authoritative values.  The superblock's copies are only brought up to date at unmount, so
+    /// they may be stale if the file system wasn't cleanly unmounted.
+    pub fn from_ag<R>(
+        buf_reader: &mut R,
+        superblock: &Sb,
+        agno: XfsAgnumber,
+    ) -> Result<Counters, XfsError>
+    where
+        R: BufRead + Seek + bincode::de::read::Reader,
+    {
+        let agf = Agf::from(buf_reader.by_ref(), superblock, agno)?;
+        let agi = Agi::from(buf_reader.by_ref(), superblock, agno)?;
+        Ok(Counters {
+            fdblocks: u64::from(agf.agf_freeblks)
+                + u64::from(agf.agf_flcount)
+                + u64::from(agf.agf_btreeblks),
+            icount:   u64::from(agi.agi_count),
+            ifree:    u64::from(agi.agi_freecount),
+        })
+    }
+}
+
+impl Add for Counters {
+    type Output = Counters;
+
+    fn add(self, rhs: Counters) -> Counters {
+        Counters {
+            fdblocks: self.fdblocks + rhs.fdblocks,
+            icount:   self.icount + rhs.icount,
+            ifree:    self.ifree + rhs.ifree,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{fs, os::unix::fs::FileExt};
+
+    use super::*;
+    use crate::libxfuse::{agi::tests::mkagi, block_reader::BlockReader, sb::tests::superblock};
+
+    /// Build an AGF with the given free list, free block, and B+tree block counts
+    fn mkagf(flcount: u32, freeblks: u32, btreeblks: u32) -> Vec<u8> {
+        let mut raw = vec![0u8; 512];
+        raw[0..4].copy_from_slice(&XFS_AGF_MAGIC.to_be_bytes());
+        raw[48..52].copy_from_slice(&flcount.to_be_bytes());
+        raw[52..56].copy_from_slice(&freeblks.to_be_bytes());
+        raw[56..60].copy_from_slice(&freeblks.to_be_bytes()); // agf_longest
+        raw[60..64].copy_from_slice(&btreeblks.to_be_bytes());
+        raw
+    }
+
+    #[test]
+    fn decode() {
+        let agf: Agf = crate::libxfuse::utils::decode(&mkagf(4, 1000, 2))
+            .unwrap()
+            .0;
+        assert_eq!(agf.agf_flcount, 4);
+        assert_eq!(agf.agf_freeblks, 1000);
+        assert_eq!(agf.agf_btreeblks, 2);
+    }
+
+    /// An AGF with the wrong magic number is reported as corruption
+    #[test]
+    fn bad_magic() {
+        let mut raw = mkagf(4, 1000, 2);
+        raw[0..4].copy_from_slice(&XFS_AGI_MAGIC.to_be_bytes());
+        let sb = superblock();
+        let img = tempfile::NamedTempFile::new().unwrap();
+        let f: &fs::File = img.as_file();
+        f.set_len(u64::from(sb.sb_blocksize)).unwrap();
+        f.write_all_at(&raw, sb.agf_offset(0)).unwrap();
+        let mut reader = BlockReader::open(img.path()).unwrap();
+        reader.set_bufsize(sb.geometry.blocksize);
+        assert_eq!(
+            Agf::from(&mut reader, sb, 0).unwrap_err(),
+            XfsError::Corrupt("AGF of AG 0: Other(\"AGF magic number is invalid\")".into())
+        );
+    }
+
+    /// The counters of every AG should be summed
+    #[test]
+    fn sum() {
+        let sb = superblock();
+        let img = tempfile::NamedTempFile::new().unwrap();
+        let f: &fs::File = img.as_file();
+        f.set_len(sb.sb_dblocks << sb.sb_blocklog).unwrap();
+        for agno in 0..sb.sb_agcount {
+            f.write_all_at(&mkagf(4, 1000 * (agno + 1), 2), sb.agf_offset(agno))
+                .unwrap();
+            let mut agi = mkagi(0, NULLAGINO);
+            agi[16..20].copy_from_slice(&64u32.to_be_bytes()); // agi_count
+            agi[28..32].copy_from_slice(&agno.to_be_bytes()); // agi_freecount
+            f.write_all_at(&agi, sb.agi_offset(agno)).unwrap();
+        }
+
+        let mut reader = BlockReader::open(img.path()).unwrap();
+        reader.set_bufsize(sb.geometry.blocksize);
+        let n = u64::from(sb.sb_agcount);
+        let sum = (0..sb.sb_agcount)
+            .map(|agno| Counters::from_ag(&mut reader, sb, agno).unwrap())
+            .fold(Counters::default(), Add::add);
+        assert_eq!(
+            sum,
+            Counters {
+                fdblocks: 1000 * n * (n + 1) / 2 + 6 * n,
+                icount:   64 * n,
+                ifree:    n * (n - 1) / 2,
+            }
+        );
+    }
+}
@@ -0,0 +1,324 @@
+/*
+ * BSD 2-Clause License
+ *
+ * Copyright (c) 2021, Khaled Emara
+ * All rights reserved.
+ *
+ * Redistribution and use in source and binary forms, with or without
+ * modification, are permitted provided that the following conditions are met:
+ *
+ * 1. Redistributions of source code must retain the above copyright notice, this
+ *    list of conditions and the following disclaimer.
+ *
+ * 2. Redistributions in binary form must reproduce the above copyright notice,
+ *    this list of conditions and the following disclaimer in the documentation
+ *    and/or other materials provided with the distribution.
+ *
+ * THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS IS"
+ * AND ANY EXPRESS OR IMPLIED WARRANTIES, INCLUDING, BUT NOT LIMITED TO, THE
+ * IMPLIED WARRANTIES OF MERCHANTABILITY AND FITNESS FOR A PARTICULAR PURPOSE ARE
+ * DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT HOLDER OR CONTRIBUTORS BE LIABLE
+ * FOR ANY DIRECT, INDIRECT, INCIDENTAL, SPECIAL, EXEMPLARY, OR CONSEQUENTIAL
+ * DAMAGES (INCLUDING, BUT NOT LIMITED TO, PROCUREMENT OF SUBSTITUTE GOODS OR
+ * SERVICES; LOSS OF USE, DATA, OR PROFITS; OR BUSINESS INTERRUPTION) HOWEVER
+ * CAUSED AND ON ANY THEORY OF LIABILITY, WHETHER IN CONTRACT, STRICT LIABILITY,
+ * OR TORT (INCLUDING NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE USE
+ * OF THIS SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+ */
+use std::io::{BufRead, Seek, SeekFrom};
+
+use bincode::{de::Decoder, error::DecodeError, impl_borrow_decode, Decode};
+use tracing::warn;
+
+use super::{definitions::*, dinode_core::DinodeCore, error::XfsError, sb::Sb, utils::decode_from};
+
+/// Number of hash buckets for the unlinked inode lists
+pub const XFS_AGI_UNLINKED_BUCKETS: usize = 64;
+
+/// The AG inode B+tree information header.  We only need its inode counters, the location of its
+/// inode B+tree, and its unlinked inode lists.
+#[derive(Debug)]
+pub struct Agi {
+    // agi_magicnum: u32,
+    // agi_versionnum: u32,
+    // agi_seqno: XfsAgnumber,
+    // agi_length: XfsAgblock,
+    /// Number of allocated inodes in the AG
+    pub agi_count:     XfsAgino,
+    /// Root block of the inode B+tree
+    pub agi_root:      XfsAgblock,
+    /// Height of the inode B+tree
+    pub agi_level:     u32,
+    /// Number of free inodes in the AG
+    pub agi_freecount: XfsAgino,
+    // agi_newino: XfsAgino,
+    // agi_dirino: XfsAgino,
+    /// Heads of the lists of inodes that were unlinked while still open
+    pub agi_unlinked:  [XfsAgino; XFS_AGI_UNLINKED_BUCKETS],
+}
+
+impl<Ctx> Decode<Ctx> for Agi {
+    fn decode<D: Decoder>(decoder: &mut D) -> Result<Self, DecodeError> {
+        let agi_magicnum: u32 = Decode::decode(decoder)?;
+        if agi_magicnum != XFS_AGI_MAGIC {
+            return Err(DecodeError::Other("AGI magic number is invalid"));
+        }
+        let _agi_versionnum: u32 = Decode::decode(decoder)?;
+        let _agi_seqno: XfsAgnumber = Decode::decode(decoder)?;
+        let _agi_length: XfsAgblock = Decode::decode(decoder)?;
+        let agi_count = Decode::decode(decoder)?;
+        let agi_root = Decode::decode(decoder)?;
+        let agi_level = Decode::decode(decoder)?;
+        let agi_freecount = Decode::decode(decoder)?;
+        let _agi_newino: XfsAgino = Decode::decode(decoder)?;
+        let _agi_dirino: XfsAgino = Decode::decode(decoder)?;
+        let agi_unlinked = Decode::decode(decoder)?;
+
+        Ok(Agi {
+            agi_count,
+            agi_root,
+            agi_level,
+            agi_freecount,
+            agi_unlinked,
+        })
+    }
+}
+impl_borrow_decode!(Agi);
+
+impl Agi {
+    pub fn from<R: BufRead + Seek + bincode::de::read::Reader>(
+        buf_reader: &mut R,
+        superblock: &Sb,
+        agno: XfsAgnumber,
+    ) -> Result<Agi, XfsError> {
+        buf_reader.seek(SeekFrom::Start(superblock.agi_offset(agno)))?;
+        decode_from(buf_reader.by_ref())
+            .map_err(|e| XfsError::Corrupt(format!("AGI of AG {}: {}", agno, e)))
+    }
+}
+
+/// Read the core of an inode on an unlinked list.  Unlike `Dinode::from`, this fails cleanly if
+/// the inode is corrupt, since a bad list pointer could lead anywhere.
+fn read_core<R>(buf_reader: &mut R, superblock: &Sb, ino: XfsIno) -> Result<DinodeCore, XfsError>
+where
+    R: BufRead + Seek + bincode::de::read::Reader,
+{
+    let index = ino & ((1 << superblock.sb_inopblog) - 1);
+    let offset =
+        superblock.fsb_to_offset(ino >> superblock.sb_inopblog) + (index << superblock.sb_inodelog);
+    buf_reader.seek(SeekFrom::Start(offset))?;
+    let mut raw = vec![0u8; superblock.inode_size()];
+    buf_reader.read_exact(&mut raw)?;
+    let config = bincode::config::standard()
+        .with_big_endian()
+        .with_fixed_int_encoding();
+    Ok(bincode::decode_from_slice(&raw[..], config)?.0)
+}
+
+/// Find every inode that was unlinked while still open, by walking each AG's unlinked lists.
+///
+/// This is best-effort.  The lists are only meaningful if the file system was not cleanly
+/// unmounted, and a list that loops, leaves its AG, or leads to a corrupt or reserved inode will
+/// be truncated with a warning.  An AG whose AGI is corrupt is skipped.  Each inode is returned with its core.
+pub fn unlinked_inodes<R>(buf_reader: &mut R, superblock: &Sb) -> Vec<(XfsIno, DinodeCore)>
+where
+    R: BufRead + Seek + bincode::de::read::Reader,
+{
+    let mut inodes: Vec<(XfsIno, DinodeCore)> = Vec::new();
+    for agno in 0..superblock.sb_agcount {
+        let agi = match Agi::from(buf_reader.by_ref(), superblock, agno) {
+            Ok(agi) => agi,
+            Err(e) => {
+                warn!("Cannot read the unlinked lists of AG {}: {}", agno, e);
+                continue;
+            }
+        };
+        for head in agi.agi_unlinked {
+            let mut agino = head;
+            let start = inodes.len();
+            while agino != NULLAGINO {
+                let ino = superblock.agino_to_ino(agno, agino);
+                if agino >> (superblock.sb_agblklog + superblock.sb_inopblog) != 0 {
+                    warn!(
+                        "Unlinked list in AG {} points to invalid inode {}",
+                        agno, agino
+                    );
+                    break;
+                }
+                if superblock.is_reserved_inode(ino) {
+                    warn!(
+                        "Unlinked list in AG {} leads to reserved inode {}",
+                        agno, ino
+                    );
+                    break;
+                }
+                if inodes[start..].iter().any(|(i, _)| *i == ino) {
+                    warn!("Unlinked list in AG {} loops at inode {}", agno, ino);
+                    break;
+                }
+                let di_core = match read_core(buf_reader.by_ref(), superblock, ino) {
+                    Ok(di_core) => di_core,
+                    Err(e) => {
+                        warn!(
+                            "Unlinked list in AG {} leads to bad inode {}: {}",
+                            agno, ino, e
+                        );
+                        break;
+                    }
+                };
+                agino = di_core.di_next_unlinked;
+                inodes.push((ino, di_core));
+            }
+        }
+    }
+    inodes
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use std::{fs, io::Write, os::unix::fs::FileExt};
+
+    use super::*;
+    use crate::libxfuse::{block_reader::BlockReader, sb::tests::superblock};
+
+    /// Build an AGI whose only nonempty unlinked list starts at `head` in the given bucket
+    pub(crate) fn mkagi(bucket: usize, head: XfsAgino) -> Vec<u8> {
+        let mut raw = vec![0u8; 512];
+        raw[0..4].copy_from_slice(&XFS_AGI_MAGIC.to_be_bytes());
+        for (i, chunk) in raw[40..40 + 4 * XFS_AGI_UNLINKED_BUCKETS]
+            .chunks_mut(4)
+            .enumerate()
+        {
+            let agino = if i == bucket { head } else { NULLAGINO };
+            chunk.copy_from_slice(&agino.to_be_bytes());
+        }
+        raw
+    }
+
+    /// Build a version 2 regular file inode with the given successor in its unlinked list
+    fn mkinode(next_unlinked: XfsAgino) -> Vec<u8> {
+        let mut raw = vec![0u8; 512];
+        raw[0..2].copy_from_slice(&XFS_DINODE_MAGIC.to_be_bytes());
+        raw[2..4].copy_from_slice(&0o100644u16.to_be_bytes());
+        raw[4] = 2; // di_version
+        raw[5] = 2; // di_format: extents
+        raw[83] = 2; // di_aformat: extents
+        raw[96..100].copy_from_slice(&next_unlinked.to_be_bytes());
+        raw
+    }
+
+    #[test]
+    fn decode() {
+        let agi: Agi = crate::libxfuse::utils::decode(&mkagi(5, 64)).unwrap().0;
+        assert_eq!(agi.agi_unlinked[5], 64);
+        assert_eq!(agi.agi_unlinked[4], NULLAGINO);
+    }
+
+    /// Walk an unlinked list of two inodes in AG 1, and one that loops in AG 3.
+    #[test]
+    fn walk() {
+        let sb = superblock();
+        let mut img = tempfile::NamedTempFile::new().unwrap();
+        let f: &fs::File = img.as_file();
+        f.set_len(sb.sb_dblocks << sb.sb_blocklog).unwrap();
+        for agno in 0..sb.sb_agcount {
+            let agi = match agno {
+                1 => mkagi(0, 64),
+                3 => mkagi(63, 80),
+                _ => mkagi(0, NULLAGINO),
+            };
+            f.write_all_at(&agi, sb.agi_offset(agno)).unwrap();
+        }
+        let inode_offset = |agno, agino| {
+            let ino = sb.agino_to_ino(agno, agino);
+            sb.fsb_to_offset(ino >> sb.sb_inopblog) + ((ino & 7) << sb.sb_inodelog)
+        };
+        f.write_all_at(&mkinode(65), inode_offset(1, 64)).unwrap();
+        f.write_all_at(&mkinode(NULLAGINO), inode_offset(1, 65))
+            .unwrap();
+        f.write_all_at(&mkinode(80), inode_offset(3, 80)).unwrap();
+        img.flush().unwrap();
+
+        let mut reader = BlockReader::open(img.path()).unwrap();
+        reader.set_bufsize(sb.geometry.blocksize);
+        let inodes = unlinked_inodes(&mut reader, sb);
+        assert_eq!(
+            inodes.iter().map(|(ino, _)| *ino).collect::<Vec<_>>(),
+            vec![
+                sb.agino_to_ino(1, 64),
+                sb.agino_to_ino(1, 65),
+                sb.agino_to_ino(3, 80)
+            ]
+        );
+        assert_eq!(inodes[0].1.di_mode, 0o100644);
+    }
+
+    /// A corrupt AGI is skipped, and a list that leads to a corrupt inode is truncated there,
+    /// without affecting the other AGs.
+    #[test]
+    fn corrupt() {
+        let sb = superblock();
+        let mut img = tempfile::NamedTempFile::new().unwrap();
+        let f: &fs::File = img.as_file();
+        f.set_len(sb.sb_dblocks << sb.sb_blocklog).unwrap();
+        for agno in 0..sb.sb_agcount {
+            let agi = match agno {
+                // AG 1's AGI has a bad magic number, but the list it would lead to is valid
+                1 => vec![0u8; 512],
+                2 | 3 => mkagi(0, 64),
+                _ => mkagi(0, NULLAGINO),
+            };
+            f.write_all_at(&agi, sb.agi_offset(agno)).unwrap();
+        }
+        let inode_offset = |agno, agino| {
+            let ino = sb.agino_to_ino(agno, agino);
+            sb.fsb_to_offset(ino >> sb.sb_inopblog) + ((ino & 7) << sb.sb_inodelog)
+        };
+        f.write_all_at(&mkinode(NULLAGINO), inode_offset(1, 64))
+            .unwrap();
+        // AG 2's list leads from inode 64 to inode 65, which is all zeros
+        f.write_all_at(&mkinode(65), inode_offset(2, 64)).unwrap();
+        f.write_all_at(&mkinode(NULLAGINO), inode_offset(3, 64))
+            .unwrap();
+        img.flush().unwrap();
+
+        let mut reader = BlockReader::open(img.path()).unwrap();
+        reader.set_bufsize(sb.geometry.blocksize);
+        let inodes = unlinked_inodes(&mut reader, sb);
+        assert_eq!(
+            inodes.iter().map(|(ino, _)| *ino).collect::<Vec<_>>(),
+            vec![sb.agino_to_ino(2, 64), sb.agino_to_ino(3, 64)]
+        );
+    }
+
+    /// A list that leads to a reserved inode, like the root directory, is truncated there, even if
+    /// that inode looks like it's on an unlinked list.
+    #[test]
+    fn reserved() {
+        let sb = superblock();
+        let mut img = tempfile::NamedTempFile::new().unwrap();
+        let f: &fs::File = img.as_file();
+        f.set_len(sb.sb_dblocks << sb.sb_blocklog).unwrap();
+        for agno in 0..sb.sb_agcount {
+            let agi = match agno {
+                0 => mkagi(0, 64),
+                _ => mkagi(0, NULLAGINO),
+            };
+            f.write_all_at(&agi, sb.agi_offset(agno)).unwrap();
+        }
+        let inode_offset =
+            |ino: XfsIno| sb.fsb_to_offset(ino >> sb.sb_inopblog) + ((ino & 7) << sb.sb_inodelog);
+        f.write_all_at(&mkinode(128), inode_offset(64)).unwrap();
+        f.write_all_at(&mkinode(NULLAGINO), inode_offset(sb.sb_rootino))
+            .unwrap();
+        img.flush().unwrap();
+
+        let mut reader = BlockReader::open(img.path()).unwrap();
+        reader.set_bufsize(sb.geometry.blocksize);
+        let inodes = unlinked_inodes(&mut reader, sb);
+        assert_eq!(
+            inodes.iter().map(|(ino, _)| *ino).collect::<Vec<_>>(),
+            vec![64]
+        );
+    }
+}
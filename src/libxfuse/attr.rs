@@ -28,6 +28,7 @@
 use std::{
     ffi::OsStr,
     io::{BufRead, Seek, SeekFrom},
+    os::unix::ffi::OsStrExt,
 };
 
 use bincode::{
@@ -36,6 +37,7 @@ use bincode::{
     impl_borrow_decode,
     Decode,
 };
+use tracing::warn;
 
 use super::{
     attr_leaf::AttrLeaf,
@@ -45,27 +47,31 @@ use super::{
     definitions::{
         XfsDablk,
         XfsFsblock,
+        XfsIno,
         XFS_ATTR3_LEAF_MAGIC,
         XFS_ATTR_LEAF_MAGIC,
         XFS_DA3_NODE_MAGIC,
         XFS_DA_NODE_MAGIC,
     },
+    error::XfsError,
     sb::Sb,
     utils,
     volume::SUPERBLOCK,
 };
 
 #[allow(dead_code)]
-mod constants {
+pub(crate) mod constants {
     pub const XFS_ATTR_LOCAL_BIT: u8 = 0;
     pub const XFS_ATTR_ROOT_BIT: u8 = 1;
     pub const XFS_ATTR_SECURE_BIT: u8 = 2;
+    pub const XFS_ATTR_PARENT_BIT: u8 = 3;
     pub const XFS_ATTR_INCOMPLETE_BIT: u8 = 7;
     pub const XFS_ATTR_LOCAL: u8 = 1 << XFS_ATTR_LOCAL_BIT;
     pub const XFS_ATTR_ROOT: u8 = 1 << XFS_ATTR_ROOT_BIT;
     pub const XFS_ATTR_SECURE: u8 = 1 << XFS_ATTR_SECURE_BIT;
+    pub const XFS_ATTR_PARENT: u8 = 1 << XFS_ATTR_PARENT_BIT;
     pub const XFS_ATTR_INCOMPLETE: u8 = 1 << XFS_ATTR_INCOMPLETE_BIT;
-    pub const XFS_ATTR_NSP_ONDISK_MASK: u8 = XFS_ATTR_ROOT | XFS_ATTR_SECURE;
+    pub const XFS_ATTR_NSP_ONDISK_MASK: u8 = XFS_ATTR_ROOT | XFS_ATTR_SECURE | XFS_ATTR_PARENT;
 }
 
 pub const fn get_namespace_from_flags(flags: u8) -> &'static [u8] {
@@ -78,6 +84,53 @@ pub const fn get_namespace_from_flags(flags: u8) -> &'static [u8] {
     }
 }
 
+/// The inverse of [`get_namespace_from_flags`].  `ns` should not include the trailing ".".
+pub fn get_flags_from_namespace(ns: &[u8]) -> Option<u8> {
+    match ns {
+        b"user" => Some(0),
+        b"trusted" => Some(constants::XFS_ATTR_ROOT),
+        b"secure" | b"security" => Some(constants::XFS_ATTR_SECURE),
+        _ => None,
+    }
+}
+
+/// Does this on-disk attribute entry belong to the given namespace?  An entry that is still being
+/// created or replaced, which a crash may leave behind, belongs to none.
+pub const fn namespace_matches(flags: u8, ns: u8) -> bool {
+    flags & (constants::XFS_ATTR_NSP_ONDISK_MASK | constants::XFS_ATTR_INCOMPLETE) == ns
+}
+
+/// Should this on-disk attribute entry be visible to listxattr?  Parent pointers are stored as
+/// attributes, but they aren't extended attributes.  And incomplete entries aren't valid yet.
+pub const fn is_listable(flags: u8) -> bool {
+    flags & (constants::XFS_ATTR_PARENT | constants::XFS_ATTR_INCOMPLETE) == 0
+}
+
+/// Size of an on-disk `xfs_parent_rec`: the parent directory's inode number and generation
+const XFS_PARENT_REC_SIZE: usize = 12;
+
+/// Decode a parent pointer, whose name is the directory entry's name and whose value is an
+/// `xfs_parent_rec`, and append it to `parents` as a (parent inode, entry name) pair.
+pub fn push_parent(parents: &mut Vec<(XfsIno, Vec<u8>)>, name: &[u8], value: &[u8]) {
+    if value.len() == XFS_PARENT_REC_SIZE {
+        let ino = u64::from_be_bytes(value[..8].try_into().unwrap());
+        parents.push((ino, name.to_vec()));
+    } else {
+        warn!(
+            "Ignoring parent pointer {:?} with bad value length {}",
+            OsStr::from_bytes(name),
+            value.len()
+        );
+    }
+}
+
+/// Map a block of an extents-format attribute fork to a file system block.  Attribute forks
+/// never contain holes, so an unmapped block means the fork is corrupt.
+pub fn map_dblock(bmx: &Bmx, dblock: XfsDablk) -> Result<XfsFsblock, XfsError> {
+    bmx.map_dblock(dblock)
+        .ok_or_else(|| XfsError::Corrupt(format!("Attribute block {} is not mapped", dblock)))
+}
+
 pub const fn get_namespace_size_from_flags(flags: u8) -> u32 {
     get_namespace_from_flags(flags).len() as u32
 }
@@ -106,7 +159,12 @@ impl<Ctx> Decode<Ctx> for AttrLeafHdr {
                 let info: XfsDa3Blkinfo = Decode::decode(decoder)?;
                 info.forw
             }
-            _ => panic!("Unexpected magic value {:#x}", magic),
+            _ => {
+                return Err(DecodeError::OtherString(format!(
+                    "Unexpected magic value {:#x}",
+                    magic
+                )))
+            }
         };
         let count = Decode::decode(decoder)?;
         let _usedbytes: u16 = Decode::decode(decoder)?;
@@ -169,13 +227,13 @@ impl AttrLeafName {
         }
     }
 
-    fn value<F, R>(&mut self, buf_reader: &mut R, map_dblock: F) -> &[u8]
+    fn value<F, R>(&mut self, buf_reader: &mut R, map_dblock: F) -> Result<&[u8], XfsError>
     where
         R: BufRead + Reader + Seek,
-        F: Fn(XfsDablk, &mut R) -> XfsFsblock,
+        F: Fn(XfsDablk, &mut R) -> Result<XfsFsblock, XfsError>,
     {
         match self {
-            AttrLeafName::Local(local) => &local.nameval[local.namelen as usize..],
+            AttrLeafName::Local(local) => Ok(&local.nameval[local.namelen as usize..]),
             AttrLeafName::Remote(remote) => remote.value(buf_reader.by_ref(), map_dblock),
         }
     }
@@ -195,7 +253,9 @@ impl AttrLeafblock {
         let mut total: u32 = 0;
 
         for (entry, name) in std::iter::zip(self.entries.iter(), self.names.iter()) {
-            total += get_namespace_size_from_flags(entry.flags) + u32::from(name.namelen()) + 1;
+            if is_listable(entry.flags) {
+                total += get_namespace_size_from_flags(entry.flags) + u32::from(name.namelen()) + 1;
+            }
         }
 
         total
@@ -203,31 +263,76 @@ impl AttrLeafblock {
 
     pub fn list(&self, list: &mut Vec<u8>) {
         for (entry, name_entry) in std::iter::zip(self.entries.iter(), self.names.iter()) {
+            if !is_listable(entry.flags) {
+                continue;
+            }
             list.extend_from_slice(get_namespace_from_flags(entry.flags));
             list.extend_from_slice(name_entry.name());
             list.push(0)
         }
     }
 
-    pub fn get<R: BufRead + Reader + Seek, F: Fn(XfsDablk, &mut R) -> XfsFsblock>(
+    /// Entries with the same hash may span leaves.  If this leaf ends with the given hash, then the
+    /// next one may begin with it.
+    pub fn hash_continues(&self, hash: u32) -> bool {
+        self.hdr.forw != 0 && self.entries.last().is_some_and(|e| e.hashval == hash)
+    }
+
+    pub fn parents(&self, parents: &mut Vec<(XfsIno, Vec<u8>)>) {
+        for (entry, name_entry) in std::iter::zip(self.entries.iter(), self.names.iter()) {
+            if entry.flags & constants::XFS_ATTR_PARENT == 0
+                || entry.flags & constants::XFS_ATTR_INCOMPLETE != 0
+            {
+                continue;
+            }
+            match name_entry {
+                AttrLeafName::Local(local) => {
+                    let namelen = usize::from(local.namelen);
+                    push_parent(
+                        parents,
+                        &local.nameval[..namelen],
+                        &local.nameval[namelen..],
+                    );
+                }
+                // Parent pointer values are always small enough to be stored locally
+                AttrLeafName::Remote(remote) => {
+                    warn!(
+                        "Ignoring parent pointer {:?} with a remote value",
+                        OsStr::from_bytes(&remote.name)
+                    );
+                }
+            }
+        }
+    }
+
+    pub fn get<
+        R: BufRead + Reader + Seek,
+        F: Fn(XfsDablk, &mut R) -> Result<XfsFsblock, XfsError>,
+    >(
         &mut self,
         buf_reader: &mut R,
         hash: u32,
+        ns: u8,
+        name: &OsStr,
         map_logical_block_to_fs_block: F,
-    ) -> Result<&[u8], i32> {
-        match self
-            .entries
-            .binary_search_by_key(&hash, |entry| entry.hashval)
-        {
-            Ok(i) => Ok(self.names[i].value(buf_reader, map_logical_block_to_fs_block)),
-            Err(_) => Err(libc::ENOATTR),
-        }
+    ) -> Result<&[u8], XfsError> {
+        // Different names may share a hash, and the same name may exist in multiple namespaces.
+        // So examine every entry with a matching hash.
+        let start = self.entries.partition_point(|entry| entry.hashval < hash);
+        let i = (start..self.entries.len())
+            .take_while(|i| self.entries[*i].hashval == hash)
+            .find(|i| {
+                namespace_matches(self.entries[*i].flags, ns)
+                    && self.names[*i].name() == name.as_bytes()
+            })
+            .ok_or(XfsError::NoAttr)?;
+        self.names[i].value(buf_reader, map_logical_block_to_fs_block)
     }
 }
 
 impl<Ctx> Decode<Ctx> for AttrLeafblock {
     fn decode<D: Decoder>(decoder: &mut D) -> Result<Self, DecodeError> {
-        let blocksize = SUPERBLOCK.get().unwrap().sb_blocksize as usize;
+        let blocksize = SUPERBLOCK.get().unwrap().geometry.blocksize;
         let mut raw = vec![0u8; blocksize];
         decoder.reader().read(&mut raw[..])?;
 
@@ -271,10 +376,10 @@ pub struct AttrLeafNameRemote {
 }
 
 impl AttrLeafNameRemote {
-    fn value<R, F>(&mut self, buf_reader: &mut R, map_dblock: F) -> &[u8]
+    fn value<R, F>(&mut self, buf_reader: &mut R, map_dblock: F) -> Result<&[u8], XfsError>
     where
         R: BufRead + Reader + Seek,
-        F: Fn(XfsDablk, &mut R) -> XfsFsblock,
+        F: Fn(XfsDablk, &mut R) -> Result<XfsFsblock, XfsError>,
     {
         if self.value.len() < self.valuelen as usize {
             let sb = SUPERBLOCK.get().unwrap();
@@ -283,7 +388,7 @@ impl AttrLeafNameRemote {
             let mut valuelen: i64 = self.valuelen.into();
 
             while valuelen > 0 {
-                let blk_num = map_dblock(valueblk, buf_reader.by_ref());
+                let blk_num = map_dblock(valueblk, buf_reader.by_ref())?;
                 buf_reader
                     .seek(SeekFrom::Start(sb.fsb_to_offset(blk_num)))
                     .unwrap();
@@ -295,7 +400,7 @@ impl AttrLeafNameRemote {
                 valueblk += 1;
             }
         }
-        &self.value[..]
+        Ok(&self.value[..])
     }
 }
 
@@ -336,17 +441,31 @@ pub trait Attr {
         &mut self,
         buf_reader: &mut R,
         super_block: &Sb,
-    ) -> u32;
+    ) -> Result<u32, XfsError>;
 
-    fn list<R: BufRead + Reader + Seek>(&mut self, buf_reader: &mut R, super_block: &Sb)
-        -> Vec<u8>;
+    fn list<R: BufRead + Reader + Seek>(
+        &mut self,
+        buf_reader: &mut R,
+        super_block: &Sb,
+    ) -> Result<Vec<u8>, XfsError>;
+
+    /// List the parent pointers stored in this attribute fork, as (parent inode, directory entry
+    /// name) pairs.
+    fn parents<R: BufRead + Reader + Seek>(
+        &mut self,
+        buf_reader: &mut R,
+        super_block: &Sb,
+    ) -> Result<Vec<(XfsIno, Vec<u8>)>, XfsError>;
 
+    /// Get the value of the attribute with the given name, in the namespace given by `ns`, which
+    /// uses the on-disk flag representation.
     fn get<R>(
         &mut self,
         buf_reader: &mut R,
         super_block: &Sb,
+        ns: u8,
         name: &OsStr,
-    ) -> Result<Vec<u8>, libc::c_int>
+    ) -> Result<Vec<u8>, XfsError>
     where
         R: BufRead + Reader + Seek;
 }
@@ -369,11 +488,7 @@ pub fn open<R: Reader + BufRead + Seek>(
         match magic {
             XFS_ATTR_LEAF_MAGIC | XFS_ATTR3_LEAF_MAGIC => {
                 let leaf: AttrLeafblock = utils::decode(&raw).unwrap().0;
-                Attributes::Leaf(AttrLeaf {
-                    bmx,
-                    leaf,
-                    total_size: -1,
-                })
+                Attributes::Leaf(AttrLeaf::new(bmx, leaf))
             }
             XFS_DA_NODE_MAGIC | XFS_DA3_NODE_MAGIC => {
                 let node: XfsDa3Intnode = utils::decode(&raw).unwrap().0;
@@ -399,3 +514,424 @@ pub enum Attributes {
     Node(AttrNode),
     Btree(crate::libxfuse::attr_bptree::AttrBtree),
 }
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use rstest::rstest;
+    use tempfile::NamedTempFile;
+
+    use super::*;
+    use crate::libxfuse::{
+        attr_bptree::AttrBtree,
+        attr_leaf::tests::mkleaf,
+        block_reader::BlockReader,
+        bmbt_rec::BmbtRec,
+        btree::{BmbtKey, BmdrBlock, BtreeRoot},
+        da_btree::hashname,
+        definitions::XfsDahash,
+        sb::tests::superblock,
+        test_support::{self, mkbmbt_leaf},
+    };
+
+    /// FS block of the attribute leaf block
+    const LEAF: u64 = 20;
+    /// FS block of the da-btree node block
+    const NODE: u64 = 21;
+    /// FS block of the bmap btree leaf block
+    const BMBT: u64 = 22;
+
+    /// A local attribute entry: flags, name, and value
+    pub(crate) type Entry = (u8, &'static [u8], &'static [u8]);
+
+    /// A parent pointer, which is stored like an attribute but isn't one
+    const PARENT: &[Entry] = &[(
+        constants::XFS_ATTR_PARENT,
+        b"parent",
+        b"\0\0\0\0\0\0\0\x80\0\0\0\x01",
+    )];
+
+    fn bmbt_rec(startoff: u64, startblock: u64) -> BmbtRec {
+        BmbtRec {
+            br_startoff:   startoff,
+            br_startblock: startblock,
+            br_blockcount: 1,
+            br_flag:       false,
+        }
+    }
+
+    /// Build a da-btree node block at the given level, from (hashval, before) pairs
+    fn mknode(level: u16, children: &[(XfsDahash, XfsDablk)]) -> Vec<u8> {
+        let mut node = vec![0u8; superblock().geometry.blocksize];
+        node[8..10].copy_from_slice(&XFS_DA3_NODE_MAGIC.to_be_bytes());
+        node[56..58].copy_from_slice(&(children.len() as u16).to_be_bytes());
+        node[58..60].copy_from_slice(&level.to_be_bytes());
+        for (i, (hashval, before)) in children.iter().enumerate() {
+            node[64 + 8 * i..68 + 8 * i].copy_from_slice(&hashval.to_be_bytes());
+            node[68 + 8 * i..72 + 8 * i].copy_from_slice(&before.to_be_bytes());
+        }
+        node
+    }
+
+    /// The blocks of every attribute fork format: an attribute leaf block, a node block pointing
+    /// to it at dablk 1, and a bmap btree leaf mapping both.
+    fn fork_blocks(entries: &[Entry]) -> [(XfsFsblock, Vec<u8>); 3] {
+        [
+            (LEAF, mkleaf(0, entries)),
+            (NODE, mknode(1, &[(u32::MAX, 1)])),
+            (BMBT, mkbmbt_leaf(&[(0, NODE, 1), (1, LEAF, 1)])),
+        ]
+    }
+
+    fn shortform(entries: &[Entry], _reader: &mut BlockReader) -> Attributes {
+        let mut raw = vec![0u8, 0, entries.len() as u8, 0];
+        for (flags, name, value) in entries {
+            raw.extend_from_slice(&[name.len() as u8, value.len() as u8, *flags]);
+            raw.extend_from_slice(name);
+            raw.extend_from_slice(value);
+        }
+        Attributes::Sf(utils::decode(&raw[..]).unwrap().0)
+    }
+
+    fn leaf(_entries: &[Entry], reader: &mut BlockReader) -> Attributes {
+        open(reader, superblock(), Bmx::new(&[bmbt_rec(0, LEAF)]))
+    }
+
+    fn node(_entries: &[Entry], reader: &mut BlockReader) -> Attributes {
+        open(
+            reader,
+            superblock(),
+            Bmx::new(&[bmbt_rec(0, NODE), bmbt_rec(1, LEAF)]),
+        )
+    }
+
+    fn btree(_entries: &[Entry], reader: &mut BlockReader) -> Attributes {
+        let root = BtreeRoot::new(
+            BmdrBlock {
+                bb_level:   1,
+                bb_numrecs: 1,
+            },
+            vec![BmbtKey { br_startoff: 0 }],
+            vec![BMBT],
+        );
+        Attributes::Btree(AttrBtree::new(reader, superblock(), root))
+    }
+
+    /// An attribute fork with no visible attributes should present an empty list, regardless of
+    /// its format.
+    #[rstest]
+    fn empty_list(
+        #[values(shortform, leaf, node, btree)] backend: fn(
+            &[Entry],
+            &mut BlockReader,
+        ) -> Attributes,
+        #[values(&[], PARENT)] entries: &[Entry],
+    ) {
+        let sb = superblock();
+        let img = test_support::mkimg(&fork_blocks(entries));
+        let mut reader = test_support::open(&img);
+        let mut attrs = backend(entries, &mut reader);
+
+        assert_eq!(attrs.get_total_size(&mut reader, sb), Ok(0));
+        assert!(attrs.list(&mut reader, sb).unwrap().is_empty());
+        for ns in [b"user".as_slice(), b"trusted", b"secure"] {
+            let ns = get_flags_from_namespace(ns).unwrap();
+            assert_eq!(
+                attrs.get(&mut reader, sb, ns, OsStr::new("parent")),
+                Err(XfsError::NoAttr)
+            );
+        }
+    }
+
+    /// Parent pointers should be decoded from every attribute fork format, ignoring ordinary
+    /// attributes.
+    #[rstest]
+    fn parents(
+        #[values(shortform, leaf, node, btree)] backend: fn(
+            &[Entry],
+            &mut BlockReader,
+        ) -> Attributes,
+    ) {
+        let entries: &[Entry] = &[
+            (0, b"user_attr", b"value"),
+            PARENT[0],
+            (constants::XFS_ATTR_PARENT, b"truncated", b"\0\0\0\0"),
+        ];
+        let sb = superblock();
+        let img = test_support::mkimg(&fork_blocks(entries));
+        let mut reader = test_support::open(&img);
+        let mut attrs = backend(entries, &mut reader);
+
+        assert_eq!(
+            attrs.parents(&mut reader, sb),
+            Ok(vec![(0x80, b"parent".to_vec())])
+        );
+    }
+
+    /// If a node's child lies outside of the attribute fork's extents, the fork is corrupt.  That
+    /// should be reported as an error rather than a panic.
+    #[rstest]
+    #[case::hole(&[bmbt_rec(0, NODE), bmbt_rec(2, LEAF)])]
+    #[case::past_end(&[bmbt_rec(0, NODE)])]
+    fn node_unmapped_leaf(#[case] recs: &[BmbtRec]) {
+        let sb = superblock();
+        let img = test_support::mkimg(&fork_blocks(&[(0, b"user_attr", b"value")]));
+        let mut reader = test_support::open(&img);
+        let mut attrs = open(&mut reader, sb, Bmx::new(recs));
+        assert!(matches!(attrs, Attributes::Node(_)));
+
+        assert!(matches!(
+            attrs.get_total_size(&mut reader, sb),
+            Err(XfsError::Corrupt(_))
+        ));
+        assert!(matches!(
+            attrs.list(&mut reader, sb),
+            Err(XfsError::Corrupt(_))
+        ));
+        assert!(matches!(
+            attrs.get(&mut reader, sb, 0, OsStr::new("user_attr")),
+            Err(XfsError::Corrupt(_))
+        ));
+    }
+
+    /// An entry with the incomplete flag was left behind by a set or replace that didn't finish.
+    /// It must be ignored, leaving any complete entry of the same name visible.
+    #[rstest]
+    fn incomplete(
+        #[values(leaf, node, btree)] backend: fn(&[Entry], &mut BlockReader) -> Attributes,
+    ) {
+        const INCOMPLETE: u8 = constants::XFS_ATTR_INCOMPLETE;
+        let mut entries: Vec<Entry> = vec![
+            (INCOMPLETE, b"creating", b"new"),
+            (0, b"replacing", b"old"),
+            (INCOMPLETE, b"replacing", b"new"),
+            (
+                constants::XFS_ATTR_PARENT | INCOMPLETE,
+                b"parent",
+                PARENT[0].2,
+            ),
+        ];
+        entries.sort_by_key(|(_, name, _)| hashname(OsStr::from_bytes(name)));
+        let sb = superblock();
+        let img = test_support::mkimg(&fork_blocks(&entries));
+        let mut reader = test_support::open(&img);
+        let mut attrs = backend(&entries, &mut reader);
+
+        assert_eq!(attrs.list(&mut reader, sb).unwrap(), b"user.replacing\0");
+        assert_eq!(
+            attrs.get_total_size(&mut reader, sb),
+            Ok(b"user.replacing\0".len() as u32)
+        );
+        assert_eq!(
+            attrs.get(&mut reader, sb, 0, OsStr::new("replacing")),
+            Ok(b"old".to_vec())
+        );
+        assert_eq!(
+            attrs.get(&mut reader, sb, 0, OsStr::new("creating")),
+            Err(XfsError::NoAttr)
+        );
+        assert_eq!(attrs.parents(&mut reader, sb), Ok(vec![]));
+    }
+
+    /// An attribute may have an empty value, in which case it's just a flag.  It must be found and
+    /// listed like any other, with a value of length 0.
+    #[rstest]
+    fn empty_value(
+        #[values(shortform, leaf, node, btree)] backend: fn(
+            &[Entry],
+            &mut BlockReader,
+        ) -> Attributes,
+    ) {
+        let mut entries: Vec<Entry> = vec![(0, b"a", b"x"), (0, b"flag", b""), (0, b"b", b"yz")];
+        entries.sort_by_key(|(_, name, _)| hashname(OsStr::from_bytes(name)));
+        let sb = superblock();
+        let img = test_support::mkimg(&fork_blocks(&entries));
+        let mut reader = test_support::open(&img);
+        let mut attrs = backend(&entries, &mut reader);
+
+        assert_eq!(
+            attrs.get(&mut reader, sb, 0, OsStr::new("flag")),
+            Ok(Vec::new())
+        );
+        assert_eq!(
+            attrs.get(&mut reader, sb, 0, OsStr::new("b")),
+            Ok(b"yz".to_vec())
+        );
+        let list = attrs.list(&mut reader, sb).unwrap();
+        assert!(list.split(|c| *c == 0).any(|name| name == b"user.flag"));
+        assert_eq!(attrs.get_total_size(&mut reader, sb), Ok(list.len() as u32));
+    }
+
+    /// Removing an attribute from a leaf block shifts the following entries down and adds its
+    /// name and value to the freemap, but leaves their bytes in place.  Only the first `count`
+    /// entries may be used, and the stale name must not be listed or retrievable.
+    #[test]
+    fn leaf_removed_entry() {
+        let mut entries: Vec<Entry> = vec![
+            (0, b"attr.a", b"value.a"),
+            (0, b"attr.b", b"value.b"),
+            (0, b"attr.c", b"value.c"),
+        ];
+        entries.sort_by_key(|e| hashname(OsStr::from_bytes(e.1)));
+        let mut leaf = mkleaf(0, &entries);
+        let removed = entries.remove(1);
+
+        // Remove the middle entry, leaving a copy of the last one beyond the new count
+        leaf.copy_within(96..104, 88);
+        leaf[56..58].copy_from_slice(&2u16.to_be_bytes());
+        // freemap[0] covers the removed entry's name and value
+        let nameidx = u16::from_be_bytes(leaf[92..94].try_into().unwrap()) + 16;
+        leaf[64..66].copy_from_slice(&nameidx.to_be_bytes());
+        leaf[66..68].copy_from_slice(&16u16.to_be_bytes());
+        assert_eq!(&leaf[usize::from(nameidx) + 3..][..6], removed.1);
+
+        let mut block: AttrLeafblock = utils::decode(&leaf[..]).unwrap().0;
+        assert_eq!(block.entries.len(), 2);
+        let mut list = Vec::new();
+        block.list(&mut list);
+        let expected = entries
+            .iter()
+            .flat_map(|e| [b"user.", e.1, b"\0"].concat())
+            .collect::<Vec<u8>>();
+        assert_eq!(list, expected);
+        assert_eq!(block.get_total_size() as usize, expected.len());
+
+        let img = NamedTempFile::new().unwrap();
+        let mut reader = BlockReader::open(img.path()).unwrap();
+        let map =
+            |_: XfsDablk, _: &mut BlockReader| -> Result<XfsFsblock, XfsError> { unreachable!() };
+        for (_, name, value) in entries.iter() {
+            let name = OsStr::from_bytes(name);
+            let hash = hashname(name);
+            assert_eq!(block.get(&mut reader, hash, 0, name, map), Ok(*value));
+        }
+        let name = OsStr::from_bytes(removed.1);
+        assert_eq!(
+            block.get(&mut reader, hashname(name), 0, name, map),
+            Err(XfsError::NoAttr)
+        );
+    }
+
+    /// A large attribute fork can have a da-btree with more than one level of nodes.  Lookups must
+    /// descend through every level, and listing must follow the leaves from the leftmost one.
+    #[test]
+    fn node_two_levels() {
+        /// FS block of dablk 0.  The others follow contiguously.
+        const BASE: u64 = 40;
+        const NAMES: [&[u8]; 8] = [
+            b"attr.0", b"attr.1", b"attr.2", b"attr.3", b"attr.4", b"attr.5", b"attr.6", b"attr.7",
+        ];
+
+        let sb = superblock();
+        let mut entries: Vec<Entry> = NAMES.iter().map(|name| (0, *name, *name)).collect();
+        entries.sort_by_key(|e| hashname(OsStr::from_bytes(e.1)));
+        let last_hash = |leaf: usize| hashname(OsStr::from_bytes(entries[2 * leaf + 1].1));
+
+        // The root at dablk 0 points to nodes at dablks 1 and 2, each of which points to two of
+        // the leaves at dablks 3 through 6.
+        let mut blocks = vec![
+            mknode(2, &[(last_hash(1), 1), (last_hash(3), 2)]),
+            mknode(1, &[(last_hash(0), 3), (last_hash(1), 4)]),
+            mknode(1, &[(last_hash(2), 5), (last_hash(3), 6)]),
+        ];
+        for (i, pair) in entries.chunks(2).enumerate() {
+            let forw = if i < 3 { 4 + i as u32 } else { 0 };
+            blocks.push(mkleaf(forw, pair));
+        }
+
+        let img = test_support::mkimg(
+            &blocks
+                .iter()
+                .enumerate()
+                .map(|(dablk, block)| (BASE + dablk as u64, block))
+                .collect::<Vec<_>>(),
+        );
+        let mut reader = test_support::open(&img);
+        let recs = (0..blocks.len() as u64)
+            .map(|dablk| bmbt_rec(dablk, BASE + dablk))
+            .collect::<Vec<_>>();
+        let mut attrs = open(&mut reader, sb, Bmx::new(&recs));
+        assert!(matches!(attrs, Attributes::Node(_)));
+
+        for name in NAMES {
+            assert_eq!(
+                attrs.get(&mut reader, sb, 0, OsStr::from_bytes(name)),
+                Ok(name.to_vec())
+            );
+        }
+        assert_eq!(
+            attrs.get(&mut reader, sb, 0, OsStr::new("attr.8")),
+            Err(XfsError::NoAttr)
+        );
+        let expected = entries
+            .iter()
+            .flat_map(|e| [b"user.", e.1, b"\0"].concat())
+            .collect::<Vec<u8>>();
+        assert_eq!(attrs.list(&mut reader, sb).unwrap(), expected);
+    }
+
+    /// Names with the same hash may span two leaves.  Lookups must continue from the end of the
+    /// first leaf into the second, and listing must return each name exactly once.
+    #[rstest]
+    #[case::node(false)]
+    #[case::btree(true)]
+    fn hash_collision_at_leaf_boundary(#[case] use_btree: bool) {
+        /// FS block of dablk 0.  The others follow contiguously.
+        const BASE: u64 = 40;
+        /// FS block of the bmap btree leaf, if any
+        const BMBT: u64 = 50;
+        // "aaaabbbb" and "baaabzbb" have the same hash.  "a" hashes lower, and "attr.0" higher.
+        const NAMES: [&[u8]; 4] = [b"a", b"aaaabbbb", b"baaabzbb", b"attr.0"];
+
+        let sb = superblock();
+        let hash = |name: &[u8]| hashname(OsStr::from_bytes(name));
+        assert_eq!(hash(NAMES[1]), hash(NAMES[2]));
+        assert!(hash(NAMES[0]) < hash(NAMES[1]) && hash(NAMES[2]) < hash(NAMES[3]));
+        let entries: Vec<Entry> = NAMES.iter().map(|name| (0, *name, *name)).collect();
+
+        let recs = (0..3)
+            .map(|dablk| (dablk, BASE + dablk, 1))
+            .collect::<Vec<_>>();
+        let img = test_support::mkimg(&[
+            (BASE, mknode(1, &[(hash(NAMES[1]), 1), (hash(NAMES[3]), 2)])),
+            (BASE + 1, mkleaf(2, &entries[..2])),
+            (BASE + 2, mkleaf(0, &entries[2..])),
+            (BMBT, mkbmbt_leaf(&recs)),
+        ]);
+        let mut reader = test_support::open(&img);
+        let mut attrs = if use_btree {
+            let root = BtreeRoot::new(
+                BmdrBlock {
+                    bb_level:   1,
+                    bb_numrecs: 1,
+                },
+                vec![BmbtKey { br_startoff: 0 }],
+                vec![BMBT],
+            );
+            Attributes::Btree(AttrBtree::new(&mut reader, sb, root))
+        } else {
+            let recs = (0..3)
+                .map(|dablk| bmbt_rec(dablk, BASE + dablk))
+                .collect::<Vec<_>>();
+            open(&mut reader, sb, Bmx::new(&recs))
+        };
+
+        for name in NAMES {
+            assert_eq!(
+                attrs.get(&mut reader, sb, 0, OsStr::from_bytes(name)),
+                Ok(name.to_vec())
+            );
+        }
+        // A missing name with the same hash is searched for in both leaves, but no further
+        let missing = b"caaabrbb";
+        assert_eq!(hash(missing), hash(NAMES[1]));
+        assert_eq!(
+            attrs.get(&mut reader, sb, 0, OsStr::from_bytes(missing)),
+            Err(XfsError::NoAttr)
+        );
+        let expected = NAMES
+            .iter()
+            .flat_map(|name| [b"user.", *name, b"\0"].concat())
+            .collect::<Vec<u8>>();
+        assert_eq!(attrs.list(&mut reader, sb).unwrap(), expected);
+    }
+}
@@ -42,11 +42,13 @@ use super::{
     definitions::{
         XfsDablk,
         XfsFsblock,
+        XfsIno,
         XFS_ATTR3_LEAF_MAGIC,
         XFS_ATTR_LEAF_MAGIC,
         XFS_DA3_NODE_MAGIC,
         XFS_DA_NODE_MAGIC,
     },
+    error::XfsError,
     sb::Sb,
     utils,
 };
@@ -63,24 +65,32 @@ enum AttrBtreeBlock0 {
 }
 
 impl AttrBtreeBlock0 {
-    fn first_block<R, F>(&self, buf_reader: &mut R, super_block: &Sb, map_dblock: F) -> XfsDablk
+    fn first_block<R, F>(
+        &self,
+        buf_reader: &mut R,
+        super_block: &Sb,
+        map_dblock: F,
+    ) -> Result<XfsDablk, XfsError>
     where
         R: BufRead + Reader + Seek,
-        F: Fn(XfsDablk, &mut R) -> XfsFsblock,
+        F: Fn(XfsDablk, &mut R) -> Result<XfsFsblock, XfsError>,
     {
         match self {
             AttrBtreeBlock0::Node(node) => node.first_block(buf_reader, super_block, map_dblock),
-            AttrBtreeBlock0::Leaf => 0,
+            AttrBtreeBlock0::Leaf => Ok(0),
         }
     }
 
-    fn lookup<R: BufRead + Reader + Seek, F: Fn(XfsDablk, &mut R) -> XfsFsblock>(
+    fn lookup<
+        R: BufRead + Reader + Seek,
+        F: Fn(XfsDablk, &mut R) -> Result<XfsFsblock, XfsError>,
+    >(
         &self,
         buf_reader: &mut R,
         super_block: &Sb,
         hash: u32,
         map_dblock: F,
-    ) -> Result<XfsDablk, i32> {
+    ) -> Result<XfsDablk, XfsError> {
         match self {
             AttrBtreeBlock0::Node(node) => node.lookup(buf_reader, super_block, hash, map_dblock),
             AttrBtreeBlock0::Leaf => Ok(0),
@@ -138,11 +148,11 @@ impl AttrBtree {
         &self,
         buf_reader: &mut R,
         logical_block: XfsDablk,
-    ) -> Result<XfsFsblock, i32> {
+    ) -> Result<XfsFsblock, XfsError> {
         self.btree
             .map_block(buf_reader, logical_block.into())?
             .0
-            .ok_or(libc::ENOATTR)
+            .ok_or(XfsError::NoAttr)
     }
 
     /// Read the AttrLeafblock located at the given directory block number
@@ -151,7 +161,7 @@ impl AttrBtree {
         buf_reader: &mut R,
         sb: &Sb,
         dblock: XfsDablk,
-    ) -> Result<impl std::ops::DerefMut<Target = AttrLeafblock> + 'a, i32>
+    ) -> Result<impl std::ops::DerefMut<Target = AttrLeafblock> + 'a, XfsError>
     where
         R: Reader + BufRead + Seek,
     {
@@ -175,7 +185,7 @@ impl Attr for AttrBtree {
         &mut self,
         buf_reader: &mut R,
         super_block: &Sb,
-    ) -> u32 {
+    ) -> Result<u32, XfsError> {
         if self.total_size == -1 {
             let mut total_size: u32 = 0;
 
@@ -183,12 +193,10 @@ impl Attr for AttrBtree {
             let mut dablk =
                 self.node
                     .first_block(buf_reader.by_ref(), super_block, |block, reader| {
-                        self.map_dblock(reader.by_ref(), block).unwrap()
-                    });
+                        self.map_dblock(reader.by_ref(), block)
+                    })?;
             loop {
-                let leaf = self
-                    .read_leaf(buf_reader.by_ref(), super_block, dablk)
-                    .unwrap();
+                let leaf = self.read_leaf(buf_reader.by_ref(), super_block, dablk)?;
                 total_size += leaf.get_total_size();
                 dablk = leaf.hdr.forw;
                 if dablk == 0 {
@@ -199,26 +207,24 @@ impl Attr for AttrBtree {
             self.total_size = i64::from(total_size);
         }
 
-        self.total_size.try_into().unwrap()
+        Ok(self.total_size.try_into().unwrap())
     }
 
     fn list<R: Reader + BufRead + Seek>(
         &mut self,
         buf_reader: &mut R,
         super_block: &Sb,
-    ) -> Vec<u8> {
+    ) -> Result<Vec<u8>, XfsError> {
         let mut list: Vec<u8> =
-            Vec::with_capacity(self.get_total_size(buf_reader.by_ref(), super_block) as usize);
+            Vec::with_capacity(self.get_total_size(buf_reader.by_ref(), super_block)? as usize);
 
-        let mut dablk = self
-            .node
-            .first_block(buf_reader.by_ref(), super_block, |block, reader| {
-                self.map_dblock(reader.by_ref(), block).unwrap()
-            });
+        let mut dablk =
+            self.node
+                .first_block(buf_reader.by_ref(), super_block, |block, reader| {
+                    self.map_dblock(reader.by_ref(), block)
+                })?;
         loop {
-            let leaf = self
-                .read_leaf(buf_reader.by_ref(), super_block, dablk)
-                .unwrap();
+            let leaf = self.read_leaf(buf_reader.by_ref(), super_block, dablk)?;
             (*leaf).list(&mut list);
             dablk = leaf.hdr.forw;
             if dablk == 0 {
@@ -226,26 +232,64 @@ impl Attr for AttrBtree {
             }
         }
 
-        list
+        Ok(list)
     }
 
-    fn get<R>(&mut self, buf_reader: &mut R, super_block: &Sb, name: &OsStr) -> Result<Vec<u8>, i32>
+    fn parents<R: Reader + BufRead + Seek>(
+        &mut self,
+        buf_reader: &mut R,
+        super_block: &Sb,
+    ) -> Result<Vec<(XfsIno, Vec<u8>)>, XfsError> {
+        let mut parents = Vec::new();
+
+        let mut dablk =
+            self.node
+                .first_block(buf_reader.by_ref(), super_block, |block, reader| {
+                    self.map_dblock(reader.by_ref(), block)
+                })?;
+        loop {
+            let leaf = self.read_leaf(buf_reader.by_ref(), super_block, dablk)?;
+            (*leaf).parents(&mut parents);
+            dablk = leaf.hdr.forw;
+            if dablk == 0 {
+                break;
+            }
+        }
+
+        Ok(parents)
+    }
+
+    fn get<R>(
+        &mut self,
+        buf_reader: &mut R,
+        super_block: &Sb,
+        ns: u8,
+        name: &OsStr,
+    ) -> Result<Vec<u8>, XfsError>
     where
         R: Reader + BufRead + Seek,
     {
         let hash = hashname(name);
 
-        let dablk = self
+        let mut dablk = self
             .node
             .lookup(buf_reader.by_ref(), super_block, hash, |block, reader| {
-                self.map_dblock(reader.by_ref(), block).unwrap()
+                self.map_dblock(reader.by_ref(), block)
             })
-            .map_err(|e| if e == libc::ENOENT { libc::ENOATTR } else { e })?;
-        let mut leaf = self.read_leaf(buf_reader.by_ref(), super_block, dablk)?;
-
-        leaf.get(buf_reader.by_ref(), hash, |block, reader| {
-            self.map_dblock(reader.by_ref(), block).unwrap()
-        })
-        .map(Vec::from)
+            .map_err(|e| match e {
+                XfsError::NotFound => XfsError::NoAttr,
+                e => e,
+            })?;
+        loop {
+            let mut leaf = self.read_leaf(buf_reader.by_ref(), super_block, dablk)?;
+            let forw = leaf.hash_continues(hash).then_some(leaf.hdr.forw);
+            let r = leaf.get(buf_reader.by_ref(), hash, ns, name, |block, reader| {
+                self.map_dblock(reader.by_ref(), block)
+            });
+            match (r, forw) {
+                (Err(XfsError::NoAttr), Some(forw)) => dablk = forw,
+                (r, _) => return r.map(Vec::from),
+            }
+        }
     }
 }
@@ -28,36 +28,86 @@
 use std::{
     convert::TryInto,
     ffi::OsStr,
-    io::{BufRead, Seek},
+    io::{BufRead, Seek, SeekFrom},
 };
 
 use bincode::de::read::Reader;
 
 use super::{
-    attr::{Attr, AttrLeafblock},
+    attr::{map_dblock, Attr, AttrLeafblock},
     bmbt_rec::Bmx,
     da_btree::hashname,
+    definitions::{XfsDablk, XfsIno},
+    error::XfsError,
     sb::Sb,
+    utils::decode_from,
 };
 
+/// An attribute fork in extents format with no da-btree node.  Usually that means a single leaf
+/// block, but there may be several, linked in hash order by their `forw` pointers.
 #[derive(Debug)]
 pub struct AttrLeaf {
     pub bmx:        Bmx,
-    pub leaf:       AttrLeafblock,
+    /// The leaf blocks, in hash order.  Initially only the first is loaded.
+    leaves:         Vec<AttrLeafblock>,
+    /// Have we followed the `forw` chain to its end?
+    chained:        bool,
     pub total_size: i64,
 }
 
+impl AttrLeaf {
+    pub fn new(bmx: Bmx, leaf: AttrLeafblock) -> Self {
+        Self {
+            bmx,
+            leaves: vec![leaf],
+            chained: false,
+            total_size: -1,
+        }
+    }
+
+    /// Load every leaf block linked by the first one's `forw` pointer.
+    fn read_chain<R>(&mut self, buf_reader: &mut R, super_block: &Sb) -> Result<(), XfsError>
+    where
+        R: BufRead + Reader + Seek,
+    {
+        if self.chained {
+            return Ok(());
+        }
+        let mut visited: Vec<XfsDablk> = vec![0];
+        let mut forw = self.leaves[0].hdr.forw;
+        while forw != 0 {
+            if visited.contains(&forw) {
+                return Err(XfsError::Corrupt(format!(
+                    "Attribute leaf chain contains a cycle at block {}",
+                    forw
+                )));
+            }
+            visited.push(forw);
+            let fsblock = map_dblock(&self.bmx, forw)?;
+            buf_reader.seek(SeekFrom::Start(super_block.fsb_to_offset(fsblock)))?;
+            let leaf: AttrLeafblock = decode_from(buf_reader.by_ref())
+                .map_err(|e| XfsError::Corrupt(format!("Attribute leaf block {}: {}", forw, e)))?;
+            forw = leaf.hdr.forw;
+            self.leaves.push(leaf);
+        }
+        self.chained = true;
+        Ok(())
+    }
+}
+
 impl Attr for AttrLeaf {
     fn get_total_size<R: BufRead + Reader + Seek>(
         &mut self,
-        _buf_reader: &mut R,
-        _super_block: &Sb,
-    ) -> u32 {
+        buf_reader: &mut R,
+        super_block: &Sb,
+    ) -> Result<u32, XfsError> {
         if self.total_size != -1 {
-            self.total_size.try_into().unwrap()
+            Ok(self.total_size.try_into().unwrap())
         } else {
-            self.total_size = i64::from(self.leaf.get_total_size());
-            self.total_size as u32
+            self.read_chain(buf_reader.by_ref(), super_block)?;
+            let total_size: u32 = self.leaves.iter().map(AttrLeafblock::get_total_size).sum();
+            self.total_size = i64::from(total_size);
+            Ok(total_size)
         }
     }
 
@@ -65,32 +115,228 @@ impl Attr for AttrLeaf {
         &mut self,
         buf_reader: &mut R,
         super_block: &Sb,
-    ) -> Vec<u8> {
+    ) -> Result<Vec<u8>, XfsError> {
         let mut list: Vec<u8> =
-            Vec::with_capacity(self.get_total_size(buf_reader.by_ref(), super_block) as usize);
+            Vec::with_capacity(self.get_total_size(buf_reader.by_ref(), super_block)? as usize);
+
+        for leaf in self.leaves.iter() {
+            leaf.list(&mut list);
+        }
+
+        Ok(list)
+    }
+
+    fn parents<R: BufRead + Reader + Seek>(
+        &mut self,
+        buf_reader: &mut R,
+        super_block: &Sb,
+    ) -> Result<Vec<(XfsIno, Vec<u8>)>, XfsError> {
+        self.read_chain(buf_reader.by_ref(), super_block)?;
+        let mut parents = Vec::new();
 
-        self.leaf.list(&mut list);
+        for leaf in self.leaves.iter() {
+            leaf.parents(&mut parents);
+        }
 
-        list
+        Ok(parents)
     }
 
     fn get<R>(
         &mut self,
         buf_reader: &mut R,
-        _super_block: &Sb,
+        super_block: &Sb,
+        ns: u8,
         name: &OsStr,
-    ) -> Result<Vec<u8>, i32>
+    ) -> Result<Vec<u8>, XfsError>
     where
         R: BufRead + Reader + Seek,
     {
         let hash = hashname(name);
 
+        self.read_chain(buf_reader.by_ref(), super_block)?;
         let bmx = &self.bmx;
-        self.leaf
-            .get(buf_reader.by_ref(), hash, |block, _| {
-                bmx.map_dblock(block)
-                    .expect("holes are not allowed in attr forks")
+        for leaf in self.leaves.iter_mut() {
+            match leaf.get(buf_reader.by_ref(), hash, ns, name, |block, _| {
+                map_dblock(bmx, block)
+            }) {
+                Ok(value) => return Ok(Vec::from(value)),
+                Err(XfsError::NoAttr) => (),
+                Err(e) => return Err(e),
+            }
+            // Leaves are in hash order, so no later leaf can contain this hash.
+            if leaf.entries.last().is_some_and(|e| e.hashval > hash) {
+                break;
+            }
+        }
+        Err(XfsError::NoAttr)
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use std::os::unix::ffi::OsStrExt;
+
+    use rstest::rstest;
+
+    use super::*;
+    use crate::libxfuse::{
+        attr::{constants::XFS_ATTR_LOCAL, open, Attributes},
+        bmbt_rec::BmbtRec,
+        definitions::XFS_ATTR3_LEAF_MAGIC,
+        sb::tests::superblock,
+        test_support::{self, mkimg},
+    };
+
+    /// Build a v5 attribute leaf block containing the given local entries, each given as flags,
+    /// name, and value.  The entries must already be in hash order.
+    pub(crate) fn mkleaf(forw: u32, entries: &[(u8, &[u8], &[u8])]) -> Vec<u8> {
+        let bs = superblock().geometry.blocksize;
+        let mut leaf = vec![0u8; bs];
+        leaf[0..4].copy_from_slice(&forw.to_be_bytes());
+        leaf[8..10].copy_from_slice(&XFS_ATTR3_LEAF_MAGIC.to_be_bytes());
+        leaf[56..58].copy_from_slice(&(entries.len() as u16).to_be_bytes());
+        let mut nameidx = bs;
+        for (i, (flags, name, value)) in entries.iter().enumerate() {
+            nameidx -= (3 + name.len() + value.len() + 3) & !3;
+            leaf[nameidx..nameidx + 2].copy_from_slice(&(value.len() as u16).to_be_bytes());
+            leaf[nameidx + 2] = name.len() as u8;
+            leaf[nameidx + 3..nameidx + 3 + name.len()].copy_from_slice(name);
+            leaf[nameidx + 3 + name.len()..nameidx + 3 + name.len() + value.len()]
+                .copy_from_slice(value);
+            let ent = 80 + 8 * i;
+            let hash = hashname(OsStr::from_bytes(name));
+            leaf[ent..ent + 4].copy_from_slice(&hash.to_be_bytes());
+            leaf[ent + 4..ent + 6].copy_from_slice(&(nameidx as u16).to_be_bytes());
+            leaf[ent + 6] = flags | XFS_ATTR_LOCAL;
+        }
+        leaf
+    }
+
+    /// Borrow hash-sorted user attributes as `mkleaf` entries
+    fn user_entries(attrs: &[(u32, String, String)]) -> Vec<(u8, &[u8], &[u8])> {
+        attrs
+            .iter()
+            .map(|(_, name, value)| (0, name.as_bytes(), value.as_bytes()))
+            .collect()
+    }
+
+    /// An extents-format attribute fork with two leaf blocks linked by their forw pointers, but
+    /// no node block above them.
+    #[test]
+    fn two_chained_leaves() {
+        let sb = superblock();
+        let mut attrs = (0..200)
+            .map(|i| {
+                let name = format!("attr{:03}", i);
+                let hash = hashname(OsStr::new(&name));
+                (hash, name, format!("value{:03}", i))
             })
-            .map(Vec::from)
+            .collect::<Vec<_>>();
+        attrs.sort();
+        let (first, second) = attrs.split_at(attrs.len() / 2);
+
+        let img = mkimg(&[
+            (10, mkleaf(1, &user_entries(first))),
+            (11, mkleaf(0, &user_entries(second))),
+        ]);
+        let mut reader = test_support::open(&img);
+        let bmx = Bmx::new(&[BmbtRec {
+            br_startoff:   0,
+            br_startblock: 10,
+            br_blockcount: 2,
+            br_flag:       false,
+        }]);
+        let mut attributes = open(&mut reader, sb, bmx);
+        assert!(matches!(attributes, Attributes::Leaf(_)));
+
+        let list = attributes.list(&mut reader, sb).unwrap();
+        let expected = attrs
+            .iter()
+            .map(|(_, name, _)| format!("user.{}\0", name))
+            .collect::<String>();
+        assert_eq!(list, expected.as_bytes());
+        assert_eq!(
+            attributes.get_total_size(&mut reader, sb).unwrap() as usize,
+            list.len()
+        );
+
+        for (_, name, value) in attrs.iter() {
+            let v = attributes
+                .get(&mut reader, sb, 0, OsStr::new(name))
+                .unwrap();
+            assert_eq!(v, value.as_bytes());
+        }
+        assert_eq!(
+            attributes.get(&mut reader, sb, 0, OsStr::new("attr200")),
+            Err(XfsError::NoAttr)
+        );
+    }
+
+    /// A leaf attribute with an empty value occupies only its name, padded.  Retrieving it must
+    /// return an empty value, and the neighboring entries' values must be unaffected.
+    #[test]
+    fn empty_value() {
+        let sb = superblock();
+        let mut attrs = ["a", "flag", "b"]
+            .iter()
+            .map(|name| {
+                let value = if *name == "flag" { "" } else { "value" };
+                (
+                    hashname(OsStr::new(name)),
+                    name.to_string(),
+                    value.to_string(),
+                )
+            })
+            .collect::<Vec<_>>();
+        attrs.sort();
+
+        let img = mkimg(&[(10, mkleaf(0, &user_entries(&attrs)))]);
+        let mut reader = test_support::open(&img);
+        let bmx = Bmx::new(&[BmbtRec {
+            br_startoff:   0,
+            br_startblock: 10,
+            br_blockcount: 1,
+            br_flag:       false,
+        }]);
+        let mut attributes = open(&mut reader, sb, bmx);
+        for (_, name, value) in attrs.iter() {
+            let v = attributes
+                .get(&mut reader, sb, 0, OsStr::new(name))
+                .unwrap();
+            assert_eq!(v, value.as_bytes());
+        }
+    }
+
+    /// A corrupt forw chain is an error, not a crash.  The second leaf's forw pointer either leads
+    /// back to itself, or past the end of the fork, or the block it leads to isn't a leaf at all.
+    #[rstest]
+    #[case::cycle(1, "Attribute leaf chain contains a cycle at block 1")]
+    #[case::hole(5, "Attribute block 5 is not mapped")]
+    #[case::bad_magic(2, "Attribute leaf block 2: ")]
+    fn corrupt_chain(#[case] forw: u32, #[case] expected: &str) {
+        let sb = superblock();
+        let attrs = [(
+            hashname(OsStr::new("a")),
+            "a".to_string(),
+            "value".to_string(),
+        )];
+
+        let img = mkimg(&[
+            (10, mkleaf(1, &user_entries(&attrs))),
+            (11, mkleaf(forw, &[])),
+        ]);
+        let mut reader = test_support::open(&img);
+        let bmx = Bmx::new(&[BmbtRec {
+            br_startoff:   0,
+            br_startblock: 10,
+            br_blockcount: 3,
+            br_flag:       false,
+        }]);
+        let mut attributes = open(&mut reader, sb, bmx);
+        let e = attributes.list(&mut reader, sb).unwrap_err();
+        assert!(
+            matches!(e, XfsError::Corrupt(ref s) if s.starts_with(expected)),
+            "{e:?}"
+        );
     }
 }
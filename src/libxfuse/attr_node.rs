@@ -36,10 +36,11 @@ use std::{
 use bincode::de::read::Reader;
 
 use super::{
-    attr::{Attr, AttrLeafblock},
+    attr::{self, Attr, AttrLeafblock},
     bmbt_rec::Bmx,
     da_btree::{hashname, XfsDa3Intnode},
-    definitions::{XfsDablk, XfsFsblock},
+    definitions::{XfsDablk, XfsFsblock, XfsIno},
+    error::XfsError,
     sb::Sb,
     utils::decode_from,
 };
@@ -63,10 +64,8 @@ impl AttrNode {
         }
     }
 
-    fn map_dblock(&self, dblock: XfsDablk) -> XfsFsblock {
-        self.bmx
-            .map_dblock(dblock)
-            .expect("holes are not allowed in attr forks")
+    fn map_dblock(&self, dblock: XfsDablk) -> Result<XfsFsblock, XfsError> {
+        attr::map_dblock(&self.bmx, dblock)
     }
 
     /// Read the AttrLeafblock located at the given directory block number
@@ -75,14 +74,14 @@ impl AttrNode {
         buf_reader: &mut R,
         sb: &Sb,
         dblock: XfsDablk,
-    ) -> Result<impl std::ops::DerefMut<Target = AttrLeafblock> + 'a, i32>
+    ) -> Result<impl std::ops::DerefMut<Target = AttrLeafblock> + 'a, XfsError>
     where
         R: Reader + BufRead + Seek,
     {
         let mut cache_guard = self.leaves.borrow_mut();
         let entry = cache_guard.entry(dblock);
         if matches!(entry, Entry::Vacant(_)) {
-            let fsblock = self.map_dblock(dblock);
+            let fsblock = self.map_dblock(dblock)?;
             let leaf_offset = sb.fsb_to_offset(fsblock);
             buf_reader.seek(SeekFrom::Start(leaf_offset)).unwrap();
             let node: AttrLeafblock = decode_from(buf_reader.by_ref()).unwrap();
@@ -99,19 +98,17 @@ impl Attr for AttrNode {
         &mut self,
         buf_reader: &mut R,
         super_block: &Sb,
-    ) -> u32 {
+    ) -> Result<u32, XfsError> {
         if self.total_size == -1 {
             let mut total_size: u32 = 0;
 
-            let mut dablk = self
-                .node
-                .first_block(buf_reader.by_ref(), super_block, |block, _| {
-                    self.map_dblock(block)
-                });
+            let mut dablk =
+                self.node
+                    .first_block(buf_reader.by_ref(), super_block, |block, _| {
+                        self.map_dblock(block)
+                    })?;
             while dablk != 0 {
-                let leaf = self
-                    .read_leaf(buf_reader.by_ref(), super_block, dablk)
-                    .unwrap();
+                let leaf = self.read_leaf(buf_reader.by_ref(), super_block, dablk)?;
                 total_size += leaf.get_total_size();
                 dablk = leaf.hdr.forw;
             }
@@ -119,48 +116,83 @@ impl Attr for AttrNode {
             self.total_size = i64::from(total_size);
         }
 
-        self.total_size.try_into().unwrap()
+        Ok(self.total_size.try_into().unwrap())
     }
 
     fn list<R: Reader + BufRead + Seek>(
         &mut self,
         buf_reader: &mut R,
         super_block: &Sb,
-    ) -> Vec<u8> {
+    ) -> Result<Vec<u8>, XfsError> {
         let mut list: Vec<u8> =
-            Vec::with_capacity(self.get_total_size(buf_reader.by_ref(), super_block) as usize);
+            Vec::with_capacity(self.get_total_size(buf_reader.by_ref(), super_block)? as usize);
 
         let mut dablk = self
             .node
             .first_block(buf_reader.by_ref(), super_block, |block, _| {
                 self.map_dblock(block)
-            });
+            })?;
         while dablk != 0 {
-            let leaf = self
-                .read_leaf(buf_reader.by_ref(), super_block, dablk)
-                .unwrap();
+            let leaf = self.read_leaf(buf_reader.by_ref(), super_block, dablk)?;
             (*leaf).list(&mut list);
             dablk = leaf.hdr.forw;
         }
 
-        list
+        Ok(list)
+    }
+
+    fn parents<R: Reader + BufRead + Seek>(
+        &mut self,
+        buf_reader: &mut R,
+        super_block: &Sb,
+    ) -> Result<Vec<(XfsIno, Vec<u8>)>, XfsError> {
+        let mut parents = Vec::new();
+
+        let mut dablk = self
+            .node
+            .first_block(buf_reader.by_ref(), super_block, |block, _| {
+                self.map_dblock(block)
+            })?;
+        while dablk != 0 {
+            let leaf = self.read_leaf(buf_reader.by_ref(), super_block, dablk)?;
+            (*leaf).parents(&mut parents);
+            dablk = leaf.hdr.forw;
+        }
+
+        Ok(parents)
     }
 
-    fn get<R>(&mut self, buf_reader: &mut R, super_block: &Sb, name: &OsStr) -> Result<Vec<u8>, i32>
+    fn get<R>(
+        &mut self,
+        buf_reader: &mut R,
+        super_block: &Sb,
+        ns: u8,
+        name: &OsStr,
+    ) -> Result<Vec<u8>, XfsError>
     where
         R: Reader + BufRead + Seek,
     {
         let hash = hashname(name);
 
-        let dablk = self
+        let mut dablk = self
             .node
             .lookup(buf_reader.by_ref(), super_block, hash, |block, _| {
                 self.map_dblock(block)
             })
-            .map_err(|e| if e == libc::ENOENT { libc::ENOATTR } else { e })?;
-        let mut leaf = self.read_leaf(buf_reader.by_ref(), super_block, dablk)?;
-
-        leaf.get(buf_reader.by_ref(), hash, |block, _| self.map_dblock(block))
-            .map(Vec::from)
+            .map_err(|e| match e {
+                XfsError::NotFound => XfsError::NoAttr,
+                e => e,
+            })?;
+        loop {
+            let mut leaf = self.read_leaf(buf_reader.by_ref(), super_block, dablk)?;
+            let forw = leaf.hash_continues(hash).then_some(leaf.hdr.forw);
+            let r = leaf.get(buf_reader.by_ref(), hash, ns, name, |block, _| {
+                self.map_dblock(block)
+            });
+            match (r, forw) {
+                (Err(XfsError::NoAttr), Some(forw)) => dablk = forw,
+                (r, _) => return r.map(Vec::from),
+            }
+        }
     }
 }
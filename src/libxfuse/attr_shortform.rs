@@ -38,7 +38,17 @@ use bincode::{
 };
 
 use super::{
-    attr::{get_namespace_from_flags, get_namespace_size_from_flags, Attr},
+    attr::{
+        constants::XFS_ATTR_PARENT,
+        get_namespace_from_flags,
+        get_namespace_size_from_flags,
+        is_listable,
+        namespace_matches,
+        push_parent,
+        Attr,
+    },
+    definitions::XfsIno,
+    error::XfsError,
     sb::Sb,
 };
 
@@ -88,7 +98,10 @@ impl<Ctx> Decode<Ctx> for AttrShortform {
 
         for _ in 0..hdr.count {
             let entry: AttrSfEntry = Decode::decode(decoder)?;
-            total_size += get_namespace_size_from_flags(entry.flags) + u32::from(entry.namelen) + 1;
+            if is_listable(entry.flags) {
+                total_size +=
+                    get_namespace_size_from_flags(entry.flags) + u32::from(entry.namelen) + 1;
+            }
             list.push(entry);
         }
 
@@ -101,47 +114,150 @@ impl Attr for AttrShortform {
         &mut self,
         _buf_reader: &mut R,
         _super_block: &Sb,
-    ) -> u32 {
-        self.total_size
+    ) -> Result<u32, XfsError> {
+        Ok(self.total_size)
     }
 
     fn list<R: BufRead + Reader + Seek>(
         &mut self,
         buf_reader: &mut R,
         super_block: &Sb,
-    ) -> Vec<u8> {
+    ) -> Result<Vec<u8>, XfsError> {
         let mut list: Vec<u8> =
-            Vec::with_capacity(self.get_total_size(buf_reader.by_ref(), super_block) as usize);
+            Vec::with_capacity(self.get_total_size(buf_reader.by_ref(), super_block)? as usize);
 
-        for entry in self.list.iter() {
+        for entry in self.list.iter().filter(|e| is_listable(e.flags)) {
             list.extend_from_slice(get_namespace_from_flags(entry.flags));
             let namelen = entry.namelen as usize;
             list.extend_from_slice(&entry.nameval[0..namelen]);
             list.push(0)
         }
 
-        list
+        Ok(list)
+    }
+
+    fn parents<R: BufRead + Reader + Seek>(
+        &mut self,
+        _buf_reader: &mut R,
+        _super_block: &Sb,
+    ) -> Result<Vec<(XfsIno, Vec<u8>)>, XfsError> {
+        let mut parents = Vec::new();
+
+        for entry in self.list.iter().filter(|e| e.flags & XFS_ATTR_PARENT != 0) {
+            let namelen = entry.namelen as usize;
+            push_parent(
+                &mut parents,
+                &entry.nameval[0..namelen],
+                &entry.nameval[namelen..],
+            );
+        }
+
+        Ok(parents)
     }
 
     fn get<R>(
         &mut self,
         _buf_reader: &mut R,
         _super_block: &Sb,
+        ns: u8,
         name: &OsStr,
-    ) -> Result<Vec<u8>, i32>
+    ) -> Result<Vec<u8>, XfsError>
     where
         R: BufRead + Reader + Seek,
     {
         for entry in &self.list {
-            let entry_name = entry.nameval[0..(entry.namelen as usize)].to_vec();
+            let entry_name = &entry.nameval[0..(entry.namelen as usize)];
 
-            if name.as_bytes().to_vec() == entry_name {
+            if namespace_matches(entry.flags, ns) && name.as_bytes() == entry_name {
                 let namelen = entry.namelen as usize;
 
                 return Ok(entry.nameval[namelen..].to_vec());
             }
         }
 
-        Err(libc::ENOATTR)
+        Err(XfsError::NoAttr)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::libxfuse::{attr::get_flags_from_namespace, block_reader::BlockReader, utils};
+
+    /// Build a shortform attribute fork containing the given (flags, name, value) entries
+    fn mkattrs(entries: &[(u8, &[u8], &[u8])]) -> AttrShortform {
+        let mut raw = vec![0u8, 0, entries.len() as u8, 0];
+        for (flags, name, value) in entries {
+            raw.push(name.len() as u8);
+            raw.push(value.len() as u8);
+            raw.push(*flags);
+            raw.extend_from_slice(name);
+            raw.extend_from_slice(value);
+        }
+        utils::decode(&raw[..]).unwrap().0
+    }
+
+    /// Overlayfs stores its metadata in the trusted namespace.  It must be retrieved exactly, and
+    /// not confused with an attribute of the same name in a different namespace.
+    #[test]
+    fn trusted_overlay() {
+        let trusted = get_flags_from_namespace(b"trusted").unwrap();
+        let user = get_flags_from_namespace(b"user").unwrap();
+        let mut attrs = mkattrs(&[
+            (user, b"overlay.opaque", b"n"),
+            (trusted, b"overlay.opaque", b"y"),
+            (trusted, b"overlay.redirect", b"/a/b"),
+        ]);
+        let img = tempfile::NamedTempFile::new().unwrap();
+        let mut reader = BlockReader::open(img.path()).unwrap();
+        let sb = crate::libxfuse::sb::tests::superblock();
+
+        let name = OsStr::new("overlay.opaque");
+        assert_eq!(attrs.get(&mut reader, sb, trusted, name).unwrap(), b"y");
+        assert_eq!(attrs.get(&mut reader, sb, user, name).unwrap(), b"n");
+        let name = OsStr::new("overlay.redirect");
+        assert_eq!(attrs.get(&mut reader, sb, trusted, name).unwrap(), b"/a/b");
+        assert_eq!(
+            attrs.get(&mut reader, sb, user, name),
+            Err(XfsError::NoAttr)
+        );
+
+        let list = attrs.list(&mut reader, sb).unwrap();
+        assert_eq!(
+            &list[..],
+            &b"user.overlay.opaque\0trusted.overlay.opaque\0trusted.overlay.redirect\0"[..]
+        );
+        assert_eq!(
+            list.len(),
+            attrs.get_total_size(&mut reader, sb).unwrap() as usize
+        );
+    }
+
+    /// An attribute may have an empty value, in which case it's just a flag.  Retrieving it must
+    /// succeed, and must not disturb the entries that follow.
+    #[test]
+    fn empty_value() {
+        let user = get_flags_from_namespace(b"user").unwrap();
+        let mut attrs = mkattrs(&[
+            (user, b"a", b"x"),
+            (user, b"flag", b""),
+            (user, b"b", b"yz"),
+        ]);
+        let img = tempfile::NamedTempFile::new().unwrap();
+        let mut reader = BlockReader::open(img.path()).unwrap();
+        let sb = crate::libxfuse::sb::tests::superblock();
+
+        let flag = attrs
+            .get(&mut reader, sb, user, OsStr::new("flag"))
+            .unwrap();
+        assert!(flag.is_empty());
+        assert_eq!(
+            attrs.get(&mut reader, sb, user, OsStr::new("b")).unwrap(),
+            b"yz"
+        );
+        assert_eq!(
+            attrs.get(&mut reader, sb, user, OsStr::new("nonexistent")),
+            Err(XfsError::NoAttr)
+        );
     }
 }
@@ -35,6 +35,8 @@ use std::{
 
 use bincode::{de::read::Reader, error::DecodeError};
 use cfg_if::cfg_if;
+use memmap2::{Mmap, MmapOptions};
+use tracing::debug;
 
 #[cfg(target_os = "freebsd")]
 mod ffi {
@@ -44,6 +46,19 @@ mod ffi {
         /// might not be (i.e. CDROM audio).
         diocgsectorsize, b'd', 128, u32
     }
+    nix::ioctl_read! {
+        /// Get the size of the entire device in bytes.  This should be a multiple of the sector
+        /// size.
+        diocgmediasize, b'd', 129, libc::off_t
+    }
+}
+
+#[cfg(target_os = "linux")]
+mod ffi {
+    nix::ioctl_read! {
+        /// Get the size of a block device in bytes
+        blkgetsize64, 0x12, 114, u64
+    }
 }
 
 #[derive(Debug)]
@@ -53,6 +68,12 @@ pub struct BlockReader {
     idx:        usize,
     /// The absolute minimum that we can read in any operation
     sectorsize: usize,
+    /// If set, log every read from the underlying file under this label
+    trace:      Option<&'static str>,
+    /// If set, a mapping of the entire file.  Reads are copied from it instead of using syscalls.
+    map:        Option<Mmap>,
+    /// When reading from `map`, the file offset of the end of the buffer
+    mpos:       u64,
 }
 
 impl BlockReader {
@@ -76,8 +97,59 @@ impl BlockReader {
         md.blksize() as usize
     }
 
+    /// Get the size in bytes of a regular file or a disk device.  A device's metadata doesn't
+    /// report its size, so ask its driver instead.
+    fn file_size(f: &File) -> IoResult<u64> {
+        let md = f.metadata()?;
+        cfg_if! {
+            if #[cfg(target_os = "freebsd")] {
+                use std::os::unix::fs::FileTypeExt;
+
+                let ft = md.file_type();
+                if ft.is_block_device() || ft.is_char_device() {
+                    let mut size = mem::MaybeUninit::<libc::off_t>::uninit();
+                    unsafe {
+                        // This ioctl is always safe
+                        ffi::diocgmediasize(f.as_raw_fd(), size.as_mut_ptr())?;
+                        return Ok(size.assume_init() as u64);
+                    }
+                }
+            } else if #[cfg(target_os = "linux")] {
+                use std::os::unix::fs::FileTypeExt;
+
+                if md.file_type().is_block_device() {
+                    let mut size = mem::MaybeUninit::<u64>::uninit();
+                    unsafe {
+                        // This ioctl is always safe
+                        ffi::blkgetsize64(f.as_raw_fd(), size.as_mut_ptr())?;
+                        return Ok(size.assume_init());
+                    }
+                }
+            }
+        }
+        Ok(md.len())
+    }
+
+    fn open_file(path: &Path) -> IoResult<File> {
+        let mut options = File::options();
+        options.read(true).write(false);
+        cfg_if! {
+            if #[cfg(target_os = "linux")] {
+                use std::os::unix::fs::OpenOptionsExt;
+
+                // Don't update the backing file's atime on every scan.  Only the file's owner may
+                // do that, so fall back to a regular open if we aren't.
+                match options.clone().custom_flags(libc::O_NOATIME).open(path) {
+                    Err(e) if e.raw_os_error() == Some(libc::EPERM) => (),
+                    r => return r,
+                }
+            }
+        }
+        options.open(path)
+    }
+
     pub fn open(path: &Path) -> IoResult<Self> {
-        let file = File::options().read(true).write(false).open(path)?;
+        let file = Self::open_file(path)?;
 
         let sectorsize = Self::sectorsize(&file);
         let block = vec![0u8; sectorsize];
@@ -86,11 +158,42 @@ impl BlockReader {
             block,
             idx: sectorsize,
             sectorsize,
+            trace: None,
+            map: None,
+            mpos: 0,
         })
     }
 
+    /// The file offset of the end of the buffer
+    fn position(&mut self) -> IoResult<u64> {
+        match self.map {
+            Some(_) => Ok(self.mpos),
+            None => self.file.stream_position(),
+        }
+    }
+
     fn refill(&mut self) -> IoResult<()> {
-        self.file.read_exact(&mut self.block)?;
+        if let Some(label) = self.trace {
+            let offset = self.position()?;
+            debug!(
+                "{} read at {:#x}, {} bytes",
+                label,
+                offset,
+                self.block.len()
+            );
+        }
+        match &self.map {
+            Some(map) => {
+                let src = usize::try_from(self.mpos)
+                    .ok()
+                    .and_then(|start| map.get(start..))
+                    .and_then(|tail| tail.get(..self.block.len()))
+                    .ok_or_else(|| io::Error::from(io::ErrorKind::UnexpectedEof))?;
+                self.block.copy_from_slice(src);
+                self.mpos += self.block.len() as u64;
+            }
+            None => self.file.read_exact(&mut self.block)?,
+        }
         self.idx = 0;
         Ok(())
     }
@@ -106,6 +209,45 @@ impl BlockReader {
         Ok(())
     }
 
+    /// Override the detected sector size.  Every subsequent read, even one smaller than a
+    /// sector, will be padded out to a multiple of this granularity.  It must be a power of 2.
+    pub fn set_sectorsize(&mut self, sectorsize: usize) {
+        assert!(
+            sectorsize.is_power_of_two(),
+            "Sector size {} is not a power of 2",
+            sectorsize
+        );
+        self.sectorsize = sectorsize;
+        self.set_bufsize(sectorsize);
+    }
+
+    /// Log the offset and length of every subsequent read from the underlying file, at debug
+    /// level, prefixed by `label`.
+    pub fn set_trace(&mut self, label: &'static str) {
+        self.trace = Some(label);
+    }
+
+    /// Serve every subsequent read from a memory mapping of the whole file, rather than with read
+    /// syscalls.  That can be faster for random access to a large, cached image.  On failure, for
+    /// example if the file is larger than the address space or can't be mapped, reads will
+    /// continue to use syscalls.
+    pub fn set_mmap(&mut self) -> IoResult<()> {
+        let pos = self.file.stream_position()?;
+        let len =
+            usize::try_from(self.size()?).map_err(|_| io::Error::from_raw_os_error(libc::EFBIG))?;
+        // Safety: the mapping is read-only, and like the rest of xfs-fuse it assumes that the
+        // image isn't modified while in use.
+        let map = unsafe { MmapOptions::new().len(len).map(&self.file)? };
+        self.map = Some(map);
+        self.mpos = pos;
+        Ok(())
+    }
+
+    /// The size of the underlying file or device, in bytes
+    pub fn size(&self) -> IoResult<u64> {
+        Self::file_size(&self.file)
+    }
+
     /// The current size of the buffer
     pub fn bufsize(&self) -> usize {
         self.block.len()
@@ -154,7 +296,12 @@ impl Seek for BlockReader {
         let bs = self.bufsize() as u64;
         match pos {
             SeekFrom::Start(pos) => {
-                let real = self.file.seek(SeekFrom::Start(pos / bs * bs))?;
+                let real = if self.map.is_some() {
+                    self.mpos = pos / bs * bs;
+                    self.mpos
+                } else {
+                    self.file.seek(SeekFrom::Start(pos / bs * bs))?
+                };
                 let rem = pos - real;
                 assert!(rem < bs);
 
@@ -164,7 +311,7 @@ impl Seek for BlockReader {
                 Ok(real + rem)
             }
             SeekFrom::Current(offset) => {
-                let real = self.file.stream_position()?;
+                let real = self.position()?;
                 let cur = real - self.block.len() as u64 + self.idx as u64;
                 let newidx = offset + self.idx as i64;
                 if newidx >= 0 && newidx < self.bufsize() as i64 {
@@ -297,4 +444,149 @@ mod t {
             );
         }
     }
+
+    mod sectorsize {
+        use std::os::unix::fs::FileExt;
+
+        use super::*;
+
+        /// With an overridden sector size, even an inode-sized read should be padded out to a
+        /// whole sector, and still return the requested data.
+        #[test]
+        fn padded_inode_read() {
+            const SECTORSIZE: usize = 8192;
+            const INODESIZE: usize = 512;
+
+            let f = tempfile::NamedTempFile::new().unwrap();
+            f.as_file().set_len(4 * SECTORSIZE as u64).unwrap();
+            let ofs = SECTORSIZE + 3 * INODESIZE;
+            f.as_file().write_all_at(b"IN", ofs as u64).unwrap();
+            let mut br = BlockReader::open(f.path()).unwrap();
+            br.set_sectorsize(SECTORSIZE);
+
+            br.set_bufsize(INODESIZE);
+            assert_eq!(br.bufsize(), SECTORSIZE);
+            br.seek(SeekFrom::Start(ofs as u64)).unwrap();
+            let pos = br.file.stream_position().unwrap();
+            assert_eq!(pos % SECTORSIZE as u64, 0);
+            let mut buf = [0u8; 2];
+            br.read_exact(&mut buf).unwrap();
+            assert_eq!(&buf, b"IN");
+        }
+
+        #[test]
+        #[should_panic(expected = "not a power of 2")]
+        fn not_power_of_2() {
+            let f = tempfile::NamedTempFile::new().unwrap();
+            let mut br = BlockReader::open(f.path()).unwrap();
+            br.set_sectorsize(3000);
+        }
+    }
+    mod size {
+        use std::{fs, os::unix::fs::FileTypeExt};
+
+        use super::*;
+
+        #[test]
+        fn regular_file() {
+            let f = tempfile::NamedTempFile::new().unwrap();
+            f.as_file().set_len(3 << 20).unwrap();
+            let br = BlockReader::open(f.path()).unwrap();
+            assert_eq!(br.size().unwrap(), 3 << 20);
+        }
+
+        /// A disk device's size must match the position of its end.  Only runs if a readable disk
+        /// device exists.
+        #[test]
+        fn device() {
+            let is_disk = |e: &fs::DirEntry| {
+                let Ok(ft) = e.file_type() else {
+                    return false;
+                };
+                cfg_if! {
+                    if #[cfg(target_os = "freebsd")] {
+                        let name = e.file_name();
+                        let name = name.as_encoded_bytes();
+                        ft.is_char_device() &&
+                            [&b"md"[..], b"ada", b"da", b"nvd", b"vtbd"]
+                                .iter()
+                                .any(|p| name.starts_with(p))
+                    } else {
+                        ft.is_block_device()
+                    }
+                }
+            };
+            let Some(mut f) = fs::read_dir("/dev")
+                .unwrap()
+                .filter_map(Result::ok)
+                .filter(is_disk)
+                .find_map(|e| File::open(e.path()).ok())
+            else {
+                return;
+            };
+            let end = f.seek(SeekFrom::End(0)).unwrap();
+            assert_eq!(BlockReader::file_size(&f).unwrap(), end);
+        }
+    }
+
+    mod mmap {
+        use std::os::unix::fs::FileExt;
+
+        use super::*;
+
+        /// Reads from the mapping must return the same data as reads from the file, at any
+        /// offset and through any sequence of seeks.
+        #[test]
+        fn same_as_file() {
+            let f = tempfile::NamedTempFile::new().unwrap();
+            let data = (0..1u32 << 16)
+                .flat_map(u32::to_be_bytes)
+                .collect::<Vec<_>>();
+            f.as_file().write_all_at(&data, 0).unwrap();
+            let mut br = BlockReader::open(f.path()).unwrap();
+            let mut mbr = BlockReader::open(f.path()).unwrap();
+            mbr.set_mmap().unwrap();
+
+            let bs = br.bufsize();
+            for ofs in [
+                0,
+                5,
+                bs as u64 - 2,
+                3 * bs as u64 + 7,
+                data.len() as u64 - 4,
+            ] {
+                let mut expected = [0u8; 4];
+                let mut actual = [0u8; 4];
+                br.seek(SeekFrom::Start(ofs)).unwrap();
+                br.read_exact(&mut expected).unwrap();
+                mbr.seek(SeekFrom::Start(ofs)).unwrap();
+                mbr.read_exact(&mut actual).unwrap();
+                assert_eq!(actual, expected, "mismatch at offset {}", ofs);
+
+                br.seek(SeekFrom::Current(-4)).unwrap();
+                br.read_exact(&mut expected).unwrap();
+                mbr.seek(SeekFrom::Current(-4)).unwrap();
+                mbr.read_exact(&mut actual).unwrap();
+                assert_eq!(actual, expected, "mismatch after seeking back to {}", ofs);
+            }
+        }
+
+        /// Reading beyond the end of the mapping must fail, not panic
+        #[test]
+        fn eof() {
+            let f = tempfile::NamedTempFile::new().unwrap();
+            let mut br = BlockReader::open(f.path()).unwrap();
+            let bs = br.bufsize() as u64;
+            f.as_file().set_len(2 * bs).unwrap();
+            br.set_mmap().unwrap();
+
+            br.seek(SeekFrom::Start(bs)).unwrap();
+            let mut buf = vec![0u8; br.bufsize()];
+            br.read_exact(&mut buf).unwrap();
+            let e = br.read_exact(&mut buf).unwrap_err();
+            assert_eq!(e.kind(), io::ErrorKind::UnexpectedEof);
+            let e = br.seek(SeekFrom::Start(3 * bs)).unwrap_err();
+            assert_eq!(e.kind(), io::ErrorKind::UnexpectedEof);
+        }
+    }
 }
@@ -29,7 +29,7 @@
 use bincode::{de::Decoder, error::DecodeError, Decode};
 use num_derive::FromPrimitive;
 
-use super::{definitions::*, volume::SUPERBLOCK};
+use super::{definitions::*, error::XfsError, volume::SUPERBLOCK};
 
 #[derive(Debug, FromPrimitive, Clone)]
 pub enum XfsExntst {
@@ -48,6 +48,11 @@ pub struct BmbtRec {
     pub br_flag:       bool,
 }
 
+impl BmbtRec {
+    /// On-disk size in bytes
+    pub const SIZE: usize = 16;
+}
+
 impl<Ctx> Decode<Ctx> for BmbtRec {
     fn decode<D: Decoder>(decoder: &mut D) -> Result<Self, DecodeError> {
         let br: u128 = Decode::decode(decoder)?;
@@ -124,7 +129,7 @@ impl Bmx {
         self.0.first()
     }
 
-    pub fn lseek(&self, offset: u64, whence: i32) -> Result<u64, i32> {
+    pub fn lseek(&self, offset: u64, whence: i32) -> Result<u64, XfsError> {
         let sb = SUPERBLOCK.get().unwrap();
 
         let dblock = offset >> sb.sb_blocklog;
@@ -136,7 +141,7 @@ impl Bmx {
                 } else {
                     self.first()
                         .map(|b| b.br_startoff << sb.sb_blocklog)
-                        .ok_or(libc::ENXIO)
+                        .ok_or(XfsError::NoData)
                 }
             }
             i => {
@@ -169,7 +174,7 @@ impl Bmx {
                     } else {
                         match self.0.get(i) {
                             Some(next_entry) => Ok(next_entry.br_startoff << sb.sb_blocklog),
-                            None => Err(libc::ENXIO),
+                            None => Err(XfsError::NoData),
                         }
                     }
                 }
@@ -180,8 +185,8 @@ impl Bmx {
     pub fn map_dblock(&self, dblock: XfsDablk) -> Option<XfsFsblock> {
         let dblock = XfsFileoff::from(dblock);
         let i = self.0.partition_point(|rec| rec.br_startoff <= dblock);
-        let rec = &self.0[i - 1];
-        if i == 0 || rec.br_startoff > dblock || rec.br_startoff + rec.br_blockcount <= dblock {
+        let rec = &self.0[i.checked_sub(1)?];
+        if rec.br_startoff + rec.br_blockcount <= dblock {
             None
         } else {
             Some(rec.br_startblock + dblock - rec.br_startoff)
@@ -227,5 +232,29 @@ mod tests {
         ]);
 
         assert_eq!(bmx.map_dblock(6), Some(41));
+        assert_eq!(bmx.map_dblock(100), None);
+    }
+
+    /// Blocks before the first extent, or in a hole between extents, aren't mapped
+    #[test]
+    fn map_dblock_unmapped() {
+        let bmx = Bmx::new(&[
+            BmbtRec {
+                br_startoff:   3,
+                br_startblock: 20,
+                br_blockcount: 1,
+                br_flag:       false,
+            },
+            BmbtRec {
+                br_startoff:   6,
+                br_startblock: 30,
+                br_blockcount: 1,
+                br_flag:       false,
+            },
+        ]);
+
+        assert_eq!(bmx.map_dblock(0), None);
+        assert_eq!(bmx.map_dblock(3), Some(20));
+        assert_eq!(bmx.map_dblock(5), None);
     }
 }
@@ -40,8 +40,9 @@ use bincode::{
 use num_traits::{PrimInt, Unsigned};
 
 use super::{
-    bmbt_rec::Bmx,
+    bmbt_rec::{BmbtRec, Bmx},
     definitions::{XfsFileoff, XfsFsblock, XFS_BMAP_CRC_MAGIC, XFS_BMAP_MAGIC},
+    error::XfsError,
     utils::{decode, decode_from, Uuid},
     volume::SUPERBLOCK,
 };
@@ -63,6 +64,23 @@ pub struct BtreeBlockHdr<T: PrimInt + Unsigned> {
     //_bb_pad: u32,
 }
 
+impl<T: PrimInt + Unsigned> BtreeBlockHdr<T> {
+    /// Size of the header in bytes
+    fn size(&self) -> usize {
+        let short = 8 + 2 * std::mem::size_of::<T>();
+        match self.bb_magic {
+            XFS_BMAP_CRC_MAGIC => short + 48,
+            _ => short,
+        }
+    }
+
+    /// The most records that can fit in a block with this header.  Each record is 16 bytes: either
+    /// a key and a pointer, or a BmbtRec.
+    fn maxrecs(&self, blocksize: usize) -> usize {
+        (blocksize - self.size()) / 16
+    }
+}
+
 impl<T: Decode<Ctx> + PrimInt + Unsigned, Ctx> Decode<Ctx> for BtreeBlockHdr<T> {
     fn decode<D: Decoder<Context = Ctx>>(decoder: &mut D) -> Result<Self, DecodeError> {
         let bb_magic: u32 = Decode::decode(decoder)?;
@@ -77,12 +95,19 @@ impl<T: Decode<Ctx> + PrimInt + Unsigned, Ctx> Decode<Ctx> for BtreeBlockHdr<T>
                 let _bb_lsn: u64 = Decode::decode(decoder)?;
                 let bb_uuid: Uuid = Decode::decode(decoder)?;
                 let super_block = SUPERBLOCK.get().unwrap();
-                assert_eq!(bb_uuid, super_block.sb_uuid);
+                if bb_uuid != super_block.sb_uuid {
+                    return Err(DecodeError::Other("bmap btree block has the wrong UUID"));
+                }
                 let _bb_owner: u64 = Decode::decode(decoder)?;
                 let _bb_crc: u32 = Decode::decode(decoder)?;
                 let _bb_pad: u32 = Decode::decode(decoder)?;
             }
-            _ => panic!("Unexpected magic value {:#x}", bb_magic),
+            _ => {
+                return Err(DecodeError::OtherString(format!(
+                    "Unexpected magic value {:#x}",
+                    bb_magic
+                )))
+            }
         };
         Ok(BtreeBlockHdr {
             bb_magic,
@@ -133,7 +158,7 @@ pub trait Btree: BtreePriv {
         &self,
         buf_reader: &mut R,
         logical_block: XfsFileoff,
-    ) -> Result<(Option<XfsFsblock>, Option<u64>), i32> {
+    ) -> Result<(Option<XfsFsblock>, Option<u64>), XfsError> {
         let super_block = SUPERBLOCK.get().unwrap();
         let pp = self
             .keys()
@@ -143,7 +168,7 @@ pub trait Btree: BtreePriv {
         let idx = pp.saturating_sub(1);
 
         let mut guard = self.block_cache().borrow_mut();
-        match &mut *guard {
+        let (start, len) = match &mut *guard {
             BlockCache::Intermediate(bci) => {
                 assert!(self.level() > 1);
 
@@ -151,11 +176,23 @@ pub trait Btree: BtreePriv {
                 match entry {
                     Entry::Vacant(ve) => {
                         let offset = super_block.fsb_to_offset(self.ptrs()[idx]);
-                        buf_reader
-                            .seek(SeekFrom::Start(offset))
-                            .map_err(|e| e.raw_os_error().unwrap())?;
+                        buf_reader.seek(SeekFrom::Start(offset))?;
                         let bti: BtreeIntermediate =
-                            decode_from(buf_reader.by_ref()).map_err(|_| libc::EDESTADDRREQ)?;
+                            decode_from(buf_reader.by_ref()).map_err(|e| {
+                                XfsError::Corrupt(format!(
+                                    "bmap btree block at {:#x}: {}",
+                                    offset, e
+                                ))
+                            })?;
+                        if bti.level() != self.level() - 1 {
+                            return Err(XfsError::Corrupt(format!(
+                                "bmap btree block at {:#x} has level {} but its parent has level \
+                                 {}",
+                                offset,
+                                bti.level(),
+                                self.level()
+                            )));
+                        }
                         ve.insert(bti).map_block(buf_reader, logical_block)
                     }
                     Entry::Occupied(oe) => {
@@ -171,11 +208,10 @@ pub trait Btree: BtreePriv {
                 match entry {
                     Entry::Vacant(ve) => {
                         let offset = super_block.fsb_to_offset(self.ptrs()[idx]);
-                        buf_reader
-                            .seek(SeekFrom::Start(offset))
-                            .map_err(|e| e.raw_os_error().unwrap())?;
-                        let btl: BtreeLeaf =
-                            decode_from(buf_reader.by_ref()).map_err(|_| libc::EDESTADDRREQ)?;
+                        buf_reader.seek(SeekFrom::Start(offset))?;
+                        let btl: BtreeLeaf = decode_from(buf_reader.by_ref()).map_err(|e| {
+                            XfsError::Corrupt(format!("bmap btree leaf at {:#x}: {}", offset, e))
+                        })?;
                         Ok(ve.insert(btl).get_extent(logical_block))
                     }
                     Entry::Occupied(oe) => {
@@ -184,7 +220,18 @@ pub trait Btree: BtreePriv {
                     }
                 }
             }
-        }
+        }?;
+        // A hole at the end of this child's range extends to the next child's first block, not
+        // necessarily to EoF.
+        let len = match (start, len) {
+            (None, None) => self
+                .keys()
+                .get(idx + 1)
+                .and_then(|k| k.br_startoff.checked_sub(logical_block))
+                .filter(|len| *len > 0),
+            _ => len,
+        };
+        Ok((start, len))
     }
 }
 
@@ -219,7 +266,7 @@ pub struct BtreeRoot {
 }
 
 impl BtreeRoot {
-    pub fn lseek<R>(&self, buf_reader: &mut R, offset: u64, whence: i32) -> Result<u64, i32>
+    pub fn lseek<R>(&self, buf_reader: &mut R, offset: u64, whence: i32) -> Result<u64, XfsError>
     where
         R: BufRead + Reader + Seek,
     {
@@ -277,12 +324,25 @@ impl BtreeRoot {
                 if whence == libc::SEEK_HOLE {
                     Ok(offset)
                 } else {
-                    Err(libc::ENXIO)
+                    Err(XfsError::NoData)
                 }
             }
         }
     }
 
+    /// Return every record in the tree, in file offset order.  Unlike the lookup methods, this
+    /// includes unwritten extents.
+    pub fn records<R>(&self, buf_reader: &mut R) -> Result<Vec<BmbtRec>, XfsError>
+    where
+        R: BufRead + Reader + Seek,
+    {
+        let mut recs = Vec::new();
+        for ptr in self.ptrs.iter() {
+            collect_records(buf_reader.by_ref(), *ptr, self.bmdr.bb_level - 1, &mut recs)?;
+        }
+        Ok(recs)
+    }
+
     pub fn new(bmdr: BmdrBlock, keys: Vec<BmbtKey>, ptrs: Vec<XfsBmdrPtr>) -> Self {
         let blocks = RefCell::new(BlockCache::new(bmdr.bb_level));
         Self {
@@ -346,11 +406,21 @@ impl Btree for BtreeIntermediate {}
 
 impl<Ctx> Decode<Ctx> for BtreeIntermediate {
     fn decode<D: Decoder>(decoder: &mut D) -> Result<Self, DecodeError> {
-        let blocksize = SUPERBLOCK.get().unwrap().sb_blocksize as usize;
+        let blocksize = SUPERBLOCK.get().unwrap().geometry.blocksize;
         let mut raw = vec![0u8; blocksize];
         decoder.reader().read(&mut raw)?;
         let (hdr, mut ofs) = decode::<XfsBmbtLblock>(&raw)?;
-        assert!(hdr.bb_level > 0);
+        if hdr.bb_level == 0 {
+            return Err(DecodeError::Other(
+                "Intermediate bmap btree block has level 0",
+            ));
+        }
+        if hdr.bb_numrecs == 0 || usize::from(hdr.bb_numrecs) > hdr.maxrecs(blocksize) {
+            return Err(DecodeError::OtherString(format!(
+                "Invalid bb_numrecs {}",
+                hdr.bb_numrecs
+            )));
+        }
 
         let mut keys = Vec::with_capacity(usize::from(hdr.bb_numrecs));
         for _ in 0..hdr.bb_numrecs {
@@ -384,6 +454,52 @@ impl<Ctx> Decode<Ctx> for BtreeIntermediate {
     }
 }
 
+/// Append every record beneath the given btree block, which should be at the given level, to
+/// `recs`.  The block cache isn't used, because cached leaves lack unwritten extents.
+fn collect_records<R>(
+    buf_reader: &mut R,
+    fsblock: XfsBmbtPtr,
+    level: u16,
+    recs: &mut Vec<BmbtRec>,
+) -> Result<(), XfsError>
+where
+    R: BufRead + Reader + Seek,
+{
+    let super_block = SUPERBLOCK.get().unwrap();
+    let offset = super_block.fsb_to_offset(fsblock);
+    buf_reader.seek(SeekFrom::Start(offset))?;
+    if level > 0 {
+        let bti: BtreeIntermediate = decode_from(buf_reader.by_ref())
+            .map_err(|e| XfsError::Corrupt(format!("bmap btree block at {:#x}: {}", offset, e)))?;
+        if bti.level() != level {
+            return Err(XfsError::Corrupt(format!(
+                "bmap btree block at {:#x} has level {} but should have level {}",
+                offset,
+                bti.level(),
+                level
+            )));
+        }
+        for ptr in bti.ptrs.iter() {
+            collect_records(buf_reader.by_ref(), *ptr, level - 1, recs)?;
+        }
+    } else {
+        let hdr: XfsBmbtLblock = decode_from(buf_reader.by_ref())
+            .map_err(|e| XfsError::Corrupt(format!("bmap btree leaf at {:#x}: {}", offset, e)))?;
+        if hdr.bb_level != 0
+            || usize::from(hdr.bb_numrecs) > hdr.maxrecs(super_block.geometry.blocksize)
+        {
+            return Err(XfsError::Corrupt(format!(
+                "bmap btree leaf at {:#x} has bad level or record count",
+                offset
+            )));
+        }
+        for _ in 0..hdr.bb_numrecs {
+            recs.push(decode_from(buf_reader.by_ref())?);
+        }
+    }
+    Ok(())
+}
+
 /// A Leaf Btree.
 #[derive(Debug)]
 struct BtreeLeaf {
@@ -403,10 +519,167 @@ impl BtreeLeaf {
 impl<Ctx> Decode<Ctx> for BtreeLeaf {
     fn decode<D: Decoder>(decoder: &mut D) -> Result<Self, DecodeError> {
         let hdr: XfsBmbtLblock = Decode::decode(decoder)?;
-        assert_eq!(hdr.bb_level, 0);
+        if hdr.bb_level != 0 {
+            return Err(DecodeError::Other(
+                "Leaf bmap btree block has nonzero level",
+            ));
+        }
+        let blocksize = SUPERBLOCK.get().unwrap().geometry.blocksize;
+        if usize::from(hdr.bb_numrecs) > hdr.maxrecs(blocksize) {
+            return Err(DecodeError::OtherString(format!(
+                "Invalid bb_numrecs {}",
+                hdr.bb_numrecs
+            )));
+        }
 
         let bmx = Bmx::from((0..hdr.bb_numrecs).map(|_| Decode::decode(decoder).unwrap()));
 
         Ok(Self { bmx })
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use rstest::rstest;
+
+    use super::*;
+    use crate::libxfuse::test_support::{mkbmbt_leaf, mkbmbt_node, mkimg, open};
+
+    /// Build a root with a single pointer to an intermediate block at FSblock 10, whose contents
+    /// are supplied by the caller.  Return the result of looking up block 0.
+    fn map_corrupt(
+        f: impl FnOnce(&mut [u8]),
+    ) -> Result<(Option<XfsFsblock>, Option<u64>), XfsError> {
+        let mut block = mkbmbt_node(1, &[(0, 0)]);
+        f(&mut block[..]);
+        let img = mkimg(&[(10, block)]);
+        let mut reader = open(&img);
+
+        let root = BtreeRoot::new(
+            BmdrBlock {
+                bb_level:   2,
+                bb_numrecs: 1,
+            },
+            vec![BmbtKey { br_startoff: 0 }],
+            vec![10],
+        );
+        root.map_block(&mut reader, 0)
+    }
+
+    /// Records should be collected from every leaf, in order, including unwritten extents.
+    #[test]
+    fn records() {
+        let mut leaf = mkbmbt_leaf(&[(0, 1000, 10), (50, 2000, 10)]);
+        leaf[88] |= 0x80; // The second extent is unwritten
+        let img = mkimg(&[
+            (10, mkbmbt_node(1, &[(0, 11), (100, 12)])),
+            (11, leaf),
+            (12, mkbmbt_leaf(&[(100, 3000, 10)])),
+        ]);
+        let mut reader = open(&img);
+
+        let root = BtreeRoot::new(
+            BmdrBlock {
+                bb_level:   2,
+                bb_numrecs: 1,
+            },
+            vec![BmbtKey { br_startoff: 0 }],
+            vec![10],
+        );
+        let recs = root.records(&mut reader).unwrap();
+        let summary = recs
+            .iter()
+            .map(|r| (r.br_startoff, r.br_startblock, r.br_blockcount, r.br_flag))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            summary,
+            vec![
+                (0, 1000, 10, false),
+                (50, 2000, 10, true),
+                (100, 3000, 10, false)
+            ]
+        );
+    }
+
+    #[test]
+    fn records_corrupt() {
+        let img = mkimg::<&[u8]>(&[]);
+        let mut reader = open(&img);
+
+        let root = BtreeRoot::new(
+            BmdrBlock {
+                bb_level:   1,
+                bb_numrecs: 1,
+            },
+            vec![BmbtKey { br_startoff: 0 }],
+            vec![10],
+        );
+        assert!(matches!(
+            root.records(&mut reader),
+            Err(XfsError::Corrupt(_))
+        ));
+    }
+
+    #[test]
+    fn intermediate_bad_magic() {
+        let r = map_corrupt(|block| block[0..4].copy_from_slice(&0xdeadbeefu32.to_be_bytes()));
+        assert!(matches!(r, Err(XfsError::Corrupt(_))));
+    }
+
+    #[test]
+    fn intermediate_wrong_level() {
+        let r = map_corrupt(|block| block[4..6].copy_from_slice(&5u16.to_be_bytes()));
+        assert!(matches!(r, Err(XfsError::Corrupt(_))));
+    }
+
+    #[test]
+    fn intermediate_too_many_records() {
+        let r = map_corrupt(|block| block[6..8].copy_from_slice(&1000u16.to_be_bytes()));
+        assert!(matches!(r, Err(XfsError::Corrupt(_))));
+    }
+
+    #[test]
+    fn intermediate_no_records() {
+        let r = map_corrupt(|block| block[6..8].copy_from_slice(&0u16.to_be_bytes()));
+        assert!(matches!(r, Err(XfsError::Corrupt(_))));
+    }
+
+    /// A heavily fragmented file may need a bmbt more than two levels tall.  Lookups must descend
+    /// through every intermediate level, and a hole that spans two leaves must end where the
+    /// next leaf's first extent begins, not at EoF.
+    #[rstest]
+    #[case::first_leaf(0, (Some(5000), Some(10)))]
+    #[case::mid_extent(104, (Some(6004), Some(6)))]
+    #[case::hole_before_next_leaf(50, (None, Some(50)))]
+    #[case::hole_before_next_subtree(600, (None, Some(400)))]
+    #[case::second_subtree(1000, (Some(8000), Some(10)))]
+    #[case::last_extent(1105, (Some(9005), Some(5)))]
+    #[case::hole_to_eof(2000, (None, None))]
+    fn three_levels(#[case] block: u64, #[case] expected: (Option<u64>, Option<u64>)) {
+        let img = mkimg(&[
+            (10, mkbmbt_node(2, &[(0, 11), (500, 12)])),
+            (20, mkbmbt_node(2, &[(1000, 21)])),
+            (11, mkbmbt_node(1, &[(0, 13), (100, 14)])),
+            (12, mkbmbt_node(1, &[(500, 15)])),
+            (21, mkbmbt_node(1, &[(1000, 22)])),
+            (13, mkbmbt_leaf(&[(0, 5000, 10)])),
+            (14, mkbmbt_leaf(&[(100, 6000, 10)])),
+            (15, mkbmbt_leaf(&[(500, 7000, 10)])),
+            (22, mkbmbt_leaf(&[(1000, 8000, 10), (1100, 9000, 10)])),
+        ]);
+        let mut reader = open(&img);
+
+        let root = BtreeRoot::new(
+            BmdrBlock {
+                bb_level:   3,
+                bb_numrecs: 2,
+            },
+            vec![BmbtKey { br_startoff: 0 }, BmbtKey { br_startoff: 1000 }],
+            vec![10, 20],
+        );
+        assert_eq!(root.map_block(&mut reader, block).unwrap(), expected);
+        // A second lookup is served from the block cache
+        assert_eq!(root.map_block(&mut reader, block).unwrap(), expected);
+        assert_eq!(root.records(&mut reader).unwrap().len(), 5);
+    }
+}
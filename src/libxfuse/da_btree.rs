@@ -41,7 +41,7 @@ use bincode::{
 };
 use byteorder::{BigEndian, ReadBytesExt};
 
-use super::{definitions::*, sb::Sb, utils, utils::Uuid, volume::SUPERBLOCK};
+use super::{definitions::*, error::XfsError, sb::Sb, utils, utils::Uuid, volume::SUPERBLOCK};
 
 pub fn hashname(name: &OsStr) -> XfsDahash {
     let name = name.as_bytes();
@@ -91,7 +91,8 @@ pub struct XfsDa3Blkinfo {
     // _blkno: u64
     // _lsn: u64
     // uuid: Uuid
-    // _owner: u64
+    /// The inode that this block belongs to
+    pub owner: u64,
 }
 
 impl<Ctx> Decode<Ctx> for XfsDa3Blkinfo {
@@ -104,10 +105,10 @@ impl<Ctx> Decode<Ctx> for XfsDa3Blkinfo {
         let _blkno: u64 = Decode::decode(decoder)?;
         let _lsn: u64 = Decode::decode(decoder)?;
         let uuid: Uuid = Decode::decode(decoder)?;
-        let _owner: u64 = Decode::decode(decoder)?;
+        let owner = Decode::decode(decoder)?;
         assert_eq!(uuid, SUPERBLOCK.get().unwrap().sb_uuid, "UUID mismatch!");
 
-        Ok(XfsDa3Blkinfo { forw, magic })
+        Ok(XfsDa3Blkinfo { forw, magic, owner })
     }
 }
 impl_borrow_decode!(XfsDa3Blkinfo);
@@ -198,16 +199,19 @@ impl XfsDa3Intnode {
         }
     }
 
-    pub fn lookup<R: BufRead + Reader + Seek, F: Fn(XfsDablk, &mut R) -> XfsFsblock>(
+    pub fn lookup<
+        R: BufRead + Reader + Seek,
+        F: Fn(XfsDablk, &mut R) -> Result<XfsFsblock, XfsError>,
+    >(
         &self,
         buf_reader: &mut R,
         super_block: &Sb,
         hash: u32,
         map_dblock: F,
-    ) -> Result<XfsDablk, i32> {
+    ) -> Result<XfsDablk, XfsError> {
         let pidx = self.btree.partition_point(|k| k.hashval < hash);
         if pidx >= self.btree.len() {
-            return Err(libc::ENOENT);
+            return Err(XfsError::NotFound);
         }
         let before = self.btree[pidx].before;
 
@@ -221,18 +225,21 @@ impl XfsDa3Intnode {
         }
     }
 
-    pub fn first_block<R, F>(&self, buf_reader: &mut R, super_block: &Sb, map_dblock: F) -> XfsDablk
+    pub fn first_block<R, F>(
+        &self,
+        buf_reader: &mut R,
+        super_block: &Sb,
+        map_dblock: F,
+    ) -> Result<XfsDablk, XfsError>
     where
         R: BufRead + Reader + Seek,
-        F: Fn(XfsDablk, &mut R) -> XfsFsblock,
+        F: Fn(XfsDablk, &mut R) -> Result<XfsFsblock, XfsError>,
     {
         if self.level == 1 {
-            self.btree.first().unwrap().before
+            Ok(self.btree.first().unwrap().before)
         } else {
             let before = self.btree.first().unwrap().before;
-            let node = self
-                .read_child(buf_reader.by_ref(), super_block, before, &map_dblock)
-                .unwrap();
+            let node = self.read_child(buf_reader.by_ref(), super_block, before, &map_dblock)?;
             node.first_block(buf_reader.by_ref(), super_block, map_dblock)
         }
     }
@@ -243,15 +250,15 @@ impl XfsDa3Intnode {
         super_block: &Sb,
         dblock: XfsDablk,
         map_dblock: &F,
-    ) -> Result<impl std::ops::Deref<Target = Self> + 'a, i32>
+    ) -> Result<impl std::ops::Deref<Target = Self> + 'a, XfsError>
     where
         R: BufRead + Reader + Seek,
-        F: Fn(XfsDablk, &mut R) -> XfsFsblock,
+        F: Fn(XfsDablk, &mut R) -> Result<XfsFsblock, XfsError>,
     {
         let mut cache_guard = self.children.borrow_mut();
         let entry = cache_guard.entry(dblock);
         if matches!(entry, Entry::Vacant(_)) {
-            let fsblock = map_dblock(dblock, buf_reader.by_ref());
+            let fsblock = map_dblock(dblock, buf_reader.by_ref())?;
             let offset = super_block.fsb_to_offset(fsblock);
             buf_reader.seek(SeekFrom::Start(offset)).unwrap();
             buf_reader.fill_buf().unwrap();
@@ -324,3 +331,30 @@ fn hashname_collisions() {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::os::unix::ffi::OsStrExt;
+
+    use rstest::rstest;
+
+    use super::*;
+
+    /// The hash consumes the name in 4-byte groups, with special handling for the last 1-3 bytes.
+    /// Expected values come from the kernel's xfs_da_hashname.
+    #[rstest]
+    #[case::len1(b"a", 0x00000061)]
+    #[case::len2(b"ab", 0x000030e2)]
+    #[case::len3(b"abc", 0x00187163)]
+    #[case::len4(b"abcd", 0x0c38b1e4)]
+    #[case::len5(b"abcde", 0x1c58f263)]
+    #[case::len6(b"abcdef", 0x2c7931e8)]
+    #[case::len7(b"abcdefg", 0x3c98f471)]
+    #[case::len8(b"abcdefgh", 0x4c7a38f6)]
+    // Bytes must be treated as unsigned
+    #[case::high_bit3(b"\xc3\xa9t", 0x003094f4)]
+    #[case::high_bit6(b"\xc3\xa9t\xc3\xa9\xff", 0x9eb0926d)]
+    fn hashname_tail(#[case] name: &[u8], #[case] expected: XfsDahash) {
+        assert_eq!(hashname(OsStr::from_bytes(name)), expected);
+    }
+}
@@ -35,6 +35,7 @@ pub const XFS_DQUOT_MAGIC: u16 = 0x4451; // Quota Inodes
 pub const XFS_SYMLINK_MAGIC: u32 = 0x58534c4d; // Symbolic Links
 pub const XFS_ABTB_CRC_MAGIC: u32 = 0x41423342; // Free Space by Block B+tree
 pub const XFS_ABTC_CRC_MAGIC: u32 = 0x41423343; // Free Space by Size B+tree
+pub const XFS_IBT_MAGIC: u32 = 0x49414254; // Inode B+tree, V4
 pub const XFS_IBT_CRC_MAGIC: u32 = 0x49414233; // Inode B+tree
 pub const XFS_FIBT_CRC_MAGIC: u32 = 0x46494233; // Free Inode B+tree
 pub const XFS_BMAP_MAGIC: u32 = 0x424d4150; // B+Tree Extent List, V5
@@ -50,6 +51,7 @@ pub const XFS_DIR2_LEAF1_MAGIC: u16 = 0xd2f1; // Leaf Directory
 pub const XFS_DIR3_LEAF1_MAGIC: u16 = 0x3df1; // Leaf Directory, V5
 pub const XFS_DIR2_LEAFN_MAGIC: u16 = 0xd2ff; // Node Directory
 pub const XFS_DIR3_LEAFN_MAGIC: u16 = 0x3dff; // Node Directory, V5
+pub const XFS_DIR2_FREE_MAGIC: u32 = 0x58443246; // Node Directory Free Space
 pub const XFS_DIR3_FREE_MAGIC: u32 = 0x58444633; // Node Directory Free Space
 pub const XFS_ATTR_LEAF_MAGIC: u16 = 0xfbee; // Leaf Attribute
 pub const XFS_ATTR3_LEAF_MAGIC: u16 = 0x3bee; // Leaf Attribute, V5
@@ -64,6 +66,7 @@ pub type XfsOff = i64; // file offset
 pub type XfsDaddr = i64; // disk address (sectors)
 pub type XfsAgnumber = u32; // AG number
 pub type XfsAgblock = u32; // AG relative block number
+pub type XfsAgino = u32; // AG relative inode number
 pub type XfsExtlen = u32; // extent length in blocks
 pub type XfsExtnum = i32; // number of extends in a data fork
 pub type XfsAextnum = i16; // number of extents in an attribute fork
@@ -75,3 +78,11 @@ pub type XfsRtblock = u64; // extent number in the real-time sub-volume
 pub type XfsFileoff = u64; // block offset into a file
 pub type XfsFilblks = u64; // block count for a file
 pub type XfsFsize = i64; // byte size of a file
+
+pub const NULLAGBLOCK: XfsAgblock = u32::MAX; // no such AG block
+pub const NULLAGINO: XfsAgino = u32::MAX; // end of an AG's unlinked inode list
+pub const NULLFSINO: XfsIno = u64::MAX; // no such inode
+pub const XFS_INODES_PER_CHUNK: u64 = 64; // inodes are allocated in chunks of this many
+pub const XFS_MAXINUMBER: XfsIno = (1 << 56) - 1; // largest possible inode number
+pub const BBSHIFT: u8 = 9; // log2 of the basic block size, the unit of XfsDaddr and st_blocks
+pub const XFS_SYMLINK_MAXLEN: usize = 1024; // longest symbolic link target
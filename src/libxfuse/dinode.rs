@@ -48,13 +48,54 @@ use super::{
     dir3_block::Dir2Block,
     dir3_lf::Dir2Lf,
     dir3_sf::Dir2Sf,
-    file::File,
+    error::XfsError,
+    file::{ExtentInfo, File},
     file_btree::FileBtree,
     file_extent_list::FileExtentList,
+    patch::InodePatches,
     sb::Sb,
-    symlink_extent::SymlinkExtents,
+    symlink_extent::{self, SymlinkExtents},
 };
 
+/// Decode an inode fork's extent list of `nextents` records.  They must all fit within the fork's
+/// `fork_size` bytes.
+fn decode_bmx<D, N>(
+    decoder: &mut D,
+    nextents: N,
+    fork_size: usize,
+) -> Result<Vec<BmbtRec>, XfsError>
+where
+    D: Decoder,
+    N: TryInto<usize> + Copy + std::fmt::Display,
+{
+    let n = nextents
+        .try_into()
+        .map_err(|_| XfsError::Corrupt(format!("Invalid extent count {}", nextents)))?;
+    if n.checked_mul(BmbtRec::SIZE)
+        .is_none_or(|size| size > fork_size)
+    {
+        return Err(XfsError::Corrupt(format!(
+            "{} extents don't fit in a {}-byte inode fork",
+            n, fork_size
+        )));
+    }
+    (0..n)
+        .map(|_| BmbtRec::decode(decoder).map_err(XfsError::from))
+        .collect()
+}
+
+/// The length of a symbolic link's target, which must be nonempty and no longer than
+/// `XFS_SYMLINK_MAXLEN`.
+fn symlink_len(di_size: i64) -> Result<usize, XfsError> {
+    match usize::try_from(di_size) {
+        Ok(len @ 1..=XFS_SYMLINK_MAXLEN) => Ok(len),
+        _ => Err(XfsError::Corrupt(format!(
+            "Symbolic link has invalid size {}",
+            di_size
+        ))),
+    }
+}
+
 #[derive(Debug)]
 pub enum DiU {
     Blk,
@@ -62,6 +103,10 @@ pub enum DiU {
     Bmx(Vec<BmbtRec>),
     Chr,
     Dir2Sf(Dir2Sf),
+    /// A directory whose data fork has a format that directories can't use.  It's reported as
+    /// corruption when the directory is read, rather than when the inode is loaded, so the
+    /// directory can still be stat'ed.
+    DirBadFormat(XfsDinodeFmt),
     Fifo,
     Socket,
     Symlink(Vec<u8>),
@@ -83,6 +128,12 @@ pub struct Dinode {
     directory:   Option<Directory>,
     /// Cache of this inode's attribute object, if any
     attributes:  Option<Attributes>,
+    /// Cache of this inode's data, if its data fork is a B+tree, so the tree's blocks needn't be
+    /// read again for every request
+    btree_file:  Option<FileBtree>,
+    /// Cache of this inode's extents on the realtime device, if any, so a B+tree data fork needn't
+    /// be read again for every request
+    rt_file:     Option<FileExtentList>,
 }
 
 impl Dinode {
@@ -90,47 +141,64 @@ impl Dinode {
         buf_reader: &mut R,
         superblock: &Sb,
         inode_number: XfsIno,
-    ) -> Dinode {
+        patches: Option<&InodePatches>,
+    ) -> Result<Dinode, XfsError> {
         let ag_no: u64 = inode_number >> (superblock.sb_agblklog + superblock.sb_inopblog);
         if ag_no >= superblock.sb_agcount.into() {
-            panic!("Wrong AG number!");
+            return Err(XfsError::Corrupt(format!(
+                "Inode {} lies beyond the last AG",
+                inode_number
+            )));
         }
 
         let ag_blk: u64 =
             (inode_number >> superblock.sb_inopblog) & ((1 << superblock.sb_agblklog) - 1);
+        // Inode numbers encode their block directly, so locating an inode needs no knowledge of
+        // the chunk alignment.  But the alignment does tell us where chunks can't be.
+        if ag_blk < superblock.first_inode_agbno() {
+            return Err(XfsError::Corrupt(format!(
+                "Inode {} lies within the AG headers",
+                inode_number
+            )));
+        }
         let blk_ino = inode_number & ((1 << superblock.sb_inopblog) - 1);
 
         let off: u64 = ((ag_no * u64::from(superblock.sb_agblocks)) << superblock.sb_blocklog)
             + (ag_blk << superblock.sb_blocklog)
             + (blk_ino << superblock.sb_inodelog);
 
-        buf_reader.seek(SeekFrom::Start(off)).unwrap();
+        buf_reader.seek(SeekFrom::Start(off))?;
         let mut raw = vec![0u8; superblock.inode_size()];
-        buf_reader.read_exact(&mut raw).unwrap();
+        buf_reader.read_exact(&mut raw)?;
         let config = bincode::config::standard()
             .with_big_endian()
             .with_fixed_int_encoding();
-        let reader = bincode::de::read::SliceReader::new(&raw[..]);
-        let mut decoder = bincode::de::DecoderImpl::new(reader, config, ());
+        // The core's size depends on di_version: the v3 fields are only decoded for v3 inodes.
+        let (mut di_core, core_size): (DinodeCore, usize) =
+            bincode::decode_from_slice(&raw[..], config)?;
+        debug_assert_eq!(core_size, di_core.literal_area_offset());
 
-        let di_core = DinodeCore::decode(&mut decoder).unwrap();
+        // The data fork occupies the literal area up to the attribute fork, if any.  Decode it
+        // from a slice that ends there, so it can never run into the attribute fork.
+        let attr_fork_ofs = di_core.attr_fork_offset(raw.len())?;
+        let dfork_size = attr_fork_ofs - di_core.literal_area_offset();
+        let reader =
+            bincode::de::read::SliceReader::new(&raw[di_core.literal_area_offset()..attr_fork_ofs]);
+        let mut decoder = bincode::de::DecoderImpl::new(reader, config, ());
 
         let di_u: Option<DiU>;
         match (di_core.di_mode as mode_t) & S_IFMT {
             S_IFREG => match di_core.di_format {
                 XfsDinodeFmt::Extents => {
-                    let mut bmx = Vec::<BmbtRec>::new();
-                    for _i in 0..di_core.di_nextents {
-                        bmx.push(BmbtRec::decode(&mut decoder).unwrap())
-                    }
+                    let bmx = decode_bmx(&mut decoder, di_core.di_nextents, dfork_size)?;
                     di_u = Some(DiU::Bmx(bmx));
                 }
                 XfsDinodeFmt::Btree => {
-                    let bmbt = BmdrBlock::decode(&mut decoder).unwrap();
+                    let bmbt = BmdrBlock::decode(&mut decoder)?;
 
                     let mut keys = Vec::<BmbtKey>::new();
                     for _i in 0..bmbt.bb_numrecs {
-                        keys.push(BmbtKey::decode(&mut decoder).unwrap())
+                        keys.push(BmbtKey::decode(&mut decoder)?)
                     }
 
                     let gap = di_core.dfork_btree_ptr_gap(superblock.inode_size(), bmbt.bb_numrecs);
@@ -138,35 +206,35 @@ impl Dinode {
 
                     let mut pointers = Vec::<XfsBmbtPtr>::new();
                     for _i in 0..bmbt.bb_numrecs {
-                        let pointer = u64::decode(&mut decoder).unwrap();
+                        let pointer = u64::decode(&mut decoder)?;
                         pointers.push(pointer)
                     }
 
                     di_u = Some(DiU::Bmbt((bmbt, keys, pointers)));
                 }
-                _ => {
-                    panic!("Directory format not yet supported.");
+                fmt => {
+                    return Err(XfsError::Corrupt(format!(
+                        "Regular file {} has data fork format {:?}",
+                        inode_number, fmt
+                    )));
                 }
             },
             S_IFDIR => match di_core.di_format {
                 XfsDinodeFmt::Local => {
-                    let mut dir_sf = Dir2Sf::decode(&mut decoder).unwrap();
+                    let mut dir_sf = Dir2Sf::decode(&mut decoder)?;
                     dir_sf.set_ino(inode_number);
                     di_u = Some(DiU::Dir2Sf(dir_sf));
                 }
                 XfsDinodeFmt::Extents => {
-                    let mut bmx = Vec::<BmbtRec>::new();
-                    for _i in 0..di_core.di_nextents {
-                        bmx.push(BmbtRec::decode(&mut decoder).unwrap())
-                    }
+                    let bmx = decode_bmx(&mut decoder, di_core.di_nextents, dfork_size)?;
                     di_u = Some(DiU::Bmx(bmx));
                 }
                 XfsDinodeFmt::Btree => {
-                    let bmbt = BmdrBlock::decode(&mut decoder).unwrap();
+                    let bmbt = BmdrBlock::decode(&mut decoder)?;
 
                     let mut keys = Vec::<BmbtKey>::new();
                     for _i in 0..bmbt.bb_numrecs {
-                        keys.push(BmbtKey::decode(&mut decoder).unwrap());
+                        keys.push(BmbtKey::decode(&mut decoder)?);
                     }
 
                     let gap = di_core.dfork_btree_ptr_gap(superblock.inode_size(), bmbt.bb_numrecs);
@@ -174,43 +242,45 @@ impl Dinode {
 
                     let mut pointers = Vec::<XfsBmbtPtr>::new();
                     for _i in 0..bmbt.bb_numrecs {
-                        let pointer = u64::decode(&mut decoder).unwrap();
+                        let pointer = u64::decode(&mut decoder)?;
                         pointers.push(pointer)
                     }
 
                     di_u = Some(DiU::Bmbt((bmbt, keys, pointers)));
                 }
-                _ => {
-                    panic!("Directory format not yet supported.");
-                }
+                fmt => di_u = Some(DiU::DirBadFormat(fmt)),
             },
             S_IFLNK => match di_core.di_format {
                 XfsDinodeFmt::Local => {
-                    let mut data = vec![0u8; di_core.di_size as usize];
-                    decoder.reader().read(&mut data[..]).unwrap();
+                    let mut data = vec![0u8; symlink_len(di_core.di_size)?];
+                    decoder.reader().read(&mut data[..])?;
                     di_u = Some(DiU::Symlink(data))
                 }
                 XfsDinodeFmt::Extents => {
-                    let mut bmx = Vec::<BmbtRec>::new();
-                    for _i in 0..di_core.di_nextents {
-                        bmx.push(BmbtRec::decode(&mut decoder).unwrap());
-                    }
+                    let bmx = decode_bmx(&mut decoder, di_core.di_nextents, dfork_size)?;
                     di_u = Some(DiU::Bmx(bmx));
                 }
-                _ => {
-                    panic!("Unexpected format for symlink");
+                fmt => {
+                    return Err(XfsError::Corrupt(format!(
+                        "Symbolic link {} has data fork format {:?}",
+                        inode_number, fmt
+                    )));
                 }
             },
             S_IFBLK => di_u = Some(DiU::Blk),
             S_IFCHR => di_u = Some(DiU::Chr),
             S_IFIFO => di_u = Some(DiU::Fifo),
             S_IFSOCK => di_u = Some(DiU::Socket),
-            x => panic!("Inode type ({:#o}) not yet supported.", x),
+            x => {
+                return Err(XfsError::Corrupt(format!(
+                    "Inode {} has unknown type {:#o}",
+                    inode_number, x
+                )))
+            }
         }
 
         let di_a: Option<DiA>;
         if di_core.di_forkoff != 0 {
-            let attr_fork_ofs = di_core.literal_area_offset() + di_core.di_forkoff as usize * 8;
             let config = bincode::config::standard()
                 .with_big_endian()
                 .with_fixed_int_encoding();
@@ -219,115 +289,215 @@ impl Dinode {
 
             match di_core.di_aformat {
                 XfsDinodeFmt::Local => {
-                    let attr_shortform = AttrShortform::decode(&mut decoder).unwrap();
+                    let attr_shortform = AttrShortform::decode(&mut decoder)?;
                     di_a = Some(DiA::Attrsf(attr_shortform));
                 }
                 XfsDinodeFmt::Extents => {
-                    let mut bmx = Vec::<BmbtRec>::new();
-                    for _i in 0..di_core.di_anextents {
-                        bmx.push(BmbtRec::decode(&mut decoder).unwrap());
-                    }
+                    let afork_size = raw.len() - attr_fork_ofs;
+                    let bmx = decode_bmx(&mut decoder, di_core.di_anextents, afork_size)?;
                     di_a = Some(DiA::Abmx(bmx));
                 }
                 XfsDinodeFmt::Btree => {
-                    let bmbt = BmdrBlock::decode(&mut decoder).unwrap();
+                    let bmbt = BmdrBlock::decode(&mut decoder)?;
 
                     let mut keys = Vec::<BmbtKey>::new();
                     for _i in 0..bmbt.bb_numrecs {
-                        keys.push(BmbtKey::decode(&mut decoder).unwrap());
+                        keys.push(BmbtKey::decode(&mut decoder)?);
                     }
 
                     let gap = di_core.afork_btree_ptr_gap(superblock.inode_size(), bmbt.bb_numrecs);
                     decoder.reader().consume(gap as usize);
                     let mut pointers = Vec::<XfsBmbtPtr>::new();
                     for _i in 0..bmbt.bb_numrecs {
-                        pointers.push(XfsBmbtPtr::decode(&mut decoder).unwrap());
+                        pointers.push(XfsBmbtPtr::decode(&mut decoder)?);
                     }
 
                     di_a = Some(DiA::Abmbt((bmbt, keys, pointers)));
                 }
-                _ => {
-                    panic!("Attributes format not yet supported.");
+                fmt => {
+                    return Err(XfsError::Corrupt(format!(
+                        "Inode {} has attribute fork format {:?}",
+                        inode_number, fmt
+                    )));
                 }
             }
         } else {
             di_a = None;
         }
 
-        Dinode {
+        // Patch only after decoding the forks, so that an overridden mode or size can't change
+        // how they're decoded.
+        if let Some(patches) = patches {
+            patches.apply(inode_number, &mut di_core);
+        }
+
+        Ok(Dinode {
             di_core,
             di_u: di_u.unwrap(),
             di_a,
             directory: None,
             attributes: None,
-        }
+            btree_file: None,
+            rt_file: None,
+        })
     }
 
     pub fn get_dir<R: bincode::de::read::Reader + BufRead + Seek>(
         &mut self,
         buf_reader: &mut R,
         sb: &Sb,
-    ) -> &Directory {
+    ) -> Result<&Directory, XfsError> {
         if self.directory.is_none() {
             let directory = match &self.di_u {
                 DiU::Dir2Sf(dir) => Directory::Sf(dir.clone()),
                 DiU::Bmx(bmbtv) => {
+                    // Extents are sorted, so if the first isn't a data block, none are.  That
+                    // leaves nothing to hold even the "." and ".." entries.
+                    let leaf_offset = u64::from(sb.get_dir3_leaf_offset());
+                    if bmbtv
+                        .first()
+                        .is_none_or(|rec| rec.br_startoff >= leaf_offset)
+                    {
+                        return Err(XfsError::Corrupt(
+                            "directory has no data blocks".to_string(),
+                        ));
+                    }
                     if bmbtv.len() == 1 {
                         Directory::Block(Dir2Block::new(
                             buf_reader.by_ref(),
                             sb,
                             bmbtv[0].br_startblock,
+                            self.di_core.di_ino,
                         ))
                     } else {
-                        let bmx = Bmx::new(bmbtv);
-                        Directory::Lf(Dir2Lf::from_bmx(bmx))
+                        let mut dir = Dir2Lf::from_bmx(Bmx::new(bmbtv));
+                        dir.set_ino(self.di_core.di_ino);
+                        Directory::Lf(dir)
                     }
                 }
-                DiU::Bmbt((bmbt, keys, pointers)) => Directory::Lf(Dir2Lf::from_btree(
-                    bmbt.clone(),
-                    keys.clone(),
-                    pointers.clone(),
-                )),
-                _ => {
-                    panic!("Unsupported dir format!");
+                DiU::Bmbt((bmbt, keys, pointers)) => {
+                    let mut dir = Dir2Lf::from_btree(bmbt.clone(), keys.clone(), pointers.clone());
+                    dir.set_ino(self.di_core.di_ino);
+                    Directory::Lf(dir)
                 }
+                DiU::DirBadFormat(fmt) => {
+                    return Err(XfsError::Corrupt(format!(
+                        "directory has invalid data fork format {:?}",
+                        fmt
+                    )))
+                }
+                _ => return Err(XfsError::NotDir),
             };
             self.directory = Some(directory);
         }
-        self.directory.as_ref().unwrap()
+        Ok(self.directory.as_ref().unwrap())
     }
 
+    /// Return this inode's directory object, if [`Dinode::get_dir`] has already loaded it.
+    pub fn cached_dir(&self) -> Option<&Directory> {
+        self.directory.as_ref()
+    }
+
+    /// Get this inode's data.  FIFOs, sockets, and device nodes have none; the kernel should
+    /// handle their I/O itself, so fail with ENXIO if it ever asks us.
     pub fn get_file<R: bincode::de::read::Reader + BufRead + Seek>(
-        &self,
+        &mut self,
         _buf_reader: &mut R,
-    ) -> Box<dyn File<R>> {
+    ) -> Result<Box<dyn File<R> + '_>, XfsError> {
         match &self.di_u {
-            DiU::Bmx(bmx) => Box::new(FileExtentList {
-                bmx:  Bmx::new(bmx),
-                size: self.di_core.di_size,
-            }),
-            DiU::Bmbt((bmdr, keys, pointers)) => Box::new(FileBtree {
-                btree: BtreeRoot::new(bmdr.clone(), keys.clone(), pointers.clone()),
-                size:  self.di_core.di_size,
-            }),
-            _ => {
-                panic!("Unsupported file format!");
+            DiU::Bmx(bmx) => Ok(Box::new(FileExtentList {
+                bmx:      Bmx::new(bmx),
+                size:     self.di_core.di_size,
+                realtime: false,
+            })),
+            DiU::Bmbt((bmdr, keys, pointers)) => {
+                let file = self.btree_file.get_or_insert_with(|| {
+                    let btree = BtreeRoot::new(bmdr.clone(), keys.clone(), pointers.clone());
+                    FileBtree::new(btree, self.di_core.di_size)
+                });
+                Ok(Box::new(&*file))
+            }
+            DiU::Blk | DiU::Chr | DiU::Fifo | DiU::Socket => Err(XfsError::Special),
+            DiU::Dir2Sf(_) | DiU::DirBadFormat(_) | DiU::Symlink(_) => {
+                Err(XfsError::Corrupt(format!(
+                    "Data fork of mode {:#o} can't be read as a file",
+                    self.di_core.di_mode
+                )))
             }
         }
     }
 
-    pub fn get_link_data<R>(&self, buf_reader: &mut R, superblock: &Sb) -> CString
+    /// Return every record of this inode's data fork, including unwritten extents.
+    fn data_records<R>(&self, buf_reader: &mut R) -> Result<Vec<BmbtRec>, XfsError>
     where
         R: BufRead + Reader + Seek,
     {
-        match &self.di_u {
-            DiU::Symlink(data) => CString::new(data.clone()).unwrap(),
-            DiU::Bmx(bmbtv) => {
-                SymlinkExtents::get_target(buf_reader.by_ref(), &Bmx::new(bmbtv), superblock)
+        Ok(match &self.di_u {
+            DiU::Bmx(bmx) => bmx.clone(),
+            DiU::Bmbt((bmdr, keys, pointers)) => {
+                BtreeRoot::new(bmdr.clone(), keys.clone(), pointers.clone()).records(buf_reader)?
             }
-            _ => {
-                panic!("Unsupported link format!");
+            _ => Vec::new(),
+        })
+    }
+
+    /// Like [`Dinode::get_file`], but for a file whose data is on the realtime device.  The data
+    /// fork's btree, if any, is on the data device, so it is read in full the first time.  The
+    /// returned file must then be read from the realtime device.
+    pub fn get_rt_file<R>(
+        &mut self,
+        buf_reader: &mut R,
+        sb: &Sb,
+    ) -> Result<&FileExtentList, XfsError>
+    where
+        R: BufRead + Reader + Seek,
+    {
+        if self.rt_file.is_none() {
+            let recs = self.data_records(buf_reader)?;
+            if let Some(rec) = recs
+                .iter()
+                .find(|rec| rec.br_startblock + rec.br_blockcount > sb.sb_rblocks)
+            {
+                return Err(XfsError::Corrupt(format!(
+                    "Realtime extent {:?} is beyond the end of the realtime device",
+                    rec
+                )));
             }
+            self.rt_file = Some(FileExtentList {
+                bmx:      Bmx::new(&recs),
+                size:     self.di_core.di_size,
+                realtime: true,
+            });
+        }
+        Ok(self.rt_file.as_ref().unwrap())
+    }
+
+    /// Return the complete extent map of this inode's data fork, including unwritten extents.
+    /// Inodes whose data is stored within the inode itself have no extents.
+    pub fn file_extents<R>(&self, buf_reader: &mut R, sb: &Sb) -> Result<Vec<ExtentInfo>, XfsError>
+    where
+        R: BufRead + Reader + Seek,
+    {
+        let recs = self.data_records(buf_reader)?;
+        Ok(recs
+            .iter()
+            .map(|rec| ExtentInfo::from_rec(rec, sb))
+            .collect())
+    }
+
+    pub fn get_link_data<R>(&self, buf_reader: &mut R, superblock: &Sb) -> Result<CString, XfsError>
+    where
+        R: BufRead + Reader + Seek,
+    {
+        match &self.di_u {
+            DiU::Symlink(data) => Ok(symlink_extent::to_cstring(data.clone())),
+            DiU::Bmx(bmbtv) => SymlinkExtents::get_target(
+                buf_reader.by_ref(),
+                &Bmx::new(bmbtv),
+                superblock,
+                symlink_len(self.di_core.di_size)?,
+            ),
+            _ => Err(XfsError::Corrupt("unsupported symlink format".into())),
         }
     }
 
@@ -364,3 +534,829 @@ impl Dinode {
         &mut self.attributes
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        ffi::OsStr,
+        io,
+        os::unix::{ffi::OsStrExt, fs::FileExt},
+        sync::{Arc, Mutex},
+    };
+
+    use fuser::FileType;
+    use rstest::rstest;
+    use tempfile::NamedTempFile;
+
+    use super::*;
+    use crate::libxfuse::{
+        attr::{tests::Entry, Attr},
+        attr_leaf::tests::mkleaf as mkattrleaf,
+        block_reader::BlockReader,
+        da_btree::hashname,
+        dir3::{Dir3, Dir3BlkHdr, Dir3DataHdr, Directory, XFS_DIR3_FT_REG_FILE},
+        dir3_lf::tests::{mkdata, mkleafdir},
+        sb::tests::{mksb, superblock},
+        test_support::{self, bmbt_rec_raw, mkbmbt_leaf},
+    };
+
+    /// Inode number used by these tests.  It's the first inode in AG 0's block 16.
+    const INO: XfsIno = 128;
+
+    /// Write a V3 inode to a new image, with the given mode and format, and the given data and
+    /// attribute fork contents.  The attribute fork, if any, is in shortform and starts at
+    /// `forkoff`.  An extents-format data fork holds as many extents as fit in `dfork`.
+    fn mkinode(
+        mode: u16,
+        format: XfsDinodeFmt,
+        size: usize,
+        dfork: &[u8],
+        forkoff: u8,
+        afork: &[u8],
+    ) -> (NamedTempFile, Dinode) {
+        let (img, dinode) = try_mkinode(mode, format, size, dfork, forkoff, afork);
+        (img, dinode.unwrap())
+    }
+
+    /// Like [`mkinode`], but return the result of decoding the inode, even if it fails
+    fn try_mkinode(
+        mode: u16,
+        format: XfsDinodeFmt,
+        size: usize,
+        dfork: &[u8],
+        forkoff: u8,
+        afork: &[u8],
+    ) -> (NamedTempFile, Result<Dinode, XfsError>) {
+        let extents = matches!(format, XfsDinodeFmt::Extents);
+        let mut raw = vec![0u8; superblock().inode_size()];
+        raw[0..2].copy_from_slice(&XFS_DINODE_MAGIC.to_be_bytes());
+        raw[2..4].copy_from_slice(&mode.to_be_bytes());
+        raw[4] = 3;
+        raw[5] = format as u8;
+        raw[56..64].copy_from_slice(&(size as u64).to_be_bytes());
+        if extents {
+            let nextents = (dfork.len() / BmbtRec::SIZE) as u32;
+            raw[76..80].copy_from_slice(&nextents.to_be_bytes());
+        }
+        raw[82] = forkoff;
+        raw[83] = XfsDinodeFmt::Local as u8;
+        raw[152..160].copy_from_slice(&INO.to_be_bytes());
+        raw[176..176 + dfork.len()].copy_from_slice(dfork);
+        if forkoff > 0 && !afork.is_empty() {
+            let ofs = 176 + usize::from(forkoff) * 8;
+            raw[ofs..ofs + afork.len()].copy_from_slice(afork);
+        }
+        try_mkimg(&raw)
+    }
+
+    /// Write a raw inode to a new image, as inode [`INO`], and decode it
+    fn mkimg(raw: &[u8]) -> (NamedTempFile, Dinode) {
+        let (img, dinode) = try_mkimg(raw);
+        (img, dinode.unwrap())
+    }
+
+    /// Like [`mkimg`], but return the result of decoding the inode, even if it fails
+    fn try_mkimg(raw: &[u8]) -> (NamedTempFile, Result<Dinode, XfsError>) {
+        let sb = superblock();
+        let img = NamedTempFile::new().unwrap();
+        let offset = sb.fsb_to_offset(INO >> sb.sb_inopblog);
+        img.as_file()
+            .set_len(offset + u64::from(sb.sb_blocksize))
+            .unwrap();
+        img.as_file().write_all_at(raw, offset).unwrap();
+        let mut reader = BlockReader::open(img.path()).unwrap();
+        reader.set_bufsize(sb.inode_size());
+        let dinode = Dinode::from(&mut reader, sb, INO, None);
+        (img, dinode)
+    }
+
+    /// A shortform attribute fork holding a single user attribute
+    fn mkattrsf() -> Vec<u8> {
+        vec![0, 8, 1, 0, 1, 1, 0, b'a', b'b']
+    }
+
+    /// On a 512-byte inode, a shortform directory may use all of the literal area up to the
+    /// attribute fork, well beyond what a 256-byte inode could hold.
+    #[rstest]
+    #[case::no_afork(0)]
+    #[case::small_afork(40)]
+    fn big_shortform_dir(#[case] forkoff: u8) {
+        let names = (0..11)
+            .map(|i| format!("entry_number_{:07}", i))
+            .collect::<Vec<_>>();
+        let mut dfork = vec![names.len() as u8, 0];
+        dfork.extend_from_slice(&(INO as u32).to_be_bytes());
+        for (i, name) in names.iter().enumerate() {
+            dfork.push(name.len() as u8);
+            dfork.extend_from_slice(&(0x60 + 0x20 * i as u16).to_be_bytes());
+            dfork.extend_from_slice(name.as_bytes());
+            dfork.push(XFS_DIR3_FT_REG_FILE);
+            dfork.extend_from_slice(&(200 + i as u32).to_be_bytes());
+        }
+        assert!(dfork.len() > 256 - 176);
+        assert!(dfork.len() <= usize::from(forkoff) * 8 || forkoff == 0);
+
+        let sb = superblock();
+        let (img, mut dinode) = mkinode(
+            0o40755,
+            XfsDinodeFmt::Local,
+            dfork.len(),
+            &dfork,
+            forkoff,
+            &mkattrsf(),
+        );
+        assert_eq!(dinode.di_a.is_some(), forkoff > 0);
+        let mut reader = BlockReader::open(img.path()).unwrap();
+        let dir = dinode.get_dir(&mut reader, sb).unwrap();
+        let last = names.last().unwrap();
+        assert_eq!(dir.lookup(&mut reader, sb, OsStr::new(last)), Ok(210));
+        let mut offset = 0;
+        let mut found = Vec::new();
+        while let Ok((_, next, _, name)) = dir.next(&mut reader, sb, offset) {
+            found.push(name.into_string().unwrap());
+            offset = next;
+        }
+        assert_eq!(found[2..], names[..]);
+    }
+
+    /// A directory whose data fork format makes no sense for a directory can still be loaded and
+    /// stat'ed, but reading it reports corruption instead of panicking.
+    #[rstest]
+    #[case::dev(XfsDinodeFmt::Dev)]
+    #[case::uuid(XfsDinodeFmt::Uuid)]
+    #[case::rmap(XfsDinodeFmt::Rmap)]
+    fn dir_bad_format(#[case] format: XfsDinodeFmt) {
+        let sb = superblock();
+        let (img, mut dinode) = mkinode(0o40755, format, 0, &[], 0, &[]);
+        assert_eq!(
+            dinode.di_core.stat(INO, superblock()).unwrap().kind,
+            FileType::Directory
+        );
+        let mut reader = BlockReader::open(img.path()).unwrap();
+        assert!(matches!(
+            dinode.get_dir(&mut reader, sb),
+            Err(XfsError::Corrupt(_))
+        ));
+    }
+
+    /// A local symlink may also fill the literal area up to the attribute fork
+    #[test]
+    fn big_local_symlink() {
+        let target = vec![b'x'; 300];
+        let (img, dinode) = mkinode(
+            0o120777,
+            XfsDinodeFmt::Local,
+            target.len(),
+            &target,
+            38,
+            &mkattrsf(),
+        );
+        let mut reader = BlockReader::open(img.path()).unwrap();
+        let link = dinode.get_link_data(&mut reader, superblock()).unwrap();
+        assert_eq!(link.as_bytes(), &target[..]);
+    }
+
+    /// A version 2 inode's core is shorter, so its data fork starts earlier
+    #[test]
+    fn v2_local_symlink() {
+        let target = b"/some/where";
+        let mut raw = vec![0u8; superblock().inode_size()];
+        raw[0..2].copy_from_slice(&XFS_DINODE_MAGIC.to_be_bytes());
+        raw[2..4].copy_from_slice(&0o120777u16.to_be_bytes());
+        raw[4] = 2;
+        raw[5] = XfsDinodeFmt::Local as u8;
+        raw[56..64].copy_from_slice(&(target.len() as u64).to_be_bytes());
+        raw[83] = XfsDinodeFmt::Extents as u8;
+        let ofs = DinodeCore::V2_SIZE;
+        raw[ofs..ofs + target.len()].copy_from_slice(target);
+        // Garbage where a v3 core would continue, to catch reading the fork from there
+        raw[ofs + target.len()..DinodeCore::V3_SIZE + target.len()].fill(b'!');
+
+        let (img, dinode) = mkimg(&raw);
+        assert_eq!(dinode.di_core.di_version, 2);
+        let mut reader = BlockReader::open(img.path()).unwrap();
+        let link = dinode.get_link_data(&mut reader, superblock()).unwrap();
+        assert_eq!(link.as_bytes(), target);
+    }
+
+    /// Patches are applied after the forks are decoded, so they change what's reported about the
+    /// inode but not how its on-disk fork is read.
+    #[test]
+    fn patch_after_decode() {
+        let target = b"/some/where";
+        let (img, _) = mkinode(0o120777, XfsDinodeFmt::Local, target.len(), target, 0, &[]);
+        let patches: InodePatches = format!("{} di_size 1000000000\n{} di_mode 16877", INO, INO)
+            .parse()
+            .unwrap();
+        let sb = superblock();
+        let mut reader = BlockReader::open(img.path()).unwrap();
+        reader.set_bufsize(sb.inode_size());
+        let dinode = Dinode::from(&mut reader, sb, INO, Some(&patches)).unwrap();
+        assert_eq!(dinode.di_core.di_size, 1000000000);
+        assert_eq!(dinode.di_core.di_mode, 0o40755);
+        assert!(matches!(&dinode.di_u, DiU::Symlink(data) if data == target));
+    }
+
+    /// A local symlink longer than its fork would run into the attribute fork
+    #[test]
+    fn local_symlink_overlaps_afork() {
+        let (_img, r) = try_mkinode(
+            0o120777,
+            XfsDinodeFmt::Local,
+            300,
+            &[b'x'; 300],
+            30,
+            &mkattrsf(),
+        );
+        let e = r.unwrap_err();
+        assert!(
+            matches!(&e, XfsError::Corrupt(s) if s.contains("UnexpectedEnd")),
+            "{:?}",
+            e
+        );
+    }
+
+    /// A local symlink whose size is empty, negative, or too long is corrupt
+    #[rstest]
+    #[case::empty(0, "0")]
+    #[case::negative(usize::MAX, "-1")]
+    #[case::too_long(1025, "1025")]
+    fn local_symlink_bad_size(#[case] size: usize, #[case] expected: &str) {
+        let (_img, r) = try_mkinode(0o120777, XfsDinodeFmt::Local, size, b"x", 0, &[]);
+        assert_eq!(
+            r.unwrap_err(),
+            XfsError::Corrupt(format!("Symbolic link has invalid size {}", expected))
+        );
+    }
+
+    /// Likewise for a remote symlink, but that's only detected when its target is read
+    #[rstest]
+    #[case::empty(0, "0")]
+    #[case::negative(usize::MAX, "-1")]
+    #[case::too_long(4096, "4096")]
+    fn remote_symlink_bad_size(#[case] size: usize, #[case] expected: &str) {
+        let (img, dinode) = mkinode(0o120777, XfsDinodeFmt::Extents, size, &mkextents(1), 0, &[]);
+        let mut reader = BlockReader::open(img.path()).unwrap();
+        assert_eq!(
+            dinode.get_link_data(&mut reader, superblock()).unwrap_err(),
+            XfsError::Corrupt(format!("Symbolic link has invalid size {}", expected))
+        );
+    }
+
+    /// A local symlink's target may not contain a NUL byte.  If it does, it's truncated there.
+    #[test]
+    fn local_symlink_nul() {
+        let target = b"/some\0where";
+        let (img, dinode) = mkinode(0o120777, XfsDinodeFmt::Local, target.len(), target, 0, &[]);
+        let mut reader = BlockReader::open(img.path()).unwrap();
+        let link = dinode.get_link_data(&mut reader, superblock()).unwrap();
+        assert_eq!(link.as_bytes(), b"/some");
+    }
+
+    /// An extent list of `n` contiguous one-block extents
+    fn mkextents(n: u64) -> Vec<u8> {
+        bmbt_rec_raw(&(0..n).map(|i| (i, 1000 + i, 1)).collect::<Vec<_>>())
+    }
+
+    /// A data fork extent list that exactly fills the space before the attribute fork
+    #[test]
+    fn extents_fill_dfork() {
+        let (_img, dinode) = mkinode(
+            0o100644,
+            XfsDinodeFmt::Extents,
+            18 * 4096,
+            &mkextents(18),
+            36,
+            &mkattrsf(),
+        );
+        let DiU::Bmx(bmx) = &dinode.di_u else {
+            panic!("Wrong data fork format {:?}", dinode.di_u);
+        };
+        assert_eq!(bmx.len(), 18);
+        assert_eq!(bmx[17].br_startoff, 17);
+        assert_eq!(bmx[17].br_startblock, 1017);
+        assert_eq!(bmx[17].br_blockcount, 1);
+        let Some(DiA::Attrsf(attrs)) = &dinode.di_a else {
+            panic!("Wrong attribute fork format {:?}", dinode.di_a);
+        };
+        assert_eq!(attrs.list.len(), 1);
+        assert_eq!(attrs.list[0].nameval, b"ab");
+    }
+
+    /// More extents than fit before the attribute fork.  The excess would be read from the
+    /// attribute fork.
+    #[test]
+    fn extents_overlap_afork() {
+        let (_img, r) = try_mkinode(
+            0o100644,
+            XfsDinodeFmt::Extents,
+            20 * 4096,
+            &mkextents(20),
+            36,
+            &mkattrsf(),
+        );
+        assert_eq!(
+            r.unwrap_err(),
+            XfsError::Corrupt("20 extents don't fit in a 288-byte inode fork".into())
+        );
+    }
+
+    /// An attribute fork offset past the end of the inode
+    #[test]
+    fn forkoff_beyond_inode() {
+        let (_img, r) = try_mkinode(0o100644, XfsDinodeFmt::Extents, 0, &[], 43, &[]);
+        assert_eq!(
+            r.unwrap_err(),
+            XfsError::Corrupt("di_forkoff 43 is beyond the end of the inode".into())
+        );
+    }
+
+    /// A leaf directory's format depends on its data fork's format, not on how many entries or
+    /// blocks it has.  With one data block per extent, 20 data blocks and the leaf exactly fill an
+    /// extent list.  One more data block spills the fork into a B+tree, and the directory must
+    /// still be read the same way.
+    #[rstest]
+    #[case::extents(20)]
+    #[case::btree(21)]
+    fn leaf_dir_fork_format(#[case] ndata: u32) {
+        let sb = superblock();
+        let bs = sb.geometry.dirblksize;
+        // The literal area of a 512-byte inode with no attribute fork
+        let dfork_size = sb.inode_size() - 176;
+        let max_extents = dfork_size / BmbtRec::SIZE;
+        let names = (0..ndata).map(|i| format!("e{:02}", i)).collect::<Vec<_>>();
+
+        // Each data block holds one entry, and is stored in its own extent
+        let data_fsblock = |i: u32| 1000 + 2 * u64::from(i);
+        let mut recs = (0..ndata)
+            .map(|i| (u64::from(i), data_fsblock(i), 1))
+            .collect::<Vec<_>>();
+        let leaf_fsblock: XfsFsblock = 1100;
+        recs.push((sb.get_dir3_leaf_offset().into(), leaf_fsblock, 1));
+        let bmbt = mkbmbt_leaf(&recs);
+        let recs_raw = bmbt_rec_raw(&recs);
+
+        let leaf = mkleafdir(
+            &names
+                .iter()
+                .enumerate()
+                .map(|(i, name)| (name.as_str(), i as u64 * bs as u64 + Dir3DataHdr::SIZE))
+                .collect::<Vec<_>>(),
+            ndata,
+        );
+
+        let bmbt_fsblock: XfsFsblock = 1200;
+        let (format, dfork) = if recs.len() <= max_extents {
+            (XfsDinodeFmt::Extents, recs_raw)
+        } else {
+            (XfsDinodeFmt::Btree, mkbmdr(dfork_size, bmbt_fsblock))
+        };
+        let (img, mut dinode) = mkinode(0o40755, format, 2 * bs, &dfork, 0, &[]);
+        assert_eq!(
+            matches!(dinode.di_u, DiU::Bmbt(_)),
+            recs.len() > max_extents
+        );
+
+        let f = img.as_file();
+        for (i, name) in names.iter().enumerate() {
+            f.write_all_at(
+                &mkdata(&[(name, 0)]),
+                sb.fsb_to_offset(data_fsblock(i as u32)),
+            )
+            .unwrap();
+        }
+        f.write_all_at(&leaf, sb.fsb_to_offset(leaf_fsblock))
+            .unwrap();
+        f.write_all_at(&bmbt, sb.fsb_to_offset(bmbt_fsblock))
+            .unwrap();
+
+        let mut reader = BlockReader::open(img.path()).unwrap();
+        reader.set_bufsize(bs);
+        let dir = dinode.get_dir(&mut reader, sb).unwrap();
+        assert!(matches!(dir, Directory::Lf(_)));
+        for name in names.iter() {
+            assert_eq!(dir.lookup(&mut reader, sb, OsStr::new(name)), Ok(200));
+        }
+        assert_eq!(
+            dir.lookup(&mut reader, sb, OsStr::new("missing")),
+            Err(XfsError::NotFound)
+        );
+        let mut offset = 0;
+        let mut found = Vec::new();
+        while let Ok((_, next, _, name)) = dir.next(&mut reader, sb, offset) {
+            found.push(name.into_string().unwrap());
+            offset = next;
+        }
+        assert_eq!(found, names);
+    }
+
+    /// Build a one-level B+tree root for a fork of the given size, pointing to a single bmap
+    /// B+tree leaf
+    fn mkbmdr(fork_size: usize, leaf: XfsFsblock) -> Vec<u8> {
+        let maxrecs = (fork_size - 4) / 16;
+        let mut fork = vec![0u8; fork_size];
+        fork[0..2].copy_from_slice(&1u16.to_be_bytes()); // bb_level
+        fork[2..4].copy_from_slice(&1u16.to_be_bytes()); // bb_numrecs
+        let pofs = 4 + 8 * maxrecs;
+        fork[pofs..pofs + 8].copy_from_slice(&leaf.to_be_bytes());
+        fork
+    }
+
+    /// A file may have B+tree roots in both forks.  Each must be decoded from its own part of the
+    /// literal area, as split by di_forkoff, and both must be usable at once.
+    #[test]
+    fn btree_data_and_attr_forks() {
+        const FORKOFF: u8 = 24;
+        const DATA_BMBT: XfsFsblock = 1200;
+        const ATTR_BMBT: XfsFsblock = 1201;
+        const ATTR_LEAF: XfsFsblock = 1202;
+        const VALUES: [&[u8]; 4] = [&[b'a'; 200], &[b'b'; 300], &[b'c'; 400], &[b'd'; 500]];
+
+        let sb = superblock();
+        let bs = sb.geometry.blocksize;
+        // Every block of the file lies in a different, discontiguous extent
+        let data_recs = (0..4u64).map(|i| (i, 1300 + 2 * i, 1)).collect::<Vec<_>>();
+        let mut attrs: Vec<Entry> = [&b"big.0"[..], b"big.1", b"big.2", b"big.3"]
+            .into_iter()
+            .zip(VALUES)
+            .map(|(name, value)| (0, name, value))
+            .collect();
+        attrs.sort_by_key(|e| hashname(OsStr::from_bytes(e.1)));
+
+        let dfork_size = usize::from(FORKOFF) * 8;
+        let afork_size = sb.inode_size() - 176 - dfork_size;
+        let mut raw = vec![0u8; sb.inode_size()];
+        raw[0..2].copy_from_slice(&XFS_DINODE_MAGIC.to_be_bytes());
+        raw[2..4].copy_from_slice(&0o100644u16.to_be_bytes());
+        raw[4] = 3;
+        raw[5] = XfsDinodeFmt::Btree as u8;
+        raw[56..64].copy_from_slice(&(4 * bs as u64).to_be_bytes());
+        raw[80..82].copy_from_slice(&1u16.to_be_bytes()); // di_anextents
+        raw[82] = FORKOFF;
+        raw[83] = XfsDinodeFmt::Btree as u8;
+        raw[152..160].copy_from_slice(&INO.to_be_bytes());
+        raw[176..176 + dfork_size].copy_from_slice(&mkbmdr(dfork_size, DATA_BMBT));
+        raw[176 + dfork_size..].copy_from_slice(&mkbmdr(afork_size, ATTR_BMBT));
+        let (img, mut dinode) = mkimg(&raw);
+        assert!(matches!(dinode.di_u, DiU::Bmbt(_)));
+        assert!(matches!(dinode.di_a, Some(DiA::Abmbt(_))));
+
+        let f = img.as_file();
+        f.write_all_at(&mkbmbt_leaf(&data_recs), sb.fsb_to_offset(DATA_BMBT))
+            .unwrap();
+        f.write_all_at(
+            &mkbmbt_leaf(&[(0, ATTR_LEAF, 1)]),
+            sb.fsb_to_offset(ATTR_BMBT),
+        )
+        .unwrap();
+        f.write_all_at(&mkattrleaf(0, &attrs), sb.fsb_to_offset(ATTR_LEAF))
+            .unwrap();
+        for (i, blk, _) in data_recs.iter() {
+            f.write_all_at(&vec![b'0' + *i as u8; bs], sb.fsb_to_offset(*blk))
+                .unwrap();
+        }
+
+        let mut reader = BlockReader::open(img.path()).unwrap();
+        reader.set_bufsize(bs);
+        let data = dinode
+            .get_file(&mut reader)
+            .unwrap()
+            .read(&mut reader, 0, 4 * bs as u32)
+            .unwrap();
+        let expected = (0..4u8)
+            .flat_map(|i| vec![b'0' + i; bs])
+            .collect::<Vec<_>>();
+        assert!(data == expected, "file data was misread");
+        // The inode keeps its data fork's btree, so later reads needn't read it again
+        f.write_all_at(&vec![0u8; bs], sb.fsb_to_offset(DATA_BMBT))
+            .unwrap();
+        let data = dinode
+            .get_file(&mut reader)
+            .unwrap()
+            .read(&mut reader, 0, 4 * bs as u32)
+            .unwrap();
+        assert!(data == expected, "file data was misread from the cache");
+
+        let attributes = dinode.get_attrs(&mut reader, sb).as_mut().unwrap();
+        assert!(matches!(attributes, Attributes::Btree(_)));
+        for (_, name, value) in attrs.iter() {
+            assert_eq!(
+                attributes.get(&mut reader, sb, 0, OsStr::from_bytes(name)),
+                Ok(value.to_vec())
+            );
+        }
+        let expected = attrs
+            .iter()
+            .flat_map(|e| [b"user.", e.1, b"\0"].concat())
+            .collect::<Vec<u8>>();
+        assert_eq!(attributes.list(&mut reader, sb).unwrap(), expected);
+    }
+
+    /// Every version 5 directory block records which inode owns it.  A block that names some other
+    /// owner draws a warning, but is still read.
+    #[rstest]
+    #[case::data(0)]
+    #[case::leaf(1)]
+    fn dir_block_owner(#[case] bad_block: usize, #[values(false, true)] wrong: bool) {
+        let sb = superblock();
+        let name = "e00";
+        let data_fsblock: XfsFsblock = 1000;
+        let leaf_fsblock: XfsFsblock = 1100;
+        let recs_raw = bmbt_rec_raw(&[
+            (0, data_fsblock, 1),
+            (sb.get_dir3_leaf_offset().into(), leaf_fsblock, 1),
+        ]);
+        let mut blocks = [
+            mkdata(&[(name, 0)]),
+            mkleafdir(&[(name, Dir3DataHdr::SIZE)], 1),
+        ];
+        if wrong {
+            // The owner is the last field of both the data and the leaf block headers
+            let ofs = [Dir3BlkHdr::SIZE as usize, 56][bad_block] - 8;
+            blocks[bad_block][ofs..ofs + 8].copy_from_slice(&(INO + 1).to_be_bytes());
+        }
+
+        let (img, mut dinode) = mkinode(
+            0o40755,
+            XfsDinodeFmt::Extents,
+            sb.geometry.dirblksize,
+            &recs_raw,
+            0,
+            &[],
+        );
+        let f = img.as_file();
+        f.write_all_at(&blocks[0], sb.fsb_to_offset(data_fsblock))
+            .unwrap();
+        f.write_all_at(&blocks[1], sb.fsb_to_offset(leaf_fsblock))
+            .unwrap();
+        let mut reader = BlockReader::open(img.path()).unwrap();
+        reader.set_bufsize(sb.geometry.dirblksize);
+
+        let logbuf = LogBuf::default();
+        let subscriber = tracing_subscriber::fmt()
+            .with_ansi(false)
+            .with_writer({
+                let logbuf = logbuf.clone();
+                move || logbuf.clone()
+            })
+            .finish();
+        tracing::subscriber::with_default(subscriber, || {
+            let dir = dinode.get_dir(&mut reader, sb).unwrap();
+            assert_eq!(dir.lookup(&mut reader, sb, OsStr::new(name)), Ok(200));
+        });
+
+        let log = String::from_utf8(logbuf.0.lock().unwrap().clone()).unwrap();
+        let fsblock = [data_fsblock, leaf_fsblock][bad_block];
+        let expected = format!(
+            "Directory block {} of inode {} belongs to inode {}",
+            fsblock,
+            INO,
+            INO + 1
+        );
+        assert_eq!(log.contains(&expected), wrong, "{}", log);
+    }
+
+    /// The smallest leaf directory has just two extents: one data block and the leaf.  A directory
+    /// with only a leaf extent, or none at all, has nowhere to store its entries, and must be
+    /// reported as corrupt rather than read as a block directory.
+    #[rstest]
+    #[case::minimal(&[0, 1], true)]
+    #[case::leaf_only(&[1], false)]
+    #[case::no_extents(&[], false)]
+    fn leaf_dir_extents(#[case] extents: &[usize], #[case] ok: bool) {
+        let sb = superblock();
+        let name = "e00";
+        let recs = [
+            (0, 1000, 1),
+            (u64::from(sb.get_dir3_leaf_offset()), 1100, 1),
+        ];
+        let recs_raw = bmbt_rec_raw(&extents.iter().map(|i| recs[*i]).collect::<Vec<_>>());
+        let leaf = mkleafdir(&[(name, Dir3DataHdr::SIZE)], 1);
+
+        let (img, mut dinode) = mkinode(
+            0o40755,
+            XfsDinodeFmt::Extents,
+            sb.geometry.dirblksize,
+            &recs_raw,
+            0,
+            &[],
+        );
+        let f = img.as_file();
+        f.write_all_at(&mkdata(&[(name, 0)]), sb.fsb_to_offset(recs[0].1))
+            .unwrap();
+        f.write_all_at(&leaf, sb.fsb_to_offset(recs[1].1)).unwrap();
+        let mut reader = BlockReader::open(img.path()).unwrap();
+        reader.set_bufsize(sb.geometry.dirblksize);
+
+        match dinode.get_dir(&mut reader, sb) {
+            Ok(dir) => {
+                assert!(ok);
+                assert!(matches!(dir, Directory::Lf(_)));
+                assert_eq!(dir.lookup(&mut reader, sb, OsStr::new(name)), Ok(200));
+                let (ino, _, _, ent) = dir.next(&mut reader, sb, 0).unwrap();
+                assert_eq!((ino, ent.as_os_str()), (200, OsStr::new(name)));
+            }
+            Err(e) => {
+                assert!(!ok);
+                assert!(matches!(e, XfsError::Corrupt(_)), "{:?}", e);
+            }
+        }
+    }
+
+    /// Special files have no data, so trying to read one fails instead of panicking
+    #[rstest]
+    #[case::blk(0o060644)]
+    #[case::chr(0o020644)]
+    #[case::fifo(0o010644)]
+    #[case::socket(0o140755)]
+    fn special_file_data(#[case] mode: u16) {
+        let (img, mut dinode) = mkinode(mode, XfsDinodeFmt::Dev, 0, &[], 0, &[]);
+        let mut reader = BlockReader::open(img.path()).unwrap();
+        assert!(matches!(
+            dinode.get_file(&mut reader),
+            Err(XfsError::Special)
+        ));
+    }
+
+    /// Directories and symbolic links whose data is stored in the inode have no file data to
+    /// read, so trying to read one is reported as corruption instead of panicking
+    #[rstest]
+    #[case::local_symlink(0o120777, XfsDinodeFmt::Local, b"/target")]
+    #[case::dir_bad_format(0o040755, XfsDinodeFmt::Dev, b"")]
+    fn local_file_data(#[case] mode: u16, #[case] format: XfsDinodeFmt, #[case] dfork: &[u8]) {
+        let (img, mut dinode) = mkinode(mode, format, dfork.len(), dfork, 0, &[]);
+        let mut reader = BlockReader::open(img.path()).unwrap();
+        assert!(matches!(
+            dinode.get_file(&mut reader),
+            Err(XfsError::Corrupt(_))
+        ));
+    }
+
+    /// An inode number that points into the AG headers, as a corrupt directory entry might, must
+    /// be rejected rather than decoding a superblock or AGF as an inode.
+    #[test]
+    fn ino_in_ag_headers() {
+        let img = NamedTempFile::new().unwrap();
+        let mut reader = BlockReader::open(img.path()).unwrap();
+        assert_eq!(
+            Dinode::from(&mut reader, superblock(), 7, None).unwrap_err(),
+            XfsError::Corrupt("Inode 7 lies within the AG headers".into())
+        );
+    }
+
+    /// The golden images all set sb_inoalignmt.  Their root directories must lie at or after the
+    /// first aligned block, and the last inode before that block must be rejected.  On
+    /// xfs1024.img the alignment matches the chunk size and the root directory sits exactly at
+    /// the first aligned block.  On xfsv4.img the alignment is smaller than a chunk, so it's
+    /// ignored.
+    #[rstest]
+    #[case::v5_1k("xfs1024.img", 32)]
+    #[case::v5_4k("xfs4096.img", 8)]
+    #[case::v5_4kn("xfs_4kn.img", 8)]
+    #[case::v4("xfsv4.img", 4)]
+    fn golden_inoalignmt(#[case] filename: &str, #[case] expected: u64) {
+        let img = test_support::golden(filename);
+        let mut reader = BlockReader::open(img.path()).unwrap();
+        let sb = Sb::from(&mut reader).unwrap();
+        assert_ne!(sb.sb_inoalignmt, 0);
+        assert_eq!(sb.first_inode_agbno(), expected);
+
+        // Decoding the root directory consults the global superblock.  Like all the golden
+        // images, the test superblock has the ftype feature.
+        superblock();
+        let root = Dinode::from(&mut reader, &sb, sb.sb_rootino, None).unwrap();
+        assert_eq!(mode_t::from(root.di_core.di_mode) & S_IFMT, S_IFDIR);
+
+        let ino = (expected << sb.sb_inopblog) - 1;
+        assert_eq!(
+            Dinode::from(&mut reader, &sb, ino, None).unwrap_err(),
+            XfsError::Corrupt(format!("Inode {} lies within the AG headers", ino))
+        );
+    }
+
+    /// The first inodes of an AG other than 0 sit just after that AG's headers.  They must be read
+    /// from there, even when the AG size isn't a power of 2 and so AGs aren't contiguous in the
+    /// inode number space.
+    #[rstest]
+    #[case::pow2(16384, 65536)]
+    #[case::not_pow2(16000, 64000)]
+    fn first_inodes_of_ag1(#[case] agblocks: u32, #[case] dblocks: u64) {
+        let sb = Sb::from(&mut io::Cursor::new(mksb(|buf| {
+            buf[8..16].copy_from_slice(&dblocks.to_be_bytes());
+            buf[84..88].copy_from_slice(&agblocks.to_be_bytes());
+        })))
+        .unwrap();
+        let agbno = sb.first_inode_agbno();
+        let img = NamedTempFile::new().unwrap();
+        let chunk_ofs = (u64::from(agblocks) + agbno) * u64::from(sb.sb_blocksize);
+        img.as_file()
+            .set_len(dblocks * u64::from(sb.sb_blocksize))
+            .unwrap();
+        let inodes = (0..2u64)
+            .map(|i| {
+                let ino = sb.agino_to_ino(1, ((agbno << sb.sb_inopblog) + i) as u32);
+                let mut raw = vec![0u8; sb.inode_size()];
+                raw[0..2].copy_from_slice(&XFS_DINODE_MAGIC.to_be_bytes());
+                raw[2..4].copy_from_slice(&0o100644u16.to_be_bytes());
+                raw[4] = 3;
+                raw[5] = XfsDinodeFmt::Extents as u8;
+                raw[56..64].copy_from_slice(&(1000 + i).to_be_bytes());
+                raw[152..160].copy_from_slice(&ino.to_be_bytes());
+                let ofs = chunk_ofs + i * sb.inode_size() as u64;
+                img.as_file().write_all_at(&raw, ofs).unwrap();
+                ino
+            })
+            .collect::<Vec<_>>();
+
+        let mut reader = BlockReader::open(img.path()).unwrap();
+        reader.set_bufsize(sb.inode_size());
+        for (i, ino) in inodes.into_iter().enumerate() {
+            let dinode = Dinode::from(&mut reader, &sb, ino, None).unwrap();
+            assert_eq!(dinode.di_core.di_ino, ino);
+            assert_eq!(dinode.di_core.di_size, 1000 + i as i64);
+        }
+    }
+
+    /// An empty regular file has no extents at all.  It should stat as empty, and reads should
+    /// return nothing without touching the device.
+    #[test]
+    fn empty_file() {
+        let (img, mut dinode) = mkinode(0o100644, XfsDinodeFmt::Extents, 0, &[], 0, &[]);
+        let DiU::Bmx(bmx) = &dinode.di_u else {
+            panic!("Wrong data fork format {:?}", dinode.di_u);
+        };
+        assert!(bmx.is_empty());
+
+        let attr = dinode.di_core.stat(INO, superblock()).unwrap();
+        assert_eq!(attr.size, 0);
+        assert_eq!(attr.blocks, 0);
+
+        let mut reader = BlockReader::open(img.path()).unwrap();
+        let file = dinode.get_file(&mut reader).unwrap();
+        let v = file.read(&mut reader, 0, 4096).unwrap();
+        assert!(v.is_empty());
+        assert_eq!(
+            file.lseek(&mut reader, 0, libc::SEEK_DATA),
+            Err(XfsError::NoData)
+        );
+    }
+
+    /// A [`MakeWriter`](tracing_subscriber::fmt::MakeWriter) that collects log output in memory
+    #[derive(Clone, Default)]
+    struct LogBuf(Arc<Mutex<Vec<u8>>>);
+
+    impl io::Write for LogBuf {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    /// Looking up a name in a shortform directory should read the device exactly once: for the
+    /// child's inode.
+    #[test]
+    fn traced_lookup() {
+        let name = "foo";
+        let mut dfork = vec![1, 0];
+        dfork.extend_from_slice(&(INO as u32).to_be_bytes());
+        dfork.push(name.len() as u8);
+        dfork.extend_from_slice(&0x60u16.to_be_bytes());
+        dfork.extend_from_slice(name.as_bytes());
+        dfork.push(XFS_DIR3_FT_REG_FILE);
+        dfork.extend_from_slice(&(INO as u32).to_be_bytes());
+
+        let sb = superblock();
+        let (img, mut dinode) = mkinode(0o40755, XfsDinodeFmt::Local, dfork.len(), &dfork, 0, &[]);
+        let mut reader = BlockReader::open(img.path()).unwrap();
+        reader.set_trace("data");
+
+        let logbuf = LogBuf::default();
+        let subscriber = tracing_subscriber::fmt()
+            .with_max_level(tracing::Level::DEBUG)
+            .with_ansi(false)
+            .with_writer({
+                let logbuf = logbuf.clone();
+                move || logbuf.clone()
+            })
+            .finish();
+        tracing::subscriber::with_default(subscriber, || {
+            let dir = dinode.get_dir(&mut reader, sb).unwrap();
+            let ino = dir.lookup(&mut reader, sb, OsStr::new(name)).unwrap();
+            reader.set_bufsize(sb.inode_size());
+            Dinode::from(&mut reader, sb, ino, None).unwrap();
+        });
+
+        let log = String::from_utf8(logbuf.0.lock().unwrap().clone()).unwrap();
+        let reads = log
+            .lines()
+            .filter(|l| l.contains("data read at"))
+            .collect::<Vec<_>>();
+        assert_eq!(reads.len(), 1, "{}", log);
+        let offset = sb.fsb_to_offset(INO >> sb.sb_inopblog);
+        let expected = format!("data read at {:#x}, {} bytes", offset, reader.bufsize());
+        assert!(reads[0].ends_with(&expected), "{}", reads[0]);
+    }
+}
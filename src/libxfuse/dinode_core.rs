@@ -28,19 +28,20 @@
 use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
 use bincode::{de::Decoder, error::DecodeError, impl_borrow_decode, Decode};
-use fuser::FileAttr;
-use libc::c_int;
+use fuser::{FileAttr, FileType};
 use num_derive::FromPrimitive;
 use num_traits::FromPrimitive;
 
 use super::{
     btree::{BmbtKey, BmdrBlock},
     definitions::*,
+    error::XfsError,
+    sb::Sb,
     utils::{get_file_type, FileKind, Uuid},
     S_IFMT,
 };
 
-#[derive(Debug, FromPrimitive)]
+#[derive(Clone, Copy, Debug, FromPrimitive)]
 #[cfg_attr(test, derive(Default))]
 pub enum XfsDinodeFmt {
     Dev,
@@ -55,7 +56,7 @@ pub enum XfsDinodeFmt {
 impl<Ctx> bincode::Decode<Ctx> for XfsDinodeFmt {
     fn decode<D: Decoder>(decoder: &mut D) -> Result<Self, DecodeError> {
         let discriminant: u8 = Decode::decode(decoder)?;
-        Ok(XfsDinodeFmt::from_u8(discriminant).expect("Unknown dinode fmt"))
+        XfsDinodeFmt::from_u8(discriminant).ok_or(DecodeError::Other("Unknown dinode fmt"))
     }
 }
 impl_borrow_decode!(XfsDinodeFmt);
@@ -66,6 +67,9 @@ pub struct XfsTimestamp {
     pub t_nsec: u32,
 }
 
+/// The latest time, in seconds since the Epoch, that a bigtime timestamp can hold
+pub const XFS_BIGTIME_TIME_MAX: i64 = (u64::MAX / 1_000_000_000) as i64 - (1 << 31);
+
 #[allow(dead_code)]
 mod constants {
     pub const XFS_DIFLAG_REALTIME: u16 = 1 << 0;
@@ -85,53 +89,67 @@ mod constants {
     pub const XFS_DIFLAG_FILESTREAMS: u16 = 1 << 14;
 
     pub const XFS_DIFLAG2_BIGTIME: u64 = 1 << 3;
+
+    // BSD file flags, as reported by stat(2) in st_flags.  They're the same on every BSD, but the
+    // libc crate doesn't define them on Linux.
+    pub const UF_NODUMP: u32 = 0x0000_0001;
+    pub const SF_IMMUTABLE: u32 = 0x0002_0000;
+    pub const SF_APPEND: u32 = 0x0004_0000;
 }
 
 #[derive(Debug)]
 #[cfg_attr(test, derive(Default))]
 pub struct DinodeCore {
     //_di_magic: u16,
-    pub di_mode:      u16,
-    pub di_version:   i8,
-    pub di_format:    XfsDinodeFmt,
+    pub di_mode:          u16,
+    pub di_version:       i8,
+    pub di_format:        XfsDinodeFmt,
     //_di_onlink: u16,
-    pub di_uid:       u32,
-    pub di_gid:       u32,
-    pub di_nlink:     u32,
+    pub di_uid:           u32,
+    pub di_gid:           u32,
+    pub di_nlink:         u32,
     //_di_projid: u16,
     //_di_projid_hi: u16,
     //_di_pad: [u8; 6],
     //_di_flushiter: u16,
-    pub di_atime:     XfsTimestamp,
-    pub di_mtime:     XfsTimestamp,
-    pub di_ctime:     XfsTimestamp,
-    pub di_size:      XfsFsize,
-    pub di_nblocks:   XfsRfsblock,
-    //_di_extsize: XfsExtlen,
-    pub di_nextents:  XfsExtnum,
-    pub di_anextents: XfsAextnum,
-    pub di_forkoff:   u8,
-    pub di_aformat:   XfsDinodeFmt,
+    pub di_atime:         XfsTimestamp,
+    pub di_mtime:         XfsTimestamp,
+    pub di_ctime:         XfsTimestamp,
+    pub di_size:          XfsFsize,
+    pub di_nblocks:       XfsRfsblock,
+    /// Extent size hint, in file system blocks
+    pub di_extsize:       XfsExtlen,
+    pub di_nextents:      XfsExtnum,
+    pub di_anextents:     XfsAextnum,
+    pub di_forkoff:       u8,
+    pub di_aformat:       XfsDinodeFmt,
     //_di_dmevmask: u32,
     //_di_dmstate: u16,
-    //_di_flags: u16,
-    pub di_gen:       u32,
-
-    //_di_next_unlinked: u32,
-
+    pub di_flags:         u16,
+    pub di_gen:           u32,
+    /// The next inode in this AG's list of unlinked but still open inodes
+    pub di_next_unlinked: XfsAgino,
     /* Version 5 file system (inode version 3) fields start here */
+
     //_di_crc: u32,
-    //_di_changecount: u64,
+    /// Incremented on every change to the inode, like NFSv4's change attribute
+    pub di_changecount: u64,
     //_di_lsn: u64,
-    pub di_flags2: u64,
+    pub di_flags2:      u64,
     //_di_cowextsize: u32,
     //_di_pad2: [u8; 12],
-    pub di_crtime: XfsTimestamp,
-    pub di_ino:    u64,
+    pub di_crtime:      XfsTimestamp,
+    pub di_ino:         u64,
     //_di_uuid: Uuid,
 }
 
 impl DinodeCore {
+    /// Size of the core of a version 1 or 2 inode, through di_next_unlinked
+    pub const V2_SIZE: usize = 0x64;
+    /// Size of the core of a version 3 inode, which adds the CRC, change count, LSN, flags2,
+    /// cowextsize, crtime, inode number and UUID
+    pub const V3_SIZE: usize = 0xb0;
+
     /// Compute the gap in bytes between the end of the keys and the start of the pointers, for
     /// BTree-formatted inodes only, for the data fork.
     pub const fn dfork_btree_ptr_gap(&self, inode_size: usize, bb_numrecs: u16) -> usize {
@@ -181,15 +199,73 @@ impl DinodeCore {
         ptr_ofs - attr_fork_ofs - already
     }
 
+    /// Is this file's data stored on the realtime device?
+    pub const fn is_realtime(&self) -> bool {
+        self.di_flags & constants::XFS_DIFLAG_REALTIME != 0
+    }
+
+    /// Describe the allocation hints that this directory passes on to new files created within
+    /// it, as `extsize=<blocks> inherit=<flags>`, where the flags are a comma-separated list of
+    /// `rtinherit` and `extszinherit`, or `none`.  Other inodes have no such hints.
+    pub fn extsize_hint(&self) -> Option<String> {
+        if get_file_type(FileKind::Mode(self.di_mode)) != Ok(FileType::Directory) {
+            return None;
+        }
+        let flags = [
+            (constants::XFS_DIFLAG_RTINHERIT, "rtinherit"),
+            (constants::XFS_DIFLAG_EXTSZINHERIT, "extszinherit"),
+        ]
+        .iter()
+        .filter(|(flag, _)| self.di_flags & flag != 0)
+        .map(|(_, name)| *name)
+        .collect::<Vec<_>>();
+        let inherit = if flags.is_empty() {
+            "none".to_string()
+        } else {
+            flags.join(",")
+        };
+        Some(format!("extsize={} inherit={}", self.di_extsize, inherit))
+    }
+
     pub const fn literal_area_offset(&self) -> usize {
         match self.di_version {
-            1..=2 => 0x64,
-            3 => 0xb0,
+            1..=2 => Self::V2_SIZE,
+            3 => Self::V3_SIZE,
             _ => unreachable!(),
         }
     }
 
-    pub fn stat(&self, ino: XfsIno) -> Result<FileAttr, c_int> {
+    /// The offset within an inode of the given size at which the attribute fork begins.  The
+    /// data fork ends there, or at the end of the inode if there is no attribute fork.
+    pub fn attr_fork_offset(&self, inode_size: usize) -> Result<usize, XfsError> {
+        let ofs = if self.di_forkoff == 0 {
+            inode_size
+        } else {
+            self.literal_area_offset() + usize::from(self.di_forkoff) * 8
+        };
+        if ofs > inode_size {
+            return Err(XfsError::Corrupt(format!(
+                "di_forkoff {} is beyond the end of the inode",
+                self.di_forkoff
+            )));
+        }
+        Ok(ofs)
+    }
+
+    /// This inode's flags as BSD file flags, like chflags(2) sets.  Only the immutable, append-only,
+    /// and nodump flags have equivalents.
+    fn bsd_flags(&self) -> u32 {
+        [
+            (constants::XFS_DIFLAG_IMMUTABLE, constants::SF_IMMUTABLE),
+            (constants::XFS_DIFLAG_APPEND, constants::SF_APPEND),
+            (constants::XFS_DIFLAG_NODUMP, constants::UF_NODUMP),
+        ]
+        .iter()
+        .filter(|(xfs, _)| self.di_flags & xfs != 0)
+        .fold(0, |flags, (_, bsd)| flags | bsd)
+    }
+
+    pub fn stat(&self, ino: XfsIno, sb: &Sb) -> Result<FileAttr, XfsError> {
         let kind = get_file_type(FileKind::Mode(self.di_mode))?;
         // Special case for ino 1.  FUSE requires / to have inode 1, but XFS
         // does not.
@@ -199,8 +275,15 @@ impl DinodeCore {
         Ok(FileAttr {
             ino,
             size: self.di_size as u64,
-            blocks: self.di_nblocks,
-            atime: self.timestamp(&self.di_atime),
+            // st_blocks is in 512-byte units.  Realtime blocks are counted in file system blocks
+            // too, so they need no special treatment.
+            blocks: self.di_nblocks << (sb.sb_blocklog - BBSHIFT),
+            // The realtime bitmap inode repurposes its atime as an allocator sequence number.
+            atime: if self.di_flags & constants::XFS_DIFLAG_NEWRTBM != 0 {
+                self.timestamp(&self.di_mtime)
+            } else {
+                self.timestamp(&self.di_atime)
+            },
             mtime: self.timestamp(&self.di_mtime),
             ctime: self.timestamp(&self.di_ctime),
             crtime: self.timestamp(&self.di_crtime),
@@ -211,12 +294,37 @@ impl DinodeCore {
             gid: self.di_gid,
             rdev: 0,
             blksize: 0,
-            flags: 0,
+            // fuser only sends these to the kernel on macOS
+            flags: self.bsd_flags(),
         })
     }
 
+    /// Are this inode's timestamps in the bigtime encoding: nanoseconds since the start of 1901?
+    fn bigtime(&self) -> bool {
+        self.di_version >= 3 && (self.di_flags2 & constants::XFS_DIFLAG2_BIGTIME != 0)
+    }
+
+    /// Encode a time, in whole seconds since the Epoch, the way this inode stores its timestamps.
+    /// Return `None` if the encoding can't represent it.
+    pub fn encode_timestamp(&self, secs: i64) -> Option<XfsTimestamp> {
+        if self.bigtime() {
+            let ns = u64::try_from(secs + (1 << 31))
+                .ok()?
+                .checked_mul(1_000_000_000)?;
+            Some(XfsTimestamp {
+                t_sec:  (ns >> 32) as u32 as i32,
+                t_nsec: ns as u32,
+            })
+        } else {
+            Some(XfsTimestamp {
+                t_sec:  i32::try_from(secs).ok()?,
+                t_nsec: 0,
+            })
+        }
+    }
+
     fn timestamp(&self, ts: &XfsTimestamp) -> SystemTime {
-        if self.di_version >= 3 && (self.di_flags2 & constants::XFS_DIFLAG2_BIGTIME != 0) {
+        if self.bigtime() {
             // XXX this could be made a const if the Rust const_trait_impl
             // feature stabilizes.
             let classic_epoch: SystemTime = UNIX_EPOCH - Duration::from_secs(i32::MAX as u64 + 1);
@@ -225,6 +333,9 @@ impl DinodeCore {
                 + Duration::from_nanos(
                     u64::from(ts.t_sec as u32) * (1u64 << 32) + u64::from(ts.t_nsec),
                 )
+        } else if ts.t_sec < 0 {
+            UNIX_EPOCH - Duration::from_secs(u64::from(ts.t_sec.unsigned_abs()))
+                + Duration::from_nanos(ts.t_nsec.into())
         } else {
             UNIX_EPOCH + Duration::new(ts.t_sec as u64, ts.t_nsec)
         }
@@ -236,20 +347,32 @@ impl<Ctx> Decode<Ctx> for DinodeCore {
         let mut di_flags2 = 0;
         let mut di_crtime: XfsTimestamp = Default::default();
         let mut di_ino = 0;
+        let mut di_changecount = 0;
 
         let di_magic: u16 = Decode::decode(decoder)?;
-        assert_eq!(di_magic, XFS_DINODE_MAGIC, "Inode magic number is invalid");
+        if di_magic != XFS_DINODE_MAGIC {
+            return Err(DecodeError::Other("Inode magic number is invalid"));
+        }
         let di_mode: u16 = Decode::decode(decoder)?;
         let di_version: i8 = Decode::decode(decoder)?;
-        assert!(
-            di_version == 2 || di_version == 3,
-            "Only inode versions 2 and 3 are supported"
-        );
+        if !(1..=3).contains(&di_version) {
+            return Err(DecodeError::OtherString(format!(
+                "Unsupported inode version {}",
+                di_version
+            )));
+        }
         let di_format: XfsDinodeFmt = Decode::decode(decoder)?;
-        let _di_onlink: u16 = Decode::decode(decoder)?;
+        let di_onlink: u16 = Decode::decode(decoder)?;
         let di_uid: u32 = Decode::decode(decoder)?;
         let di_gid: u32 = Decode::decode(decoder)?;
         let di_nlink: u32 = Decode::decode(decoder)?;
+        // Version 1 inodes store the link count in the 16-bit di_onlink field.  Its core is
+        // otherwise laid out the same as version 2, with the unused fields zeroed.
+        let di_nlink = if di_version == 1 {
+            u32::from(di_onlink)
+        } else {
+            di_nlink
+        };
         let _di_projid: u16 = Decode::decode(decoder)?;
         let _di_projid_hi: u16 = Decode::decode(decoder)?;
         let _di_pad: [u8; 6] = Decode::decode(decoder)?;
@@ -259,19 +382,19 @@ impl<Ctx> Decode<Ctx> for DinodeCore {
         let di_ctime: XfsTimestamp = Decode::decode(decoder)?;
         let di_size: XfsFsize = Decode::decode(decoder)?;
         let di_nblocks: XfsRfsblock = Decode::decode(decoder)?;
-        let _di_extsize: XfsExtlen = Decode::decode(decoder)?;
+        let di_extsize: XfsExtlen = Decode::decode(decoder)?;
         let di_nextents: XfsExtnum = Decode::decode(decoder)?;
         let di_anextents: XfsAextnum = Decode::decode(decoder)?;
         let di_forkoff: u8 = Decode::decode(decoder)?;
         let di_aformat: XfsDinodeFmt = Decode::decode(decoder)?;
         let _di_dmevmask: u32 = Decode::decode(decoder)?;
         let _di_dmstate: u16 = Decode::decode(decoder)?;
-        let _di_flags: u16 = Decode::decode(decoder)?;
+        let di_flags: u16 = Decode::decode(decoder)?;
         let di_gen: u32 = Decode::decode(decoder)?;
-        let _di_next_unlinked: u32 = Decode::decode(decoder)?;
+        let di_next_unlinked: XfsAgino = Decode::decode(decoder)?;
         if di_version >= 3 {
             let _di_crc: u32 = Decode::decode(decoder)?;
-            let _di_changecount: u64 = Decode::decode(decoder)?;
+            di_changecount = Decode::decode(decoder)?;
             let _di_lsn: u64 = Decode::decode(decoder)?;
             di_flags2 = Decode::decode(decoder)?;
             let _di_cowextsize: u32 = Decode::decode(decoder)?;
@@ -293,11 +416,15 @@ impl<Ctx> Decode<Ctx> for DinodeCore {
             di_ctime,
             di_size,
             di_nblocks,
+            di_extsize,
             di_nextents,
             di_anextents,
             di_forkoff,
             di_aformat,
             di_gen,
+            di_next_unlinked,
+            di_flags,
+            di_changecount,
             di_flags2,
             di_crtime,
             di_ino,
@@ -311,6 +438,7 @@ mod tests {
     use rstest::rstest;
 
     use super::*;
+    use crate::libxfuse::{sb::tests::superblock, utils::decode};
 
     /// Test the afork_btree_ptr_gap function against data from real live file systems.  The XFS
     /// Algorithms & Data Structures book does not accurately document this gap.
@@ -334,6 +462,291 @@ mod tests {
         assert_eq!(dic.afork_btree_ptr_gap(inode_size, bb_numrecs), gap);
     }
 
+    /// Build the raw core of a version 1 or 2 inode
+    fn mkcore(di_version: u8, di_onlink: u16, di_nlink: u32) -> Vec<u8> {
+        let mut raw = vec![0u8; 100];
+        raw[0..2].copy_from_slice(&XFS_DINODE_MAGIC.to_be_bytes());
+        raw[2..4].copy_from_slice(&0o100644u16.to_be_bytes());
+        raw[4] = di_version;
+        raw[5] = XfsDinodeFmt::Extents as u8;
+        raw[6..8].copy_from_slice(&di_onlink.to_be_bytes());
+        raw[16..20].copy_from_slice(&di_nlink.to_be_bytes());
+        raw[56..64].copy_from_slice(&14i64.to_be_bytes());
+        raw[83] = XfsDinodeFmt::Extents as u8;
+        raw
+    }
+
+    /// Version 1 inodes store their link count in di_onlink
+    #[test]
+    fn decode_v1() {
+        let dic: DinodeCore = decode(&mkcore(1, 3, 0)).unwrap().0;
+        assert_eq!(dic.di_version, 1);
+        assert_eq!(dic.di_nlink, 3);
+        assert_eq!(dic.di_size, 14);
+        assert_eq!(dic.literal_area_offset(), 0x64);
+        assert_eq!(dic.stat(128, superblock()).unwrap().nlink, 3);
+    }
+
+    #[test]
+    fn is_realtime() {
+        let mut raw = mkcore(2, 0, 1);
+        let dic: DinodeCore = decode(&raw).unwrap().0;
+        assert!(!dic.is_realtime());
+        raw[90..92].copy_from_slice(&constants::XFS_DIFLAG_REALTIME.to_be_bytes());
+        let dic: DinodeCore = decode(&raw).unwrap().0;
+        assert!(dic.is_realtime());
+    }
+
+    #[test]
+    fn decode_changecount() {
+        let mut raw = mkcore(3, 0, 1);
+        raw.resize(176, 0);
+        raw[104..112].copy_from_slice(&0x0123456789abcdefu64.to_be_bytes());
+        let dic: DinodeCore = decode(&raw).unwrap().0;
+        assert_eq!(dic.di_changecount, 0x0123456789abcdef);
+    }
+
+    /// A directory's extent size hint and inheritance flags are described; other inodes have none
+    #[rstest]
+    #[case::none(0o40755, 0, 0, Some("extsize=0 inherit=none"))]
+    #[case::extszinherit(
+        0o40755,
+        16,
+        constants::XFS_DIFLAG_EXTSZINHERIT,
+        Some("extsize=16 inherit=extszinherit")
+    )]
+    #[case::both(
+        0o40755,
+        256,
+        constants::XFS_DIFLAG_RTINHERIT | constants::XFS_DIFLAG_EXTSZINHERIT | constants::XFS_DIFLAG_NODUMP,
+        Some("extsize=256 inherit=rtinherit,extszinherit")
+    )]
+    #[case::file(0o100644, 16, constants::XFS_DIFLAG_EXTSIZE, None)]
+    fn extsize_hint(
+        #[case] mode: u16,
+        #[case] extsize: u32,
+        #[case] flags: u16,
+        #[case] expected: Option<&str>,
+    ) {
+        let mut raw = mkcore(3, 0, 1);
+        raw.resize(176, 0);
+        raw[2..4].copy_from_slice(&mode.to_be_bytes());
+        raw[72..76].copy_from_slice(&extsize.to_be_bytes());
+        raw[90..92].copy_from_slice(&flags.to_be_bytes());
+        let dic: DinodeCore = decode(&raw).unwrap().0;
+        assert_eq!(dic.extsize_hint().as_deref(), expected);
+    }
+
+    /// The core of inode 142530, files/hello.txt, copied from the xfs4096 golden image.  It has
+    /// mode 0101234, uid 1234, gid 5678, two links, size 14 in one extent, an attribute fork at
+    /// forkoff 24, and the bigtime flag.
+    const GOLDEN_V3_CORE: [u8; 176] = [
+        0x49, 0x4e, 0x82, 0x9c, 0x03, 0x02, 0x00, 0x00, 0x00, 0x00, 0x04, 0xd2, 0x00, 0x00, 0x16,
+        0x2e, 0x00, 0x00, 0x00, 0x02, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+        0x00, 0x00, 0x30, 0x4b, 0x60, 0x0e, 0xdb, 0x5d, 0xb4, 0x00, 0x23, 0x5f, 0xe6, 0xe1, 0xa9,
+        0x74, 0x6e, 0x00, 0x35, 0xa9, 0xb3, 0x0f, 0x72, 0xe8, 0x4e, 0x0a, 0x00, 0x00, 0x00, 0x00,
+        0x00, 0x00, 0x00, 0x0e, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x01, 0x00, 0x00, 0x00,
+        0x00, 0x00, 0x00, 0x00, 0x01, 0x00, 0x00, 0x18, 0x02, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+        0x00, 0x00, 0xba, 0xa5, 0x70, 0xf1, 0xff, 0xff, 0xff, 0xff, 0xcb, 0xf1, 0x05, 0x54, 0x00,
+        0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x0a, 0x00, 0x00, 0x00, 0x0e, 0x00, 0x00, 0x1c, 0x6f,
+        0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x08, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+        0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x35, 0xa9, 0xb3, 0x0f, 0x72, 0xe8,
+        0x4e, 0x0a, 0x00, 0x00, 0x00, 0x00, 0x00, 0x02, 0x2c, 0xc2, 0x73, 0x31, 0x58, 0x98, 0x4f,
+        0xd6, 0x48, 0x11, 0x88, 0x21, 0x74, 0x1e, 0xc5, 0x37, 0x53, 0x48,
+    ];
+
+    /// Every field of a version 3 inode core must be decoded from the correct offset
+    #[test]
+    fn decode_golden_v3() {
+        let (dic, len): (DinodeCore, usize) = decode(&GOLDEN_V3_CORE).unwrap();
+        assert_eq!(len, DinodeCore::V3_SIZE);
+        assert_eq!(dic.di_mode, 0o101234);
+        assert_eq!(dic.di_version, 3);
+        assert!(matches!(dic.di_format, XfsDinodeFmt::Extents));
+        assert_eq!(dic.di_uid, 1234);
+        assert_eq!(dic.di_gid, 5678);
+        assert_eq!(dic.di_nlink, 2);
+        assert_eq!(dic.di_atime.t_sec, 0x304b600e);
+        assert_eq!(dic.di_atime.t_nsec, 0xdb5db400);
+        assert_eq!(dic.di_mtime.t_sec, 0x235fe6e1);
+        assert_eq!(dic.di_mtime.t_nsec, 0xa9746e00);
+        assert_eq!(dic.di_ctime.t_sec, 0x35a9b30f);
+        assert_eq!(dic.di_ctime.t_nsec, 0x72e84e0a);
+        assert_eq!(dic.di_size, 14);
+        assert_eq!(dic.di_nblocks, 1);
+        assert_eq!(dic.di_extsize, 0);
+        assert_eq!(dic.di_nextents, 1);
+        assert_eq!(dic.di_anextents, 0);
+        assert_eq!(dic.di_forkoff, 24);
+        assert!(matches!(dic.di_aformat, XfsDinodeFmt::Extents));
+        assert_eq!(dic.di_flags, 0);
+        assert_eq!(dic.di_gen, 0xbaa570f1);
+        assert_eq!(dic.di_next_unlinked, NULLAGINO);
+        assert_eq!(dic.di_changecount, 10);
+        assert_eq!(dic.di_flags2, constants::XFS_DIFLAG2_BIGTIME);
+        assert_eq!(dic.di_crtime.t_sec, 0x35a9b30f);
+        assert_eq!(dic.di_crtime.t_nsec, 0x72e84e0a);
+        assert_eq!(dic.di_ino, 142530);
+        assert_eq!(dic.literal_area_offset(), 0xb0);
+    }
+
+    /// di_pad, di_flushiter, and di_pad2 are never used.  Garbage in them must not change the
+    /// decoded inode.
+    #[rstest]
+    #[case::pad(24..30)]
+    #[case::flushiter(30..32)]
+    #[case::pad2(132..144)]
+    fn decode_ignores_padding(#[case] range: std::ops::Range<usize>) {
+        let mut raw = GOLDEN_V3_CORE;
+        raw[range].fill(0xa5);
+        let golden: (DinodeCore, usize) = decode(&GOLDEN_V3_CORE).unwrap();
+        let garbled: (DinodeCore, usize) = decode(&raw).unwrap();
+        assert_eq!(format!("{:?}", garbled), format!("{:?}", golden));
+    }
+
+    /// Version 1 and 2 inodes have di_flushiter, but their core ends before di_crc, without
+    /// di_pad2
+    #[rstest]
+    fn decode_v2_core_size(#[values(1, 2)] version: u8) {
+        let mut raw = mkcore(version, 1, 1);
+        raw[30..32].copy_from_slice(&0xffffu16.to_be_bytes());
+        let (dic, len): (DinodeCore, usize) = decode(&raw).unwrap();
+        assert_eq!(len, DinodeCore::V2_SIZE);
+        assert_eq!(dic.di_size, 14);
+        assert_eq!(dic.di_changecount, 0);
+    }
+
+    /// Version 2 inodes ignore di_onlink
+    #[test]
+    fn decode_v2() {
+        let dic: DinodeCore = decode(&mkcore(2, 0, 5)).unwrap().0;
+        assert_eq!(dic.di_nlink, 5);
+    }
+
+    #[test]
+    #[should_panic(expected = "Unsupported inode version 4")]
+    fn decode_unknown_version() {
+        let _: (DinodeCore, usize) = decode(&mkcore(4, 0, 1)).unwrap();
+    }
+
+    /// The realtime bitmap inode stores a sequence number in its atime, which may not be a valid
+    /// timestamp.  It should still stat as an ordinary regular file.
+    #[test]
+    fn stat_rtbitmap() {
+        let dic = DinodeCore {
+            di_mode: 0o100000,
+            di_version: 3,
+            di_ino: 129,
+            di_flags: constants::XFS_DIFLAG_NEWRTBM,
+            di_atime: XfsTimestamp {
+                t_sec:  -1,
+                t_nsec: u32::MAX,
+            },
+            di_mtime: XfsTimestamp {
+                t_sec:  1_700_000_000,
+                t_nsec: 0,
+            },
+            ..Default::default()
+        };
+        let attr = dic.stat(129, superblock()).unwrap();
+        assert_eq!(attr.kind, fuser::FileType::RegularFile);
+        assert_eq!(attr.atime, attr.mtime);
+    }
+
+    /// XFS's immutable, append-only, and nodump flags are reported as their BSD equivalents, and
+    /// its other flags not at all
+    #[rstest]
+    #[case::none(0, 0)]
+    #[case::immutable(constants::XFS_DIFLAG_IMMUTABLE, constants::SF_IMMUTABLE)]
+    #[case::append(constants::XFS_DIFLAG_APPEND, constants::SF_APPEND)]
+    #[case::nodump(constants::XFS_DIFLAG_NODUMP, constants::UF_NODUMP)]
+    #[case::all(
+        constants::XFS_DIFLAG_IMMUTABLE
+            | constants::XFS_DIFLAG_APPEND
+            | constants::XFS_DIFLAG_NODUMP
+            | constants::XFS_DIFLAG_NOATIME,
+        constants::SF_IMMUTABLE | constants::SF_APPEND | constants::UF_NODUMP
+    )]
+    fn stat_flags(#[case] di_flags: u16, #[case] expected: u32) {
+        let dic = DinodeCore {
+            di_mode: 0o100644,
+            di_version: 3,
+            di_ino: 128,
+            di_flags,
+            ..Default::default()
+        };
+        assert_eq!(dic.stat(128, superblock()).unwrap().flags, expected);
+    }
+
+    /// A file with blocks preallocated beyond EOF reports its size, not its allocation, but its
+    /// block count includes the preallocated blocks.  st_blocks is in 512-byte units, so 1 MiB
+    /// worth of 4 KiB blocks is 2048.
+    #[test]
+    fn stat_prealloc_beyond_eof() {
+        let dic = DinodeCore {
+            di_mode: 0o100644,
+            di_version: 3,
+            di_ino: 128,
+            di_size: 4096,
+            di_nblocks: 256,
+            ..Default::default()
+        };
+        let attr = dic.stat(128, superblock()).unwrap();
+        assert_eq!(attr.size, 4096);
+        assert_eq!(attr.blocks, 2048);
+    }
+
+    /// A realtime file's di_nblocks counts file system blocks too, so its block count is
+    /// converted to 512-byte units the same way.
+    #[test]
+    fn stat_blocks_realtime() {
+        let dic = DinodeCore {
+            di_mode: 0o100644,
+            di_version: 3,
+            di_ino: 128,
+            di_flags: constants::XFS_DIFLAG_REALTIME,
+            di_size: 8192,
+            di_nblocks: 2,
+            ..Default::default()
+        };
+        assert_eq!(dic.stat(128, superblock()).unwrap().blocks, 16);
+    }
+
+    /// Timestamps before the epoch are valid without bigtime
+    #[test]
+    fn stat_pre_epoch() {
+        let dic = DinodeCore {
+            di_mode: 0o100644,
+            di_version: 3,
+            di_ino: 128,
+            di_mtime: XfsTimestamp {
+                t_sec:  -86400,
+                t_nsec: 500_000_000,
+            },
+            ..Default::default()
+        };
+        let attr = dic.stat(128, superblock()).unwrap();
+        assert_eq!(
+            attr.mtime,
+            UNIX_EPOCH - Duration::from_secs(86400) + Duration::from_millis(500)
+        );
+    }
+
+    /// A directory's st_size should be its di_size
+    #[test]
+    fn stat_dir_size() {
+        let dic = DinodeCore {
+            di_mode: 0o040755,
+            di_version: 3,
+            di_ino: 128,
+            di_size: 12288,
+            ..Default::default()
+        };
+        let attr = dic.stat(128, superblock()).unwrap();
+        assert_eq!(attr.kind, fuser::FileType::Directory);
+        assert_eq!(attr.size, 12288);
+    }
+
     /// Test the dfork_btree_ptr_gap function against data from real live file systems.  The XFS
     /// Algorithms & Data Structures book does not accurately document this gap.
     #[rstest]
@@ -37,10 +37,12 @@ use bincode::{
     Decode,
 };
 use fuser::FileType;
-use libc::c_int;
+use tracing::warn;
 
 use super::{
+    da_btree::XfsDa3Blkinfo,
     definitions::*,
+    error::XfsError,
     sb::Sb,
     utils::{decode, Uuid},
     volume::SUPERBLOCK,
@@ -63,6 +65,12 @@ mod constants {
     pub const XFS_DIR3_FT_SOCK: u8 = 6;
     pub const XFS_DIR3_FT_SYMLINK: u8 = 7;
     pub const XFS_DIR3_FT_WHT: u8 = 8;
+
+    /// Size in bytes of a directory's data segment.  The leaf and free index segments follow it.
+    pub const XFS_DIR2_DATA_SPACE_SIZE: u64 = 1 << 35;
+
+    /// Address of a stale leaf entry, one whose directory entry has been removed
+    pub const XFS_DIR2_NULL_DATAPTR: u32 = 0;
 }
 pub use constants::*;
 
@@ -73,17 +81,48 @@ pub struct Dir3BlkHdr {
     _blkno:    u64,
     _lsn:      u64,
     _uuid:     Uuid,
-    _owner:    u64,
+    /// The directory inode that this block belongs to
+    pub owner: u64,
 }
 
 impl Dir3BlkHdr {
     pub const SIZE: u64 = 48;
 }
 
+/// Warn if `raw`, a version 5 directory block at `fsblock`, doesn't name directory `ino` as its
+/// owner.  Such a block may be cross-linked with another directory, or merely have a corrupt
+/// header, so its contents are still used.  Returns whether the owner was correct.  Version 4
+/// blocks have no owner to check.
+pub fn check_block_owner(raw: &[u8], ino: XfsIno, fsblock: XfsFsblock) -> bool {
+    let magic32 = decode::<u32>(raw).map(|r| r.0);
+    let magic16 = raw.get(8..).map(|r| decode::<u16>(r).map(|r| r.0));
+    // Data and free index blocks begin with a Dir3BlkHdr, but leaf and node blocks begin with an
+    // XfsDa3Blkinfo.
+    let owner = match (magic32, magic16) {
+        (Ok(XFS_DIR3_BLOCK_MAGIC | XFS_DIR3_DATA_MAGIC | XFS_DIR3_FREE_MAGIC), _) => {
+            decode::<Dir3BlkHdr>(raw).map(|(hdr, _)| hdr.owner)
+        }
+        (_, Some(Ok(XFS_DIR3_LEAF1_MAGIC | XFS_DIR3_LEAFN_MAGIC | XFS_DA3_NODE_MAGIC))) => {
+            decode::<XfsDa3Blkinfo>(raw).map(|(info, _)| info.owner)
+        }
+        _ => return true,
+    };
+    match owner {
+        Ok(owner) if owner != ino => {
+            warn!(
+                "Directory block {} of inode {} belongs to inode {}",
+                fsblock, ino, owner
+            );
+            false
+        }
+        _ => true,
+    }
+}
+
 #[derive(Debug, Decode, Clone, Copy)]
 struct Dir2DataFree {
     _offset: XfsDir2DataOff,
-    _length: XfsDir2DataOff,
+    length:  XfsDir2DataOff,
 }
 
 impl Dir2DataFree {
@@ -92,24 +131,34 @@ impl Dir2DataFree {
 
 #[derive(Debug, Decode)]
 pub struct Dir2DataHdr {
-    pub magic:  u32,
-    _best_free: [Dir2DataFree; constants::XFS_DIR2_DATA_FD_COUNT],
+    pub magic: u32,
+    best_free: [Dir2DataFree; constants::XFS_DIR2_DATA_FD_COUNT],
 }
 
 impl Dir2DataHdr {
     pub const SIZE: u64 = 4 + constants::XFS_DIR2_DATA_FD_COUNT as u64 * Dir2DataFree::SIZE;
+
+    /// Length of the block's largest free region
+    pub fn longest_free(&self) -> XfsDir2DataOff {
+        self.best_free[0].length
+    }
 }
 
 #[derive(Debug, Decode)]
 pub struct Dir3DataHdr {
-    pub hdr:    Dir3BlkHdr,
-    _best_free: [Dir2DataFree; constants::XFS_DIR2_DATA_FD_COUNT],
-    _pad:       u32,
+    pub hdr:   Dir3BlkHdr,
+    best_free: [Dir2DataFree; constants::XFS_DIR2_DATA_FD_COUNT],
+    _pad:      u32,
 }
 
 impl Dir3DataHdr {
     pub const SIZE: u64 =
         Dir3BlkHdr::SIZE + constants::XFS_DIR2_DATA_FD_COUNT as u64 * Dir2DataFree::SIZE + 4;
+
+    /// Length of the block's largest free region
+    pub fn longest_free(&self) -> XfsDir2DataOff {
+        self.best_free[0].length
+    }
 }
 
 #[derive(Debug)]
@@ -198,6 +247,28 @@ impl Dir2LeafEntry {
     pub const SIZE: usize = 8;
 }
 
+/// Convert the byte address of an entry within a directory's data segment into a readdir cookie.
+///
+/// The cookie is simply the address, so cookies are unique and monotonically increasing.  Since
+/// the data segment is limited to 32 GiB and every entry occupies at least 16 bytes, a directory
+/// can hold no more than 2^31 entries, and every cookie fits in 35 of the 63 available bits.  An
+/// address beyond the data segment can only come from a corrupt directory.
+pub fn readdir_cookie(address: u64) -> Result<i64, XfsError> {
+    if address < XFS_DIR2_DATA_SPACE_SIZE {
+        Ok(address as i64)
+    } else {
+        Err(XfsError::Overflow)
+    }
+}
+
+/// The inverse of [`readdir_cookie`]
+pub fn readdir_address(cookie: i64) -> Result<u64, XfsError> {
+    match u64::try_from(cookie) {
+        Ok(address) if address < XFS_DIR2_DATA_SPACE_SIZE => Ok(address),
+        _ => Err(XfsError::Invalid),
+    }
+}
+
 #[enum_dispatch::enum_dispatch]
 pub trait Dir3 {
     fn lookup<R: Reader + BufRead + Seek>(
@@ -205,7 +276,7 @@ pub trait Dir3 {
         buf_reader: &mut R,
         sb: &Sb,
         name: &OsStr,
-    ) -> Result<u64, c_int>;
+    ) -> Result<u64, XfsError>;
 
     /// Read the next dirent from a Directory
     fn next<R: Reader + BufRead + Seek>(
@@ -213,7 +284,16 @@ pub trait Dir3 {
         buf_reader: &mut R,
         sb: &Sb,
         offset: i64,
-    ) -> Result<(XfsIno, i64, Option<FileType>, OsString), c_int>;
+    ) -> Result<(XfsIno, i64, Option<FileType>, OsString), XfsError>;
+
+    /// Check the parts of a Directory that reading it doesn't depend on
+    fn verify<R: Reader + BufRead + Seek>(
+        &self,
+        _buf_reader: &mut R,
+        _sb: &Sb,
+    ) -> Result<(), XfsError> {
+        Ok(())
+    }
 }
 
 #[derive(Debug)]
@@ -223,3 +303,34 @@ pub enum Directory {
     Block(super::dir3_block::Dir2Block),
     Lf(super::dir3_lf::Dir2Lf),
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Cookies at the extreme end of the data segment are still monotonic and round-trip
+    #[test]
+    fn readdir_cookie_max() {
+        let last = XFS_DIR2_DATA_SPACE_SIZE - 16;
+        let a = readdir_cookie(last - 16).unwrap();
+        let b = readdir_cookie(last).unwrap();
+        assert!(a < b);
+        assert_eq!(readdir_address(b), Ok(last));
+    }
+
+    /// Addresses beyond the data segment can't be encoded
+    #[test]
+    fn readdir_cookie_overflow() {
+        assert_eq!(
+            readdir_cookie(XFS_DIR2_DATA_SPACE_SIZE),
+            Err(XfsError::Overflow)
+        );
+        assert_eq!(readdir_cookie(u64::MAX), Err(XfsError::Overflow));
+    }
+
+    #[test]
+    fn readdir_address_invalid() {
+        assert_eq!(readdir_address(-1), Err(XfsError::Invalid));
+        assert_eq!(readdir_address(i64::MAX), Err(XfsError::Invalid));
+    }
+}
@@ -33,14 +33,23 @@ use std::{
 
 use bincode::{de::read::Reader, Decode};
 use fuser::FileType;
-use libc::{c_int, ENOENT};
 
 use super::{
     da_btree::hashname,
     definitions::*,
-    dir3::{Dir2DataEntry, Dir2DataHdr, Dir2DataUnused, Dir2LeafEntry, Dir3, Dir3DataHdr},
+    dir3::{
+        check_block_owner,
+        Dir2DataEntry,
+        Dir2DataHdr,
+        Dir2DataUnused,
+        Dir2LeafEntry,
+        Dir3,
+        Dir3DataHdr,
+        XFS_DIR2_NULL_DATAPTR,
+    },
+    error::XfsError,
     sb::Sb,
-    utils::{decode, get_file_type, FileKind},
+    utils::{decode, get_dirent_type},
 };
 
 #[derive(Debug, Decode)]
@@ -127,11 +136,13 @@ impl Dir2Block {
         buf_reader: &mut T,
         superblock: &Sb,
         start_block: XfsFsblock,
+        ino: XfsIno,
     ) -> Dir2Block {
         let offset = superblock.fsb_to_offset(start_block);
-        let dir_blk_size = superblock.sb_blocksize << superblock.sb_dirblklog;
+        let dir_blk_size = superblock.geometry.dirblksize as u32;
 
         let dir_disk = Dir2BlockDisk::new(buf_reader.by_ref(), offset, dir_blk_size);
+        check_block_owner(&dir_disk.raw, ino, start_block);
 
         let data_len = dir_disk.get_data_len(dir_blk_size);
         assert!(data_len as usize <= dir_disk.raw.len());
@@ -151,6 +162,7 @@ impl Dir2Block {
         let j = (i..l).find(|x| self.ents[*x].hashval > hash).unwrap_or(l);
         self.ents[i..j]
             .iter()
+            .filter(|ent| ent.address != XFS_DIR2_NULL_DATAPTR)
             .map(|ent| (ent.address << 3) as usize)
     }
 }
@@ -161,7 +173,7 @@ impl Dir3 for Dir2Block {
         _buf_reader: &mut R,
         _sb: &Sb,
         name: &OsStr,
-    ) -> Result<u64, c_int> {
+    ) -> Result<u64, XfsError> {
         let hash = hashname(name);
 
         for offset in self.get_addresses(hash) {
@@ -171,7 +183,7 @@ impl Dir3 for Dir2Block {
                 return Ok(entry.inumber);
             }
         }
-        Err(libc::ENOENT)
+        Err(XfsError::NotFound)
     }
 
     /// Read the next dirent from a Directory
@@ -180,7 +192,7 @@ impl Dir3 for Dir2Block {
         _buf_reader: &mut R,
         sb: &Sb,
         offset: i64,
-    ) -> Result<(XfsIno, i64, Option<FileType>, OsString), c_int> {
+    ) -> Result<(XfsIno, i64, Option<FileType>, OsString), XfsError> {
         let mut offset: usize = offset.try_into().unwrap();
         assert!(offset < self.raw.len());
         let mut next = offset == 0;
@@ -200,15 +212,76 @@ impl Dir3 for Dir2Block {
                 next = true;
             } else {
                 let (entry, _l) = decode::<Dir2DataEntry>(&self.raw[offset..]).unwrap();
-                let kind = match entry.ftype {
-                    Some(ftype) => Some(get_file_type(FileKind::Type(ftype))?),
-                    None => None,
-                };
+                let kind = get_dirent_type(entry.ftype);
                 let name = entry.name;
                 let entry_offset = entry.tag as u64;
                 return Ok((entry.inumber, entry_offset as i64, kind, name));
             }
         }
-        Err(ENOENT)
+        Err(XfsError::NotFound)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{fs, io::Write, os::unix::fs::FileExt};
+
+    use super::*;
+    use crate::libxfuse::{
+        block_reader::BlockReader,
+        dir3_lf::tests::{mkdata, OWNER},
+        sb::tests::superblock,
+    };
+
+    /// Lookups must skip stale leaf entries, even when their hash matches, and continue on to the
+    /// live entry.  Like the leaf directory case, but the leaf entries share the data's block.
+    #[test]
+    fn lookup_stale() {
+        let sb = superblock();
+        let bs = sb.geometry.dirblksize;
+        let mut raw = mkdata(&[("a", 0), ("b", 0)]);
+        raw[0..4].copy_from_slice(&XFS_DIR3_BLOCK_MAGIC.to_be_bytes());
+        // A stale entry's null address leads to the block header.  Make its blkno field decode
+        // as an entry named "c", so following one would find a bogus match.
+        raw[8] = 1;
+        raw[9] = b'c';
+        let ha = hashname(OsStr::new("a"));
+        let hb = hashname(OsStr::new("b"));
+        let hc = hashname(OsStr::new("c"));
+        // Entry "b" immediately follows "a", which takes 16 bytes
+        let addr_a = (Dir3DataHdr::SIZE >> 3) as u32;
+        let addr_b = addr_a + 2;
+        let mut ents = vec![
+            (ha, XFS_DIR2_NULL_DATAPTR),
+            (ha, addr_a),
+            (hb, addr_b),
+            (hb, XFS_DIR2_NULL_DATAPTR),
+            (hc, XFS_DIR2_NULL_DATAPTR),
+        ];
+        ents.sort_by_key(|(hashval, _)| *hashval);
+        let tail = bs - Dir2BlockTail::SIZE;
+        let mut ofs = tail - Dir2LeafEntry::SIZE * ents.len();
+        for (hashval, address) in ents.iter() {
+            raw[ofs..ofs + 4].copy_from_slice(&hashval.to_be_bytes());
+            raw[ofs + 4..ofs + 8].copy_from_slice(&address.to_be_bytes());
+            ofs += Dir2LeafEntry::SIZE;
+        }
+        raw[tail..tail + 4].copy_from_slice(&(ents.len() as u32).to_be_bytes());
+        raw[tail + 4..].copy_from_slice(&3u32.to_be_bytes());
+
+        let mut img = tempfile::NamedTempFile::new().unwrap();
+        let f: &fs::File = img.as_file();
+        f.write_all_at(&raw, sb.fsb_to_offset(10)).unwrap();
+        img.flush().unwrap();
+        let mut reader = BlockReader::open(img.path()).unwrap();
+        reader.set_bufsize(bs);
+
+        let dir = Dir2Block::new(&mut reader, sb, 10, OWNER);
+        assert_eq!(dir.lookup(&mut reader, sb, OsStr::new("a")), Ok(200));
+        assert_eq!(dir.lookup(&mut reader, sb, OsStr::new("b")), Ok(200));
+        assert_eq!(
+            dir.lookup(&mut reader, sb, OsStr::new("c")),
+            Err(XfsError::NotFound)
+        );
     }
 }
@@ -39,7 +39,6 @@ use bincode::{
     Decode,
 };
 use fuser::FileType;
-use libc::c_int;
 use tracing::error;
 
 use super::{
@@ -47,9 +46,23 @@ use super::{
     btree::{BmbtKey, BmdrBlock, Btree, BtreeRoot, XfsBmbtPtr},
     da_btree::{hashname, XfsDa3Blkinfo, XfsDa3Intnode, XfsDaBlkinfo},
     definitions::*,
-    dir3::{Dir2DataEntry, Dir2DataHdr, Dir2DataUnused, Dir3, Dir3DataHdr, XfsDir2Dataptr},
+    dir3::{
+        check_block_owner,
+        readdir_address,
+        readdir_cookie,
+        Dir2DataEntry,
+        Dir2DataHdr,
+        Dir2DataUnused,
+        Dir3,
+        Dir3BlkHdr,
+        Dir3DataHdr,
+        XfsDir2Dataptr,
+        XFS_DIR2_DATA_SPACE_SIZE,
+        XFS_DIR2_NULL_DATAPTR,
+    },
+    error::XfsError,
     sb::Sb,
-    utils::{decode, get_file_type, FileKind},
+    utils::{decode, get_dirent_type},
     volume::SUPERBLOCK,
 };
 
@@ -64,7 +77,7 @@ enum Dfork {
 }
 
 impl Dfork {
-    fn lseek<R>(&self, buf_reader: &mut R, offset: u64, whence: i32) -> Result<u64, i32>
+    fn lseek<R>(&self, buf_reader: &mut R, offset: u64, whence: i32) -> Result<u64, XfsError>
     where
         R: BufRead + Reader + Seek,
     {
@@ -78,13 +91,13 @@ impl Dfork {
         &self,
         buf_reader: &mut R,
         dblock: XfsDablk,
-    ) -> Result<XfsFsblock, i32> {
+    ) -> Result<XfsFsblock, XfsError> {
         match self {
-            Dfork::Bmx(bmx) => bmx.map_dblock(dblock).ok_or(libc::ENOENT),
+            Dfork::Bmx(bmx) => bmx.map_dblock(dblock).ok_or(XfsError::NotFound),
             Dfork::Btree(root) => root
                 .map_block(buf_reader, dblock.into())?
                 .0
-                .ok_or(libc::ENOENT),
+                .ok_or(XfsError::NotFound),
         }
     }
 }
@@ -117,6 +130,39 @@ struct Dir2LeafNDisk {
 }
 
 impl Dir2LeafNDisk {
+    /// Size of a Dir2LeafTail structure
+    const TAIL_SIZE: usize = 4;
+
+    /// Decode a leaf block, checking that its entries fit within it.  In a single-leaf directory,
+    /// the leaf block also holds the bests array and the tail, which must not overlap the
+    /// entries.
+    fn from(raw: &[u8]) -> Result<Self, XfsError> {
+        let magic: u16 = decode(&raw[8..])?.0;
+        let (leaf, end_of_entries): (Self, usize) = decode(raw)?;
+        let bests_start = if magic == XFS_DIR2_LEAF1_MAGIC || magic == XFS_DIR3_LEAF1_MAGIC {
+            let tail = raw
+                .len()
+                .checked_sub(Self::TAIL_SIZE)
+                .ok_or_else(|| XfsError::Corrupt("Leaf block too small for its tail".into()))?;
+            let bestcount: u32 = decode(&raw[tail..])?.0;
+            (bestcount as usize)
+                .checked_mul(2)
+                .and_then(|bests_size| tail.checked_sub(bests_size))
+                .ok_or_else(|| {
+                    XfsError::Corrupt(format!("Leaf block bests array too large: {}", bestcount))
+                })?
+        } else {
+            raw.len()
+        };
+        if bests_start < end_of_entries {
+            return Err(XfsError::Corrupt(format!(
+                "Leaf block entries overlap the bests array: {} > {}",
+                end_of_entries, bests_start
+            )));
+        }
+        Ok(leaf)
+    }
+
     /// Return the range of entry indices that include the given hash
     pub fn get_address_range(&self, hash: XfsDahash) -> Range<usize> {
         let l = self.ents.len();
@@ -150,6 +196,91 @@ impl<Context> Decode<Context> for Dir2LeafNDisk {
     }
 }
 
+#[derive(Debug, Decode)]
+struct Dir2FreeHdr {
+    magic:   u32,
+    firstdb: i32,
+    nvalid:  i32,
+    _nused:  i32,
+}
+
+impl Dir2FreeHdr {
+    const SIZE: usize = 16;
+}
+
+#[derive(Debug, Decode)]
+struct Dir3FreeHdr {
+    hdr:     Dir3BlkHdr,
+    firstdb: i32,
+    nvalid:  i32,
+    _nused:  i32,
+    _pad:    u32,
+}
+
+impl Dir3FreeHdr {
+    const SIZE: usize = Dir3BlkHdr::SIZE as usize + 16;
+}
+
+/// A free index block of a Node or Btree directory.  It records the length of the longest free
+/// region in each of a contiguous range of data blocks.
+#[derive(Debug)]
+struct Dir2Free {
+    /// Directory block number of the data block described by `bests[0]`
+    firstdb: u32,
+    bests:   Vec<u16>,
+}
+
+impl Dir2Free {
+    /// Marks a data block that doesn't exist
+    const NULLDATAOFF: u16 = 0xffff;
+
+    /// The number of data blocks that each free index block describes
+    fn max_bests(raw: &[u8]) -> Result<usize, XfsError> {
+        Ok((raw.len() - Self::hdr_size(raw)?) / 2)
+    }
+
+    fn hdr_size(raw: &[u8]) -> Result<usize, XfsError> {
+        match decode::<u32>(raw)?.0 {
+            XFS_DIR2_FREE_MAGIC => Ok(Dir2FreeHdr::SIZE),
+            XFS_DIR3_FREE_MAGIC => Ok(Dir3FreeHdr::SIZE),
+            magic => Err(XfsError::Corrupt(format!(
+                "Bad magic in free index block: {:#x}",
+                magic
+            ))),
+        }
+    }
+
+    fn from(raw: &[u8]) -> Result<Self, XfsError> {
+        let hdr_size = Self::hdr_size(raw)?;
+        let (firstdb, nvalid) = if hdr_size == Dir2FreeHdr::SIZE {
+            let hdr: Dir2FreeHdr = decode(raw)?.0;
+            debug_assert_eq!(hdr.magic, XFS_DIR2_FREE_MAGIC);
+            (hdr.firstdb, hdr.nvalid)
+        } else {
+            let hdr: Dir3FreeHdr = decode(raw)?.0;
+            debug_assert_eq!(hdr.hdr.magic, XFS_DIR3_FREE_MAGIC);
+            (hdr.firstdb, hdr.nvalid)
+        };
+        let firstdb = u32::try_from(firstdb).map_err(|_| {
+            XfsError::Corrupt(format!(
+                "Free index block has negative firstdb: {}",
+                firstdb
+            ))
+        })?;
+        let nvalid = usize::try_from(nvalid)
+            .ok()
+            .filter(|n| *n <= Self::max_bests(raw).unwrap())
+            .ok_or_else(|| {
+                XfsError::Corrupt(format!("Free index block has bad nvalid: {}", nvalid))
+            })?;
+        let bests = raw[hdr_size..hdr_size + 2 * nvalid]
+            .chunks_exact(2)
+            .map(|c| u16::from_be_bytes([c[0], c[1]]))
+            .collect();
+        Ok(Dir2Free { firstdb, bests })
+    }
+}
+
 /// Stores the "leaf" info (the hash => address map) for Leaf, Node, and Btree directories.  But
 /// does not store the freeindex info.
 #[derive(Debug)]
@@ -160,7 +291,7 @@ enum Leaf {
 }
 
 impl Leaf {
-    fn open(raw: &[u8]) -> Self {
+    fn open(raw: &[u8]) -> Result<Self, XfsError> {
         let magic: u16 = decode(&raw[8..]).unwrap().0;
         let config = bincode::config::standard()
             .with_big_endian()
@@ -169,19 +300,17 @@ impl Leaf {
         let mut decoder = bincode::de::DecoderImpl::new(reader, config, ());
         match magic {
             XFS_DA_NODE_MAGIC | XFS_DA3_NODE_MAGIC => {
-                let leaf_btree = XfsDa3Intnode::decode(&mut decoder)
-                    .map_err(|_| libc::EIO)
-                    .unwrap();
+                let leaf_btree = XfsDa3Intnode::decode(&mut decoder).unwrap();
                 assert!(
                     leaf_btree.magic == XFS_DA3_NODE_MAGIC || leaf_btree.magic == XFS_DA_NODE_MAGIC
                 );
-                Self::Btree(leaf_btree)
+                Ok(Self::Btree(leaf_btree))
             }
             XFS_DIR2_LEAFN_MAGIC | XFS_DIR3_LEAFN_MAGIC => {
-                Self::LeafN(Dir2LeafNDisk::decode(&mut decoder).unwrap())
+                Ok(Self::LeafN(Dir2LeafNDisk::from(raw)?))
             }
             XFS_DIR2_LEAF1_MAGIC | XFS_DIR3_LEAF1_MAGIC => {
-                Self::LeafN(Dir2LeafNDisk::decode(&mut decoder).unwrap())
+                Ok(Self::LeafN(Dir2LeafNDisk::from(raw)?))
             }
             magic => panic!("Bad magic in Leaf block! {:#x}", magic),
         }
@@ -193,7 +322,7 @@ impl Leaf {
         sb: &Sb,
         dir: &Dir2Lf,
         hash: u32,
-    ) -> Result<Dir2LeafNDisk, i32>
+    ) -> Result<Dir2LeafNDisk, XfsError>
     where
         R: BufRead + Reader + Seek,
     {
@@ -202,10 +331,16 @@ impl Leaf {
             Leaf::Btree(btree) => {
                 let dablk: XfsDablk =
                     btree.lookup(buf_reader.by_ref(), sb, hash, |block, br| {
-                        dir.dfork.map_dblock(br, block).unwrap()
+                        dir.dfork.map_dblock(br, block).map_err(|e| match e {
+                            XfsError::NotFound => XfsError::Corrupt(format!(
+                                "Directory block {} is not mapped",
+                                block
+                            )),
+                            e => e,
+                        })
                     })?;
                 let raw = dir.read_dblock(buf_reader.by_ref(), sb, dablk)?;
-                Ok(decode(&raw).unwrap().0)
+                Dir2LeafNDisk::from(&raw)
             }
         }
     }
@@ -226,13 +361,13 @@ impl<'a, R: Reader + BufRead + Seek + 'a> NodeLikeAddressIterator<'a, R> {
         dir: &'a Dir2Lf,
         brrc: &'a RefCell<&'a mut R>,
         hash: XfsDahash,
-    ) -> Result<Self, i32> {
+    ) -> Result<Self, XfsError> {
         let sb = SUPERBLOCK.get().unwrap();
         let dblock = sb.get_dir3_leaf_offset();
         let mut buf_reader = brrc.borrow_mut();
         let leaf_btree = {
             let raw = dir.read_dblock(buf_reader.by_ref(), sb, dblock)?;
-            Leaf::open(raw.deref())
+            Leaf::open(raw.deref())?
         };
         let leaf = leaf_btree.lookup_leaf_blk(buf_reader.by_ref(), sb, dir, hash)?;
 
@@ -276,7 +411,13 @@ impl<'a, R: Reader + BufRead + Seek + 'a> Iterator for NodeLikeAddressIterator<'
                             return None;
                         }
                     };
-                    self.leaf = decode(raw.deref()).unwrap().0;
+                    self.leaf = match Dir2LeafNDisk::from(raw.deref()) {
+                        Ok(leaf) => leaf,
+                        Err(e) => {
+                            error!("Cannot decode dblock {}: {}", forw, e);
+                            return None;
+                        }
+                    };
                     self.leaf_range = self.leaf.get_address_range(self.hash);
                 } else {
                     return None;
@@ -286,7 +427,9 @@ impl<'a, R: Reader + BufRead + Seek + 'a> Iterator for NodeLikeAddressIterator<'
                 self.leaf_range.start += 1;
                 let ent = self.leaf.ents[i];
                 debug_assert_eq!(ent.hashval, self.hash);
-                return Some(ent.address << 3);
+                if ent.address != XFS_DIR2_NULL_DATAPTR {
+                    return Some(ent.address << 3);
+                }
             }
         }
     }
@@ -303,20 +446,36 @@ pub struct Dir2Lf {
 
     /// A cache of directory blocks, indexed by directory block number
     blocks: RefCell<BTreeMap<XfsDablk, Vec<u8>>>,
+
+    /// The directory's own inode number, if known, to check each block's owner against
+    ino: Option<XfsIno>,
 }
 
 impl Dir2Lf {
     pub fn from_bmx(bmx: Bmx) -> Self {
         let dfork = Dfork::Bmx(bmx);
         let blocks = Default::default();
-        Dir2Lf { dfork, blocks }
+        Dir2Lf {
+            dfork,
+            blocks,
+            ino: None,
+        }
     }
 
     pub fn from_btree(bmbt: BmdrBlock, keys: Vec<BmbtKey>, pointers: Vec<XfsBmbtPtr>) -> Self {
         let root = BtreeRoot::new(bmbt, keys, pointers);
         let dfork = Dfork::Btree(root);
         let blocks = Default::default();
-        Dir2Lf { dfork, blocks }
+        Dir2Lf {
+            dfork,
+            blocks,
+            ino: None,
+        }
+    }
+
+    /// Set the inode of this directory, so that every block read can be checked to belong to it
+    pub fn set_ino(&mut self, ino: XfsIno) {
+        self.ino = Some(ino);
     }
 
     fn get_addresses<'a, R>(
@@ -339,7 +498,7 @@ impl Dir2Lf {
         mut buf_reader: R,
         sb: &Sb,
         dblock: XfsDablk,
-    ) -> Result<impl Deref<Target = [u8]> + 'a, i32>
+    ) -> Result<impl Deref<Target = [u8]> + 'a, XfsError>
     where
         R: Reader + BufRead + Seek,
     {
@@ -356,24 +515,98 @@ impl Dir2Lf {
         Ok(Ref::map(cache_guard, |v| &v[&dblock][..]))
     }
 
-    // NB: this code could be combined with File::read_sectors.  However, the latter must contend
-    // with much larger extents, and with reads of partial sectors.
+    /// Check that the free index agrees with the data blocks' own record of their longest free
+    /// region.  Only node directories have a free index.
+    fn verify_free<R>(&self, buf_reader: &mut R, sb: &Sb) -> Result<(), XfsError>
+    where
+        R: Reader + BufRead + Seek,
+    {
+        let free_start = u64::from(sb.get_dir3_free_offset()) << sb.sb_blocklog;
+        let dirblksize = sb.geometry.dirblksize as u64;
+        let fsbs_per_dirblk = sb.geometry.dirfsbmask + 1;
+        let mut offset = free_start;
+        loop {
+            offset = match self
+                .dfork
+                .lseek(buf_reader.by_ref(), offset, libc::SEEK_DATA)
+            {
+                Ok(o) => o,
+                Err(XfsError::NoData) => return Ok(()),
+                Err(e) => return Err(e),
+            };
+            let dblock = XfsDablk::try_from(offset >> sb.sb_blocklog)
+                .map_err(|_| XfsError::Corrupt("Free index block is too far".into()))?;
+            let (free, max_bests) = {
+                let raw = self.read_dblock(buf_reader.by_ref(), sb, dblock)?;
+                (Dir2Free::from(&raw)?, Dir2Free::max_bests(&raw)?)
+            };
+            let findex = (offset - free_start) / dirblksize;
+            if u64::from(free.firstdb) != findex * max_bests as u64 {
+                return Err(XfsError::Corrupt(format!(
+                    "Free index block {} has firstdb {}",
+                    findex, free.firstdb
+                )));
+            }
+            for (i, best) in free.bests.iter().enumerate() {
+                let db = u64::from(free.firstdb) + i as u64;
+                let data_dblock = XfsDablk::try_from(db * fsbs_per_dirblk)
+                    .ok()
+                    .filter(|d| u64::from(*d) < u64::from(sb.get_dir3_leaf_offset()))
+                    .ok_or_else(|| {
+                        XfsError::Corrupt(format!("Free index describes data block {}", db))
+                    })?;
+                if *best == Dir2Free::NULLDATAOFF {
+                    continue;
+                }
+                let raw = self
+                    .read_dblock(buf_reader.by_ref(), sb, data_dblock)
+                    .map_err(|e| match e {
+                        XfsError::NotFound => XfsError::Corrupt(format!(
+                            "Free index describes unmapped data block {}",
+                            db
+                        )),
+                        e => e,
+                    })?;
+                let longest = match decode::<u32>(&raw)?.0 {
+                    XFS_DIR2_DATA_MAGIC => decode::<Dir2DataHdr>(&raw)?.0.longest_free(),
+                    XFS_DIR3_DATA_MAGIC => decode::<Dir3DataHdr>(&raw)?.0.longest_free(),
+                    magic => {
+                        return Err(XfsError::Corrupt(format!(
+                            "Bad magic in directory data block {}: {:#x}",
+                            db, magic
+                        )))
+                    }
+                };
+                if longest != *best {
+                    return Err(XfsError::Corrupt(format!(
+                        "Free index records {} free bytes in data block {}, but it has {}",
+                        best, db, longest
+                    )));
+                }
+            }
+            offset += dirblksize;
+        }
+    }
+
+    // NB: this code could be combined with File::read_into.  However, the latter must contend
+    // with much larger extents, and with reads of partial blocks.
     fn read_fsblock<R>(
         &self,
         mut buf_reader: R,
         sb: &Sb,
         fsblock: XfsFsblock,
-    ) -> Result<Vec<u8>, i32>
+    ) -> Result<Vec<u8>, XfsError>
     where
         R: Reader + BufRead + Seek,
     {
-        let dblksize: usize = 1 << (sb.sb_blocklog + sb.sb_dirblklog);
-
-        let mut buf = vec![0; dblksize];
+        let mut buf = vec![0; sb.geometry.dirblksize];
         buf_reader
             .seek(SeekFrom::Start(sb.fsb_to_offset(fsblock)))
             .unwrap();
         buf_reader.read_exact(&mut buf).unwrap();
+        if let Some(ino) = self.ino {
+            check_block_owner(&buf, ino, fsblock);
+        }
         Ok(buf)
     }
 }
@@ -384,14 +617,13 @@ impl Dir3 for Dir2Lf {
         buf_reader: &mut R,
         sb: &Sb,
         name: &OsStr,
-    ) -> Result<u64, c_int> {
+    ) -> Result<u64, XfsError> {
         let hash = hashname(name);
 
         let brrc = RefCell::new(buf_reader);
         for address in self.get_addresses(&brrc, hash) {
-            let blk_offset =
-                (address & ((1u32 << (sb.sb_dirblklog + sb.sb_blocklog)) - 1)) as usize;
-            let dblock = (address >> sb.sb_blocklog) & !((1u32 << sb.sb_dirblklog) - 1);
+            let blk_offset = (u64::from(address) & sb.geometry.dirblkmask) as usize;
+            let dblock = (address >> sb.sb_blocklog) & !(sb.geometry.dirfsbmask as u32);
             let mut guard = brrc.borrow_mut();
             let raw = self.read_dblock(guard.by_ref(), sb, dblock)?;
             let entry: Dir2DataEntry = decode(&raw[blk_offset..]).unwrap().0;
@@ -399,7 +631,7 @@ impl Dir3 for Dir2Lf {
                 return Ok(entry.inumber);
             }
         }
-        Err(libc::ENOENT)
+        Err(XfsError::NotFound)
     }
 
     fn next<R: Reader + BufRead + Seek>(
@@ -407,10 +639,9 @@ impl Dir3 for Dir2Lf {
         buf_reader: &mut R,
         sb: &Sb,
         offset: i64,
-    ) -> Result<(XfsIno, i64, Option<FileType>, OsString), c_int> {
-        let dblksize: u64 = 1 << (sb.sb_blocklog + sb.sb_dirblklog);
-        let dblkmask: u64 = dblksize - 1;
-        let mut offset: u64 = offset.try_into().unwrap();
+    ) -> Result<(XfsIno, i64, Option<FileType>, OsString), XfsError> {
+        let dblkmask: u64 = sb.geometry.dirblkmask;
+        let mut offset = readdir_address(offset)?;
         let mut next = offset == 0;
 
         loop {
@@ -418,18 +649,21 @@ impl Dir3 for Dir2Lf {
             let newoffset = self
                 .dfork
                 .lseek(buf_reader.by_ref(), offset, libc::SEEK_DATA)
-                .map_err(|e| if e == libc::ENXIO { libc::ENOENT } else { e })?;
-            if newoffset >= u64::from(sb.get_dir3_leaf_offset()) << sb.sb_blocklog {
-                return Err(libc::ENOENT);
+                .map_err(|e| match e {
+                    XfsError::NoData => XfsError::NotFound,
+                    e => e,
+                })?;
+            if newoffset >= XFS_DIR2_DATA_SPACE_SIZE {
+                return Err(XfsError::NotFound);
             }
             offset = newoffset;
 
             // Byte offset within this directory block
-            let dir_block_offset = offset & ((1 << (sb.sb_dirblklog + sb.sb_blocklog)) - 1);
+            let dir_block_offset = offset & dblkmask;
             // Offset of this directory block within the directory
             let doffset = offset - dir_block_offset;
 
-            let dblock = ((offset >> sb.sb_blocklog) & !((1u64 << sb.sb_dirblklog) - 1))
+            let dblock = ((offset >> sb.sb_blocklog) & !sb.geometry.dirfsbmask)
                 .try_into()
                 .unwrap();
             let raw = self.read_dblock(buf_reader.by_ref(), sb, dblock)?;
@@ -444,6 +678,8 @@ impl Dir3 for Dir2Lf {
                     _ => panic!("Unknown magic number for block directory {:#x}", magic),
                 }
             };
+            // Keep offset in step with blk_offset, in case this block contains no more entries.
+            offset = doffset + blk_offset as u64;
             while blk_offset < raw.len() {
                 let freetag: u16 = decode(&raw[blk_offset..]).unwrap().0;
                 if freetag == 0xffff {
@@ -457,15 +693,549 @@ impl Dir3 for Dir2Lf {
                     next = true;
                 } else {
                     let (entry, _l) = decode::<Dir2DataEntry>(&raw[blk_offset..]).unwrap();
-                    let kind = match entry.ftype {
-                        Some(ftype) => Some(get_file_type(FileKind::Type(ftype))?),
-                        None => None,
-                    };
+                    let kind = get_dirent_type(entry.ftype);
                     let name = entry.name;
                     let entry_offset = doffset + entry.tag as u64;
-                    return Ok((entry.inumber, entry_offset as i64, kind, name));
+                    return Ok((entry.inumber, readdir_cookie(entry_offset)?, kind, name));
                 }
             }
         }
     }
+
+    fn verify<R: Reader + BufRead + Seek>(
+        &self,
+        buf_reader: &mut R,
+        sb: &Sb,
+    ) -> Result<(), XfsError> {
+        self.verify_free(buf_reader, sb)
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use std::{fs, io::Write, os::unix::fs::FileExt};
+
+    use rstest::rstest;
+
+    use super::*;
+    use crate::libxfuse::{
+        block_reader::BlockReader,
+        bmbt_rec::BmbtRec,
+        dir3::XFS_DIR3_FT_REG_FILE,
+        sb::tests::superblock,
+    };
+
+    /// The inode that owns the blocks built by [`mkdata`] and [`mkleaf1`]
+    pub(crate) const OWNER: XfsIno = 128;
+
+    /// Build a V5 directory data block.  Each region is either a named entry, or a free region of
+    /// the given length if the name is empty.  Any remaining space is free.
+    pub(crate) fn mkdata(regions: &[(&str, usize)]) -> Vec<u8> {
+        let bs = superblock().geometry.dirblksize;
+        let mut raw = vec![0u8; bs];
+        raw[0..4].copy_from_slice(&XFS_DIR3_DATA_MAGIC.to_be_bytes());
+        raw[40..48].copy_from_slice(&OWNER.to_be_bytes());
+        let mut ofs = Dir3DataHdr::SIZE as usize;
+        let free = |raw: &mut [u8], ofs: usize, len: usize| {
+            raw[ofs..ofs + 2].copy_from_slice(&0xffffu16.to_be_bytes());
+            raw[ofs + 2..ofs + 4].copy_from_slice(&(len as u16).to_be_bytes());
+            raw[ofs + len - 2..ofs + len].copy_from_slice(&(ofs as u16).to_be_bytes());
+        };
+        for (name, len) in regions {
+            if name.is_empty() {
+                free(&mut raw, ofs, *len);
+                ofs += len;
+            } else {
+                let len = (name.len() + 19) / 8 * 8;
+                raw[ofs..ofs + 8].copy_from_slice(&200u64.to_be_bytes());
+                raw[ofs + 8] = name.len() as u8;
+                raw[ofs + 9..ofs + 9 + name.len()].copy_from_slice(name.as_bytes());
+                raw[ofs + 9 + name.len()] = XFS_DIR3_FT_REG_FILE;
+                raw[ofs + len - 2..ofs + len].copy_from_slice(&(ofs as u16).to_be_bytes());
+                ofs += len;
+            }
+        }
+        if ofs < bs {
+            free(&mut raw, ofs, bs - ofs);
+        }
+        raw
+    }
+
+    /// Data blocks may begin with free space, immediately following the header.  A block may
+    /// even be entirely free.
+    #[test]
+    fn readdir_leading_free_space() {
+        let sb = superblock();
+        let bs = sb.geometry.dirblksize;
+        let blocks = [
+            mkdata(&[("", 64), ("a", 0)]),
+            mkdata(&[("", 16), ("b", 0), ("", 32), ("c", 0)]),
+            mkdata(&[]),
+            mkdata(&[("d", 0)]),
+        ];
+
+        let mut img = tempfile::NamedTempFile::new().unwrap();
+        let f: &fs::File = img.as_file();
+        for (i, block) in blocks.iter().enumerate() {
+            f.write_all_at(block, sb.fsb_to_offset(10 + i as u64))
+                .unwrap();
+        }
+        img.flush().unwrap();
+        let mut reader = BlockReader::open(img.path()).unwrap();
+        reader.set_bufsize(bs);
+
+        let dir = Dir2Lf::from_bmx(Bmx::new(&[BmbtRec {
+            br_startoff:   0,
+            br_startblock: 10,
+            br_blockcount: blocks.len() as u64,
+            br_flag:       false,
+        }]));
+        let mut names = Vec::new();
+        let mut offset = 0;
+        loop {
+            match dir.next(&mut reader, sb, offset) {
+                Ok((ino, cookie, kind, name)) => {
+                    assert_eq!(ino, 200);
+                    assert_eq!(kind, Some(FileType::RegularFile));
+                    names.push(name);
+                    offset = cookie;
+                }
+                Err(e) => {
+                    assert_eq!(e, XfsError::NotFound);
+                    break;
+                }
+            }
+        }
+        assert_eq!(names, ["a", "b", "c", "d"]);
+    }
+
+    /// Nothing requires a directory's first data block to be at logical offset 0.  Readdir must
+    /// find the first block wherever it is, and resume from any cookie it returned.
+    #[test]
+    fn readdir_nonzero_first_dblock() {
+        let sb = superblock();
+        let bs = sb.geometry.dirblksize;
+        let blocks = [mkdata(&[("a", 0), ("b", 0)]), mkdata(&[("c", 0)])];
+
+        let mut img = tempfile::NamedTempFile::new().unwrap();
+        let f: &fs::File = img.as_file();
+        for (i, block) in blocks.iter().enumerate() {
+            f.write_all_at(block, sb.fsb_to_offset(10 + i as u64))
+                .unwrap();
+        }
+        img.flush().unwrap();
+        let mut reader = BlockReader::open(img.path()).unwrap();
+        reader.set_bufsize(bs);
+
+        let dir = Dir2Lf::from_bmx(Bmx::new(&[
+            BmbtRec {
+                br_startoff:   4,
+                br_startblock: 10,
+                br_blockcount: 1,
+                br_flag:       false,
+            },
+            BmbtRec {
+                br_startoff:   7,
+                br_startblock: 11,
+                br_blockcount: 1,
+                br_flag:       false,
+            },
+        ]));
+        let mut ents = Vec::new();
+        let mut offset = 0;
+        loop {
+            match dir.next(&mut reader, sb, offset) {
+                Ok((_ino, cookie, _kind, name)) => {
+                    ents.push((cookie, name));
+                    offset = cookie;
+                }
+                Err(e) => {
+                    assert_eq!(e, XfsError::NotFound);
+                    break;
+                }
+            }
+        }
+        let names = ents.iter().map(|(_, n)| n.clone()).collect::<Vec<_>>();
+        assert_eq!(names, ["a", "b", "c"]);
+
+        // Resuming from each cookie returns exactly the remaining entries
+        for (i, (cookie, _)) in ents.iter().enumerate() {
+            let r = dir.next(&mut reader, sb, *cookie);
+            match ents.get(i + 1) {
+                Some((_, name)) => assert_eq!(&r.unwrap().3, name),
+                None => assert_eq!(r.unwrap_err(), XfsError::NotFound),
+            }
+        }
+    }
+
+    /// Entries never straddle data blocks, but readdir must still move from the last entry of one
+    /// block to the first of the next, whether or not the block ends in free space.  Here the
+    /// first block is exactly filled by entries, the second ends in free space, and the third
+    /// follows a hole.  Read one entry per call, as the kernel might with a tiny buffer.
+    #[test]
+    fn readdir_block_boundaries() {
+        let sb = superblock();
+        let bs = sb.geometry.dirblksize;
+        // 5-byte names make 24-byte entries, which exactly fill the space after the header.
+        let per_block = (bs - Dir3DataHdr::SIZE as usize) / 24;
+        let names = (0..per_block + 3)
+            .map(|i| format!("{:05}", i))
+            .collect::<Vec<_>>();
+        let regions = names.iter().map(|n| (n.as_str(), 0)).collect::<Vec<_>>();
+        let blocks = [
+            mkdata(&regions[..per_block]),
+            mkdata(&regions[per_block..per_block + 2]),
+            mkdata(&regions[per_block + 2..]),
+        ];
+
+        let mut img = tempfile::NamedTempFile::new().unwrap();
+        let f: &fs::File = img.as_file();
+        for (i, block) in blocks.iter().enumerate() {
+            f.write_all_at(block, sb.fsb_to_offset(10 + i as u64))
+                .unwrap();
+        }
+        img.flush().unwrap();
+        let mut reader = BlockReader::open(img.path()).unwrap();
+        reader.set_bufsize(bs);
+
+        let dir = Dir2Lf::from_bmx(Bmx::new(&[
+            BmbtRec {
+                br_startoff:   0,
+                br_startblock: 10,
+                br_blockcount: 2,
+                br_flag:       false,
+            },
+            BmbtRec {
+                br_startoff:   5,
+                br_startblock: 12,
+                br_blockcount: 1,
+                br_flag:       false,
+            },
+        ]));
+        let mut actual = Vec::new();
+        let mut offset = 0;
+        loop {
+            match dir.next(&mut reader, sb, offset) {
+                Ok((_ino, cookie, _kind, name)) => {
+                    assert!(cookie > offset, "cookie {} did not advance", cookie);
+                    actual.push(name.into_string().unwrap());
+                    offset = cookie;
+                }
+                Err(e) => {
+                    assert_eq!(e, XfsError::NotFound);
+                    break;
+                }
+            }
+        }
+        assert_eq!(actual, names);
+    }
+
+    /// Build a V5 single-leaf directory's leaf block
+    pub(crate) fn mkleaf1(count: u16, bestcount: u32) -> Vec<u8> {
+        let bs = superblock().geometry.dirblksize;
+        let mut raw = vec![0u8; bs];
+        raw[8..10].copy_from_slice(&XFS_DIR3_LEAF1_MAGIC.to_be_bytes());
+        raw[48..56].copy_from_slice(&OWNER.to_be_bytes());
+        raw[56..58].copy_from_slice(&count.to_be_bytes());
+        for i in 0..usize::from(count) {
+            let ofs = 64 + 8 * i;
+            raw[ofs..ofs + 4].copy_from_slice(&(i as u32).to_be_bytes());
+        }
+        raw[bs - 4..].copy_from_slice(&bestcount.to_be_bytes());
+        raw
+    }
+
+    /// Build a V5 single-leaf directory's leaf block with the given (hashval, address) entries,
+    /// in the given order
+    pub(crate) fn mkleaf1_with(ents: &[(XfsDahash, u32)], bestcount: u32) -> Vec<u8> {
+        let mut raw = mkleaf1(ents.len() as u16, bestcount);
+        for (i, (hashval, address)) in ents.iter().enumerate() {
+            let ofs = 64 + 8 * i;
+            raw[ofs..ofs + 4].copy_from_slice(&hashval.to_be_bytes());
+            raw[ofs + 4..ofs + 8].copy_from_slice(&address.to_be_bytes());
+        }
+        raw
+    }
+
+    /// Build a V5 single-leaf directory's leaf block indexing each name at the given byte offset
+    /// within the directory's data space, sorted by hash as lookups require
+    pub(crate) fn mkleafdir(names: &[(&str, u64)], bestcount: u32) -> Vec<u8> {
+        let mut ents = names
+            .iter()
+            .map(|(name, offset)| (hashname(OsStr::new(name)), (offset >> 3) as u32))
+            .collect::<Vec<_>>();
+        ents.sort();
+        mkleaf1_with(&ents, bestcount)
+    }
+
+    #[test]
+    fn leaf1() {
+        let leaf = Dir2LeafNDisk::from(&mkleaf1(10, 1)).unwrap();
+        assert_eq!(leaf.ents.len(), 10);
+        assert_eq!(leaf.ents[9].hashval, 9);
+    }
+
+    /// A leaf whose entries fill the block exactly up to the bests array
+    #[test]
+    fn leaf1_full() {
+        // (4096 - 64 header - 4 tail - 2 * 2 bests) / 8
+        let leaf = Dir2LeafNDisk::from(&mkleaf1(503, 2)).unwrap();
+        assert_eq!(leaf.ents.len(), 503);
+    }
+
+    /// The entries must not overlap the bests array
+    #[test]
+    fn leaf1_overlapping_bests() {
+        assert!(matches!(
+            Dir2LeafNDisk::from(&mkleaf1(10, 2000)),
+            Err(XfsError::Corrupt(_))
+        ));
+        assert!(matches!(
+            Dir2LeafNDisk::from(&mkleaf1(504, 2)),
+            Err(XfsError::Corrupt(_))
+        ));
+    }
+
+    /// Lookups must skip stale leaf entries, even when their hash matches, and continue on to the
+    /// live entry.  Directory block 0 is a hole, so following a stale entry's null address would
+    /// fail.
+    #[test]
+    fn lookup_stale() {
+        let sb = superblock();
+        let bs = sb.geometry.dirblksize;
+        let data = mkdata(&[("a", 0), ("b", 0)]);
+        let ha = hashname(OsStr::new("a"));
+        let hb = hashname(OsStr::new("b"));
+        assert!(ha < hb);
+        // Entry "b" immediately follows "a", which takes 16 bytes
+        let addr_a = ((bs as u64 + Dir3DataHdr::SIZE) >> 3) as u32;
+        let addr_b = addr_a + 2;
+        let ents = [
+            (ha, XFS_DIR2_NULL_DATAPTR),
+            (ha, addr_a),
+            (hb, addr_b),
+            (hb, XFS_DIR2_NULL_DATAPTR),
+        ];
+        let leaf = mkleaf1_with(&ents, 2);
+
+        let mut img = tempfile::NamedTempFile::new().unwrap();
+        let f: &fs::File = img.as_file();
+        f.write_all_at(&data, sb.fsb_to_offset(10)).unwrap();
+        f.write_all_at(&leaf, sb.fsb_to_offset(20)).unwrap();
+        img.flush().unwrap();
+        let mut reader = BlockReader::open(img.path()).unwrap();
+        reader.set_bufsize(bs);
+
+        let rec = |br_startoff, br_startblock| BmbtRec {
+            br_startoff,
+            br_startblock,
+            br_blockcount: 1,
+            br_flag: false,
+        };
+        let dir = Dir2Lf::from_bmx(Bmx::new(&[
+            rec(1, 10),
+            rec(sb.get_dir3_leaf_offset().into(), 20),
+        ]));
+        assert_eq!(dir.lookup(&mut reader, sb, OsStr::new("a")), Ok(200));
+        assert_eq!(dir.lookup(&mut reader, sb, OsStr::new("b")), Ok(200));
+    }
+
+    /// A leaf directory may shrink to a single entry without being converted back to block
+    /// format.  Its leaf then has only the entries for ".", "..", and that one name; or, in the
+    /// degenerate case, just the one name.  Lookups must find it and nothing else, whether the
+    /// missing name's hash sorts before or after it, and readdir must return all three entries.
+    #[rstest]
+    #[case::with_dots(&[".", "..", "only"])]
+    #[case::alone(&["only"])]
+    fn single_entry(#[case] indexed: &[&str]) {
+        let sb = superblock();
+        let bs = sb.geometry.dirblksize;
+        let data = mkdata(&[(".", 0), ("..", 0), ("only", 0)]);
+        // Each of these names takes 16 bytes
+        let names = [".", "..", "only"]
+            .iter()
+            .enumerate()
+            .filter(|(_, name)| indexed.contains(name))
+            .map(|(i, name)| (*name, Dir3DataHdr::SIZE + 16 * i as u64))
+            .collect::<Vec<_>>();
+        let leaf = mkleafdir(&names, 1);
+
+        let mut img = tempfile::NamedTempFile::new().unwrap();
+        let f: &fs::File = img.as_file();
+        f.write_all_at(&data, sb.fsb_to_offset(10)).unwrap();
+        f.write_all_at(&leaf, sb.fsb_to_offset(20)).unwrap();
+        img.flush().unwrap();
+        let mut reader = BlockReader::open(img.path()).unwrap();
+        reader.set_bufsize(bs);
+
+        let rec = |br_startoff, br_startblock| BmbtRec {
+            br_startoff,
+            br_startblock,
+            br_blockcount: 1,
+            br_flag: false,
+        };
+        let dir = Dir2Lf::from_bmx(Bmx::new(&[
+            rec(0, 10),
+            rec(sb.get_dir3_leaf_offset().into(), 20),
+        ]));
+        assert_eq!(dir.lookup(&mut reader, sb, OsStr::new("only")), Ok(200));
+        // Short names hash low, and long ones high
+        let (below, above) = ("a", "abcdefgh");
+        assert!(hashname(OsStr::new(below)) < hashname(OsStr::new("only")));
+        assert!(hashname(OsStr::new(above)) > hashname(OsStr::new("only")));
+        for name in [below, above] {
+            assert_eq!(
+                dir.lookup(&mut reader, sb, OsStr::new(name)),
+                Err(XfsError::NotFound)
+            );
+        }
+
+        let mut names = Vec::new();
+        let mut offset = 0;
+        loop {
+            match dir.next(&mut reader, sb, offset) {
+                Ok((_ino, cookie, _kind, name)) => {
+                    names.push(name);
+                    offset = cookie;
+                }
+                Err(e) => {
+                    assert_eq!(e, XfsError::NotFound);
+                    break;
+                }
+            }
+        }
+        assert_eq!(names, [".", "..", "only"]);
+    }
+
+    /// Without the ASCII case-insensitive feature, names that differ only in case are distinct
+    /// entries.  Each must lead to its own inode, and other spellings to neither.
+    #[test]
+    fn lookup_case_sensitive() {
+        let sb = superblock();
+        let bs = sb.geometry.dirblksize;
+        let names = ["Foo", "foo"];
+        let mut data = mkdata(&[(names[0], 0), (names[1], 0)]);
+        // Each of these names takes 16 bytes.  Give each its own inode.
+        let ofs = |i: usize| Dir3DataHdr::SIZE as usize + 16 * i;
+        data[ofs(0)..ofs(0) + 8].copy_from_slice(&300u64.to_be_bytes());
+        data[ofs(1)..ofs(1) + 8].copy_from_slice(&301u64.to_be_bytes());
+        let leaf = mkleafdir(&[(names[0], ofs(0) as u64), (names[1], ofs(1) as u64)], 1);
+
+        let mut img = tempfile::NamedTempFile::new().unwrap();
+        let f: &fs::File = img.as_file();
+        f.write_all_at(&data, sb.fsb_to_offset(10)).unwrap();
+        f.write_all_at(&leaf, sb.fsb_to_offset(20)).unwrap();
+        img.flush().unwrap();
+        let mut reader = BlockReader::open(img.path()).unwrap();
+        reader.set_bufsize(bs);
+
+        let rec = |br_startoff, br_startblock| BmbtRec {
+            br_startoff,
+            br_startblock,
+            br_blockcount: 1,
+            br_flag: false,
+        };
+        let dir = Dir2Lf::from_bmx(Bmx::new(&[
+            rec(0, 10),
+            rec(sb.get_dir3_leaf_offset().into(), 20),
+        ]));
+        assert_eq!(dir.lookup(&mut reader, sb, OsStr::new("Foo")), Ok(300));
+        assert_eq!(dir.lookup(&mut reader, sb, OsStr::new("foo")), Ok(301));
+        for name in ["FOO", "fOO"] {
+            assert_eq!(
+                dir.lookup(&mut reader, sb, OsStr::new(name)),
+                Err(XfsError::NotFound)
+            );
+        }
+    }
+
+    /// A bests array larger than the block
+    #[test]
+    fn leaf1_huge_bestcount() {
+        assert!(matches!(
+            Dir2LeafNDisk::from(&mkleaf1(1, u32::MAX)),
+            Err(XfsError::Corrupt(_))
+        ));
+    }
+
+    /// Build a V5 free index block
+    fn mkfree(firstdb: i32, nvalid: i32, bests: &[u16]) -> Vec<u8> {
+        let bs = superblock().geometry.dirblksize;
+        let mut raw = vec![0u8; bs];
+        raw[0..4].copy_from_slice(&XFS_DIR3_FREE_MAGIC.to_be_bytes());
+        raw[48..52].copy_from_slice(&firstdb.to_be_bytes());
+        raw[52..56].copy_from_slice(&nvalid.to_be_bytes());
+        for (i, best) in bests.iter().enumerate() {
+            raw[64 + 2 * i..66 + 2 * i].copy_from_slice(&best.to_be_bytes());
+        }
+        raw
+    }
+
+    #[test]
+    fn free() {
+        let free = Dir2Free::from(&mkfree(0, 3, &[16, 0xffff, 4000])).unwrap();
+        assert_eq!(free.firstdb, 0);
+        assert_eq!(free.bests, [16, 0xffff, 4000]);
+        // (4096 - 64 header) / 2
+        assert_eq!(Dir2Free::max_bests(&mkfree(0, 0, &[])), Ok(2016));
+    }
+
+    #[rstest]
+    #[case::negative_firstdb(-1, 1)]
+    #[case::negative_nvalid(0, -1)]
+    #[case::nvalid_overflows_block(0, 2017)]
+    fn free_corrupt(#[case] firstdb: i32, #[case] nvalid: i32) {
+        assert!(matches!(
+            Dir2Free::from(&mkfree(firstdb, nvalid, &[])),
+            Err(XfsError::Corrupt(_))
+        ));
+    }
+
+    #[test]
+    fn free_bad_magic() {
+        let mut raw = mkfree(0, 0, &[]);
+        raw[0..4].copy_from_slice(&XFS_DIR3_DATA_MAGIC.to_be_bytes());
+        assert!(matches!(Dir2Free::from(&raw), Err(XfsError::Corrupt(_))));
+    }
+
+    /// The free index must agree with each data block's longest free region.  Data block 1 is a
+    /// hole.
+    #[rstest]
+    #[case::consistent(&[64, 0xffff, 16], true)]
+    #[case::wrong_best(&[64, 0xffff, 32], false)]
+    #[case::missing_data_block(&[64, 8, 16], false)]
+    fn verify_free(#[case] bests: &[u16], #[case] ok: bool) {
+        let sb = superblock();
+        let bs = sb.geometry.dirblksize;
+        let mut blocks = [
+            mkdata(&[("", 64), ("a", 0)]),
+            mkdata(&[("b", 0), ("", 16), ("c", 0)]),
+        ];
+        // mkdata doesn't fill in the header's best_free array
+        blocks[0][50..52].copy_from_slice(&64u16.to_be_bytes());
+        blocks[1][50..52].copy_from_slice(&16u16.to_be_bytes());
+
+        let mut img = tempfile::NamedTempFile::new().unwrap();
+        let f: &fs::File = img.as_file();
+        f.write_all_at(&blocks[0], sb.fsb_to_offset(10)).unwrap();
+        f.write_all_at(&blocks[1], sb.fsb_to_offset(12)).unwrap();
+        f.write_all_at(&mkfree(0, bests.len() as i32, bests), sb.fsb_to_offset(20))
+            .unwrap();
+        img.flush().unwrap();
+        let mut reader = BlockReader::open(img.path()).unwrap();
+        reader.set_bufsize(bs);
+
+        let rec = |br_startoff, br_startblock| BmbtRec {
+            br_startoff,
+            br_startblock,
+            br_blockcount: 1,
+            br_flag: false,
+        };
+        let dir = Dir2Lf::from_bmx(Bmx::new(&[
+            rec(0, 10),
+            rec(2, 12),
+            rec(sb.get_dir3_free_offset().into(), 20),
+        ]));
+        assert_eq!(dir.verify_free(&mut reader, sb).is_ok(), ok);
+    }
 }
@@ -37,13 +37,13 @@ use bincode::{
     Decode,
 };
 use fuser::FileType;
-use libc::{c_int, ENOENT};
 
 use super::{
     definitions::*,
     dir3::{Dir3, XFS_DIR3_FT_DIR},
+    error::XfsError,
     sb::Sb,
-    utils::{get_file_type, FileKind},
+    utils::get_dirent_type,
     volume::SUPERBLOCK,
 };
 
@@ -202,7 +202,7 @@ impl Dir3 for Dir2Sf {
         _buf_reader: &mut R,
         _super_block: &Sb,
         name: &OsStr,
-    ) -> Result<u64, c_int> {
+    ) -> Result<u64, XfsError> {
         let mut inode: Option<XfsIno> = None;
 
         for entry in self.list.iter() {
@@ -214,7 +214,7 @@ impl Dir3 for Dir2Sf {
         if let Some(ino) = inode {
             Ok(ino)
         } else {
-            Err(ENOENT)
+            Err(XfsError::NotFound)
         }
     }
 
@@ -223,7 +223,7 @@ impl Dir3 for Dir2Sf {
         _buf_reader: &mut R,
         _super_block: &Sb,
         offset: i64,
-    ) -> Result<(XfsIno, i64, Option<FileType>, OsString), c_int> {
+    ) -> Result<(XfsIno, i64, Option<FileType>, OsString), XfsError> {
         for entry in self.list.iter() {
             if i64::from(entry.offset) <= offset {
                 continue;
@@ -231,16 +231,120 @@ impl Dir3 for Dir2Sf {
 
             let ino = entry.inumber;
 
-            let kind = match entry.ftype {
-                Some(ftype) => Some(get_file_type(FileKind::Type(ftype))?),
-                None => None,
-            };
+            let kind = get_dirent_type(entry.ftype);
 
             let name = entry.name.to_owned();
 
             return Ok((ino, entry.offset as i64, kind, name));
         }
 
-        Err(-1)
+        Err(XfsError::NotFound)
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use rstest::rstest;
+
+    use super::*;
+    use crate::libxfuse::{block_reader::BlockReader, dir3::XFS_DIR3_FT_REG_FILE, utils};
+
+    /// Build a shortform directory with the given parent and (name, offset, inode) entries.
+    /// If `i8` is set, inode numbers are stored in 8 bytes; otherwise 4.
+    pub(crate) fn mkdir(i8: bool, parent: XfsIno, entries: &[(&[u8], u16, XfsIno)]) -> Vec<u8> {
+        // Like the kernel, count the parent along with the entries
+        let i8count = std::iter::once(parent)
+            .chain(entries.iter().map(|e| e.2))
+            .filter(|ino| *ino > u64::from(u32::MAX))
+            .count() as u8;
+        assert_eq!(i8, i8count > 0);
+        let mut raw = vec![entries.len() as u8, i8count];
+        if i8 {
+            raw.extend_from_slice(&parent.to_be_bytes());
+        } else {
+            raw.extend_from_slice(&(parent as u32).to_be_bytes());
+        }
+        for (name, offset, ino) in entries {
+            raw.push(name.len() as u8);
+            raw.extend_from_slice(&offset.to_be_bytes());
+            raw.extend_from_slice(name);
+            raw.push(XFS_DIR3_FT_REG_FILE);
+            if i8 {
+                raw.extend_from_slice(&ino.to_be_bytes());
+            } else {
+                raw.extend_from_slice(&(*ino as u32).to_be_bytes());
+            }
+        }
+        raw
+    }
+
+    /// Decode a shortform directory, with both 4-byte and 8-byte inode numbers.  When any entry
+    /// needs 8 bytes, i8count is nonzero and every inode number, including the parent's, uses 8.
+    #[rstest]
+    #[case::i4(false, 128, 0xffff_fffe)]
+    #[case::i8(true, 128, 0x1_0000_0083)]
+    #[case::i8_parent(true, 0x2_0000_0080, 132)]
+    fn decode(#[case] i8: bool, #[case] parent: XfsIno, #[case] ino: XfsIno) {
+        let entries: [(&[u8], u16, XfsIno); 2] = [(b"a", 0x60, 131), (b"bcd", 0x70, ino)];
+        let sb = crate::libxfuse::sb::tests::superblock();
+        assert!(sb.has_ftype());
+        let raw = mkdir(i8, parent, &entries);
+        let (hdr, _) = utils::decode::<Dir2SfHdr>(&raw[..]).unwrap();
+        assert_eq!(hdr.count, 2);
+        assert_eq!(hdr.i8count > 0, i8);
+        assert_eq!(hdr.parent, parent);
+
+        let (mut dir, len) = utils::decode::<Dir2Sf>(&raw[..]).unwrap();
+        assert_eq!(len, raw.len());
+        dir.set_ino(100);
+
+        let img = tempfile::NamedTempFile::new().unwrap();
+        let mut reader = BlockReader::open(img.path()).unwrap();
+        assert_eq!(dir.lookup(&mut reader, sb, OsStr::new("..")), Ok(parent));
+        let mut offset = 0;
+        let mut found = Vec::new();
+        while let Ok((ino, next, kind, name)) = dir.next(&mut reader, sb, offset) {
+            found.push((name, ino, kind));
+            offset = next;
+        }
+        assert_eq!(found.len(), 4);
+        assert_eq!(
+            found[0],
+            (OsString::from("."), 100, Some(FileType::Directory))
+        );
+        assert_eq!(
+            found[1],
+            (OsString::from(".."), parent, Some(FileType::Directory))
+        );
+        for (e, f) in entries.iter().zip(found[2..].iter()) {
+            let name = OsStr::from_bytes(e.0);
+            assert_eq!(*f, (name.to_owned(), e.2, Some(FileType::RegularFile)));
+            assert_eq!(dir.lookup(&mut reader, sb, name), Ok(e.2));
+        }
+    }
+
+    /// An entry with an unknown ftype is returned without a type, rather than ending the listing
+    #[test]
+    fn unknown_ftype() {
+        let entries: [(&[u8], u16, XfsIno); 2] = [(b"a", 0x60, 131), (b"bcd", 0x70, 132)];
+        let sb = crate::libxfuse::sb::tests::superblock();
+        let mut raw = mkdir(false, 128, &entries);
+        // The first entry's ftype follows its namelen, offset, and 1-byte name
+        raw[10] = 0x7f;
+        let (mut dir, _) = utils::decode::<Dir2Sf>(&raw[..]).unwrap();
+        dir.set_ino(100);
+
+        let img = tempfile::NamedTempFile::new().unwrap();
+        let mut reader = BlockReader::open(img.path()).unwrap();
+        let mut offset = 0;
+        let mut found = Vec::new();
+        while let Ok((ino, next, kind, _name)) = dir.next(&mut reader, sb, offset) {
+            found.push((ino, kind));
+            offset = next;
+        }
+        assert_eq!(
+            found[2..],
+            [(131, None), (132, Some(FileType::RegularFile))]
+        );
     }
 }
@@ -0,0 +1,142 @@
+/*
+ * BSD 2-Clause License
+ *
+ * Copyright (c) 2021, Khaled Emara
+ * All rights reserved.
+ *
+ * Redistribution and use in source and binary forms, with or without
+ * modification, are permitted provided that the following conditions are met:
+ *
+ * 1. Redistributions of source code must retain the above copyright notice, this
+ *    list of conditions and the following disclaimer.
+ *
+ * 2. Redistributions in binary form must reproduce the above copyright notice,
+ *    this list of conditions and the following disclaimer in the documentation
+ *    and/or other materials provided with the distribution.
+ *
+ * THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS IS"
+ * AND ANY EXPRESS OR IMPLIED WARRANTIES, INCLUDING, BUT NOT LIMITED TO, THE
+ * IMPLIED WARRANTIES OF MERCHANTABILITY AND FITNESS FOR A PARTICULAR PURPOSE ARE
+ * DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT HOLDER OR CONTRIBUTORS BE LIABLE
+ * FOR ANY DIRECT, INDIRECT, INCIDENTAL, SPECIAL, EXEMPLARY, OR CONSEQUENTIAL
+ * DAMAGES (INCLUDING, BUT NOT LIMITED TO, PROCUREMENT OF SUBSTITUTE GOODS OR
+ * SERVICES; LOSS OF USE, DATA, OR PROFITS; OR BUSINESS INTERRUPTION) HOWEVER
+ * CAUSED AND ON ANY THEORY OF LIABILITY, WHETHER IN CONTRACT, STRICT LIABILITY,
+ * OR TORT (INCLUDING NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE USE
+ * OF THIS SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+ */
+use std::{fmt, io};
+
+use bincode::error::DecodeError;
+use libc::c_int;
+
+/// An error encountered while reading the file system.
+///
+/// Each variant maps to a single errno, which is what gets reported to the kernel.  The variants
+/// that indicate a problem with the file system itself carry a description, for the log.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum XfsError {
+    /// Reading from the device failed with the given errno
+    Io(c_int),
+    /// Some on-disk structure is inconsistent
+    Corrupt(String),
+    /// No such directory entry, or no such block within a directory
+    NotFound,
+    /// No such extended attribute
+    NoAttr,
+    /// The file's data lives somewhere that we can't read, like an absent realtime device
+    Unsupported(String),
+    /// There is no more data at or after the requested offset
+    NoData,
+    /// An argument, like a readdir cookie, is out of range
+    Invalid,
+    /// A value doesn't fit in the type that must be returned to the kernel
+    Overflow,
+    /// A path component that must be a directory isn't one
+    NotDir,
+    /// Too many symbolic links were encountered while resolving a path
+    Loop,
+    /// The file is a FIFO, socket, or device node, so it has no data of its own to read
+    Special,
+}
+
+impl XfsError {
+    /// The errno that should be returned to the kernel
+    pub fn errno(&self) -> c_int {
+        match self {
+            XfsError::Io(e) => *e,
+            XfsError::Corrupt(_) => libc::EIO,
+            XfsError::NotFound => libc::ENOENT,
+            XfsError::NoAttr => libc::ENOATTR,
+            XfsError::Unsupported(_) => libc::ENODEV,
+            XfsError::NoData => libc::ENXIO,
+            XfsError::Invalid => libc::EINVAL,
+            XfsError::Overflow => libc::EOVERFLOW,
+            XfsError::NotDir => libc::ENOTDIR,
+            XfsError::Loop => libc::ELOOP,
+            XfsError::Special => libc::ENXIO,
+        }
+    }
+}
+
+impl fmt::Display for XfsError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            XfsError::Io(e) => write!(f, "{}", io::Error::from_raw_os_error(*e)),
+            XfsError::Corrupt(s) => write!(f, "Corrupt file system: {}", s),
+            XfsError::NotFound => write!(f, "Not found"),
+            XfsError::NoAttr => write!(f, "No such attribute"),
+            XfsError::Unsupported(s) => write!(f, "Unsupported: {}", s),
+            XfsError::NoData => write!(f, "No data at or after offset"),
+            XfsError::Invalid => write!(f, "Invalid argument"),
+            XfsError::Overflow => write!(f, "Value too large"),
+            XfsError::NotDir => write!(f, "Not a directory"),
+            XfsError::Loop => write!(f, "Too many levels of symbolic links"),
+            XfsError::Special => write!(f, "Special file has no data"),
+        }
+    }
+}
+
+impl From<io::Error> for XfsError {
+    fn from(e: io::Error) -> Self {
+        XfsError::Io(e.raw_os_error().unwrap_or(libc::EIO))
+    }
+}
+
+impl From<DecodeError> for XfsError {
+    fn from(e: DecodeError) -> Self {
+        XfsError::Corrupt(e.to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rstest::rstest;
+
+    use super::*;
+
+    #[rstest]
+    #[case::io(XfsError::Io(libc::ENXIO), libc::ENXIO)]
+    #[case::corrupt(XfsError::Corrupt("bad magic".into()), libc::EIO)]
+    #[case::not_found(XfsError::NotFound, libc::ENOENT)]
+    #[case::no_attr(XfsError::NoAttr, libc::ENOATTR)]
+    #[case::unsupported(XfsError::Unsupported("realtime".into()), libc::ENODEV)]
+    #[case::no_data(XfsError::NoData, libc::ENXIO)]
+    #[case::invalid(XfsError::Invalid, libc::EINVAL)]
+    #[case::overflow(XfsError::Overflow, libc::EOVERFLOW)]
+    #[case::not_dir(XfsError::NotDir, libc::ENOTDIR)]
+    #[case::too_many_links(XfsError::Loop, libc::ELOOP)]
+    #[case::special(XfsError::Special, libc::ENXIO)]
+    fn to_errno(#[case] e: XfsError, #[case] expected: c_int) {
+        assert_eq!(e.errno(), expected);
+    }
+
+    /// An I/O error keeps the device's errno, or becomes EIO if it has none
+    #[test]
+    fn from_io() {
+        let e = io::Error::from_raw_os_error(libc::EINVAL);
+        assert_eq!(XfsError::from(e), XfsError::Io(libc::EINVAL));
+        let e = io::Error::new(io::ErrorKind::UnexpectedEof, "short read");
+        assert_eq!(XfsError::from(e).errno(), libc::EIO);
+    }
+}
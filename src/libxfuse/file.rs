@@ -33,94 +33,147 @@ use std::{
 use bincode::de::read::Reader;
 
 use super::{
+    bmbt_rec::BmbtRec,
     definitions::{XfsFileoff, XfsFsblock, XfsFsize},
+    error::XfsError,
+    sb::Sb,
     volume::SUPERBLOCK,
 };
 
+/// One extent of a file's data fork
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct ExtentInfo {
+    /// Offset of the extent within the file, in bytes
+    pub file_offset: u64,
+    /// Location of the extent on disk
+    pub fsblock:     XfsFsblock,
+    /// Length of the extent, in bytes
+    pub length:      u64,
+    /// Is the extent preallocated but not yet written?  If so, it reads as zeros.
+    pub unwritten:   bool,
+}
+
+impl ExtentInfo {
+    pub fn from_rec(rec: &BmbtRec, sb: &Sb) -> Self {
+        Self {
+            file_offset: rec.br_startoff << sb.sb_blocklog,
+            fsblock:     rec.br_startblock,
+            length:      rec.br_blockcount << sb.sb_blocklog,
+            unwritten:   rec.br_flag,
+        }
+    }
+}
+
 pub trait File<R: BufRead + Reader + Seek> {
     /// Return the extent, if any, that contains the given data block within the file.
     /// Return its starting position as an FSblock, and its length in file system block units
-    fn get_extent(&self, buf_reader: &mut R, block: XfsFileoff) -> (Option<XfsFsblock>, u64);
+    fn get_extent(
+        &self,
+        buf_reader: &mut R,
+        block: XfsFileoff,
+    ) -> Result<(Option<XfsFsblock>, u64), XfsError>;
+
+    /// Convert a block returned by [`File::get_extent`] to a byte offset on the device that holds
+    /// the file's data.
+    fn block_offset(&self, sb: &Sb, blk: XfsFsblock) -> u64 {
+        sb.fsb_to_offset(blk)
+    }
 
     /// Like lseek(2), but only works for SEEK_HOLE and SEEK_DATA
-    fn lseek(&self, buf_reader: &mut R, offset: u64, whence: i32) -> Result<u64, i32>;
+    fn lseek(&self, buf_reader: &mut R, offset: u64, whence: i32) -> Result<u64, XfsError>;
+
+    /// Like [`File::lseek`], but never report data at or beyond EOF.  Extents may be preallocated
+    /// there, but like the rest of the file beyond EOF, they're a hole.
+    fn seek_hole_data(
+        &self,
+        buf_reader: &mut R,
+        offset: u64,
+        whence: i32,
+    ) -> Result<u64, XfsError> {
+        let size = u64::try_from(self.size()).unwrap_or(0);
+        match self.lseek(buf_reader, offset, whence)? {
+            ofs if ofs < size => Ok(ofs),
+            _ if whence == libc::SEEK_HOLE => Ok(size),
+            _ => Err(XfsError::NoData),
+        }
+    }
 
-    /// Perform a sector-size aligned read of the file
-    fn read_sectors(
+    /// Read from a file into a caller-provided buffer, so that it may be reused across reads.
+    /// Return the number of bytes read, which will be less than the buffer's length only at EOF.
+    fn read_into(
         &self,
         buf_reader: &mut R,
         offset: i64,
-        mut size: usize,
-    ) -> Result<Vec<u8>, i32> {
+        buf: &mut [u8],
+    ) -> Result<usize, XfsError> {
         let sb = SUPERBLOCK.get().unwrap();
-        debug_assert_eq!(
-            offset & ((1i64 << sb.sb_blocklog) - 1),
-            0,
-            "fusefs did a non-sector-size aligned read.  offset={:?} size={:?}",
-            offset,
-            size
-        );
-        debug_assert_eq!(
-            size & ((1usize << sb.sb_blocklog) - 1),
-            0,
-            "fusefs did a non-sector-size aligned read.  offset={:?} size={:?}",
-            offset,
-            size
-        );
-
-        let mut data = Vec::<u8>::with_capacity(size);
-
-        let mut logical_block = u64::try_from(offset >> sb.sb_blocklog).unwrap();
-        let mut block_offset: u64 = 0;
-
-        while size > 0 {
-            let (blk, blocks) = self.get_extent(buf_reader.by_ref(), logical_block);
+        let mut pos = u64::try_from(offset).map_err(|_| XfsError::Invalid)?;
+        if offset >= self.size() {
+            // Nothing to read, whether from an empty file or at or past EOF.
+            return Ok(0);
+        }
+        // Both are nonnegative, so this can't overflow even for the largest files.
+        let remaining = u64::try_from(self.size() - offset).unwrap();
+        let len = usize::try_from(remaining).map_or(buf.len(), |r| r.min(buf.len()));
+
+        let mut done = 0;
+        while done < len {
+            let logical_block = pos >> sb.sb_blocklog;
+            let block_offset = pos & sb.geometry.blockmask;
+            let (blk, blocks) = self.get_extent(buf_reader.by_ref(), logical_block)?;
+            // Holes may be longer than can be expressed in bytes, so saturate.
             let z = usize::try_from(min(
-                u64::try_from(size).unwrap(),
-                (blocks << sb.sb_blocklog) - block_offset,
+                u64::try_from(len - done).unwrap(),
+                blocks.saturating_mul(sb.geometry.blocksize as u64) - block_offset,
             ))
             .unwrap();
 
-            let oldlen = data.len();
-            data.resize(oldlen + z, 0u8);
+            let chunk = &mut buf[done..done + z];
             if let Some(blk) = blk {
-                buf_reader
-                    .seek(SeekFrom::Start(sb.fsb_to_offset(blk) + block_offset))
-                    .map_err(|e| e.raw_os_error().unwrap())?;
-
-                buf_reader
-                    .read_exact(&mut data[oldlen..])
-                    .map_err(|e| e.raw_os_error().unwrap())?;
+                buf_reader.seek(SeekFrom::Start(self.block_offset(sb, blk) + block_offset))?;
+                buf_reader.read_exact(chunk)?;
             } else {
                 // A hole
+                chunk.fill(0);
             }
-            logical_block += blocks;
-            size -= z;
-            block_offset = 0;
+            pos += z as u64;
+            done += z;
         }
 
-        Ok(data)
+        Ok(len)
     }
 
-    /// Return from a file.  Return a buffer containing the requested data, plus a number of bytes
-    /// that the caller should ignore from the head of the vector.
-    fn read(&self, buf_reader: &mut R, offset: i64, size: u32) -> Result<(Vec<u8>, usize), i32> {
-        let sb = SUPERBLOCK.get().unwrap();
-        let size = u32::try_from(i64::from(size).min(self.size() - offset)).unwrap();
-
-        let block_offset = usize::try_from(offset & ((1i64 << sb.sb_blocklog) - 1)).unwrap();
-        let size_with_leader = usize::try_from(size).unwrap() + block_offset;
-        let size_remainder = size_with_leader & ((1 << sb.sb_blocklog) - 1);
-        let actual_size = if size_remainder > 0 {
-            size_with_leader + usize::try_from(sb.sb_blocksize).unwrap() - size_remainder
-        } else {
-            size_with_leader
-        };
-        let actual_offset = offset - i64::try_from(block_offset).unwrap();
-        let mut v = self.read_sectors(buf_reader, actual_offset, actual_size)?;
-        v.resize(size_with_leader, 0);
-        Ok((v, block_offset))
+    /// Read from a file, returning a newly allocated buffer containing the requested data.
+    #[allow(dead_code)] // Only used by tests and benches
+    fn read(&self, buf_reader: &mut R, offset: i64, size: u32) -> Result<Vec<u8>, XfsError> {
+        let mut v = vec![0u8; usize::try_from(size).unwrap()];
+        let len = self.read_into(buf_reader, offset, &mut v)?;
+        v.truncate(len);
+        Ok(v)
     }
 
     fn size(&self) -> XfsFsize;
 }
+
+/// Allow a file object that's cached elsewhere, such as in its inode, to be lent out.
+impl<R: BufRead + Reader + Seek, F: File<R>> File<R> for &F {
+    fn get_extent(
+        &self,
+        buf_reader: &mut R,
+        block: XfsFileoff,
+    ) -> Result<(Option<XfsFsblock>, u64), XfsError> {
+        (**self).get_extent(buf_reader, block)
+    }
+
+    fn block_offset(&self, sb: &Sb, blk: XfsFsblock) -> u64 {
+        (**self).block_offset(sb, blk)
+    }
+
+    fn lseek(&self, buf_reader: &mut R, offset: u64, whence: i32) -> Result<u64, XfsError> {
+        (**self).lseek(buf_reader, offset, whence)
+    }
+
+    fn size(&self) -> XfsFsize {
+        (**self).size()
+    }
+}
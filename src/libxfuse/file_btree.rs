@@ -25,13 +25,17 @@
  * OR TORT (INCLUDING NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE USE
  * OF THIS SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
  */
-use std::io::{BufRead, Seek};
+use std::{
+    cell::Cell,
+    io::{BufRead, Seek},
+};
 
 use bincode::de::read::Reader;
 
 use super::{
     btree::{Btree, BtreeRoot},
     definitions::{XfsFileoff, XfsFsblock, XfsFsize},
+    error::XfsError,
     file::File,
     volume::SUPERBLOCK,
 };
@@ -40,17 +44,40 @@ use super::{
 pub struct FileBtree {
     pub btree: BtreeRoot,
     pub size:  XfsFsize,
+    /// The extent or hole most recently returned by `get_extent`, as its first block, starting
+    /// FSblock, and length.  Sequential reads within it needn't walk the btree again.
+    last:      Cell<Option<(XfsFileoff, Option<XfsFsblock>, u64)>>,
+}
+
+impl FileBtree {
+    pub fn new(btree: BtreeRoot, size: XfsFsize) -> Self {
+        Self {
+            btree,
+            size,
+            last: Cell::new(None),
+        }
+    }
 }
 
 impl<R: BufRead + Reader + Seek> File<R> for FileBtree {
-    fn get_extent(&self, buf_reader: &mut R, block: XfsFileoff) -> (Option<XfsFsblock>, u64) {
+    fn get_extent(
+        &self,
+        buf_reader: &mut R,
+        block: XfsFileoff,
+    ) -> Result<(Option<XfsFsblock>, u64), XfsError> {
+        if let Some((first, start, len)) = self.last.get() {
+            if let Some(skip) = block.checked_sub(first).filter(|skip| *skip < len) {
+                return Ok((start.map(|start| start + skip), len - skip));
+            }
+        }
         let sb = SUPERBLOCK.get().unwrap();
-        let (start, len) = self.btree.map_block(buf_reader.by_ref(), block).unwrap();
+        let (start, len) = self.btree.map_block(buf_reader.by_ref(), block)?;
         let len = len.unwrap_or((self.size as u64).div_ceil(sb.sb_blocksize.into()) - block);
-        (start, len)
+        self.last.set(Some((block, start, len)));
+        Ok((start, len))
     }
 
-    fn lseek(&self, buf_reader: &mut R, offset: u64, whence: i32) -> Result<u64, i32> {
+    fn lseek(&self, buf_reader: &mut R, offset: u64, whence: i32) -> Result<u64, XfsError> {
         self.btree.lseek(buf_reader, offset, whence)
     }
 
@@ -58,3 +85,51 @@ impl<R: BufRead + Reader + Seek> File<R> for FileBtree {
         self.size
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::libxfuse::{
+        btree::{BmbtKey, BmdrBlock},
+        sb::tests::superblock,
+        test_support::{mkbmbt_leaf, mkimg, open},
+    };
+
+    /// Lookups within the most recently returned extent or hole are served from the cache, with
+    /// their start and length adjusted.  Any other lookup must walk the btree again, whether it
+    /// lies before or after the cached range.
+    #[test]
+    fn last_extent() {
+        let sb = superblock();
+        let bs = sb.geometry.blocksize;
+        let img = mkimg(&[(10, mkbmbt_leaf(&[(0, 5000, 10), (20, 6000, 10)]))]);
+        let mut reader = open(&img);
+
+        let root = BtreeRoot::new(
+            BmdrBlock {
+                bb_level:   1,
+                bb_numrecs: 1,
+            },
+            vec![BmbtKey { br_startoff: 0 }],
+            vec![10],
+        );
+        let file = FileBtree::new(root, 40 * bs as XfsFsize);
+
+        // (block, expected extent, first block of the cached range afterwards)
+        let lookups = [
+            (0, (Some(5000), 10), 0),
+            (3, (Some(5003), 7), 0),
+            (9, (Some(5009), 1), 0),
+            (10, (None, 10), 10),
+            (15, (None, 5), 10),
+            (25, (Some(6005), 5), 25),
+            (20, (Some(6000), 10), 20),
+            (29, (Some(6009), 1), 20),
+            (35, (None, 5), 35),
+        ];
+        for (block, expected, first) in lookups {
+            assert_eq!(file.get_extent(&mut reader, block).unwrap(), expected);
+            assert_eq!(file.last.get().unwrap().0, first);
+        }
+    }
+}
@@ -32,25 +32,42 @@ use bincode::de::read::Reader;
 use super::{
     bmbt_rec::Bmx,
     definitions::{XfsFileoff, XfsFsblock, XfsFsize},
+    error::XfsError,
     file::File,
+    sb::Sb,
     volume::SUPERBLOCK,
 };
 
 #[derive(Debug)]
 pub struct FileExtentList {
-    pub bmx:  Bmx,
-    pub size: XfsFsize,
+    pub bmx:      Bmx,
+    pub size:     XfsFsize,
+    /// Are the extents on the realtime device?  If so, their block numbers are realtime block
+    /// numbers, not file system block numbers.
+    pub realtime: bool,
 }
 
 impl<R: BufRead + Reader + Seek> File<R> for FileExtentList {
-    fn get_extent(&self, _buf_reader: &mut R, block: XfsFileoff) -> (Option<XfsFsblock>, u64) {
+    fn get_extent(
+        &self,
+        _buf_reader: &mut R,
+        block: XfsFileoff,
+    ) -> Result<(Option<XfsFsblock>, u64), XfsError> {
         let sb = SUPERBLOCK.get().unwrap();
         let (start, len) = self.bmx.get_extent(block);
         let len = len.unwrap_or((self.size as u64).div_ceil(sb.sb_blocksize.into()) - block);
-        (start, len)
+        Ok((start, len))
     }
 
-    fn lseek(&self, _buf_reader: &mut R, offset: u64, whence: i32) -> Result<u64, i32> {
+    fn block_offset(&self, sb: &Sb, blk: XfsFsblock) -> u64 {
+        if self.realtime {
+            sb.rtb_to_offset(blk)
+        } else {
+            sb.fsb_to_offset(blk)
+        }
+    }
+
+    fn lseek(&self, _buf_reader: &mut R, offset: u64, whence: i32) -> Result<u64, XfsError> {
         self.bmx.lseek(offset, whence)
     }
 
@@ -58,3 +75,350 @@ impl<R: BufRead + Reader + Seek> File<R> for FileExtentList {
         self.size
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        fs,
+        io::{Read, SeekFrom, Write},
+        os::unix::fs::FileExt,
+    };
+
+    use rstest::rstest;
+
+    use super::*;
+    use crate::libxfuse::{block_reader::BlockReader, bmbt_rec::BmbtRec, sb::tests::superblock};
+
+    /// Read across the end of an extent of the maximum possible length
+    #[test]
+    fn read_maximal_extent() {
+        const MAXEXTLEN: u64 = (1 << 21) - 1;
+        let sb = superblock();
+        let bs = u64::from(sb.sb_blocksize);
+
+        let mut img = tempfile::NamedTempFile::new().unwrap();
+        let f: &fs::File = img.as_file();
+        // The last block of the long extent, and the first block of the following extent
+        f.write_all_at(&vec![b'a'; bs as usize], (100 + MAXEXTLEN - 1) * bs)
+            .unwrap();
+        f.write_all_at(&vec![b'b'; bs as usize], (200 + MAXEXTLEN) * bs)
+            .unwrap();
+        img.flush().unwrap();
+
+        let file = FileExtentList {
+            bmx:      Bmx::new(&[
+                BmbtRec {
+                    br_startoff:   0,
+                    br_startblock: 100,
+                    br_blockcount: MAXEXTLEN,
+                    br_flag:       false,
+                },
+                BmbtRec {
+                    br_startoff:   MAXEXTLEN,
+                    br_startblock: 200 + MAXEXTLEN,
+                    br_blockcount: 1,
+                    br_flag:       false,
+                },
+            ]),
+            size:     ((MAXEXTLEN + 1) * bs) as XfsFsize,
+            realtime: false,
+        };
+        let mut reader = BlockReader::open(img.path()).unwrap();
+        reader.set_bufsize(bs as usize);
+        assert_eq!(file.get_extent(&mut reader, 0), Ok((Some(100), MAXEXTLEN)));
+
+        let offset = ((MAXEXTLEN - 1) * bs) as i64;
+        let v = file.read(&mut reader, offset, 2 * bs as u32).unwrap();
+        assert_eq!(v.len(), 2 * bs as usize);
+        assert!(v[..bs as usize].iter().all(|b| *b == b'a'));
+        assert!(v[bs as usize..].iter().all(|b| *b == b'b'));
+    }
+
+    /// A single read that straddles two extents, which are not physically adjacent, must return
+    /// each extent's own data.  Every 16 bytes of the file contain their own file offset.  The
+    /// boundary between the extents is at 8192 bytes.
+    #[rstest]
+    #[case::aligned(4096, 8192)]
+    #[case::unaligned(7184, 1200)]
+    #[case::short(8176, 32)]
+    #[case::whole_file(16, 16352)]
+    fn read_across_extents(
+        #[values(true, false)] forward: bool,
+        #[case] offset: u64,
+        #[case] size: u32,
+    ) {
+        let sb = superblock();
+        let bs = u64::from(sb.sb_blocksize);
+        assert_eq!(bs, 4096);
+        // Each extent is 2 blocks long.  The second one is stored either well after or well
+        // before the first one.
+        let startblocks = if forward { [100, 300] } else { [300, 100] };
+
+        let mut img = tempfile::NamedTempFile::new().unwrap();
+        let f: &fs::File = img.as_file();
+        for (i, startblock) in startblocks.iter().enumerate() {
+            let data = (0..2 * bs)
+                .step_by(16)
+                .map(|o| format!("{:016x}", o + 2 * bs * i as u64))
+                .collect::<String>();
+            f.write_all_at(data.as_bytes(), startblock * bs).unwrap();
+        }
+        img.flush().unwrap();
+
+        let file = FileExtentList {
+            bmx:      Bmx::new(&[
+                BmbtRec {
+                    br_startoff:   0,
+                    br_startblock: startblocks[0],
+                    br_blockcount: 2,
+                    br_flag:       false,
+                },
+                BmbtRec {
+                    br_startoff:   2,
+                    br_startblock: startblocks[1],
+                    br_blockcount: 2,
+                    br_flag:       false,
+                },
+            ]),
+            size:     (4 * bs) as XfsFsize,
+            realtime: false,
+        };
+        let mut reader = BlockReader::open(img.path()).unwrap();
+        reader.set_bufsize(bs as usize);
+
+        let data = file.read(&mut reader, offset as i64, size).unwrap();
+        assert_eq!(data.len(), size as usize);
+        for (i, chunk) in data.chunks(16).enumerate() {
+            let expected = format!("{:016x}", offset + 16 * i as u64);
+            assert_eq!(chunk, &expected.as_bytes()[..chunk.len()]);
+        }
+    }
+
+    /// Realtime block numbers are linear, unlike file system block numbers, which encode the AG
+    /// number in their upper bits.
+    #[test]
+    fn realtime_block_offset() {
+        // Use an AG size that isn't a power of two, so the two numbering schemes differ
+        let buf = crate::libxfuse::sb::tests::mksb(|buf| {
+            buf[84..88].copy_from_slice(&16000u32.to_be_bytes()); // sb_agblocks
+            buf[8..16].copy_from_slice(&64000u64.to_be_bytes()); // sb_dblocks
+        });
+        let sb = Sb::from(&mut std::io::Cursor::new(buf)).unwrap();
+        let blk = (1 << sb.sb_agblklog) + 5;
+        let mut file = FileExtentList {
+            bmx:      Bmx::new(&[]),
+            size:     0,
+            realtime: false,
+        };
+        assert_eq!(
+            <FileExtentList as File<BlockReader>>::block_offset(&file, &sb, blk),
+            (16000 + 5) * 4096
+        );
+        file.realtime = true;
+        assert_eq!(
+            <FileExtentList as File<BlockReader>>::block_offset(&file, &sb, blk),
+            blk * 4096
+        );
+    }
+
+    /// The last AG may be shorter than the others, but its blocks are still numbered with the
+    /// full `sb_agblklog` shift, so a file whose extents land there, up to the very last block
+    /// of the device, must be read from the right place.
+    #[test]
+    fn short_last_ag() {
+        // 3 full AGs of 16000 blocks and a short one of 1000
+        let buf = crate::libxfuse::sb::tests::mksb(|buf| {
+            buf[84..88].copy_from_slice(&16000u32.to_be_bytes()); // sb_agblocks
+            buf[8..16].copy_from_slice(&49000u64.to_be_bytes()); // sb_dblocks
+        });
+        let sb = Sb::from(&mut std::io::Cursor::new(buf)).unwrap();
+        let bs = u64::from(sb.sb_blocksize);
+        let ag3 = 3 << sb.sb_agblklog;
+        let file = FileExtentList {
+            bmx:      Bmx::new(&[
+                BmbtRec {
+                    br_startoff:   0,
+                    br_startblock: ag3 + 10,
+                    br_blockcount: 2,
+                    br_flag:       false,
+                },
+                BmbtRec {
+                    br_startoff:   2,
+                    br_startblock: ag3 + 998,
+                    br_blockcount: 2,
+                    br_flag:       false,
+                },
+            ]),
+            size:     (4 * bs) as XfsFsize,
+            realtime: false,
+        };
+
+        // Each file block is stored at this linear block number on the device
+        let expected = [48010, 48011, 48998, 48999];
+        let mut img = tempfile::NamedTempFile::new().unwrap();
+        let f: &fs::File = img.as_file();
+        f.set_len(sb.sb_dblocks * bs).unwrap();
+        for (i, blk) in expected.iter().enumerate() {
+            f.write_all_at(&vec![b'a' + i as u8; bs as usize], blk * bs)
+                .unwrap();
+        }
+        img.flush().unwrap();
+        let mut reader = BlockReader::open(img.path()).unwrap();
+        reader.set_bufsize(bs as usize);
+
+        let mut data = vec![0u8; bs as usize];
+        for (i, blk) in expected.iter().enumerate() {
+            let fsblock = file.bmx.get_extent(i as XfsFileoff).0.unwrap();
+            let offset = <FileExtentList as File<BlockReader>>::block_offset(&file, &sb, fsblock);
+            assert_eq!(offset, blk * bs);
+            reader.seek(SeekFrom::Start(offset)).unwrap();
+            reader.read_exact(&mut data).unwrap();
+            assert!(data.iter().all(|b| *b == b'a' + i as u8));
+        }
+        // The file's last block is the device's last block
+        assert_eq!(expected[3] + 1, sb.sb_dblocks);
+    }
+
+    /// Written extents may be preallocated beyond EOF, as when a 4 KiB file has 1 MiB allocated.
+    /// Reads must stop at EOF, and SEEK_HOLE and SEEK_DATA must treat EOF as the start of a hole.
+    #[test]
+    fn prealloc_beyond_eof() {
+        let sb = superblock();
+        let bs = u64::from(sb.sb_blocksize);
+        let mut img = tempfile::NamedTempFile::new().unwrap();
+        let f: &fs::File = img.as_file();
+        f.write_all_at(&vec![b'a'; bs as usize], 100 * bs).unwrap();
+        f.write_all_at(&vec![b'b'; (255 * bs) as usize], 101 * bs)
+            .unwrap();
+        img.flush().unwrap();
+
+        let file = FileExtentList {
+            bmx:      Bmx::new(&[BmbtRec {
+                br_startoff:   0,
+                br_startblock: 100,
+                br_blockcount: (1 << 20) / bs,
+                br_flag:       false,
+            }]),
+            size:     bs as XfsFsize,
+            realtime: false,
+        };
+        let mut reader = BlockReader::open(img.path()).unwrap();
+        reader.set_bufsize(bs as usize);
+
+        let v = file.read(&mut reader, 0, 1 << 20).unwrap();
+        assert_eq!(v.len(), bs as usize);
+        assert!(v.iter().all(|b| *b == b'a'));
+        assert!(file
+            .read(&mut reader, bs as i64, 1 << 20)
+            .unwrap()
+            .is_empty());
+
+        assert_eq!(file.seek_hole_data(&mut reader, 0, libc::SEEK_HOLE), Ok(bs));
+        assert_eq!(file.seek_hole_data(&mut reader, 0, libc::SEEK_DATA), Ok(0));
+        assert_eq!(
+            file.seek_hole_data(&mut reader, bs, libc::SEEK_DATA),
+            Err(XfsError::NoData)
+        );
+    }
+
+    /// Reading an empty file, or past the end of any file, should return no data.
+    #[rstest]
+    #[case::empty(0, 0)]
+    #[case::empty_past_eof(0, 4096)]
+    #[case::at_eof(14, 14)]
+    #[case::past_eof(14, 8192)]
+    fn read_eof(#[case] size: XfsFsize, #[case] offset: i64) {
+        let sb = superblock();
+        let img = tempfile::NamedTempFile::new().unwrap();
+        let bmx = if size > 0 {
+            Bmx::new(&[BmbtRec {
+                br_startoff:   0,
+                br_startblock: 100,
+                br_blockcount: 1,
+                br_flag:       false,
+            }])
+        } else {
+            Bmx::new(&[])
+        };
+        let file = FileExtentList {
+            bmx,
+            size,
+            realtime: false,
+        };
+        let mut reader = BlockReader::open(img.path()).unwrap();
+        reader.set_bufsize(sb.geometry.blocksize);
+
+        let v = file.read(&mut reader, offset, 4096).unwrap();
+        assert!(v.is_empty());
+    }
+
+    /// A reused buffer must not leak stale data into holes, and a read that reaches EOF must
+    /// report how much of the buffer it filled.
+    #[test]
+    fn read_into_reused_buffer() {
+        let sb = superblock();
+        let bs = sb.geometry.blocksize;
+        let mut img = tempfile::NamedTempFile::new().unwrap();
+        let f: &fs::File = img.as_file();
+        f.write_all_at(&vec![b'a'; bs], sb.fsb_to_offset(100))
+            .unwrap();
+        img.flush().unwrap();
+
+        // One block of data, followed by a hole, and a partial final block
+        let file = FileExtentList {
+            bmx:      Bmx::new(&[BmbtRec {
+                br_startoff:   0,
+                br_startblock: 100,
+                br_blockcount: 1,
+                br_flag:       false,
+            }]),
+            size:     (2 * bs + 100) as XfsFsize,
+            realtime: false,
+        };
+        let mut reader = BlockReader::open(img.path()).unwrap();
+        reader.set_bufsize(bs);
+
+        let mut buf = vec![0xffu8; 2 * bs];
+        assert_eq!(file.read_into(&mut reader, 0, &mut buf), Ok(2 * bs));
+        assert!(buf[..bs].iter().all(|b| *b == b'a'));
+        assert!(buf[bs..].iter().all(|b| *b == 0));
+
+        buf.fill(0xff);
+        let offset = i64::try_from(bs + 10).unwrap();
+        assert_eq!(file.read_into(&mut reader, offset, &mut buf), Ok(bs + 90));
+        assert!(buf[..bs + 90].iter().all(|b| *b == 0));
+        assert!(buf[bs + 90..].iter().all(|b| *b == 0xff));
+    }
+
+    /// Reads near the largest possible file size must not overflow.  The top of such a file is a
+    /// hole.
+    #[rstest]
+    #[case::below_top(i64::MAX - 100, Ok(100))]
+    #[case::top(i64::MAX - 1, Ok(1))]
+    #[case::eof(i64::MAX, Ok(0))]
+    #[case::negative(-1, Err(XfsError::Invalid))]
+    #[case::most_negative(i64::MIN, Err(XfsError::Invalid))]
+    fn read_max_size(#[case] offset: i64, #[case] expected: Result<usize, XfsError>) {
+        let sb = superblock();
+        let img = tempfile::NamedTempFile::new().unwrap();
+        let file = FileExtentList {
+            bmx:      Bmx::new(&[BmbtRec {
+                br_startoff:   0,
+                br_startblock: 100,
+                br_blockcount: 1,
+                br_flag:       false,
+            }]),
+            size:     XfsFsize::MAX,
+            realtime: false,
+        };
+        let mut reader = BlockReader::open(img.path()).unwrap();
+        reader.set_bufsize(sb.geometry.blocksize);
+
+        let mut buf = vec![0xffu8; 4096];
+        let r = file.read_into(&mut reader, offset, &mut buf);
+        assert_eq!(r, expected);
+        if let Ok(len) = r {
+            assert!(buf[..len].iter().all(|b| *b == 0));
+        }
+    }
+}
@@ -0,0 +1,372 @@
+/*
+ * BSD 2-Clause License
+ *
+ * Copyright (c) 2021, Khaled Emara
+ * All rights reserved.
+ *
+ * Redistribution and use in source and binary forms, with or without
+ * modification, are permitted provided that the following conditions are met:
+ *
+ * 1. Redistributions of source code must retain the above copyright notice, this
+ *    list of conditions and the following disclaimer.
+ *
+ * 2. Redistributions in binary form must reproduce the above copyright notice,
+ *    this list of conditions and the following disclaimer in the documentation
+ *    and/or other materials provided with the distribution.
+ *
+ * THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS IS"
+ * AND ANY EXPRESS OR IMPLIED WARRANTIES, INCLUDING, BUT NOT LIMITED TO, THE
+ * IMPLIED WARRANTIES OF MERCHANTABILITY AND FITNESS FOR A PARTICULAR PURPOSE ARE
+ * DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT HOLDER OR CONTRIBUTORS BE LIABLE
+ * FOR ANY DIRECT, INDIRECT, INCIDENTAL, SPECIAL, EXEMPLARY, OR CONSEQUENTIAL
+ * DAMAGES (INCLUDING, BUT NOT LIMITED TO, PROCUREMENT OF SUBSTITUTE GOODS OR
+ * SERVICES; LOSS OF USE, DATA, OR PROFITS; OR BUSINESS INTERRUPTION) HOWEVER
+ * CAUSED AND ON ANY THEORY OF LIABILITY, WHETHER IN CONTRACT, STRICT LIABILITY,
+ * OR TORT (INCLUDING NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE USE
+ * OF THIS SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+ */
+use std::io::{BufRead, Seek, SeekFrom};
+
+use bincode::{de::Decoder, error::DecodeError, impl_borrow_decode, Decode};
+
+use super::{
+    agi::Agi,
+    definitions::*,
+    error::XfsError,
+    sb::Sb,
+    utils::{decode, Uuid},
+};
+
+/// Header of a short-format B+tree block, as used by the per-AG B+trees
+#[derive(Debug)]
+struct ShortBtreeHdr {
+    bb_magic:    u32,
+    bb_level:    u16,
+    bb_numrecs:  u16,
+    // bb_leftsib: XfsAgblock,
+    bb_rightsib: XfsAgblock,
+    // Below fields are for V5 file systems only
+    // bb_blkno: u64,
+    // bb_lsn: u64,
+    bb_uuid:     Option<Uuid>,
+    // bb_owner: XfsAgnumber,
+    // bb_crc: u32,
+}
+
+impl ShortBtreeHdr {
+    /// Size of the header in bytes
+    fn size(&self) -> usize {
+        match self.bb_magic {
+            XFS_IBT_CRC_MAGIC => 56,
+            _ => 16,
+        }
+    }
+}
+
+impl<Ctx> Decode<Ctx> for ShortBtreeHdr {
+    fn decode<D: Decoder>(decoder: &mut D) -> Result<Self, DecodeError> {
+        let bb_magic: u32 = Decode::decode(decoder)?;
+        let bb_level = Decode::decode(decoder)?;
+        let bb_numrecs = Decode::decode(decoder)?;
+        let _bb_leftsib: XfsAgblock = Decode::decode(decoder)?;
+        let bb_rightsib = Decode::decode(decoder)?;
+        let bb_uuid = match bb_magic {
+            XFS_IBT_MAGIC => None,
+            XFS_IBT_CRC_MAGIC => {
+                let _bb_blkno: u64 = Decode::decode(decoder)?;
+                let _bb_lsn: u64 = Decode::decode(decoder)?;
+                let bb_uuid = Decode::decode(decoder)?;
+                let _bb_owner: XfsAgnumber = Decode::decode(decoder)?;
+                let _bb_crc: u32 = Decode::decode(decoder)?;
+                Some(bb_uuid)
+            }
+            _ => {
+                return Err(DecodeError::OtherString(format!(
+                    "Unexpected magic value {:#x}",
+                    bb_magic
+                )))
+            }
+        };
+        Ok(ShortBtreeHdr {
+            bb_magic,
+            bb_level,
+            bb_numrecs,
+            bb_rightsib,
+            bb_uuid,
+        })
+    }
+}
+impl_borrow_decode!(ShortBtreeHdr);
+
+/// An inode B+tree record, describing one chunk of 64 inodes
+#[derive(Debug, Decode)]
+struct InobtRec {
+    /// AG-relative number of the chunk's first inode
+    ir_startino:   XfsAgino,
+    /// Each bit marks 4 inodes that were never allocated, in a sparse chunk.  Without the sparse
+    /// inodes feature, this is the high half of a 32-bit free count, so it's always zero.
+    ir_holemask:   u16,
+    _ir_count:     u8,
+    _ir_freecount: u8,
+    /// Each bit marks a free inode
+    ir_free:       u64,
+}
+
+impl InobtRec {
+    /// The allocated inodes in this chunk, as AG-relative inode numbers
+    fn inodes(&self) -> impl Iterator<Item = XfsAgino> + '_ {
+        (0..XFS_INODES_PER_CHUNK)
+            .filter(|i| self.ir_holemask & (1 << (i / 4)) == 0 && self.ir_free & (1 << i) == 0)
+            .map(|i| self.ir_startino + i as XfsAgino)
+    }
+}
+
+/// Read one inode B+tree block of the given AG into `buf`, and decode its header
+fn read_block<R>(
+    buf_reader: &mut R,
+    superblock: &Sb,
+    agno: XfsAgnumber,
+    agbno: XfsAgblock,
+    buf: &mut [u8],
+) -> Result<ShortBtreeHdr, XfsError>
+where
+    R: BufRead + Seek,
+{
+    if agbno >= superblock.sb_agblocks {
+        return Err(XfsError::Corrupt(format!(
+            "inode btree block {} in AG {} is beyond the end of the AG",
+            agbno, agno
+        )));
+    }
+    let fsbno = (u64::from(agno) << superblock.sb_agblklog) | u64::from(agbno);
+    buf_reader.seek(SeekFrom::Start(superblock.fsb_to_offset(fsbno)))?;
+    buf_reader.read_exact(buf)?;
+    let hdr: ShortBtreeHdr = decode(buf)
+        .map_err(|e| {
+            XfsError::Corrupt(format!("inode btree block {} in AG {}: {}", agbno, agno, e))
+        })?
+        .0;
+    if hdr.bb_uuid.is_some_and(|uuid| uuid != superblock.sb_uuid) {
+        return Err(XfsError::Corrupt(format!(
+            "inode btree block {} in AG {} has the wrong UUID",
+            agbno, agno
+        )));
+    }
+    Ok(hdr)
+}
+
+/// List the allocated inodes of one AG, in order, by walking its inode B+tree.
+///
+/// Each AG's inode B+tree is independent, so a tool that must visit every inode can divide the
+/// work among several workers, one AG apiece.  Unlike a walk of the directory tree, this also
+/// finds metadata inodes and inodes that are allocated but no longer linked.
+pub fn ag_inodes<R>(
+    buf_reader: &mut R,
+    superblock: &Sb,
+    agno: XfsAgnumber,
+) -> Result<Vec<XfsIno>, XfsError>
+where
+    R: BufRead + Seek + bincode::de::read::Reader,
+{
+    let agi = Agi::from(buf_reader.by_ref(), superblock, agno)?;
+    let blocksize = superblock.sb_blocksize as usize;
+    let mut buf = vec![0u8; blocksize];
+
+    // Descend the leftmost path to the first leaf
+    let mut agbno = agi.agi_root;
+    for level in (1..agi.agi_level).rev() {
+        let hdr = read_block(buf_reader.by_ref(), superblock, agno, agbno, &mut buf)?;
+        if u32::from(hdr.bb_level) != level || hdr.bb_numrecs == 0 {
+            return Err(XfsError::Corrupt(format!(
+                "inode btree block {} in AG {} has level {} and {} records, but level {} was \
+                 expected",
+                agbno, agno, hdr.bb_level, hdr.bb_numrecs, level
+            )));
+        }
+        // Each key is a starting inode number, and each pointer an AG block number
+        let maxrecs = (blocksize - hdr.size()) / 8;
+        let ofs = hdr.size() + 4 * maxrecs;
+        agbno = decode(&buf[ofs..])?.0;
+    }
+
+    // Then follow the leaves' sibling pointers
+    let mut inodes = Vec::new();
+    let mut leaves = 0;
+    loop {
+        let hdr = read_block(buf_reader.by_ref(), superblock, agno, agbno, &mut buf)?;
+        let numrecs = usize::from(hdr.bb_numrecs);
+        if hdr.bb_level != 0 || hdr.size() + 16 * numrecs > blocksize {
+            return Err(XfsError::Corrupt(format!(
+                "inode btree leaf {} in AG {} has level {} and {} records",
+                agbno, agno, hdr.bb_level, numrecs
+            )));
+        }
+        for i in 0..numrecs {
+            let rec: InobtRec = decode(&buf[hdr.size() + 16 * i..])?.0;
+            inodes.extend(
+                rec.inodes()
+                    .map(|agino| superblock.agino_to_ino(agno, agino)),
+            );
+        }
+        if hdr.bb_rightsib == NULLAGBLOCK {
+            break;
+        }
+        leaves += 1;
+        if leaves >= superblock.sb_agblocks {
+            return Err(XfsError::Corrupt(format!(
+                "inode btree leaves in AG {} form a loop",
+                agno
+            )));
+        }
+        agbno = hdr.bb_rightsib;
+    }
+    Ok(inodes)
+}
+
+#[cfg(test)]
+mod tests {
+    use rstest::rstest;
+
+    use super::*;
+    use crate::libxfuse::{
+        agi::tests::mkagi,
+        block_reader::BlockReader,
+        sb::tests::superblock,
+        test_support::{self, mkinobt_leaf as mkleaf, mkinobt_node as mknode, open},
+    };
+
+    /// An AG's inode B+tree root, its level, and the tree's blocks
+    type Ag = (XfsAgblock, u32, Vec<(XfsAgblock, Vec<u8>)>);
+
+    /// The blocks of an image whose AGs each have the given inode B+tree, including each AG's
+    /// first block, which holds its AGI
+    fn ag_blocks(ags: &[Ag]) -> Vec<(XfsFsblock, Vec<u8>)> {
+        let sb = superblock();
+        let agi_ofs = 2 * usize::from(sb.sb_sectsize);
+        let mut blocks = Vec::new();
+        for (agno, (root, level, btree)) in ags.iter().enumerate() {
+            let agfsb = (agno as u64) << sb.sb_agblklog;
+            let mut agi = mkagi(0, NULLAGINO);
+            agi[20..24].copy_from_slice(&root.to_be_bytes());
+            agi[24..28].copy_from_slice(&level.to_be_bytes());
+            let mut hdr = vec![0u8; sb.geometry.blocksize];
+            hdr[agi_ofs..agi_ofs + agi.len()].copy_from_slice(&agi);
+            blocks.push((agfsb, hdr));
+            for (agbno, block) in btree {
+                blocks.push((agfsb | u64::from(*agbno), block.clone()));
+            }
+        }
+        blocks
+    }
+
+    /// Enumerating each AG separately finds every allocated inode exactly once, skipping free
+    /// inodes and sparse holes
+    #[test]
+    fn all_ags() {
+        let sb = superblock();
+        // Every inode is allocated, except those in the first 8-inode hole and the odd ones
+        let sparse = (0x0003, 0xaaaa_aaaa_aaaa_aaaa);
+        let img = test_support::mkimg(&ag_blocks(&[
+            // AG 0: a single leaf with one full chunk
+            (4, 1, vec![(4, mkleaf(&[(128, 0, 0)], NULLAGBLOCK))]),
+            // AG 1: two levels, with two leaves
+            (
+                5,
+                2,
+                vec![
+                    (5, mknode(1, &[(64, 6), (256, 7)])),
+                    (6, mkleaf(&[(64, 0, 0), (128, sparse.0, sparse.1)], 7)),
+                    (7, mkleaf(&[(256, 0, u64::MAX << 1)], NULLAGBLOCK)),
+                ],
+            ),
+            // AG 2: no inodes at all
+            (4, 1, vec![(4, mkleaf(&[], NULLAGBLOCK))]),
+            // AG 3: one chunk, with only its last inode allocated
+            (
+                4,
+                1,
+                vec![(4, mkleaf(&[(512, 0, u64::MAX >> 1)], NULLAGBLOCK))],
+            ),
+        ]));
+        let mut reader = open(&img);
+
+        let mut expected = Vec::new();
+        expected.extend((128..192).map(|i| sb.agino_to_ino(0, i)));
+        expected.extend((64..128).map(|i| sb.agino_to_ino(1, i)));
+        expected.extend((136..192).step_by(2).map(|i| sb.agino_to_ino(1, i)));
+        expected.push(sb.agino_to_ino(1, 256));
+        expected.push(sb.agino_to_ino(3, 575));
+
+        let actual = (0..sb.sb_agcount)
+            .flat_map(|agno| ag_inodes(&mut reader, sb, agno).unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(actual, expected);
+    }
+
+    /// On the golden images, each AG yields exactly the inodes that its AGI counts as allocated
+    /// and not free
+    #[rstest]
+    fn golden(#[values("xfs1024.img", "xfs4096.img", "xfs_4kn.img", "xfsv4.img")] filename: &str) {
+        let img = test_support::golden(filename);
+        let mut reader = BlockReader::open(img.path()).unwrap();
+        let sb = Sb::from(&mut reader).unwrap();
+        reader.set_bufsize(sb.geometry.blocksize);
+        let mut total = 0;
+        for agno in 0..sb.sb_agcount {
+            let agi = Agi::from(&mut reader, &sb, agno).unwrap();
+            let inodes = ag_inodes(&mut reader, &sb, agno).unwrap();
+            assert_eq!(
+                inodes.len() as u64,
+                u64::from(agi.agi_count - agi.agi_freecount),
+                "AG {}",
+                agno
+            );
+            assert!(inodes.iter().all(|ino| sb.ino_to_agino(*ino).0 == agno));
+            total += inodes.len() as u64;
+        }
+        assert_eq!(total, sb.sb_icount - sb.sb_ifree);
+    }
+
+    /// A block with the wrong magic is reported as corruption
+    #[test]
+    fn bad_magic() {
+        let mut leaf = mkleaf(&[(128, 0, 0)], NULLAGBLOCK);
+        leaf[0..4].copy_from_slice(&XFS_BMAP_CRC_MAGIC.to_be_bytes());
+        let img = test_support::mkimg(&ag_blocks(&[(4, 1, vec![(4, leaf)])]));
+        let mut reader = open(&img);
+        assert!(matches!(
+            ag_inodes(&mut reader, superblock(), 0),
+            Err(XfsError::Corrupt(_))
+        ));
+    }
+
+    /// A node whose level disagrees with the AGI is reported as corruption
+    #[test]
+    fn bad_level() {
+        let img = test_support::mkimg(&ag_blocks(&[(
+            5,
+            3,
+            vec![
+                (5, mknode(1, &[(64, 6)])),
+                (6, mkleaf(&[(64, 0, 0)], NULLAGBLOCK)),
+            ],
+        )]));
+        let mut reader = open(&img);
+        assert!(matches!(
+            ag_inodes(&mut reader, superblock(), 0),
+            Err(XfsError::Corrupt(_))
+        ));
+    }
+
+    /// Leaves whose sibling pointers form a loop are reported as corruption, rather than
+    /// enumerated forever
+    #[test]
+    fn sibling_loop() {
+        let img = test_support::mkimg(&ag_blocks(&[(4, 1, vec![(4, mkleaf(&[(128, 0, 0)], 4))])]));
+        let mut reader = open(&img);
+        assert!(matches!(
+            ag_inodes(&mut reader, superblock(), 0),
+            Err(XfsError::Corrupt(_))
+        ));
+    }
+}
@@ -25,6 +25,8 @@
  * OR TORT (INCLUDING NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE USE
  * OF THIS SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
  */
+mod agf;
+mod agi;
 mod attr;
 mod attr_bptree;
 mod attr_leaf;
@@ -41,11 +43,18 @@ mod dir3;
 mod dir3_block;
 mod dir3_lf;
 mod dir3_sf;
+mod error;
 mod file;
 mod file_btree;
 mod file_extent_list;
+mod inobt;
+pub mod patch;
+mod path;
+mod quota;
 mod sb;
 mod symlink_extent;
+#[cfg(test)]
+mod test_support;
 mod utils;
 pub mod volume;
 
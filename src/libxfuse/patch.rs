@@ -0,0 +1,316 @@
+/*
+ * BSD 2-Clause License
+ *
+ * Copyright (c) 2021, Khaled Emara
+ * All rights reserved.
+ *
+ * Redistribution and use in source and binary forms, with or without
+ * modification, are permitted provided that the following conditions are met:
+ *
+ * 1. Redistributions of source code must retain the above copyright notice, this
+ *    list of conditions and the following disclaimer.
+ *
+ * 2. Redistributions in binary form must reproduce the above copyright notice,
+ *    this list of conditions and the following disclaimer in the documentation
+ *    and/or other materials provided with the distribution.
+ *
+ * THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS IS"
+ * AND ANY EXPRESS OR IMPLIED WARRANTIES, INCLUDING, BUT NOT LIMITED TO, THE
+ * IMPLIED WARRANTIES OF MERCHANTABILITY AND FITNESS FOR A PARTICULAR PURPOSE ARE
+ * DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT HOLDER OR CONTRIBUTORS BE LIABLE
+ * FOR ANY DIRECT, INDIRECT, INCIDENTAL, SPECIAL, EXEMPLARY, OR CONSEQUENTIAL
+ * DAMAGES (INCLUDING, BUT NOT LIMITED TO, PROCUREMENT OF SUBSTITUTE GOODS OR
+ * SERVICES; LOSS OF USE, DATA, OR PROFITS; OR BUSINESS INTERRUPTION) HOWEVER
+ * CAUSED AND ON ANY THEORY OF LIABILITY, WHETHER IN CONTRACT, STRICT LIABILITY,
+ * OR TORT (INCLUDING NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE USE
+ * OF THIS SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+ */
+use std::{
+    collections::HashMap,
+    fs,
+    io::{self, ErrorKind},
+    ops::RangeInclusive,
+    path::Path,
+};
+
+use tracing::warn;
+
+use super::{
+    definitions::XfsIno,
+    dinode_core::{DinodeCore, XFS_BIGTIME_TIME_MAX},
+};
+
+/// An inode field that may be overridden by a patch file
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+enum PatchField {
+    Mode,
+    Uid,
+    Gid,
+    Nlink,
+    Size,
+    Nblocks,
+    Atime,
+    Mtime,
+    Ctime,
+}
+
+impl PatchField {
+    fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "di_mode" => Some(PatchField::Mode),
+            "di_uid" => Some(PatchField::Uid),
+            "di_gid" => Some(PatchField::Gid),
+            "di_nlink" => Some(PatchField::Nlink),
+            "di_size" => Some(PatchField::Size),
+            "di_nblocks" => Some(PatchField::Nblocks),
+            "di_atime" => Some(PatchField::Atime),
+            "di_mtime" => Some(PatchField::Mtime),
+            "di_ctime" => Some(PatchField::Ctime),
+            _ => None,
+        }
+    }
+
+    /// The values that the field's on-disk type can hold.  Sizes and block counts can't be
+    /// negative, even though di_size is signed.  Timestamps may go as late as bigtime allows;
+    /// whether a particular inode can hold one past 2038 is only known once it's read.
+    fn range(self) -> RangeInclusive<i64> {
+        match self {
+            PatchField::Mode => 0..=i64::from(u16::MAX),
+            PatchField::Uid | PatchField::Gid | PatchField::Nlink => 0..=i64::from(u32::MAX),
+            PatchField::Size | PatchField::Nblocks => 0..=i64::MAX,
+            PatchField::Atime | PatchField::Mtime | PatchField::Ctime => {
+                i64::from(i32::MIN)..=XFS_BIGTIME_TIME_MAX
+            }
+        }
+    }
+}
+
+/// A set of inode field overrides, applied after each inode is decoded.  The inode's forks are
+/// decoded according to its on-disk metadata, so an override can't make them be misread.
+///
+/// This lets the user ask "what if" questions about an image without modifying it.  The patch
+/// file contains one override per line, in the form `<inode> <field> <value>`.  Blank lines and
+/// lines beginning with `#` are ignored.  Timestamps are given in seconds since the Epoch.
+#[derive(Clone, Debug, Default)]
+pub struct InodePatches(HashMap<XfsIno, Vec<(PatchField, i64)>>);
+
+impl InodePatches {
+    pub fn from_file(path: &Path) -> io::Result<Self> {
+        fs::read_to_string(path)?.parse()
+    }
+
+    /// Apply any overrides for the given inode
+    pub fn apply(&self, ino: XfsIno, di_core: &mut DinodeCore) {
+        let Some(patches) = self.0.get(&ino) else {
+            return;
+        };
+        // Parsing checked that every value fits its field, so none of these casts truncate.
+        for (field, value) in patches {
+            let value = *value;
+            match field {
+                PatchField::Mode => di_core.di_mode = value as u16,
+                PatchField::Uid => di_core.di_uid = value as u32,
+                PatchField::Gid => di_core.di_gid = value as u32,
+                PatchField::Nlink => di_core.di_nlink = value as u32,
+                PatchField::Size => di_core.di_size = value,
+                PatchField::Nblocks => di_core.di_nblocks = value as u64,
+                PatchField::Atime | PatchField::Mtime | PatchField::Ctime => {
+                    let Some(ts) = di_core.encode_timestamp(value) else {
+                        warn!(
+                            "Inode {} can't hold a timestamp of {} without bigtime; ignoring its \
+                             {:?} patch",
+                            ino, value, field
+                        );
+                        continue;
+                    };
+                    match field {
+                        PatchField::Atime => di_core.di_atime = ts,
+                        PatchField::Mtime => di_core.di_mtime = ts,
+                        _ => di_core.di_ctime = ts,
+                    }
+                }
+            }
+        }
+    }
+}
+
+impl std::str::FromStr for InodePatches {
+    type Err = io::Error;
+
+    fn from_str(s: &str) -> io::Result<Self> {
+        let mut patches = HashMap::<XfsIno, Vec<(PatchField, i64)>>::new();
+        for (i, line) in s.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let err = |msg: &str| {
+                io::Error::new(ErrorKind::InvalidData, format!("line {}: {msg}", i + 1))
+            };
+            let mut words = line.split_whitespace();
+            let (Some(ino), Some(field), Some(value), None) =
+                (words.next(), words.next(), words.next(), words.next())
+            else {
+                return Err(err("expected <inode> <field> <value>"));
+            };
+            let ino = ino
+                .parse::<XfsIno>()
+                .map_err(|_| err("invalid inode number"))?;
+            let name = field;
+            let field = PatchField::from_name(name).ok_or_else(|| err("unknown field"))?;
+            let value = value.parse::<i64>().map_err(|_| err("invalid value"))?;
+            let range = field.range();
+            if !range.contains(&value) {
+                return Err(err(&format!(
+                    "{name} must be between {} and {}",
+                    range.start(),
+                    range.end()
+                )));
+            }
+            patches.entry(ino).or_default().push((field, value));
+        }
+        Ok(InodePatches(patches))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, UNIX_EPOCH};
+
+    use rstest::rstest;
+
+    use super::*;
+    use crate::libxfuse::{dinode_core::XfsTimestamp, sb::tests::superblock};
+
+    #[test]
+    fn apply() {
+        let patches: InodePatches = "# comment\n\n128 di_size 1000\n128 di_uid 42\n129 di_size 7\n"
+            .parse()
+            .unwrap();
+        let mut dic = DinodeCore {
+            di_size: 14,
+            ..Default::default()
+        };
+        patches.apply(128, &mut dic);
+        assert_eq!(dic.di_size, 1000);
+        assert_eq!(dic.di_uid, 42);
+    }
+
+    #[test]
+    fn unaffected() {
+        let patches: InodePatches = "129 di_size 7".parse().unwrap();
+        let mut dic = DinodeCore {
+            di_size: 14,
+            ..Default::default()
+        };
+        patches.apply(128, &mut dic);
+        assert_eq!(dic.di_size, 14);
+    }
+
+    #[test]
+    fn unknown_field() {
+        let e = "128 di_bogus 7".parse::<InodePatches>().unwrap_err();
+        assert_eq!(e.to_string(), "line 1: unknown field");
+    }
+
+    #[test]
+    fn missing_value() {
+        let e = "128 di_size".parse::<InodePatches>().unwrap_err();
+        assert_eq!(e.to_string(), "line 1: expected <inode> <field> <value>");
+    }
+
+    /// Values that don't fit their field's on-disk type are rejected, rather than truncated
+    #[rstest]
+    #[case::mode_too_big("128 di_mode 0x12345", "line 1: invalid value")]
+    #[case::mode("128 di_mode 74565", "line 1: di_mode must be between 0 and 65535")]
+    #[case::uid("128 di_uid -1", "line 1: di_uid must be between 0 and 4294967295")]
+    #[case::nlink(
+        "128 di_nlink 4294967296",
+        "line 1: di_nlink must be between 0 and 4294967295"
+    )]
+    #[case::size(
+        "128 di_size -1",
+        "line 1: di_size must be between 0 and 9223372036854775807"
+    )]
+    #[case::mtime(
+        "128 di_mtime 1099511627776",
+        "line 1: di_mtime must be between -2147483648 and 16299260425"
+    )]
+    fn out_of_range(#[case] line: &str, #[case] expected: &str) {
+        let e = line.parse::<InodePatches>().unwrap_err();
+        assert_eq!(e.to_string(), expected);
+    }
+
+    /// The extremes of each field's range are accepted
+    #[test]
+    fn in_range() {
+        let patches: InodePatches = "128 di_mode 65535\n128 di_uid 4294967295\n128 di_atime \
+                                     -2147483648\n"
+            .parse()
+            .unwrap();
+        let mut dic = DinodeCore::default();
+        patches.apply(128, &mut dic);
+        assert_eq!(dic.di_mode, u16::MAX);
+        assert_eq!(dic.di_uid, u32::MAX);
+        assert_eq!(dic.di_atime.t_sec, i32::MIN);
+    }
+
+    /// A patched timestamp replaces the whole on-disk timestamp, nanoseconds included
+    #[test]
+    fn timestamp() {
+        let patches: InodePatches = "128 di_mtime 1000".parse().unwrap();
+        let mut dic = DinodeCore {
+            di_mode: 0o100644,
+            di_version: 3,
+            di_ino: 128,
+            di_mtime: XfsTimestamp {
+                t_sec:  5,
+                t_nsec: 500_000_000,
+            },
+            ..Default::default()
+        };
+        patches.apply(128, &mut dic);
+        let attr = dic.stat(128, superblock()).unwrap();
+        assert_eq!(attr.mtime, UNIX_EPOCH + Duration::from_secs(1000));
+    }
+
+    /// Timestamps past 2038 can be set on bigtime inodes, in their own encoding
+    #[rstest]
+    #[case::before_epoch(-86400)]
+    #[case::y2100(4102444800)]
+    #[case::latest(XFS_BIGTIME_TIME_MAX)]
+    fn timestamp_bigtime(#[case] secs: i64) {
+        let patches: InodePatches = format!("128 di_atime {}", secs).parse().unwrap();
+        let mut dic = DinodeCore {
+            di_mode: 0o100644,
+            di_version: 3,
+            di_ino: 128,
+            di_flags2: 1 << 3, // XFS_DIFLAG2_BIGTIME
+            ..Default::default()
+        };
+        patches.apply(128, &mut dic);
+        let attr = dic.stat(128, superblock()).unwrap();
+        let expected = if secs < 0 {
+            UNIX_EPOCH - Duration::from_secs(secs.unsigned_abs())
+        } else {
+            UNIX_EPOCH + Duration::from_secs(secs as u64)
+        };
+        assert_eq!(attr.atime, expected);
+    }
+
+    /// Without bigtime, a timestamp past 2038 can't be stored, so its patch is ignored rather than
+    /// truncated
+    #[test]
+    fn timestamp_too_late() {
+        let patches: InodePatches = "128 di_ctime 4102444800".parse().unwrap();
+        let mut dic = DinodeCore {
+            di_ctime: XfsTimestamp {
+                t_sec:  5,
+                t_nsec: 0,
+            },
+            ..Default::default()
+        };
+        patches.apply(128, &mut dic);
+        assert_eq!(dic.di_ctime.t_sec, 5);
+    }
+}
@@ -0,0 +1,238 @@
+/*
+ * BSD 2-Clause License
+ *
+ * Copyright (c) 2021, Khaled Emara
+ * All rights reserved.
+ *
+ * Redistribution and use in source and binary forms, with or without
+ * modification, are permitted provided that the following conditions are met:
+ *
+ * 1. Redistributions of source code must retain the above copyright notice, this
+ *    list of conditions and the following disclaimer.
+ *
+ * 2. Redistributions in binary form must reproduce the above copyright notice,
+ *    this list of conditions and the following disclaimer in the documentation
+ *    and/or other materials provided with the distribution.
+ *
+ * THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS IS"
+ * AND ANY EXPRESS OR IMPLIED WARRANTIES, INCLUDING, BUT NOT LIMITED TO, THE
+ * IMPLIED WARRANTIES OF MERCHANTABILITY AND FITNESS FOR A PARTICULAR PURPOSE ARE
+ * DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT HOLDER OR CONTRIBUTORS BE LIABLE
+ * FOR ANY DIRECT, INDIRECT, INCIDENTAL, SPECIAL, EXEMPLARY, OR CONSEQUENTIAL
+ * DAMAGES (INCLUDING, BUT NOT LIMITED TO, PROCUREMENT OF SUBSTITUTE GOODS OR
+ * SERVICES; LOSS OF USE, DATA, OR PROFITS; OR BUSINESS INTERRUPTION) HOWEVER
+ * CAUSED AND ON ANY THEORY OF LIABILITY, WHETHER IN CONTRACT, STRICT LIABILITY,
+ * OR TORT (INCLUDING NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE USE
+ * OF THIS SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+ */
+use std::{
+    ffi::{OsStr, OsString},
+    os::unix::ffi::OsStrExt,
+    path::{Component, Path},
+};
+
+use super::{definitions::XfsIno, error::XfsError};
+
+/// The most symbolic links that may be followed while resolving a single path, as on FreeBSD.
+pub const MAXSYMLINKS: usize = 32;
+
+/// What path resolution needs to know about an inode
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum PathNode {
+    Dir,
+    /// A symbolic link, and its target
+    Symlink(OsString),
+    Other,
+}
+
+/// A directory tree in which paths can be resolved
+pub trait Namespace {
+    /// Describe the given inode
+    fn node(&mut self, ino: XfsIno) -> Result<PathNode, XfsError>;
+
+    /// Look up a name in the given directory
+    fn lookup(&mut self, dir: XfsIno, name: &OsStr) -> Result<XfsIno, XfsError>;
+}
+
+/// Resolve a path, relative to `root`, to an inode number.  Symbolic links in intermediate
+/// components are always followed, as are absolute links, relative to `root`.  A terminal link is
+/// only followed if `follow` is set, like the `-L` option of many commands, rather than `-P`.
+/// Following more than `max_links` links fails with ELOOP.
+pub fn resolve<N: Namespace>(
+    ns: &mut N,
+    root: XfsIno,
+    path: &Path,
+    follow: bool,
+    max_links: usize,
+) -> Result<XfsIno, XfsError> {
+    // The directories leading to the current one, so that ".." can be resolved
+    let mut dirs = vec![root];
+    // The components yet to be resolved, in reverse order
+    let mut pending = Vec::new();
+    push_components(&mut pending, &mut dirs, path);
+    let mut links = 0;
+    while let Some(name) = pending.pop() {
+        if name == ".." {
+            if dirs.len() > 1 {
+                dirs.pop();
+            }
+            continue;
+        }
+        let dir = *dirs.last().unwrap();
+        if ns.node(dir)? != PathNode::Dir {
+            return Err(XfsError::NotDir);
+        }
+        let ino = ns.lookup(dir, &name)?;
+        match ns.node(ino)? {
+            PathNode::Symlink(target) if follow || !pending.is_empty() => {
+                links += 1;
+                if links > max_links {
+                    return Err(XfsError::Loop);
+                }
+                push_components(&mut pending, &mut dirs, Path::new(&target));
+            }
+            _ => dirs.push(ino),
+        }
+    }
+    Ok(*dirs.last().unwrap())
+}
+
+/// Queue up a path's components for resolution.  An absolute path restarts from the root.
+fn push_components(pending: &mut Vec<OsString>, dirs: &mut Vec<XfsIno>, path: &Path) {
+    if path.has_root() {
+        dirs.truncate(1);
+    }
+    let start = pending.len();
+    for c in path.components() {
+        match c {
+            Component::Normal(name) => pending.push(name.to_owned()),
+            Component::ParentDir => pending.push(OsStr::from_bytes(b"..").to_owned()),
+            Component::RootDir | Component::CurDir | Component::Prefix(_) => (),
+        }
+    }
+    pending[start..].reverse();
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use rstest::rstest;
+
+    use super::*;
+
+    /// An in-memory directory tree.  Inode 1 is the root.
+    #[derive(Default)]
+    struct Tree {
+        nodes:   HashMap<XfsIno, PathNode>,
+        entries: HashMap<(XfsIno, OsString), XfsIno>,
+    }
+
+    impl Tree {
+        fn add(&mut self, dir: XfsIno, name: &str, ino: XfsIno, node: PathNode) {
+            self.nodes.insert(ino, node);
+            self.entries.insert((dir, name.into()), ino);
+        }
+
+        fn link(&mut self, dir: XfsIno, name: &str, ino: XfsIno, target: &str) {
+            self.add(dir, name, ino, PathNode::Symlink(target.into()));
+        }
+
+        /// A root directory containing:
+        ///   a/b/file
+        ///   a/up -> ..
+        ///   abs -> /a/b
+        ///   rel -> a/b
+        ///   chain0 -> chain1 -> ... -> chainN -> a/b/file
+        ///   self -> self
+        ///   file.lnk -> a/b/file
+        ///   dangling -> nonexistent
+        fn new(chain: usize) -> Self {
+            let mut tree = Tree::default();
+            tree.nodes.insert(1, PathNode::Dir);
+            tree.add(1, "a", 2, PathNode::Dir);
+            tree.add(2, "b", 3, PathNode::Dir);
+            tree.add(3, "file", 4, PathNode::Other);
+            tree.link(2, "up", 5, "..");
+            tree.link(1, "abs", 6, "/a/b");
+            tree.link(1, "rel", 7, "a/b");
+            tree.link(1, "self", 8, "self");
+            tree.link(1, "file.lnk", 9, "a/b/file");
+            tree.link(1, "dangling", 10, "nonexistent");
+            for i in 0..chain {
+                let target = if i + 1 < chain {
+                    format!("chain{}", i + 1)
+                } else {
+                    "a/b/file".into()
+                };
+                tree.link(1, &format!("chain{}", i), 100 + i as XfsIno, &target);
+            }
+            tree
+        }
+    }
+
+    impl Namespace for Tree {
+        fn node(&mut self, ino: XfsIno) -> Result<PathNode, XfsError> {
+            Ok(self.nodes[&ino].clone())
+        }
+
+        fn lookup(&mut self, dir: XfsIno, name: &OsStr) -> Result<XfsIno, XfsError> {
+            self.entries
+                .get(&(dir, name.to_owned()))
+                .copied()
+                .ok_or(XfsError::NotFound)
+        }
+    }
+
+    #[rstest]
+    #[case::root("/", false, Ok(1))]
+    #[case::empty("", false, Ok(1))]
+    #[case::deep("a/b/file", false, Ok(4))]
+    #[case::absolute("/a/b/file", false, Ok(4))]
+    #[case::dots("a/./b/../b/file", false, Ok(4))]
+    #[case::dotdot_at_root("../a", false, Ok(2))]
+    #[case::intermediate_relative("rel/file", false, Ok(4))]
+    #[case::intermediate_absolute("abs/file", false, Ok(4))]
+    #[case::intermediate_dotdot("a/up/a/b", false, Ok(3))]
+    #[case::terminal_physical("file.lnk", false, Ok(9))]
+    #[case::terminal_logical("file.lnk", true, Ok(4))]
+    #[case::dangling_physical("dangling", false, Ok(10))]
+    #[case::dangling_logical("dangling", true, Err(XfsError::NotFound))]
+    #[case::nonexistent("a/nonexistent", false, Err(XfsError::NotFound))]
+    #[case::not_dir("a/b/file/x", false, Err(XfsError::NotDir))]
+    #[case::self_physical("self", false, Ok(8))]
+    #[case::self_logical("self", true, Err(XfsError::Loop))]
+    #[case::self_intermediate("self/x", false, Err(XfsError::Loop))]
+    fn resolve_path(
+        #[case] path: &str,
+        #[case] follow: bool,
+        #[case] expected: Result<XfsIno, XfsError>,
+    ) {
+        let mut tree = Tree::new(0);
+        assert_eq!(
+            resolve(&mut tree, 1, Path::new(path), follow, MAXSYMLINKS),
+            expected
+        );
+    }
+
+    /// A chain of exactly the maximum number of links can be followed, but no more
+    #[rstest]
+    #[case::max(MAXSYMLINKS, Ok(4))]
+    #[case::too_long(MAXSYMLINKS + 1, Err(XfsError::Loop))]
+    fn resolve_chain(#[case] chain: usize, #[case] expected: Result<XfsIno, XfsError>) {
+        let mut tree = Tree::new(chain);
+        assert_eq!(
+            resolve(&mut tree, 1, Path::new("chain0"), true, MAXSYMLINKS),
+            expected
+        );
+        // The limit is configurable
+        assert_eq!(
+            resolve(&mut tree, 1, Path::new("chain0"), true, chain),
+            Ok(4)
+        );
+        assert_eq!(
+            resolve(&mut tree, 1, Path::new("chain0"), true, chain - 1),
+            Err(XfsError::Loop)
+        );
+    }
+}
@@ -0,0 +1,310 @@
+/*
+ * BSD 2-Clause License
+ *
+ * Copyright (c) 2021, Khaled Emara
+ * All rights reserved.
+ *
+ * Redistribution and use in source and binary forms, with or without
+ * modification, are permitted provided that the following conditions are met:
+ *
+ * 1. Redistributions of source code must retain the above copyright notice, this
+ *    list of conditions and the following disclaimer.
+ *
+ * 2. Redistributions in binary form must reproduce the above copyright notice,
+ *    this list of conditions and the following disclaimer in the documentation
+ *    and/or other materials provided with the distribution.
+ *
+ * THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS IS"
+ * AND ANY EXPRESS OR IMPLIED WARRANTIES, INCLUDING, BUT NOT LIMITED TO, THE
+ * IMPLIED WARRANTIES OF MERCHANTABILITY AND FITNESS FOR A PARTICULAR PURPOSE ARE
+ * DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT HOLDER OR CONTRIBUTORS BE LIABLE
+ * FOR ANY DIRECT, INDIRECT, INCIDENTAL, SPECIAL, EXEMPLARY, OR CONSEQUENTIAL
+ * DAMAGES (INCLUDING, BUT NOT LIMITED TO, PROCUREMENT OF SUBSTITUTE GOODS OR
+ * SERVICES; LOSS OF USE, DATA, OR PROFITS; OR BUSINESS INTERRUPTION) HOWEVER
+ * CAUSED AND ON ANY THEORY OF LIABILITY, WHETHER IN CONTRACT, STRICT LIABILITY,
+ * OR TORT (INCLUDING NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE USE
+ * OF THIS SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+ */
+use std::io::{BufRead, Seek, SeekFrom};
+
+use bincode::Decode;
+use crc::{Crc, CRC_32_ISCSI};
+
+use super::{
+    definitions::*,
+    error::XfsError,
+    file::ExtentInfo,
+    sb::Sb,
+    utils::{decode, Uuid},
+};
+
+const XFS_DQTYPE_USER: u8 = 0x01;
+const XFS_DQTYPE_PROJ: u8 = 0x02;
+const XFS_DQTYPE_GROUP: u8 = 0x04;
+const XFS_DQTYPE_REC_MASK: u8 = XFS_DQTYPE_USER | XFS_DQTYPE_PROJ | XFS_DQTYPE_GROUP;
+
+/// Size of one on-disk quota record, `xfs_dqblk`, in bytes
+const DQBLK_SIZE: usize = 136;
+/// Offset of `dd_crc` within `xfs_dqblk`
+const DQBLK_CRC_OFF: usize = 108;
+
+/// The usage and limits of one user, group, or project, as stored in a quota inode
+#[derive(Clone, Copy, Debug, Decode, Eq, PartialEq)]
+pub struct Dquot {
+    d_magic:             u16,
+    d_version:           u8,
+    d_type:              u8,
+    /// User, group, or project ID
+    pub d_id:            u32,
+    /// Hard limit on the number of blocks
+    pub d_blk_hardlimit: u64,
+    /// Soft limit on the number of blocks
+    pub d_blk_softlimit: u64,
+    /// Hard limit on the number of inodes
+    pub d_ino_hardlimit: u64,
+    /// Soft limit on the number of inodes
+    pub d_ino_softlimit: u64,
+    /// Number of blocks in use
+    pub d_bcount:        u64,
+    /// Number of inodes in use
+    pub d_icount:        u64,
+    // The timers, warning counts, and realtime fields follow, but aren't reported
+}
+
+impl Dquot {
+    /// Whether this is a user, group, or project quota.  Version 4 file systems store either
+    /// group or project quotas in the same inode, so the record itself must say which.
+    pub fn kind(&self) -> &'static str {
+        match self.d_type & XFS_DQTYPE_REC_MASK {
+            XFS_DQTYPE_USER => "user",
+            XFS_DQTYPE_PROJ => "project",
+            XFS_DQTYPE_GROUP => "group",
+            _ => "unknown",
+        }
+    }
+
+    /// Does this record hold anything besides zeros?  The record for ID 0 always does, because
+    /// its limits are the defaults for every other ID.
+    fn is_used(&self) -> bool {
+        self.d_id == 0
+            || self.d_blk_hardlimit != 0
+            || self.d_blk_softlimit != 0
+            || self.d_ino_hardlimit != 0
+            || self.d_ino_softlimit != 0
+            || self.d_bcount != 0
+            || self.d_icount != 0
+    }
+}
+
+/// Check one raw `xfs_dqblk` record, and decode it
+fn decode_dqblk(raw: &[u8], superblock: &Sb, id: u32) -> Result<Dquot, XfsError> {
+    let dquot: Dquot = decode(raw)?.0;
+    if dquot.d_magic != XFS_DQUOT_MAGIC {
+        return Err(XfsError::Corrupt(format!(
+            "quota record {} has bad magic {:#x}",
+            id, dquot.d_magic
+        )));
+    }
+    if dquot.d_id != id {
+        return Err(XfsError::Corrupt(format!(
+            "quota record {} has ID {}",
+            id, dquot.d_id
+        )));
+    }
+    if superblock.version() == 5 {
+        const CASTAGNOLI: Crc<u32> = Crc::<u32>::new(&CRC_32_ISCSI);
+        let mut digest = CASTAGNOLI.digest();
+        digest.update(&raw[..DQBLK_CRC_OFF]);
+        digest.update(&[0u8; 4]);
+        digest.update(&raw[DQBLK_CRC_OFF + 4..DQBLK_SIZE]);
+        let crc = u32::from_le_bytes(raw[DQBLK_CRC_OFF..DQBLK_CRC_OFF + 4].try_into().unwrap());
+        if digest.finalize() != crc {
+            return Err(XfsError::Corrupt(format!(
+                "quota record {} failed its CRC check",
+                id
+            )));
+        }
+        let uuid: Uuid = decode(&raw[DQBLK_SIZE - 16..])?.0;
+        if uuid != superblock.sb_uuid {
+            return Err(XfsError::Corrupt(format!(
+                "quota record {} has the wrong UUID",
+                id
+            )));
+        }
+    }
+    Ok(dquot)
+}
+
+/// Read every record in use from a quota inode, given its data fork's extents.  IDs are stored
+/// in order, a fixed number per block, so an ID's location follows from its value.  Records
+/// within holes or unwritten extents have never been used.
+pub fn dquots<R>(
+    buf_reader: &mut R,
+    superblock: &Sb,
+    extents: &[ExtentInfo],
+) -> Result<Vec<Dquot>, XfsError>
+where
+    R: BufRead + Seek,
+{
+    let blocksize = superblock.sb_blocksize as usize;
+    let per_block = blocksize / DQBLK_SIZE;
+    let mut buf = vec![0u8; blocksize];
+    let mut dquots = Vec::new();
+    for extent in extents.iter().filter(|e| !e.unwritten) {
+        let nblocks = extent.length >> superblock.sb_blocklog;
+        let first = extent.file_offset >> superblock.sb_blocklog;
+        for i in 0..nblocks {
+            buf_reader.seek(SeekFrom::Start(
+                superblock.fsb_to_offset(extent.fsblock + i),
+            ))?;
+            buf_reader.read_exact(&mut buf)?;
+            for (j, raw) in buf.chunks_exact(DQBLK_SIZE).enumerate() {
+                let id = u32::try_from((first + i) * per_block as u64 + j as u64)
+                    .map_err(|_| XfsError::Corrupt("quota inode is too large".to_string()))?;
+                let dquot = decode_dqblk(raw, superblock, id)?;
+                if dquot.is_used() {
+                    dquots.push(dquot);
+                }
+            }
+        }
+    }
+    Ok(dquots)
+}
+
+#[cfg(test)]
+mod tests {
+    use rstest::rstest;
+
+    use super::*;
+    use crate::libxfuse::{
+        sb::tests::superblock,
+        test_support::{self, open},
+    };
+
+    /// Build a block of initialized V5 quota records, starting with `first`, and apply `f` to the
+    /// raw bytes of each record before its CRC is computed
+    fn mkblock<F>(first: u32, mut f: F) -> Vec<u8>
+    where
+        F: FnMut(u32, &mut [u8]),
+    {
+        let sb = superblock();
+        let mut raw = vec![0u8; sb.sb_blocksize as usize];
+        for (i, rec) in raw.chunks_exact_mut(DQBLK_SIZE).enumerate() {
+            let id = first + i as u32;
+            rec[0..2].copy_from_slice(&XFS_DQUOT_MAGIC.to_be_bytes());
+            rec[2] = 1;
+            rec[3] = XFS_DQTYPE_USER;
+            rec[4..8].copy_from_slice(&id.to_be_bytes());
+            f(id, rec);
+            const CASTAGNOLI: Crc<u32> = Crc::<u32>::new(&CRC_32_ISCSI);
+            let mut digest = CASTAGNOLI.digest();
+            digest.update(&rec[..DQBLK_CRC_OFF]);
+            digest.update(&[0u8; 4]);
+            digest.update(&rec[DQBLK_CRC_OFF + 4..]);
+            rec[DQBLK_CRC_OFF..DQBLK_CRC_OFF + 4].copy_from_slice(&digest.finalize().to_le_bytes());
+        }
+        raw
+    }
+
+    /// Set a record's usage and limits: blocks, block soft limit, inodes, inode soft limit
+    fn set_usage(rec: &mut [u8], bcount: u64, bsoft: u64, icount: u64, isoft: u64) {
+        rec[16..24].copy_from_slice(&bsoft.to_be_bytes());
+        rec[32..40].copy_from_slice(&isoft.to_be_bytes());
+        rec[40..48].copy_from_slice(&bcount.to_be_bytes());
+        rec[48..56].copy_from_slice(&icount.to_be_bytes());
+    }
+
+    /// Build a quota file from the given blocks, returning the image holding them and extents
+    /// mapping quota file block `fileblk` to `fsblock`, one block apiece
+    fn quota_file(
+        blocks: &[(u64, XfsFsblock, Vec<u8>)],
+    ) -> (tempfile::NamedTempFile, Vec<ExtentInfo>) {
+        let sb = superblock();
+        let img = test_support::mkimg(
+            &blocks
+                .iter()
+                .map(|(_, fsblock, block)| (*fsblock, block))
+                .collect::<Vec<_>>(),
+        );
+        let extents = blocks
+            .iter()
+            .map(|(fileblk, fsblock, _)| ExtentInfo {
+                file_offset: fileblk << sb.sb_blocklog,
+                fsblock:     *fsblock,
+                length:      u64::from(sb.sb_blocksize),
+                unwritten:   false,
+            })
+            .collect();
+        (img, extents)
+    }
+
+    /// A known user's usage is reported, along with the default limits of ID 0, even if the
+    /// user's record lies beyond a hole in the quota file.  Unused IDs are omitted.
+    #[test]
+    fn usage() {
+        let sb = superblock();
+        let per_block = (sb.sb_blocksize as usize / DQBLK_SIZE) as u32;
+        let (img, mut extents) = quota_file(&[
+            (
+                0,
+                10,
+                mkblock(0, |id, rec| {
+                    if id == 0 {
+                        set_usage(rec, 4, 1000, 5, 100);
+                    }
+                }),
+            ),
+            (
+                1000 / u64::from(per_block),
+                20,
+                mkblock(1000 / per_block * per_block, |id, rec| {
+                    if id == 1000 {
+                        set_usage(rec, 25, 0, 3, 0);
+                    }
+                }),
+            ),
+        ]);
+        // Unwritten extents read as zeros, so they're skipped rather than reported as corrupt
+        extents.push(ExtentInfo {
+            file_offset: 100 << sb.sb_blocklog,
+            fsblock:     30,
+            length:      u64::from(sb.sb_blocksize),
+            unwritten:   true,
+        });
+        let mut reader = open(&img);
+
+        let dquots = dquots(&mut reader, sb, &extents).unwrap();
+        assert_eq!(dquots.len(), 2);
+        assert_eq!(dquots[0].d_id, 0);
+        assert_eq!(dquots[0].d_blk_softlimit, 1000);
+        assert_eq!(dquots[0].d_ino_softlimit, 100);
+        assert_eq!(dquots[1].kind(), "user");
+        assert_eq!(dquots[1].d_id, 1000);
+        assert_eq!(dquots[1].d_bcount, 25);
+        assert_eq!(dquots[1].d_icount, 3);
+        assert_eq!(dquots[1].d_blk_hardlimit, 0);
+    }
+
+    /// A damaged record is reported as corruption.  The CRC covers the whole record, so it's
+    /// damaged only after the CRC is computed, and everything else before.
+    #[rstest]
+    #[case::bad_magic(|rec: &mut [u8]| rec[0] = 0, false)]
+    #[case::wrong_id(|rec: &mut [u8]| rec[7] = 2, false)]
+    #[case::bad_crc(|rec: &mut [u8]| rec[40] = 1, true)]
+    fn corrupt(#[case] damage: fn(&mut [u8]), #[case] after_crc: bool) {
+        let mut block = mkblock(0, |id, rec| {
+            if id == 1 && !after_crc {
+                damage(rec)
+            }
+        });
+        if after_crc {
+            damage(&mut block[DQBLK_SIZE..2 * DQBLK_SIZE]);
+        }
+        let (img, extents) = quota_file(&[(0, 10, block)]);
+        let mut reader = open(&img);
+        assert!(matches!(
+            dquots(&mut reader, superblock(), &extents),
+            Err(XfsError::Corrupt(_))
+        ));
+    }
+}
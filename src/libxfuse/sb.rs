@@ -30,8 +30,9 @@ use std::io::{prelude::*, SeekFrom};
 use bitflags::bitflags;
 use byteorder::{BigEndian, LittleEndian, ReadBytesExt};
 use crc::{Crc, CRC_32_ISCSI};
+use tracing::warn;
 
-use super::{definitions::*, utils::Uuid};
+use super::{definitions::*, error::XfsError, utils::Uuid};
 
 #[allow(dead_code)]
 mod constants {
@@ -74,6 +75,9 @@ mod constants {
     pub const XFS_SB_FEAT_INCOMPAT_BIGTIME: u32 = 0x00000008;
     pub const XFS_SB_FEAT_INCOMPAT_NEEDSREPAIR: u32 = 0x00000010;
     pub const XFS_SB_FEAT_INCOMPAT_NREXT64: u32 = 0x00000020;
+    pub const XFS_SB_FEAT_INCOMPAT_PARENT: u32 = 0x00000080;
+
+    pub const XFS_SB_FEAT_INCOMPAT_LOG_XATTRS: u32 = 0x00000001;
 }
 
 bitflags! {
@@ -99,6 +103,10 @@ impl SbFeatures2 {
         self.contains(SbFeatures2::Crc)
     }
 
+    pub const fn lazysbcount(&self) -> bool {
+        self.contains(SbFeatures2::LazySbCount)
+    }
+
     pub const fn ftype(&self) -> bool {
         self.contains(SbFeatures2::Ftype)
     }
@@ -114,6 +122,7 @@ bitflags! {
         const Bigtime = constants::XFS_SB_FEAT_INCOMPAT_BIGTIME;
         const NeedsRepair = constants::XFS_SB_FEAT_INCOMPAT_NEEDSREPAIR;
         const NrExt64 = constants::XFS_SB_FEAT_INCOMPAT_NREXT64;
+        const Parent = constants::XFS_SB_FEAT_INCOMPAT_PARENT;
     }
 }
 
@@ -143,12 +152,55 @@ impl SbFeaturesIncompat {
     pub const fn large_extent_counters(&self) -> bool {
         self.contains(SbFeaturesIncompat::NrExt64)
     }
+
+    pub const fn parent(&self) -> bool {
+        self.contains(SbFeaturesIncompat::Parent)
+    }
 }
 
 bitflags! {
     #[repr(transparent)]
     #[derive(Debug, Clone, Copy, PartialEq, Eq)]
-    pub struct SbFeaturesLogIncompat: u32 {}
+    pub struct SbFeaturesLogIncompat: u32 {
+        const Xattrs = constants::XFS_SB_FEAT_INCOMPAT_LOG_XATTRS;
+        const _ = !0;
+    }
+}
+
+impl SbFeaturesLogIncompat {
+    /// Return any bits that we don't know about
+    pub const fn unknown(&self) -> u32 {
+        self.bits() & !SbFeaturesLogIncompat::Xattrs.bits()
+    }
+}
+
+/// Frequently used values derived from the superblock, precomputed at mount time
+#[derive(Clone, Copy, Debug)]
+pub struct Geometry {
+    /// File system block size in bytes
+    pub blocksize:  usize,
+    /// Mask of the byte offset within a file system block
+    pub blockmask:  u64,
+    /// Directory block size in bytes
+    pub dirblksize: usize,
+    /// Mask of the byte offset within a directory block
+    pub dirblkmask: u64,
+    /// Mask of the file system block offset within a directory block
+    pub dirfsbmask: u64,
+}
+
+impl Geometry {
+    fn new(blocklog: u8, dirblklog: u8) -> Self {
+        let blocksize = 1usize << blocklog;
+        let dirblksize = blocksize << dirblklog;
+        Geometry {
+            blocksize,
+            blockmask: blocksize as u64 - 1,
+            dirblksize,
+            dirblkmask: dirblksize as u64 - 1,
+            dirfsbmask: (1u64 << dirblklog) - 1,
+        }
+    }
 }
 
 #[derive(Clone, Copy, Debug)]
@@ -156,44 +208,51 @@ pub struct Sb {
     // sb_magicnum: u32,
     pub sb_blocksize:     u32,
     pub sb_dblocks:       XfsRfsblock,
-    // sb_rblocks: XfsRfsblock,
-    // sb_rextents: XfsRtblock,
+    pub sb_rblocks:       XfsRfsblock,
+    pub sb_rextents:      XfsRtblock,
     pub sb_uuid:          Uuid,
     // sb_logstart: XfsFsblock,
     pub sb_rootino:       XfsIno,
-    // sb_rbmino: XfsIno,
-    // sb_rsumino: XfsIno,
-    // sb_rextsize: XfsAgblock,
+    pub sb_rbmino:        XfsIno,
+    pub sb_rsumino:       XfsIno,
+    pub sb_rextsize:      XfsAgblock,
     pub sb_agblocks:      XfsAgblock,
     pub sb_agcount:       XfsAgnumber,
-    // sb_rbmblocks: XfsExtlen,
+    pub sb_rbmblocks:     XfsExtlen,
     pub sb_logblocks:     XfsExtlen,
     sb_versionnum:        u16,
-    // sb_sectsize: u16,
+    pub sb_sectsize:      u16,
     sb_inodesize:         u16,
     // sb_inopblock: u16,
-    // sb_fname: [u8; 12],
+    sb_fname:             [u8; 12],
     pub sb_blocklog:      u8,
     // sb_sectlog: u8,
     pub sb_inodelog:      u8,
     pub sb_inopblog:      u8,
     pub sb_agblklog:      u8,
-    // sb_rextslog: u8,
+    pub sb_rextslog:      u8,
     // sb_inprogress: u8,
-    // sb_imax_pct: u8,
+    /// The largest percentage of the data blocks that may be used for inodes, or 0 for no limit
+    pub sb_imax_pct:      u8,
     pub sb_icount:        u64,
     pub sb_ifree:         u64,
     pub sb_fdblocks:      u64,
     // sb_frextents: u64,
-    // sb_uquotino: XfsIno,
-    // sb_gquotino: XfsIno,
+    /// User quota inode, if any
+    pub sb_uquotino:      XfsIno,
+    /// Group quota inode, if any.  On version 4 file systems, it may instead be the project
+    /// quota inode.
+    pub sb_gquotino:      XfsIno,
     // sb_qflags: u16,
     // sb_flags: u8,
     // sb_shared_vn: u8,
-    // sb_inoalignmt: XfsExtlen,
-    // sb_unit: u32,
-    // sb_width: u32,
-    pub sb_dirblklog:     u8,
+    /// Inode chunk alignment in file system blocks, if XFS_SB_VERSION_ALIGNBIT is set
+    pub sb_inoalignmt:    XfsExtlen,
+    /// Stripe unit in file system blocks, if the file system has a stripe geometry
+    pub sb_unit:          u32,
+    /// Stripe width in file system blocks, if the file system has a stripe geometry
+    pub sb_width:         u32,
+    // sb_dirblklog: u8,
     // sb_logsectlog: u8,
     // sb_logsectsize: u16,
     // sb_logsunit: u32,
@@ -203,145 +262,295 @@ pub struct Sb {
     // sb_features_ro_compat: u32,
     sb_features_incompat: SbFeaturesIncompat,
     // sb_features_log_incompat: u32,
+    // sb_crc: u32,
+    // sb_spino_align: XfsExtlen,
+    /// Project quota inode, if any.  Only version 5 file systems have it.
+    pub sb_pquotino:      XfsIno,
+    pub geometry:         Geometry,
 }
 
 impl Sb {
     const BBSHIFT: u8 = 9;
 
-    pub fn from<T: BufRead + Seek>(buf_reader: &mut T) -> Sb {
-        let sb_magicnum = buf_reader.read_u32::<BigEndian>().unwrap();
+    /// Read and validate the superblock.  Fail if it's corrupt, or uses features that we don't
+    /// support.
+    pub fn from<T: BufRead + Seek>(buf_reader: &mut T) -> Result<Sb, XfsError> {
+        let sb_magicnum = buf_reader.read_u32::<BigEndian>()?;
         if sb_magicnum != XFS_SB_MAGIC {
-            panic!("Superblock magic number is invalid");
+            return Err(XfsError::Corrupt(
+                "Superblock magic number is invalid".into(),
+            ));
         }
 
-        let sb_blocksize = buf_reader.read_u32::<BigEndian>().unwrap();
-        let sb_dblocks = buf_reader.read_u64::<BigEndian>().unwrap();
-        let _sb_rblocks = buf_reader.read_u64::<BigEndian>().unwrap();
-        let _sb_rextents = buf_reader.read_u64::<BigEndian>().unwrap();
-        let sb_uuid = Uuid::from_u128(buf_reader.read_u128::<BigEndian>().unwrap());
-        let _sb_logstart = buf_reader.read_u64::<BigEndian>().unwrap();
-        let sb_rootino = buf_reader.read_u64::<BigEndian>().unwrap();
-        let _sb_rbmino = buf_reader.read_u64::<BigEndian>().unwrap();
-        let _sb_rsumino = buf_reader.read_u64::<BigEndian>().unwrap();
-        let _sb_rextsize = buf_reader.read_u32::<BigEndian>().unwrap();
-        let sb_agblocks = buf_reader.read_u32::<BigEndian>().unwrap();
-        let sb_agcount = buf_reader.read_u32::<BigEndian>().unwrap();
-        let _sb_rbmblocks = buf_reader.read_u32::<BigEndian>().unwrap();
-        let sb_logblocks = buf_reader.read_u32::<BigEndian>().unwrap();
-        let sb_versionnum = buf_reader.read_u16::<BigEndian>().unwrap();
-        let sb_sectsize = buf_reader.read_u16::<BigEndian>().unwrap();
-        let sb_inodesize = buf_reader.read_u16::<BigEndian>().unwrap();
-        let _sb_inopblock = buf_reader.read_u16::<BigEndian>().unwrap();
+        let sb_blocksize = buf_reader.read_u32::<BigEndian>()?;
+        let sb_dblocks = buf_reader.read_u64::<BigEndian>()?;
+        let sb_rblocks = buf_reader.read_u64::<BigEndian>()?;
+        let sb_rextents = buf_reader.read_u64::<BigEndian>()?;
+        let sb_uuid = Uuid::from_u128(buf_reader.read_u128::<BigEndian>()?);
+        let _sb_logstart = buf_reader.read_u64::<BigEndian>()?;
+        let sb_rootino = buf_reader.read_u64::<BigEndian>()?;
+        let sb_rbmino = buf_reader.read_u64::<BigEndian>()?;
+        let sb_rsumino = buf_reader.read_u64::<BigEndian>()?;
+        let sb_rextsize = buf_reader.read_u32::<BigEndian>()?;
+        let sb_agblocks = buf_reader.read_u32::<BigEndian>()?;
+        let sb_agcount = buf_reader.read_u32::<BigEndian>()?;
+        let sb_rbmblocks = buf_reader.read_u32::<BigEndian>()?;
+        let sb_logblocks = buf_reader.read_u32::<BigEndian>()?;
+        let sb_versionnum = buf_reader.read_u16::<BigEndian>()?;
+        let sb_sectsize = buf_reader.read_u16::<BigEndian>()?;
+        let sb_inodesize = buf_reader.read_u16::<BigEndian>()?;
+        let sb_inopblock = buf_reader.read_u16::<BigEndian>()?;
 
         let mut buf_fname = [0u8; 12];
-        buf_reader.read_exact(&mut buf_fname[..]).unwrap();
-        let _sb_fname = buf_fname;
-
-        let sb_blocklog = buf_reader.read_u8().unwrap();
-        let _sb_sectlog = buf_reader.read_u8().unwrap();
-        let sb_inodelog = buf_reader.read_u8().unwrap();
-        let sb_inopblog = buf_reader.read_u8().unwrap();
-        let sb_agblklog = buf_reader.read_u8().unwrap();
-        let _sb_rextslog = buf_reader.read_u8().unwrap();
-        let _sb_inprogress = buf_reader.read_u8().unwrap();
-        let _sb_imax_pct = buf_reader.read_u8().unwrap();
-        let sb_icount = buf_reader.read_u64::<BigEndian>().unwrap();
-        let sb_ifree = buf_reader.read_u64::<BigEndian>().unwrap();
-        let sb_fdblocks = buf_reader.read_u64::<BigEndian>().unwrap();
-        let _sb_frextents = buf_reader.read_u64::<BigEndian>().unwrap();
-        let _sb_uquotino = buf_reader.read_u64::<BigEndian>().unwrap();
-        let _sb_gquotino = buf_reader.read_u64::<BigEndian>().unwrap();
-        let _sb_qflags = buf_reader.read_u16::<BigEndian>().unwrap();
-        let _sb_flags = buf_reader.read_u8().unwrap();
-        let _sb_shared_vn = buf_reader.read_u8().unwrap();
-        let _sb_inoalignmt = buf_reader.read_u32::<BigEndian>().unwrap();
-        let _sb_unit = buf_reader.read_u32::<BigEndian>().unwrap();
-        let _sb_width = buf_reader.read_u32::<BigEndian>().unwrap();
-        let sb_dirblklog = buf_reader.read_u8().unwrap();
-        let _sb_logsectlog = buf_reader.read_u8().unwrap();
-        let _sb_logsectsize = buf_reader.read_u16::<BigEndian>().unwrap();
-        let _sb_logsunit = buf_reader.read_u32::<BigEndian>().unwrap();
-        let sb_features2 =
-            SbFeatures2::from_bits(buf_reader.read_u32::<BigEndian>().unwrap()).unwrap();
-        let _sb_bad_features2 = buf_reader.read_u32::<BigEndian>().unwrap();
+        buf_reader.read_exact(&mut buf_fname[..])?;
+        let sb_fname = buf_fname;
+
+        let sb_blocklog = buf_reader.read_u8()?;
+        let _sb_sectlog = buf_reader.read_u8()?;
+        let sb_inodelog = buf_reader.read_u8()?;
+        let sb_inopblog = buf_reader.read_u8()?;
+        let sb_agblklog = buf_reader.read_u8()?;
+        let sb_rextslog = buf_reader.read_u8()?;
+        let _sb_inprogress = buf_reader.read_u8()?;
+        let sb_imax_pct = buf_reader.read_u8()?;
+        let sb_icount = buf_reader.read_u64::<BigEndian>()?;
+        let sb_ifree = buf_reader.read_u64::<BigEndian>()?;
+        let sb_fdblocks = buf_reader.read_u64::<BigEndian>()?;
+        let _sb_frextents = buf_reader.read_u64::<BigEndian>()?;
+        let sb_uquotino = buf_reader.read_u64::<BigEndian>()?;
+        let sb_gquotino = buf_reader.read_u64::<BigEndian>()?;
+        let _sb_qflags = buf_reader.read_u16::<BigEndian>()?;
+        let _sb_flags = buf_reader.read_u8()?;
+        let _sb_shared_vn = buf_reader.read_u8()?;
+        let sb_inoalignmt = buf_reader.read_u32::<BigEndian>()?;
+        let sb_unit = buf_reader.read_u32::<BigEndian>()?;
+        let sb_width = buf_reader.read_u32::<BigEndian>()?;
+        let sb_dirblklog = buf_reader.read_u8()?;
+        let _sb_logsectlog = buf_reader.read_u8()?;
+        let _sb_logsectsize = buf_reader.read_u16::<BigEndian>()?;
+        let _sb_logsunit = buf_reader.read_u32::<BigEndian>()?;
+        let features2_raw = buf_reader.read_u32::<BigEndian>()?;
+        let sb_features2 = SbFeatures2::from_bits(features2_raw).ok_or_else(|| {
+            XfsError::Unsupported(format!(
+                "Unknown value in sb_features2: {:#x}",
+                features2_raw
+            ))
+        })?;
+        let _sb_bad_features2 = buf_reader.read_u32::<BigEndian>()?;
 
         /* Version 5 superblock features */
-        let _sb_features_compat = buf_reader.read_u32::<BigEndian>().unwrap();
-        let _sb_features_ro_compat = buf_reader.read_u32::<BigEndian>().unwrap();
-        let incompat_raw = buf_reader.read_u32::<BigEndian>().unwrap();
-        let sb_features_incompat = SbFeaturesIncompat::from_bits(incompat_raw)
-            .unwrap_or_else(|| panic!("Unknown value in sb_features_incompat: {:?}", incompat_raw));
-        let log_incompat_raw = buf_reader.read_u32::<BigEndian>().unwrap();
-        let _sb_features_log_incompat = SbFeaturesLogIncompat::from_bits(log_incompat_raw)
-            .unwrap_or_else(|| {
-                panic!(
-                    "Unknown value in sb_features_log_incompat: {:?}",
-                    log_incompat_raw
-                )
-            });
-
-        buf_reader.seek(SeekFrom::Start(0)).unwrap();
+        let _sb_features_compat = buf_reader.read_u32::<BigEndian>()?;
+        // Read-only compatible features, such as reflink, rmapbt, and finobt, only affect how the
+        // file system may be modified.  So we never need to check them.  That includes reflink on
+        // the realtime device.
+        let _sb_features_ro_compat = buf_reader.read_u32::<BigEndian>()?;
+        let incompat_raw = buf_reader.read_u32::<BigEndian>()?;
+        let sb_features_incompat =
+            SbFeaturesIncompat::from_bits(incompat_raw).ok_or_else(|| {
+                XfsError::Unsupported(format!(
+                    "Unknown value in sb_features_incompat: {:#x}",
+                    incompat_raw
+                ))
+            })?;
+        let sb_features_log_incompat =
+            SbFeaturesLogIncompat::from_bits_retain(buf_reader.read_u32::<BigEndian>()?);
+
+        // Validate the geometry before using any of it, lest a corrupt superblock cause a division
+        // by zero or an enormous allocation.
+        if !sb_blocksize.is_power_of_two() || !(512..=65536).contains(&sb_blocksize) {
+            return Err(XfsError::Corrupt(format!(
+                "Block size {} is invalid",
+                sb_blocksize
+            )));
+        }
+        if 1u32.checked_shl(sb_blocklog.into()) != Some(sb_blocksize) {
+            return Err(XfsError::Corrupt(format!(
+                "sb_blocklog is inconsistent: expected {} but found {}",
+                sb_blocksize.ilog2(),
+                sb_blocklog
+            )));
+        }
+        // Directory blocks, like file system blocks, may be at most 64 KiB.
+        if u32::from(sb_blocklog) + u32::from(sb_dirblklog) > 16 {
+            return Err(XfsError::Corrupt(format!(
+                "sb_dirblklog {} is invalid for block size {}",
+                sb_dirblklog, sb_blocksize
+            )));
+        }
+        if !sb_sectsize.is_power_of_two()
+            || !(512..=32768).contains(&sb_sectsize)
+            || u32::from(sb_sectsize) > sb_blocksize
+        {
+            return Err(XfsError::Corrupt(format!(
+                "Sector size {} is invalid",
+                sb_sectsize
+            )));
+        }
+        if sb_agcount == 0 {
+            return Err(XfsError::Corrupt(format!(
+                "AG count {} is invalid",
+                sb_agcount
+            )));
+        }
+        if sb_agblocks == 0 {
+            return Err(XfsError::Corrupt(format!(
+                "AG size {} is invalid",
+                sb_agblocks
+            )));
+        }
+        if u32::from(sb_agblklog) != u32::BITS - (sb_agblocks - 1).leading_zeros() {
+            return Err(XfsError::Corrupt(format!(
+                "sb_agblklog is inconsistent: expected {} but found {}",
+                u32::BITS - (sb_agblocks - 1).leading_zeros(),
+                sb_agblklog
+            )));
+        }
+        if sb_dblocks == 0 || sb_dblocks > u64::from(sb_agcount) * u64::from(sb_agblocks) {
+            return Err(XfsError::Corrupt(format!(
+                "Data block count {} is invalid",
+                sb_dblocks
+            )));
+        }
+
+        buf_reader.seek(SeekFrom::Start(0))?;
 
         const CASTAGNOLI: Crc<u32> = Crc::<u32>::new(&CRC_32_ISCSI);
         let mut digest = CASTAGNOLI.digest();
 
         let mut buf_bcrc = [0u8; 224];
-        buf_reader.read_exact(&mut buf_bcrc).unwrap();
+        buf_reader.read_exact(&mut buf_bcrc)?;
         digest.update(&buf_bcrc);
         digest.update(&[0u8; 4]);
 
-        let sb_crc = buf_reader.read_u32::<LittleEndian>().unwrap();
+        let sb_crc = buf_reader.read_u32::<LittleEndian>()?;
 
         let mut buf_acrc = vec![0u8; usize::from(sb_sectsize) - 228];
-        buf_reader.read_exact(&mut buf_acrc).unwrap();
+        buf_reader.read_exact(&mut buf_acrc)?;
         digest.update(&buf_acrc);
+        // sb_pquotino follows sb_crc and sb_spino_align
+        let sb_pquotino = if sb_versionnum & 0xF == 5 {
+            u64::from_be_bytes(buf_acrc[4..12].try_into().unwrap())
+        } else {
+            NULLFSINO
+        };
 
         if ![4, 5].contains(&(sb_versionnum & 0xF)) {
-            panic!(
-                "Unsupported filesystem version number {}",
+            return Err(XfsError::Unsupported(format!(
+                "file system version {}",
                 sb_versionnum & 0xF
-            );
+            )));
         }
         if !sb_features2.attr2() {
-            panic!("Version 1 extended attributes are not supported");
+            return Err(XfsError::Unsupported(
+                "Version 1 extended attributes are not supported".into(),
+            ));
         }
         if sb_versionnum & 0xF == 5 && !sb_features2.crc() {
-            panic!("Version 5 file systems must set the CRC bit in sb_features2");
+            return Err(XfsError::Corrupt(
+                "Version 5 file systems must set the CRC bit in sb_features2".into(),
+            ));
         }
         if sb_features2.crc() && digest.finalize() != sb_crc {
-            panic!("Crc check failed!");
+            return Err(XfsError::Corrupt("Superblock CRC check failed".into()));
         }
         if sb_features_incompat.meta_uuid() {
-            panic!("The Metadata UUID feature is not supported");
+            return Err(XfsError::Unsupported(
+                "The Metadata UUID feature is not supported".into(),
+            ));
         }
         if sb_features_incompat.needs_repair() {
-            panic!("The NeedsRepair feature is not supported");
+            return Err(XfsError::Unsupported(
+                "The NeedsRepair feature is not supported".into(),
+            ));
         }
         if sb_features_incompat.large_extent_counters() {
-            panic!("The Large Extent Counters feature is not supported");
+            return Err(XfsError::Unsupported(
+                "The Large Extent Counters feature is not supported".into(),
+            ));
+        }
+        if !sb_inodesize.is_power_of_two() || !(256..=2048).contains(&sb_inodesize) {
+            return Err(XfsError::Corrupt(format!(
+                "Inode size {} is invalid",
+                sb_inodesize
+            )));
+        }
+        if 1u16.checked_shl(sb_inodelog.into()) != Some(sb_inodesize) {
+            return Err(XfsError::Corrupt(format!(
+                "sb_inodelog is inconsistent: expected {} but found {}",
+                sb_inodesize.ilog2(),
+                sb_inodelog
+            )));
+        }
+        if sb_blocksize % u32::from(sb_inodesize) != 0 {
+            return Err(XfsError::Corrupt(format!(
+                "Inode size {} is incompatible with block size {}",
+                sb_inodesize, sb_blocksize
+            )));
+        }
+        if u32::from(sb_inopblock) != sb_blocksize / u32::from(sb_inodesize) {
+            return Err(XfsError::Corrupt(format!(
+                "sb_inopblock is inconsistent: expected {} but found {}",
+                sb_blocksize / u32::from(sb_inodesize),
+                sb_inopblock
+            )));
+        }
+        if 1u32.checked_shl(sb_inopblog.into()) != Some(u32::from(sb_inopblock)) {
+            return Err(XfsError::Corrupt(format!(
+                "sb_inopblog is inconsistent: expected {} but found {}",
+                sb_inopblock.ilog2(),
+                sb_inopblog
+            )));
+        }
+        // We never replay the log, so log-incompat features can't prevent us from mounting.  But
+        // any that are set indicate that the log is dirty, so the most recent changes may not be
+        // visible.
+        if sb_features_log_incompat.unknown() != 0 {
+            warn!(
+                "Unknown value in sb_features_log_incompat: {:#x}.  The log will not be \
+                 interpreted.",
+                sb_features_log_incompat.bits()
+            );
+        } else if !sb_features_log_incompat.is_empty() {
+            warn!("The log is dirty.  Recent changes may not be visible.");
         }
 
-        Sb {
+        Ok(Sb {
             sb_blocksize,
             sb_dblocks,
+            sb_rblocks,
+            sb_rextents,
             sb_uuid,
             sb_rootino,
+            sb_rbmino,
+            sb_rsumino,
+            sb_rextsize,
             sb_agblocks,
             sb_agcount,
+            sb_rbmblocks,
             sb_logblocks,
             sb_versionnum,
+            sb_sectsize,
             sb_inodesize,
+            sb_fname,
             sb_blocklog,
             sb_inodelog,
             sb_inopblog,
             sb_agblklog,
+            sb_rextslog,
+            sb_imax_pct,
             sb_icount,
             sb_ifree,
             sb_fdblocks,
-            sb_dirblklog,
+            sb_uquotino,
+            sb_gquotino,
+            sb_inoalignmt,
+            sb_unit,
+            sb_width,
             sb_features2,
             sb_features_incompat,
-        }
+            sb_pquotino,
+            geometry: Geometry::new(sb_blocklog, sb_dirblklog),
+        })
     }
 
     #[inline]
@@ -349,6 +558,22 @@ impl Sb {
         1 << (35 - self.sb_blocklog)
     }
 
+    #[inline]
+    pub fn get_dir3_free_offset(&self) -> XfsDablk {
+        1 << (36 - self.sb_blocklog)
+    }
+
+    /// The most inodes that may ever be allocated, as limited by `sb_imax_pct`, or `None` if
+    /// there is no limit.  Like the kernel, round down to a whole number of inode chunks.
+    pub fn maxicount(&self) -> Option<u64> {
+        if self.sb_imax_pct == 0 {
+            return None;
+        }
+        let chunk_blocks = (XFS_INODES_PER_CHUNK >> self.sb_inopblog).max(1);
+        let blocks = self.sb_dblocks * u64::from(self.sb_imax_pct) / 100;
+        Some((blocks / chunk_blocks * chunk_blocks) << self.sb_inopblog)
+    }
+
     /// Get the size of an inode in bytes
     pub fn inode_size(&self) -> usize {
         self.sb_inodesize.into()
@@ -367,6 +592,117 @@ impl Sb {
         self.fsb_to_daddr(fsbno) << Self::BBSHIFT
     }
 
+    /// Given a realtime block number, calculate its byte offset on the realtime device
+    pub fn rtb_to_offset(&self, rtbno: XfsRtblock) -> u64 {
+        rtbno << self.sb_blocklog
+    }
+
+    /// Check that the realtime geometry is self-consistent, and agrees with the size of the
+    /// realtime summary inode.  Otherwise, realtime file extents can't be trusted.
+    pub fn check_realtime(&self, rsum_size: XfsFsize) -> Result<(), XfsError> {
+        if self.sb_rblocks == 0 {
+            return Err(XfsError::Unsupported(
+                "the file system has no realtime device".into(),
+            ));
+        }
+        if self.sb_rextsize == 0
+            || self.sb_rextents != self.sb_rblocks / u64::from(self.sb_rextsize)
+        {
+            return Err(XfsError::Corrupt(format!(
+                "Realtime extent size {} is inconsistent with {} realtime extents in {} blocks",
+                self.sb_rextsize, self.sb_rextents, self.sb_rblocks
+            )));
+        }
+        // The bitmap has one bit per realtime extent
+        let rbmblocks = self.sb_rextents.div_ceil(8 * u64::from(self.sb_blocksize));
+        if u64::from(self.sb_rbmblocks) != rbmblocks {
+            return Err(XfsError::Corrupt(format!(
+                "sb_rbmblocks is inconsistent: expected {} but found {}",
+                rbmblocks, self.sb_rbmblocks
+            )));
+        }
+        let rextslog = self.sb_rextents.checked_ilog2().unwrap_or(0);
+        if u32::from(self.sb_rextslog) != rextslog {
+            return Err(XfsError::Corrupt(format!(
+                "sb_rextslog is inconsistent: expected {} but found {}",
+                rextslog, self.sb_rextslog
+            )));
+        }
+        // The summary has a 32-bit counter for each power-of-two extent size, for each bitmap
+        // block.
+        let rsumlevels = u64::from(self.sb_rextslog) + 1;
+        let rsumblocks = (4 * rsumlevels * rbmblocks).div_ceil(u64::from(self.sb_blocksize));
+        let expected = rsumblocks << self.sb_blocklog;
+        if u64::try_from(rsum_size) != Ok(expected) {
+            return Err(XfsError::Corrupt(format!(
+                "Realtime summary inode size is inconsistent: expected {} but found {}",
+                expected, rsum_size
+            )));
+        }
+        Ok(())
+    }
+
+    /// Given an AG number, calculate the disk byte offset of its AGF
+    pub fn agf_offset(&self, agno: XfsAgnumber) -> u64 {
+        ((u64::from(agno) * u64::from(self.sb_agblocks)) << self.sb_blocklog)
+            + u64::from(self.sb_sectsize)
+    }
+
+    /// Given an AG number, calculate the disk byte offset of its AGI
+    pub fn agi_offset(&self, agno: XfsAgnumber) -> u64 {
+        ((u64::from(agno) * u64::from(self.sb_agblocks)) << self.sb_blocklog)
+            + 2 * u64::from(self.sb_sectsize)
+    }
+
+    /// Convert an AG number and AG-relative inode number into an absolute inode number
+    pub fn agino_to_ino(&self, agno: XfsAgnumber, agino: XfsAgino) -> XfsIno {
+        (u64::from(agno) << (self.sb_agblklog + self.sb_inopblog)) | u64::from(agino)
+    }
+
+    /// Split an absolute inode number into its AG number and AG-relative inode number
+    pub fn ino_to_agino(&self, ino: XfsIno) -> (XfsAgnumber, XfsAgino) {
+        let shift = self.sb_agblklog + self.sb_inopblog;
+        (
+            (ino >> shift) as XfsAgnumber,
+            (ino & ((1 << shift) - 1)) as XfsAgino,
+        )
+    }
+
+    /// The first AG block that may hold an inode chunk: the first aligned block after the AG
+    /// headers.  Any inode number that points at an earlier block is corrupt.
+    pub fn first_inode_agbno(&self) -> u64 {
+        // The kernel only honors sb_inoalignmt if it's at least as large as an inode cluster.  A
+        // whole chunk is always at least that large, so alignments smaller than a chunk are
+        // conservatively ignored.
+        let chunk_blocks = (XFS_INODES_PER_CHUNK >> self.sb_inopblog).max(1);
+        let align = if self.sb_versionnum & constants::XFS_SB_VERSION_ALIGNBIT != 0
+            && u64::from(self.sb_inoalignmt) >= chunk_blocks
+        {
+            u64::from(self.sb_inoalignmt)
+        } else {
+            1
+        };
+        // The AGFL occupies the fourth sector of each AG.
+        let agfl_block = (3 * u64::from(self.sb_sectsize)) >> self.sb_blocklog;
+        (agfl_block + 1).next_multiple_of(align)
+    }
+
+    /// Is this one of the file system's reserved inodes: the root directory, the realtime bitmap
+    /// or summary, or a quota inode?  None of them can ever be unlinked.
+    pub fn is_reserved_inode(&self, ino: XfsIno) -> bool {
+        ino != 0
+            && ino != NULLFSINO
+            && [
+                self.sb_rootino,
+                self.sb_rbmino,
+                self.sb_rsumino,
+                self.sb_uquotino,
+                self.sb_gquotino,
+                self.sb_pquotino,
+            ]
+            .contains(&ino)
+    }
+
     /// Does this file system record file type in its directory inodes?
     pub fn has_ftype(&self) -> bool {
         // Though it isn't documented, it seems that the ftype bit was originally part of the
@@ -374,8 +710,354 @@ impl Sb {
         self.sb_features2.ftype() || self.sb_features_incompat.ftype()
     }
 
+    /// Are the superblock's free space and inode counters only updated at unmount?  If so, the
+    /// per-AG counters are authoritative.
+    pub fn has_lazysbcount(&self) -> bool {
+        self.sb_features2.lazysbcount()
+    }
+
+    /// Does this file system store parent pointers in each inode's attribute fork?
+    pub fn has_parent(&self) -> bool {
+        self.sb_features_incompat.parent()
+    }
+
+    /// The preferred size in bytes for I/O: the stripe width, or failing that the stripe unit, if
+    /// the file system was created with a stripe geometry.  Otherwise, the block size.
+    pub fn iosize(&self) -> u32 {
+        let stripe = if self.sb_versionnum & constants::XFS_SB_VERSION_DALIGNBIT == 0 {
+            0
+        } else if self.sb_width > 0 {
+            self.sb_width
+        } else {
+            self.sb_unit
+        };
+        stripe
+            .checked_mul(self.sb_blocksize)
+            .filter(|iosize| *iosize > 0)
+            .unwrap_or(self.sb_blocksize)
+    }
+
+    /// The file system's label, as set by `mkfs.xfs -L` or `xfs_admin -L`.  It is NUL-padded,
+    /// but needn't be NUL-terminated if it fills the whole field.
+    pub fn label(&self) -> &[u8] {
+        let len = self
+            .sb_fname
+            .iter()
+            .position(|c| *c == 0)
+            .unwrap_or(self.sb_fname.len());
+        &self.sb_fname[..len]
+    }
+
     /// Return the file system version (usually 4 or 5)
     pub fn version(&self) -> u16 {
         self.sb_versionnum & 0xF
     }
 }
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use std::io::Cursor;
+
+    use rstest::rstest;
+
+    use super::*;
+    use crate::libxfuse::volume::SUPERBLOCK;
+
+    /// Build a minimal but valid version 5 superblock, after letting the caller modify it.
+    pub(crate) fn mksb<F: FnOnce(&mut [u8])>(f: F) -> Vec<u8> {
+        let mut buf = vec![0u8; 512];
+        buf[0..4].copy_from_slice(&XFS_SB_MAGIC.to_be_bytes());
+        buf[4..8].copy_from_slice(&4096u32.to_be_bytes()); // sb_blocksize
+        buf[8..16].copy_from_slice(&65536u64.to_be_bytes()); // sb_dblocks
+        buf[56..64].copy_from_slice(&128u64.to_be_bytes()); // sb_rootino
+        buf[84..88].copy_from_slice(&16384u32.to_be_bytes()); // sb_agblocks
+        buf[88..92].copy_from_slice(&4u32.to_be_bytes()); // sb_agcount
+        buf[96..100].copy_from_slice(&1024u32.to_be_bytes()); // sb_logblocks
+        buf[100..102].copy_from_slice(&0xb4a5u16.to_be_bytes()); // sb_versionnum
+        buf[102..104].copy_from_slice(&512u16.to_be_bytes()); // sb_sectsize
+        buf[104..106].copy_from_slice(&512u16.to_be_bytes()); // sb_inodesize
+        buf[106..108].copy_from_slice(&8u16.to_be_bytes()); // sb_inopblock
+        buf[120] = 12; // sb_blocklog
+        buf[121] = 9; // sb_sectlog
+        buf[122] = 9; // sb_inodelog
+        buf[123] = 3; // sb_inopblog
+        buf[124] = 14; // sb_agblklog
+        let features2 = constants::XFS_SB_VERSION2_ATTR2BIT | constants::XFS_SB_VERSION2_CRCBIT;
+        buf[200..204].copy_from_slice(&features2.to_be_bytes());
+        buf[216..220].copy_from_slice(&constants::XFS_SB_FEAT_INCOMPAT_FTYPE.to_be_bytes());
+
+        f(&mut buf[..]);
+
+        const CASTAGNOLI: Crc<u32> = Crc::<u32>::new(&CRC_32_ISCSI);
+        buf[224..228].fill(0);
+        let crc = CASTAGNOLI.checksum(&buf[..]);
+        buf[224..228].copy_from_slice(&crc.to_le_bytes());
+        buf
+    }
+
+    /// Initialize the global superblock for unit tests that need it, using the default geometry
+    /// from [`mksb`].
+    pub(crate) fn superblock() -> &'static Sb {
+        SUPERBLOCK.get_or_init(|| Sb::from(&mut Cursor::new(mksb(|_| ()))).unwrap())
+    }
+
+    #[test]
+    fn valid() {
+        let sb = Sb::from(&mut Cursor::new(mksb(|_| ()))).unwrap();
+        assert_eq!(sb.sb_blocksize, 4096);
+        assert_eq!(sb.version(), 5);
+    }
+
+    /// Inconsistent logarithms and per-block counts are rejected with an error
+    #[rstest]
+    #[case::inopblock(106, &16u16.to_be_bytes(), "sb_inopblock is inconsistent")]
+    #[case::inopblog(123, &[4], "sb_inopblog is inconsistent")]
+    #[case::inodelog(122, &[8], "sb_inodelog is inconsistent")]
+    #[case::blocklog(120, &[11], "sb_blocklog is inconsistent")]
+    #[case::agblklog(124, &[13], "sb_agblklog is inconsistent")]
+    fn inconsistent(#[case] offset: usize, #[case] value: &[u8], #[case] expected: &str) {
+        let buf = mksb(|buf| buf[offset..offset + value.len()].copy_from_slice(value));
+        let e = Sb::from(&mut Cursor::new(buf)).unwrap_err();
+        assert!(matches!(e, XfsError::Corrupt(_)), "{:?}", e);
+        assert!(e.to_string().contains(expected), "{}", e);
+    }
+
+    /// Zero or implausible geometry should be rejected at mount time, rather than causing a
+    /// division by zero or an enormous allocation later.
+    #[rstest]
+    #[case::zero_blocksize(4, &0u32.to_be_bytes())]
+    #[case::odd_blocksize(4, &4095u32.to_be_bytes())]
+    #[case::huge_blocksize(4, &(1u32 << 17).to_be_bytes())]
+    #[case::zero_sectsize(102, &0u16.to_be_bytes())]
+    #[case::tiny_sectsize(102, &128u16.to_be_bytes())]
+    #[case::zero_agcount(88, &0u32.to_be_bytes())]
+    #[case::zero_agblocks(84, &0u32.to_be_bytes())]
+    #[case::zero_dblocks(8, &0u64.to_be_bytes())]
+    #[case::excess_dblocks(8, &(1u64 << 20).to_be_bytes())]
+    #[case::zero_inodesize(104, &0u16.to_be_bytes())]
+    #[case::odd_inodesize(104, &384u16.to_be_bytes())]
+    #[case::huge_inodesize(104, &4096u16.to_be_bytes())]
+    #[case::huge_dirblklog(192, &[5])]
+    #[case::max_dirblklog(192, &[255])]
+    fn invalid_geometry(#[case] offset: usize, #[case] value: &[u8]) {
+        let buf = mksb(|buf| buf[offset..offset + value.len()].copy_from_slice(value));
+        let e = Sb::from(&mut Cursor::new(buf)).unwrap_err();
+        assert!(matches!(e, XfsError::Corrupt(_)), "{:?}", e);
+        assert!(e.to_string().contains("is invalid"), "{}", e);
+    }
+
+    /// The largest legal directory block size is 64 KiB
+    #[test]
+    fn max_dirblksize() {
+        let buf = mksb(|buf| buf[192] = 4);
+        let sb = Sb::from(&mut Cursor::new(buf)).unwrap();
+        assert_eq!(sb.geometry.dirblksize, 65536);
+        assert_eq!(sb.geometry.dirfsbmask, 15);
+    }
+
+    /// A file system with reflink and rmapbt on a realtime device should be mountable.
+    #[test]
+    fn rtreflink() {
+        let buf = mksb(|buf| {
+            buf[16..24].copy_from_slice(&65536u64.to_be_bytes()); // sb_rblocks
+            buf[24..32].copy_from_slice(&65536u64.to_be_bytes()); // sb_rextents
+            buf[80..84].copy_from_slice(&1u32.to_be_bytes()); // sb_rextsize
+                                                              // sb_features_ro_compat: finobt, rmapbt, reflink, and inobtcnt
+            buf[212..216].copy_from_slice(&0xfu32.to_be_bytes());
+        });
+        let sb = Sb::from(&mut Cursor::new(buf)).unwrap();
+        assert_eq!(sb.version(), 5);
+    }
+
+    /// Unknown log-incompat features should not prevent mounting, since we never read the log.
+    #[test]
+    fn unknown_log_incompat() {
+        let buf = mksb(|buf| buf[220..224].copy_from_slice(&0x8000_0000u32.to_be_bytes()));
+        let sb = Sb::from(&mut Cursor::new(buf)).unwrap();
+        assert_eq!(sb.version(), 5);
+    }
+
+    /// Set up a superblock for a realtime device of `rblocks` blocks, with the given extent size
+    fn mkrtsb(rblocks: u64, rextsize: u32) -> Sb {
+        let buf = mksb(|buf| {
+            let rextents = rblocks / u64::from(rextsize);
+            buf[16..24].copy_from_slice(&rblocks.to_be_bytes()); // sb_rblocks
+            buf[24..32].copy_from_slice(&rextents.to_be_bytes()); // sb_rextents
+            buf[80..84].copy_from_slice(&rextsize.to_be_bytes()); // sb_rextsize
+            let rbmblocks = rextents.div_ceil(8 * 4096) as u32;
+            buf[92..96].copy_from_slice(&rbmblocks.to_be_bytes()); // sb_rbmblocks
+            buf[125] = rextents.checked_ilog2().unwrap_or(0) as u8; // sb_rextslog
+        });
+        Sb::from(&mut Cursor::new(buf)).unwrap()
+    }
+
+    /// The realtime summary inode must be large enough for one counter per power-of-two extent
+    /// size, per bitmap block.
+    #[rstest]
+    #[case::small(65536, 1, 4096)]
+    #[case::large_rextsize(1 << 20, 16, 4096)]
+    // 2^30 rt extents need 32768 bitmap blocks, and 31 levels
+    #[case::many_bitmap_blocks(1 << 30, 1, 32768 * 31 * 4)]
+    fn check_realtime(#[case] rblocks: u64, #[case] rextsize: u32, #[case] rsum_size: XfsFsize) {
+        assert_eq!(mkrtsb(rblocks, rextsize).check_realtime(rsum_size), Ok(()));
+    }
+
+    #[test]
+    fn check_realtime_bad_rsum() {
+        let e = mkrtsb(65536, 1).check_realtime(8192).unwrap_err();
+        assert_eq!(
+            e.to_string(),
+            "Corrupt file system: Realtime summary inode size is inconsistent: expected 4096 but \
+             found 8192"
+        );
+    }
+
+    #[test]
+    fn check_realtime_zero_rextsize() {
+        let buf = mksb(|buf| buf[16..24].copy_from_slice(&65536u64.to_be_bytes()));
+        let e = Sb::from(&mut Cursor::new(buf))
+            .unwrap()
+            .check_realtime(4096)
+            .unwrap_err();
+        assert!(
+            e.to_string()
+                .starts_with("Corrupt file system: Realtime extent size 0 is inconsistent"),
+            "{}",
+            e
+        );
+    }
+
+    #[test]
+    fn check_realtime_none() {
+        let e = Sb::from(&mut Cursor::new(mksb(|_| ())))
+            .unwrap()
+            .check_realtime(4096)
+            .unwrap_err();
+        assert_eq!(
+            e,
+            XfsError::Unsupported("the file system has no realtime device".into())
+        );
+    }
+
+    /// The preferred I/O size follows the stripe geometry, but only if the DALIGN bit says it's
+    /// valid
+    #[rstest]
+    #[case::none(false, 0, 0, 4096)]
+    #[case::width(true, 16, 64, 262144)]
+    #[case::unit_only(true, 16, 0, 65536)]
+    #[case::no_dalign(false, 16, 64, 4096)]
+    #[case::overflow(true, 1, 1 << 20, 4096)]
+    fn iosize(#[case] dalign: bool, #[case] unit: u32, #[case] width: u32, #[case] expected: u32) {
+        let sb = Sb::from(&mut Cursor::new(mksb(|buf| {
+            if dalign {
+                let versionnum = 0xb4a5 | constants::XFS_SB_VERSION_DALIGNBIT;
+                buf[100..102].copy_from_slice(&versionnum.to_be_bytes());
+            }
+            buf[184..188].copy_from_slice(&unit.to_be_bytes());
+            buf[188..192].copy_from_slice(&width.to_be_bytes());
+        })))
+        .unwrap();
+        assert_eq!(sb.sb_unit, unit);
+        assert_eq!(sb.sb_width, width);
+        assert_eq!(sb.iosize(), expected);
+    }
+
+    /// Inode chunks can't precede the first aligned block after the AGFL.  An alignment smaller
+    /// than a chunk is ignored.
+    #[rstest]
+    #[case::unaligned(false, 8, 12, 1)]
+    #[case::aligned(true, 8, 12, 8)]
+    #[case::stripe_aligned(true, 64, 12, 64)]
+    #[case::small_align(true, 4, 12, 1)]
+    #[case::zero_align(true, 0, 12, 1)]
+    #[case::small_blocks(false, 0, 9, 4)]
+    #[case::small_blocks_aligned(true, 64, 9, 64)]
+    fn first_inode_agbno(
+        #[case] align: bool,
+        #[case] inoalignmt: u32,
+        #[case] blocklog: u8,
+        #[case] expected: u64,
+    ) {
+        let sb = Sb::from(&mut Cursor::new(mksb(|buf| {
+            let versionnum = if align {
+                0xb4a5u16
+            } else {
+                0xb4a5 & !constants::XFS_SB_VERSION_ALIGNBIT
+            };
+            buf[100..102].copy_from_slice(&versionnum.to_be_bytes());
+            buf[4..8].copy_from_slice(&(1u32 << blocklog).to_be_bytes());
+            buf[106..108].copy_from_slice(&(1u16 << (blocklog - 9)).to_be_bytes());
+            buf[120] = blocklog;
+            buf[123] = blocklog - 9;
+            buf[180..184].copy_from_slice(&inoalignmt.to_be_bytes());
+        })))
+        .unwrap();
+        assert_eq!(sb.sb_inoalignmt, inoalignmt);
+        assert_eq!(sb.first_inode_agbno(), expected);
+    }
+
+    /// The label is NUL-padded, and may use the entire field
+    #[rstest]
+    #[case::none(b"", b"")]
+    #[case::short(b"mylabel", b"mylabel")]
+    #[case::full(b"twelve_chars", b"twelve_chars")]
+    #[case::garbage_after_nul(b"ab\0cd", b"ab")]
+    fn label(#[case] fname: &[u8], #[case] expected: &[u8]) {
+        let sb = Sb::from(&mut Cursor::new(mksb(|buf| {
+            buf[108..108 + fname.len()].copy_from_slice(fname)
+        })))
+        .unwrap();
+        assert_eq!(sb.label(), expected);
+    }
+
+    /// The root, realtime, and quota inodes are reserved.  Unused quota inode fields may be
+    /// either 0 or NULLFSINO, and neither is a reserved inode.
+    #[rstest]
+    #[case::root(128, true)]
+    #[case::rbmino(129, true)]
+    #[case::rsumino(130, true)]
+    #[case::uquotino(131, true)]
+    #[case::gquotino(132, true)]
+    #[case::pquotino(133, true)]
+    #[case::regular_file(134, false)]
+    #[case::zero(0, false)]
+    #[case::nullfsino(NULLFSINO, false)]
+    fn is_reserved_inode(#[case] ino: XfsIno, #[case] expected: bool) {
+        let sb = Sb::from(&mut Cursor::new(mksb(|buf| {
+            buf[64..72].copy_from_slice(&129u64.to_be_bytes());
+            buf[72..80].copy_from_slice(&130u64.to_be_bytes());
+            buf[160..168].copy_from_slice(&131u64.to_be_bytes());
+            buf[168..176].copy_from_slice(&132u64.to_be_bytes());
+            buf[232..240].copy_from_slice(&133u64.to_be_bytes());
+        })))
+        .unwrap();
+        assert_eq!(sb.sb_pquotino, 133);
+        assert_eq!(sb.is_reserved_inode(ino), expected);
+    }
+
+    /// A file system without quotas has none of them reserved
+    #[test]
+    fn is_reserved_inode_no_quotas() {
+        let sb = Sb::from(&mut Cursor::new(mksb(|buf| {
+            buf[160..168].copy_from_slice(&NULLFSINO.to_be_bytes());
+            buf[168..176].copy_from_slice(&NULLFSINO.to_be_bytes());
+            buf[232..240].copy_from_slice(&NULLFSINO.to_be_bytes());
+        })))
+        .unwrap();
+        assert!(sb.is_reserved_inode(128));
+        assert!(!sb.is_reserved_inode(NULLFSINO));
+        assert!(!sb.is_reserved_inode(131));
+    }
+
+    /// Inode numbers split into AG number and AG-relative inode number, and back again.  The test
+    /// superblock has 2^14 blocks per AG and 2^3 inodes per block.
+    #[rstest]
+    #[case::root(128, 0, 128)]
+    #[case::ag1(1 << 17 | 5, 1, 5)]
+    #[case::last_of_ag(3 << 17 | 0x1ffff, 3, 0x1ffff)]
+    fn ino_to_agino(#[case] ino: XfsIno, #[case] agno: XfsAgnumber, #[case] agino: XfsAgino) {
+        let sb = superblock();
+        assert_eq!(sb.ino_to_agino(ino), (agno, agino));
+        assert_eq!(sb.agino_to_ino(agno, agino), ino);
+    }
+}
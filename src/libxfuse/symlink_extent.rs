@@ -35,6 +35,7 @@ use bincode::{de::read::Reader, Decode};
 use super::{
     bmbt_rec::Bmx,
     definitions::XFS_SYMLINK_MAGIC,
+    error::XfsError,
     sb::Sb,
     utils::{decode_from, Uuid},
 };
@@ -55,59 +56,158 @@ pub struct DsymlinkHdr {
 pub struct SymlinkExtents;
 
 impl SymlinkExtents {
+    /// Read the target of a symlink of the given length, which may span several blocks and
+    /// extents.
     pub fn get_target<T: BufRead + Reader + Seek>(
         buf_reader: &mut T,
         bmx: &Bmx,
         superblock: &Sb,
-    ) -> CString {
-        let mut data = Vec::<u8>::with_capacity(1024);
+        size: usize,
+    ) -> Result<CString, XfsError> {
+        let mut data = Vec::<u8>::with_capacity(size);
 
         let mut dblock = 0;
-        loop {
-            let (ofsb, oblocks) = bmx.get_extent(dblock);
-            if ofsb.is_none() {
+        while data.len() < size {
+            let (Some(fsb), Some(blocks)) = bmx.get_extent(dblock) else {
                 break;
-            }
-            let fsb = ofsb.unwrap();
-            let blocks = oblocks.unwrap();
-            buf_reader
-                .seek(SeekFrom::Start(superblock.fsb_to_offset(fsb)))
-                .unwrap();
-
-            let bytes = match superblock.version() {
-                5 => {
-                    let hdr: DsymlinkHdr = decode_from(buf_reader.by_ref()).unwrap();
-                    assert_eq!(XFS_SYMLINK_MAGIC, hdr.sl_magic);
-
-                    buf_reader
-                        .seek(SeekFrom::Current(hdr.sl_offset as i64))
-                        .unwrap();
-                    hdr.sl_bytes as usize
-                }
-                4 => {
-                    // Version 4 file systems do not have the DsymlinkHdr
-                    (blocks as usize) << superblock.sb_blocklog
-                }
-                _ => unimplemented!(),
             };
+            // On V5 file systems every block, not just every extent, begins with a header.
+            for fsb in fsb..fsb + blocks {
+                if data.len() >= size {
+                    break;
+                }
+                buf_reader.seek(SeekFrom::Start(superblock.fsb_to_offset(fsb)))?;
 
-            let oldlen = data.len();
-            data.resize(oldlen + bytes, 0);
-            buf_reader.read_exact(&mut data[oldlen..]).unwrap();
+                let bytes = match superblock.version() {
+                    5 => {
+                        let hdr: DsymlinkHdr = decode_from(buf_reader.by_ref()).map_err(|e| {
+                            XfsError::Corrupt(format!("symlink block {}: {}", fsb, e))
+                        })?;
+                        if hdr.sl_magic != XFS_SYMLINK_MAGIC {
+                            return Err(XfsError::Corrupt(format!(
+                                "symlink block {} has bad magic {:#x}",
+                                fsb, hdr.sl_magic
+                            )));
+                        }
+                        if hdr.sl_offset as usize != data.len() {
+                            return Err(XfsError::Corrupt(format!(
+                                "symlink block {} is out of order",
+                                fsb
+                            )));
+                        }
+                        hdr.sl_bytes as usize
+                    }
+                    4 => {
+                        // Version 4 file systems do not have the DsymlinkHdr
+                        superblock.sb_blocksize as usize
+                    }
+                    v => {
+                        return Err(XfsError::Unsupported(format!(
+                            "symbolic links on file system version {}",
+                            v
+                        )))
+                    }
+                };
+                let bytes = bytes.min(size - data.len());
+
+                let oldlen = data.len();
+                data.resize(oldlen + bytes, 0);
+                buf_reader.read_exact(&mut data[oldlen..])?;
+            }
             dblock += blocks;
         }
 
-        match CString::new(data) {
-            Ok(s) => s,
-            Err(ne) => {
-                // A V4 file system does not store the length of the symlink target, so we must
-                // infer it by the presence of a NUL byte.
-                debug_assert_eq!(superblock.version(), 4);
-                let p = ne.nul_position();
-                let mut v = ne.into_vec();
-                v.truncate(p);
-                CString::new(v).unwrap()
-            }
+        Ok(to_cstring(data))
+    }
+}
+
+/// Convert a symbolic link's target to a C string.  The target may not contain a NUL byte.  If it
+/// does, the inode is corrupt, so truncate it there.
+pub fn to_cstring(data: Vec<u8>) -> CString {
+    match CString::new(data) {
+        Ok(s) => s,
+        Err(ne) => {
+            let p = ne.nul_position();
+            let mut v = ne.into_vec();
+            v.truncate(p);
+            CString::new(v).unwrap()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rstest::rstest;
+
+    use super::*;
+    use crate::libxfuse::{
+        bmbt_rec::BmbtRec,
+        sb::tests::superblock,
+        test_support::{mkimg, open},
+    };
+
+    /// Size of the V5 remote symlink header
+    const HDRSIZE: usize = 56;
+
+    fn bmbt_rec(startoff: u64, startblock: u64, blockcount: u64) -> BmbtRec {
+        BmbtRec {
+            br_startoff:   startoff,
+            br_startblock: startblock,
+            br_blockcount: blockcount,
+            br_flag:       false,
         }
     }
+
+    /// A target long enough to need two blocks, stored either in one two-block extent or in two
+    /// discontiguous extents.
+    #[rstest]
+    #[case::one_extent(&[bmbt_rec(0, 10, 2)], &[10, 11])]
+    #[case::two_extents(&[bmbt_rec(0, 20, 1), bmbt_rec(1, 10, 1)], &[20, 10])]
+    fn two_blocks(#[case] recs: &[BmbtRec], #[case] blocks: &[u64]) {
+        let sb = superblock();
+        let bs = sb.geometry.blocksize;
+        let target: Vec<u8> = (0..bs + 100).map(|i| b'a' + (i % 26) as u8).collect();
+
+        let mut offset = 0;
+        let mut raw = Vec::new();
+        for fsb in blocks {
+            let bytes = (bs - HDRSIZE).min(target.len() - offset);
+            let mut block = vec![0u8; bs];
+            block[0..4].copy_from_slice(&XFS_SYMLINK_MAGIC.to_be_bytes());
+            block[4..8].copy_from_slice(&(offset as u32).to_be_bytes());
+            block[8..12].copy_from_slice(&(bytes as u32).to_be_bytes());
+            block[HDRSIZE..HDRSIZE + bytes].copy_from_slice(&target[offset..offset + bytes]);
+            raw.push((*fsb, block));
+            offset += bytes;
+        }
+        let img = mkimg(&raw);
+        let mut reader = open(&img);
+
+        let link =
+            SymlinkExtents::get_target(&mut reader, &Bmx::new(recs), sb, target.len()).unwrap();
+        assert_eq!(link.as_bytes(), &target[..]);
+    }
+
+    /// A corrupt block header is an error, not a crash
+    #[rstest]
+    #[case::bad_magic(0x1234_5678, 0, "symlink block 10 has bad magic 0x12345678")]
+    #[case::out_of_order(XFS_SYMLINK_MAGIC, 1, "symlink block 10 is out of order")]
+    fn corrupt(#[case] magic: u32, #[case] offset: u32, #[case] expected: &str) {
+        let sb = superblock();
+        let bs = sb.geometry.blocksize;
+
+        let mut block = vec![0u8; bs];
+        block[0..4].copy_from_slice(&magic.to_be_bytes());
+        block[4..8].copy_from_slice(&offset.to_be_bytes());
+        block[8..12].copy_from_slice(&10u32.to_be_bytes());
+        let img = mkimg(&[(10, block)]);
+        let mut reader = open(&img);
+
+        let e = SymlinkExtents::get_target(&mut reader, &Bmx::new(&[bmbt_rec(0, 10, 1)]), sb, 10)
+            .unwrap_err();
+        assert!(
+            matches!(e, XfsError::Corrupt(ref s) if s == expected),
+            "{e:?}"
+        );
+    }
 }
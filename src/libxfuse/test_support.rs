@@ -0,0 +1,153 @@
+/*
+ * BSD 2-Clause License
+ *
+ * Copyright (c) 2021, Khaled Emara
+ * All rights reserved.
+ *
+ * Redistribution and use in source and binary forms, with or without
+ * modification, are permitted provided that the following conditions are met:
+ *
+ * 1. Redistributions of source code must retain the above copyright notice, this
+ *    list of conditions and the following disclaimer.
+ *
+ * 2. Redistributions in binary form must reproduce the above copyright notice,
+ *    this list of conditions and the following disclaimer in the documentation
+ *    and/or other materials provided with the distribution.
+ *
+ * THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS IS"
+ * AND ANY EXPRESS OR IMPLIED WARRANTIES, INCLUDING, BUT NOT LIMITED TO, THE
+ * IMPLIED WARRANTIES OF MERCHANTABILITY AND FITNESS FOR A PARTICULAR PURPOSE ARE
+ * DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT HOLDER OR CONTRIBUTORS BE LIABLE
+ * FOR ANY DIRECT, INDIRECT, INCIDENTAL, SPECIAL, EXEMPLARY, OR CONSEQUENTIAL
+ * DAMAGES (INCLUDING, BUT NOT LIMITED TO, PROCUREMENT OF SUBSTITUTE GOODS OR
+ * SERVICES; LOSS OF USE, DATA, OR PROFITS; OR BUSINESS INTERRUPTION) HOWEVER
+ * CAUSED AND ON ANY THEORY OF LIABILITY, WHETHER IN CONTRACT, STRICT LIABILITY,
+ * OR TORT (INCLUDING NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE USE
+ * OF THIS SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+ */
+//! Builders for the scratch images and B+tree blocks that the unit tests decode
+use std::{fs, io::Write, os::unix::fs::FileExt, path::PathBuf, process::Command};
+
+use tempfile::NamedTempFile;
+
+use super::{block_reader::BlockReader, definitions::*, sb::tests::superblock};
+
+/// Build an image the size of the test superblock's file system, with each block written at its
+/// FS block number
+pub(crate) fn mkimg<B: AsRef<[u8]>>(blocks: &[(XfsFsblock, B)]) -> NamedTempFile {
+    let sb = superblock();
+    let mut img = NamedTempFile::new().unwrap();
+    let f: &fs::File = img.as_file();
+    f.set_len(sb.sb_dblocks << sb.sb_blocklog).unwrap();
+    for (fsb, block) in blocks {
+        f.write_all_at(block.as_ref(), sb.fsb_to_offset(*fsb))
+            .unwrap();
+    }
+    img.flush().unwrap();
+    img
+}
+
+/// Uncompress one of the golden images from resources/ into a scratch file
+pub(crate) fn golden(filename: &str) -> NamedTempFile {
+    let mut zimg = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+    zimg.push("resources");
+    zimg.push(filename);
+    zimg.set_extension("img.zst");
+    let img = NamedTempFile::new().unwrap();
+    let status = Command::new("unzstd")
+        .arg("-f")
+        .arg("-q")
+        .arg("-o")
+        .arg(img.path())
+        .arg(&zimg)
+        .status()
+        .expect("Uncompressing golden image failed");
+    assert!(status.success(), "Uncompressing {} failed", zimg.display());
+    img
+}
+
+/// Open an image, buffering one file system block at a time
+pub(crate) fn open(img: &NamedTempFile) -> BlockReader {
+    let mut reader = BlockReader::open(img.path()).unwrap();
+    reader.set_bufsize(superblock().geometry.blocksize);
+    reader
+}
+
+/// Build an empty V5 bmap B+tree block at the given level, with no siblings
+fn mkbmbt_hdr(level: u16, numrecs: usize) -> Vec<u8> {
+    let mut raw = vec![0u8; superblock().geometry.blocksize];
+    raw[0..4].copy_from_slice(&XFS_BMAP_CRC_MAGIC.to_be_bytes());
+    raw[4..6].copy_from_slice(&level.to_be_bytes());
+    raw[6..8].copy_from_slice(&(numrecs as u16).to_be_bytes());
+    raw[8..24].fill(0xff); // bb_leftsib and bb_rightsib
+    raw
+}
+
+/// Encode (startoff, startblock, blockcount) records as packed, on-disk extent records, as found
+/// in an inode's extent list or a bmap B+tree leaf
+pub(crate) fn bmbt_rec_raw(recs: &[(u64, XfsFsblock, u64)]) -> Vec<u8> {
+    recs.iter()
+        .flat_map(|(startoff, startblock, blockcount)| {
+            ((u128::from(*startoff) << 73)
+                | (u128::from(*startblock) << 21)
+                | u128::from(*blockcount))
+            .to_be_bytes()
+        })
+        .collect()
+}
+
+/// Build a V5 bmap B+tree leaf from (startoff, startblock, blockcount) records
+pub(crate) fn mkbmbt_leaf(recs: &[(u64, XfsFsblock, u64)]) -> Vec<u8> {
+    let mut raw = mkbmbt_hdr(0, recs.len());
+    raw[72..72 + 16 * recs.len()].copy_from_slice(&bmbt_rec_raw(recs));
+    raw
+}
+
+/// Build a V5 bmap B+tree node at the given level, from (key, pointer) pairs
+pub(crate) fn mkbmbt_node(level: u16, entries: &[(u64, XfsFsblock)]) -> Vec<u8> {
+    let mut raw = mkbmbt_hdr(level, entries.len());
+    let pofs = 72 + 8 * ((raw.len() - 72) / 16);
+    for (i, (key, ptr)) in entries.iter().enumerate() {
+        raw[72 + 8 * i..80 + 8 * i].copy_from_slice(&key.to_be_bytes());
+        raw[pofs + 8 * i..pofs + 8 * i + 8].copy_from_slice(&ptr.to_be_bytes());
+    }
+    raw
+}
+
+/// Build a V5 inode B+tree block header
+fn mkinobt_hdr(level: u16, numrecs: usize, rightsib: XfsAgblock) -> Vec<u8> {
+    let mut raw = vec![0u8; superblock().geometry.blocksize];
+    raw[0..4].copy_from_slice(&XFS_IBT_CRC_MAGIC.to_be_bytes());
+    raw[4..6].copy_from_slice(&level.to_be_bytes());
+    raw[6..8].copy_from_slice(&(numrecs as u16).to_be_bytes());
+    raw[8..12].copy_from_slice(&NULLAGBLOCK.to_be_bytes());
+    raw[12..16].copy_from_slice(&rightsib.to_be_bytes());
+    raw
+}
+
+/// Build an inode B+tree leaf holding records of (startino, holemask, free)
+pub(crate) fn mkinobt_leaf(recs: &[(XfsAgino, u16, u64)], rightsib: XfsAgblock) -> Vec<u8> {
+    let mut raw = mkinobt_hdr(0, recs.len(), rightsib);
+    for (i, (startino, holemask, free)) in recs.iter().enumerate() {
+        let ofs = 56 + 16 * i;
+        raw[ofs..ofs + 4].copy_from_slice(&startino.to_be_bytes());
+        raw[ofs + 4..ofs + 6].copy_from_slice(&holemask.to_be_bytes());
+        raw[ofs + 6] = 64 - 4 * holemask.count_ones() as u8;
+        raw[ofs + 7] = free.count_ones() as u8;
+        raw[ofs + 8..ofs + 16].copy_from_slice(&free.to_be_bytes());
+    }
+    raw
+}
+
+/// Build an inode B+tree node with the given (key, pointer) pairs
+pub(crate) fn mkinobt_node(level: u16, entries: &[(XfsAgino, XfsAgblock)]) -> Vec<u8> {
+    let mut raw = mkinobt_hdr(level, entries.len(), NULLAGBLOCK);
+    let maxrecs = (raw.len() - 56) / 8;
+    for (i, (key, ptr)) in entries.iter().enumerate() {
+        let kofs = 56 + 4 * i;
+        raw[kofs..kofs + 4].copy_from_slice(&key.to_be_bytes());
+        let pofs = 56 + 4 * maxrecs + 4 * i;
+        raw[pofs..pofs + 4].copy_from_slice(&ptr.to_be_bytes());
+    }
+    raw
+}
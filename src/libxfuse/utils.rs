@@ -25,6 +25,8 @@
  * OR TORT (INCLUDING NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE USE
  * OF THIS SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
  */
+use std::sync::atomic::{AtomicBool, Ordering};
+
 use bincode::{
     de::{read::Reader, Decoder},
     error::DecodeError,
@@ -32,29 +34,20 @@ use bincode::{
     Decode,
 };
 use fuser::FileType;
-use libc::{
-    c_int,
-    mode_t,
-    ENOENT,
-    S_IFBLK,
-    S_IFCHR,
-    S_IFDIR,
-    S_IFIFO,
-    S_IFLNK,
-    S_IFMT,
-    S_IFREG,
-    S_IFSOCK,
-};
-use tracing::error;
+use libc::{mode_t, S_IFBLK, S_IFCHR, S_IFDIR, S_IFIFO, S_IFLNK, S_IFMT, S_IFREG, S_IFSOCK};
+use tracing::{error, warn};
 
-use super::dir3::{
-    XFS_DIR3_FT_BLKDEV,
-    XFS_DIR3_FT_CHRDEV,
-    XFS_DIR3_FT_DIR,
-    XFS_DIR3_FT_FIFO,
-    XFS_DIR3_FT_REG_FILE,
-    XFS_DIR3_FT_SOCK,
-    XFS_DIR3_FT_SYMLINK,
+use super::{
+    dir3::{
+        XFS_DIR3_FT_BLKDEV,
+        XFS_DIR3_FT_CHRDEV,
+        XFS_DIR3_FT_DIR,
+        XFS_DIR3_FT_FIFO,
+        XFS_DIR3_FT_REG_FILE,
+        XFS_DIR3_FT_SOCK,
+        XFS_DIR3_FT_SYMLINK,
+    },
+    error::XfsError,
 };
 
 /// xfs-fuse UUID type
@@ -85,7 +78,7 @@ pub enum FileKind {
     Mode(u16),
 }
 
-pub fn get_file_type(kind: FileKind) -> Result<FileType, c_int> {
+pub fn get_file_type(kind: FileKind) -> Result<FileType, XfsError> {
     match kind {
         FileKind::Type(file_type) => match file_type {
             XFS_DIR3_FT_REG_FILE => Ok(FileType::RegularFile),
@@ -95,10 +88,7 @@ pub fn get_file_type(kind: FileKind) -> Result<FileType, c_int> {
             XFS_DIR3_FT_CHRDEV => Ok(FileType::CharDevice),
             XFS_DIR3_FT_BLKDEV => Ok(FileType::BlockDevice),
             XFS_DIR3_FT_FIFO => Ok(FileType::NamedPipe),
-            _ => {
-                error!("Unknown file type {:?}.", file_type);
-                Err(ENOENT)
-            }
+            _ => Err(XfsError::NotFound),
         },
         FileKind::Mode(file_mode) => match (file_mode as mode_t) & S_IFMT {
             S_IFREG => Ok(FileType::RegularFile),
@@ -110,12 +100,30 @@ pub fn get_file_type(kind: FileKind) -> Result<FileType, c_int> {
             S_IFIFO => Ok(FileType::NamedPipe),
             _ => {
                 error!("Unknown file type {:?}.", (file_mode as mode_t) & S_IFMT);
-                Err(ENOENT)
+                Err(XfsError::NotFound)
             }
         },
     }
 }
 
+/// Convert a directory entry's on-disk ftype, if the file system records one, to a file type.
+/// An unknown ftype is treated as though there were none, so the caller will consult the inode
+/// instead of failing the whole directory.
+pub fn get_dirent_type(ftype: Option<u8>) -> Option<FileType> {
+    static WARNED: AtomicBool = AtomicBool::new(false);
+
+    let ftype = ftype?;
+    let kind = get_file_type(FileKind::Type(ftype)).ok();
+    if kind.is_none() && !WARNED.swap(true, Ordering::Relaxed) {
+        warn!(
+            "Unknown directory entry file type {}.  Such entries' types will be read from their \
+             inodes.",
+            ftype
+        );
+    }
+    kind
+}
+
 /// Decode a Bincode structure from a byte slice.
 pub fn decode<T>(bytes: &[u8]) -> Result<(T, usize), DecodeError>
 where
@@ -26,15 +26,19 @@
  * OF THIS SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
  */
 use std::{
-    collections::HashMap,
-    ffi::OsStr,
-    io::Read,
+    collections::{hash_map::Entry, BTreeSet, HashMap, HashSet},
+    ffi::{OsStr, OsString},
+    fmt,
+    io::{self, Read, Write},
+    mem,
     os::unix::ffi::OsStrExt,
-    path::Path,
-    sync::OnceLock,
+    panic::PanicHookInfo,
+    path::{Path, PathBuf},
+    sync::{Mutex, OnceLock},
     time::Duration,
 };
 
+use base64::{engine::general_purpose::STANDARD as BASE64, Engine};
 use fuser::{
     consts::{
         FOPEN_CACHE_DIR,
@@ -44,10 +48,13 @@ use fuser::{
         FUSE_NO_OPENDIR_SUPPORT,
         FUSE_NO_OPEN_SUPPORT,
     },
+    FileAttr,
+    FileType,
     Filesystem,
     KernelConfig,
     ReplyAttr,
     ReplyDirectory,
+    ReplyEmpty,
     ReplyEntry,
     ReplyLseek,
     ReplyOpen,
@@ -60,12 +67,21 @@ use libc::ERANGE;
 use tracing::warn;
 
 use super::{
-    attr::Attr,
+    agf::Counters,
+    agi::unlinked_inodes,
+    attr::{get_flags_from_namespace, Attr},
     block_reader::BlockReader,
-    definitions::XfsIno,
+    definitions::{XfsAgnumber, XfsFsblock, XfsIno, NULLFSINO, XFS_MAXINUMBER},
     dinode::Dinode,
     dir3::Dir3,
+    error::XfsError,
+    file::{ExtentInfo, File},
+    inobt,
+    patch::InodePatches,
+    path::{self, Namespace, PathNode},
+    quota,
     sb::Sb,
+    utils::{get_file_type, FileKind},
 };
 
 /// We must store the Superblock in a global variable.  This is unfortunate, and limits us to only
@@ -73,33 +89,355 @@ use super::{
 /// superblock within a Decode::decode implementation.
 pub(super) static SUPERBLOCK: OnceLock<Sb> = OnceLock::new();
 
+/// Every distinct corruption reported to the kernel so far, if `--repair-hint` was given, along
+/// with the structure it was found in.  Like the superblock, it's global so that `errno` can
+/// reach it from anywhere, including a panic hook.
+static CORRUPTION: Mutex<Option<BTreeSet<(Implicated, String)>>> = Mutex::new(None);
+
+/// The on-disk structure in which some corruption was found, for `--repair-hint`
+#[derive(Clone, Copy, Debug, Eq, Ord, PartialEq, PartialOrd)]
+enum Implicated {
+    /// Somewhere unknown, as when a decoder panics
+    Unknown,
+    /// The headers of an AG
+    Ag(XfsAgnumber),
+    /// An inode, or anything that it owns, like its directory or attribute blocks
+    Inode(XfsIno),
+}
+
+impl fmt::Display for Implicated {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match *self {
+            Implicated::Unknown => write!(f, "Unknown structure"),
+            Implicated::Ag(agno) => write!(f, "AG {}", agno),
+            Implicated::Inode(ino) => {
+                // Name the block that holds the inode too, and that block's AG, since those are
+                // what xfs_repair reports.
+                let sb = SUPERBLOCK.get().unwrap();
+                let fsb: XfsFsblock = ino >> sb.sb_inopblog;
+                write!(
+                    f,
+                    "AG {}, block {}, inode {}",
+                    fsb >> sb.sb_agblklog,
+                    fsb,
+                    ino
+                )
+            }
+        }
+    }
+}
+
+/// The names of the pseudo-attributes exposed by `--show-meta`, all of which begin with
+/// `META_XATTR_PREFIX`.
+const META_XATTRS: [&str; 5] = [
+    "changecount",
+    "extsize_hint",
+    "fragmentation",
+    "label",
+    "parents",
+];
+const META_XATTR_PREFIX: &[u8] = b"system.xfs.";
+
+/// Convert an error, found while handling a request for on-disk inode `ino`, into the errno to
+/// return to the kernel.  Errors that indicate a problem with the file system or the device,
+/// rather than with the request, are logged.
+fn errno(e: XfsError, ino: XfsIno) -> libc::c_int {
+    report(&e, Implicated::Inode(ino));
+    e.errno()
+}
+
+/// Log an error if it indicates a problem with the file system or the device, and record any
+/// corruption for `--repair-hint`, as found in `at`.
+fn report(e: &XfsError, at: Implicated) {
+    if matches!(
+        e,
+        XfsError::Io(_) | XfsError::Corrupt(_) | XfsError::Unsupported(_)
+    ) {
+        warn!("{}", e);
+    }
+    record(e, at);
+}
+
+/// Record an error for `--repair-hint` if it indicates corruption, as found in `at`
+fn record(e: &XfsError, at: Implicated) {
+    if let XfsError::Corrupt(s) = e {
+        if let Some(found) = CORRUPTION.lock().unwrap().as_mut() {
+            found.insert((at, s.clone()));
+        }
+    }
+}
+
+/// Print the summary of the corruption found so far, if `--repair-hint` was given and there was
+/// any.  The daemon prints it at unmount, and also whenever it's asked to with a signal.
+pub fn print_repair_hint() {
+    if let Some(hint) = CORRUPTION.lock().unwrap().as_ref().and_then(repair_hint) {
+        eprint!("{}", hint);
+    }
+}
+
+/// Record a panic in the file system code as corruption of an unknown structure, if
+/// `--repair-hint` was given, and print the summary, because the daemon won't survive to unmount.
+/// Some decoders still panic on corrupt metadata rather than returning an error.
+pub fn record_panic(info: &PanicHookInfo) {
+    let Some(loc) = info
+        .location()
+        .filter(|loc| loc.file().contains("libxfuse"))
+    else {
+        return;
+    };
+    // The panic may have happened while the lock was held
+    let Ok(mut guard) = CORRUPTION.try_lock() else {
+        return;
+    };
+    let Some(found) = guard.as_mut() else {
+        return;
+    };
+    let payload = info.payload();
+    let msg = payload
+        .downcast_ref::<&str>()
+        .copied()
+        .or_else(|| payload.downcast_ref::<String>().map(String::as_str))
+        .unwrap_or("Box<dyn Any>");
+    found.insert((
+        Implicated::Unknown,
+        format!("{} (panicked at {})", msg, loc),
+    ));
+    if let Some(hint) = repair_hint(found) {
+        eprint!("{}", hint);
+    }
+}
+
+/// Describe how fragmented a file is, given its extent map, as
+/// `extents=<n> fragments=<n> factor=<percent>`.  A fragment is a run of extents that are
+/// contiguous both within the file and on disk, and the factor is the percentage of fragments
+/// beyond the first, like xfs_db's `frag` command computes for a whole file system.
+fn fragmentation(extents: &[ExtentInfo], blocklog: u8) -> String {
+    let fragments = extents
+        .iter()
+        .zip(extents.iter().skip(1))
+        .filter(|(prev, ext)| {
+            prev.file_offset + prev.length != ext.file_offset
+                || prev.fsblock + (prev.length >> blocklog) != ext.fsblock
+        })
+        .count()
+        + usize::from(!extents.is_empty());
+    let factor = if fragments == 0 {
+        0.0
+    } else {
+        100.0 * (fragments - 1) as f64 / fragments as f64
+    };
+    format!(
+        "extents={} fragments={} factor={:.2}",
+        extents.len(),
+        fragments,
+        factor
+    )
+}
+
+/// Estimate the total and free inode counts for statfs.  XFS allocates inodes dynamically, so
+/// like the kernel, count every free block as room for as many inodes as fit in it, but no more
+/// than `sb_imax_pct` allows.  The number in use is always exact.
+fn inode_counts(sb: &Sb, counters: &Counters) -> (u64, u64) {
+    let used = counters.icount.saturating_sub(counters.ifree);
+    let mut files = counters
+        .icount
+        .saturating_add(counters.fdblocks << sb.sb_inopblog)
+        .min(XFS_MAXINUMBER);
+    if let Some(maxicount) = sb.maxicount() {
+        files = files.min(maxicount);
+    }
+    // sb_icount may overshoot the limit, if sb_imax_pct was lowered after they were allocated
+    files = files.max(counters.icount);
+    (files, files - used)
+}
+
+/// Summarize the corruption that was found, with a suggestion to repair it, or return `None` if
+/// there was none.
+fn repair_hint(found: &BTreeSet<(Implicated, String)>) -> Option<String> {
+    if found.is_empty() {
+        return None;
+    }
+    let mut hint = format!(
+        "Found {} corrupt structure(s) while mounted:\n",
+        found.len()
+    );
+    for (at, s) in found {
+        hint.push_str(&format!("    {}: {}\n", at, s));
+    }
+    hint.push_str(
+        "Unmount the file system and run xfs_repair on it, or xfs_repair -n to check without \
+         modifying it.\n",
+    );
+    Some(hint)
+}
+
 #[derive(Debug)]
 struct OpenInode {
     dinode: Dinode,
     count:  u64,
 }
 
+/// Options that control how the file system is presented to the kernel
+#[derive(Clone, Debug, Default)]
+pub struct VolumeOptions {
+    /// A constant added to every inode number reported to the kernel.  Useful
+    /// for avoiding collisions when the file system is stacked with others,
+    /// as in an overlay.
+    pub ino_offset:         u64,
+    /// Overrides to apply to inodes' metadata after decoding them.
+    pub patches:            Option<InodePatches>,
+    /// The largest read, in bytes, that will be serviced in a single request.  Larger requests
+    /// will return short reads.
+    pub max_read:           Option<u32>,
+    /// Present inodes that were unlinked while still open in a synthetic `.unlinked` directory
+    /// beneath the root.
+    pub show_deleted:       bool,
+    /// Expose internal metadata, such as parent pointers, as extended attributes in the `xfs`
+    /// namespace.
+    pub show_meta:          bool,
+    /// Pad every read of the device to a multiple of this many bytes, instead of the sector size
+    /// that the device reports.
+    pub sectorsize:         Option<u32>,
+    /// The device that holds the file system's realtime section, if any
+    pub rtdev:              Option<PathBuf>,
+    /// Log the offset and length of every read from the devices, at debug level.
+    pub trace_reads:        bool,
+    /// Return directory entries sorted by name, rather than in on-disk order.
+    pub sort_dents:         bool,
+    /// Read the devices through memory mappings, rather than with read syscalls.
+    pub mmap:               bool,
+    /// Record any corruption found, and summarize it with a hint to run xfs_repair at unmount.
+    pub repair_hint:        bool,
+    /// Leave directory entries whose on-disk file type is unknown out of readdir's results,
+    /// rather than reading their types from their inodes.
+    pub skip_unknown_types: bool,
+    /// Display inode numbers in diagnostic output, such as `xattr-dump` and the
+    /// `system.xfs.parents` attribute, as an AG number and AG-relative inode number, like xfs_db
+    /// does.  Inode numbers reported to the kernel are not affected.
+    pub relative_ino:       bool,
+}
+
+/// A directory entry as reported to the kernel: node ID, file type, and name
+type Dirent = (u64, FileType, OsString);
+
 #[derive(Debug)]
 pub struct Volume {
-    pub device: BlockReader,
-    pub sb:     Sb,
-    open_files: HashMap<u64, OpenInode>,
-    no_open:    bool,
-    no_opendir: bool,
+    pub device:         BlockReader,
+    /// The realtime device, if `--rtdev` was given
+    rtdev:              Option<BlockReader>,
+    pub sb:             Sb,
+    open_files:         HashMap<u64, OpenInode>,
+    no_open:            bool,
+    no_opendir:         bool,
+    ino_offset:         u64,
+    patches:            Option<InodePatches>,
+    max_read:           Option<u32>,
+    /// Unlinked but still allocated inodes, if `--show-deleted` was given
+    unlinked:           Option<Vec<(XfsIno, FileType)>>,
+    show_meta:          bool,
+    sort_dents:         bool,
+    skip_unknown_types: bool,
+    relative_ino:       bool,
+    /// Check each directory's consistency when it's opened, if `--repair-hint` was given
+    repair_hint:        bool,
+    /// Every entry of each directory opened with `--sort-dents`, sorted by name and indexed by
+    /// file handle
+    dirents:            HashMap<u64, Vec<Dirent>>,
+    /// The next file handle to allocate for `dirents`
+    next_fh:            u64,
+    /// Reused by every read, to avoid allocating a buffer for each one
+    read_buf:           Vec<u8>,
+    /// Free space and inode counters for statfs.  Nothing can change them while mounted, so
+    /// they're computed once at mount.
+    counters:           Counters,
 }
 
 impl Volume {
+    /// Hidden root directory entry for the realtime bitmap inode, with `--show-meta`
+    const RTBITMAP_NAME: &'static str = ".xfs-rtbitmap";
+    /// Hidden root directory entry for the realtime summary inode, with `--show-meta`
+    const RTSUMMARY_NAME: &'static str = ".xfs-rtsummary";
     // Allow the kernel to cache attributes and entries for an unlimited amount
     // of time, since nothing will ever change.
     const TTL: Duration = Duration::from_secs(u64::MAX);
+    /// Root directory cookie of the synthetic directory of unlinked inodes, when entries are
+    /// returned in on-disk order.  It sorts after every real directory entry.
+    const UNLINKED_COOKIE: i64 = i64::MAX;
+    /// Node ID of the synthetic directory of unlinked inodes
+    const UNLINKED_ID: u64 = u64::MAX;
+    /// Name of the synthetic directory of unlinked inodes
+    const UNLINKED_NAME: &'static str = ".unlinked";
 
-    pub fn from(device_name: &Path) -> Volume {
+    /// Open the file system on the given device.  Fail if it can't be mounted at all, for example
+    /// because of inconsistent realtime geometry.
+    pub fn from(device_name: &Path, opts: VolumeOptions) -> Result<Volume, XfsError> {
         let mut device = BlockReader::open(device_name).unwrap();
+        if let Some(sectorsize) = opts.sectorsize {
+            device.set_sectorsize(sectorsize as usize);
+        }
+        if opts.trace_reads {
+            device.set_trace("data");
+        }
+        if opts.mmap {
+            if let Err(e) = device.set_mmap() {
+                warn!("Cannot mmap {}: {}", device_name.display(), e);
+            }
+        }
 
-        let superblock = Sb::from(device.by_ref());
+        let superblock = Sb::from(device.by_ref())?;
         SUPERBLOCK.set(superblock).unwrap();
+        let fs_size = superblock.sb_dblocks << superblock.sb_blocklog;
+        match device.size() {
+            Ok(size) if size < fs_size => warn!(
+                "{} is only {} bytes, but its file system needs {}",
+                device_name.display(),
+                size,
+                fs_size
+            ),
+            Ok(_) => (),
+            Err(e) => warn!("Cannot get the size of {}: {}", device_name.display(), e),
+        }
+        if opts.repair_hint {
+            *CORRUPTION.lock().unwrap() = Some(BTreeSet::new());
+        }
 
-        let root_inode = Dinode::from(device.by_ref(), &superblock, superblock.sb_rootino);
+        let root_inode = Dinode::from(
+            device.by_ref(),
+            &superblock,
+            superblock.sb_rootino,
+            opts.patches.as_ref(),
+        )?;
+        // FUSE always reports the root as a directory, whatever it really is.
+        if get_file_type(FileKind::Mode(root_inode.di_core.di_mode)).ok()
+            != Some(FileType::Directory)
+        {
+            return Err(XfsError::Corrupt(format!(
+                "Root inode {} is not a directory",
+                superblock.sb_rootino
+            )));
+        }
+        let rtdev = opts
+            .rtdev
+            .as_ref()
+            .map(|path| -> Result<BlockReader, XfsError> {
+                let mut rtdev = BlockReader::open(path).unwrap();
+                if let Some(sectorsize) = opts.sectorsize {
+                    rtdev.set_sectorsize(sectorsize as usize);
+                }
+                if opts.trace_reads {
+                    rtdev.set_trace("realtime");
+                }
+                if opts.mmap {
+                    if let Err(e) = rtdev.set_mmap() {
+                        warn!("Cannot mmap {}: {}", path.display(), e);
+                    }
+                }
+                device.set_bufsize(superblock.inode_size());
+                let rsum = Dinode::from(device.by_ref(), &superblock, superblock.sb_rsumino, None)?;
+                superblock.check_realtime(rsum.di_core.di_size)?;
+                Ok(rtdev)
+            })
+            .transpose()?;
         let mut open_files = HashMap::new();
         // Prepopulate the root inode into the cache, since fusefs never sends a lookup for it.
         open_files.insert(
@@ -110,55 +448,698 @@ impl Volume {
             },
         );
 
-        Volume {
+        let unlinked = opts.show_deleted.then(|| {
+            device.set_bufsize(superblock.geometry.blocksize);
+            unlinked_inodes(device.by_ref(), &superblock)
+                .into_iter()
+                .filter_map(|(ino, mut di_core)| {
+                    if let Some(patches) = opts.patches.as_ref() {
+                        patches.apply(ino, &mut di_core);
+                    }
+                    match di_core.stat(ino, &superblock) {
+                        Ok(attr) => Some((ino, attr.kind)),
+                        Err(_) => {
+                            warn!("Ignoring unlinked inode {} of unknown type", ino);
+                            None
+                        }
+                    }
+                })
+                .collect()
+        });
+
+        // With lazy superblock counters, the AG headers' counters are authoritative.
+        let counters = if superblock.has_lazysbcount() {
+            device.set_bufsize(superblock.sb_sectsize.into());
+            (0..superblock.sb_agcount)
+                .try_fold(Counters::default(), |sum, agno| {
+                    Counters::from_ag(device.by_ref(), &superblock, agno)
+                        .map(|counters| sum + counters)
+                        .map_err(|e| (agno, e))
+                })
+                .unwrap_or_else(|(agno, e)| {
+                    warn!("Cannot sum the AG counters, using the superblock's: {}", e);
+                    record(&e, Implicated::Ag(agno));
+                    Counters::from_superblock(&superblock)
+                })
+        } else {
+            Counters::from_superblock(&superblock)
+        };
+
+        Ok(Volume {
             device,
+            rtdev,
             sb: superblock,
             open_files,
             no_open: false,
             no_opendir: false,
+            ino_offset: opts.ino_offset,
+            patches: opts.patches,
+            max_read: opts.max_read,
+            unlinked,
+            show_meta: opts.show_meta,
+            sort_dents: opts.sort_dents,
+            skip_unknown_types: opts.skip_unknown_types,
+            relative_ino: opts.relative_ino,
+            repair_hint: opts.repair_hint,
+            dirents: HashMap::new(),
+            next_fh: 1,
+            read_buf: Vec::new(),
+            counters,
+        })
+    }
+
+    /// The largest `ino_offset` that this file system can use.  Any larger, and its highest inode
+    /// numbers would overflow, or collide with the synthetic unlinked directory.
+    pub fn max_ino_offset(&self) -> u64 {
+        let max_ino = u64::from(self.sb.sb_agcount) << (self.sb.sb_agblklog + self.sb.sb_inopblog);
+        Self::UNLINKED_ID - 1 - max_ino
+    }
+
+    /// Convert an on-disk inode number to the one reported to the kernel.
+    fn to_nodeid(&self, ino: XfsIno) -> u64 {
+        // FUSE requires the file system's root directory to have a fixed inode number.
+        if ino == self.sb.sb_rootino {
+            FUSE_ROOT_ID
+        } else {
+            ino + self.ino_offset
         }
     }
 
-    fn open_inode(&mut self, ino: u64) -> &mut OpenInode {
-        let sb = &self.sb;
-        self.open_files
-            .entry(ino)
-            .and_modify(|e| e.count += 1)
-            .or_insert_with(|| {
-                self.device.set_bufsize(sb.inode_size());
-                let dinode = Dinode::from(
-                    self.device.by_ref(),
-                    sb,
-                    if ino == FUSE_ROOT_ID {
-                        sb.sb_rootino
-                    } else {
-                        ino as XfsIno
-                    },
-                );
-                OpenInode { dinode, count: 1 }
+    /// Format an on-disk inode number for diagnostic output: either as is, or as
+    /// "<agno>/<agino>" with `--relative-ino`.
+    fn display_ino(&self, ino: XfsIno) -> String {
+        if self.relative_ino {
+            let (agno, agino) = self.sb.ino_to_agino(ino);
+            format!("{}/{}", agno, agino)
+        } else {
+            ino.to_string()
+        }
+    }
+
+    /// Convert an inode number reported to the kernel back to the on-disk one.
+    fn to_ino(&self, nodeid: u64) -> XfsIno {
+        if nodeid == FUSE_ROOT_ID {
+            self.sb.sb_rootino
+        } else {
+            nodeid - self.ino_offset
+        }
+    }
+
+    /// Attributes of the synthetic directory of unlinked inodes
+    fn unlinked_dir_attr(&self) -> FileAttr {
+        let root = &self.open_files.get(&FUSE_ROOT_ID).unwrap().dinode;
+        let mut attr = root.di_core.stat(self.sb.sb_rootino, &self.sb).unwrap();
+        attr.ino = Self::UNLINKED_ID;
+        attr.size = 0;
+        attr.blocks = 0;
+        attr.nlink = 2;
+        attr.perm = 0o555;
+        attr
+    }
+
+    /// Look up an entry in the synthetic directory of unlinked inodes.  Entries are named by
+    /// their inode number.
+    fn lookup_unlinked(&mut self, name: &OsStr, reply: ReplyEntry) {
+        let unlinked = self.unlinked.as_ref().unwrap();
+        let Some(ino) = name
+            .to_str()
+            .and_then(|s| s.parse::<XfsIno>().ok())
+            .filter(|ino| unlinked.iter().any(|(i, _)| i == ino))
+        else {
+            reply.error(libc::ENOENT);
+            return;
+        };
+        self.lookup_ino(ino, reply);
+    }
+
+    /// Reply to a lookup that resolved to the given on-disk inode.  The file type always comes from
+    /// the inode's own mode, never from the directory entry's ftype.
+    fn lookup_ino(&mut self, ino: XfsIno, reply: ReplyEntry) {
+        let nodeid = self.to_nodeid(ino);
+        let sb = self.sb;
+        let oi = match self.open_inode(nodeid) {
+            Ok(oi) => oi,
+            Err(e) => {
+                reply.error(errno(e, ino));
+                return;
+            }
+        };
+        match oi.dinode.di_core.stat(ino, &sb) {
+            Ok(mut attr) => {
+                attr.ino = nodeid;
+                // We don't need to report the inode generation since this is a read-only
+                // file system.  But we'll do it anyway.
+                reply.entry(&Self::TTL, &attr, oi.dinode.di_core.di_gen.into())
+            }
+            Err(e) => reply.error(errno(e, ino)),
+        }
+    }
+
+    /// Look up one of the metadata inodes that `--show-meta` exposes by hidden names in the root
+    /// directory.
+    fn meta_inode(&self, name: &OsStr) -> Option<XfsIno> {
+        let ino = if name == Self::RTBITMAP_NAME {
+            self.sb.sb_rbmino
+        } else if name == Self::RTSUMMARY_NAME {
+            self.sb.sb_rsumino
+        } else {
+            return None;
+        };
+        (ino != NULLFSINO).then_some(ino)
+    }
+
+    /// Determine the file type of a directory entry for inode `ino`, whose on-disk ftype, if the
+    /// file system records one, is `kind`.  `open` is the inode's cache entry, if it has one.
+    fn dirent_kind(
+        device: &mut BlockReader,
+        sb: &Sb,
+        patches: Option<&InodePatches>,
+        open: Option<&OpenInode>,
+        ino: XfsIno,
+        kind: Option<FileType>,
+        name: &OsStr,
+    ) -> Result<FileType, XfsError> {
+        match kind {
+            Some(kind) => {
+                // We report the entry's ftype, whereas lookup and getattr report the inode's own
+                // mode.  The two can only disagree on a corrupt file system.  Checking every entry
+                // would defeat the purpose of ftype, but an inode that's already open can be
+                // checked for free.
+                if let Some(actual) = open
+                    .and_then(|oi| oi.dinode.di_core.stat(ino, sb).ok())
+                    .map(|attr| attr.kind)
+                    .filter(|actual| *actual != kind)
+                {
+                    warn!(
+                        "Directory entry {:?} has type {:?}, but inode {} is a {:?}",
+                        name, kind, ino, actual
+                    );
+                }
+                Ok(kind)
+            }
+            None => {
+                // This is very inefficient.  Frequently, getattr will be called for every entry
+                // returned by readdir.  In such cases, this code will read the inode twice.  The
+                // best solution is for everybody to use the ftype option in their XFS format.
+                device.set_bufsize(sb.inode_size());
+                let dinode = Dinode::from(device.by_ref(), sb, ino, patches)?;
+                Ok(dinode.di_core.stat(ino, sb)?.kind)
+            }
+        }
+    }
+
+    /// Should this directory entry be left out of readdir's results?  With
+    /// `--skip-unknown-types`, an entry whose on-disk ftype wasn't recognized is skipped, so one
+    /// corrupt entry can't affect the listing of the rest.  Otherwise, its type is read from its
+    /// inode instead.
+    fn skip_dirent(
+        sb: &Sb,
+        skip_unknown_types: bool,
+        ino: XfsIno,
+        kind: Option<FileType>,
+        name: &OsStr,
+    ) -> bool {
+        // Without ftype, no entry has a type, and they must all be read from their inodes
+        let skip = skip_unknown_types && kind.is_none() && sb.has_ftype();
+        if skip {
+            warn!(
+                "Skipping directory entry {:?} for inode {}, whose type is unknown",
+                name, ino
+            );
+        }
+        skip
+    }
+
+    /// Read every entry of a directory, sorted by name
+    fn sorted_dirents(&mut self, ino: u64) -> Result<Vec<Dirent>, XfsError> {
+        self.device.set_bufsize(self.sb.geometry.dirblksize);
+        let oi = &mut self.open_files.get_mut(&ino).unwrap();
+        oi.dinode.get_dir(self.device.by_ref(), &self.sb)?;
+        let dir = self.open_files[&ino].dinode.cached_dir().unwrap();
+        // A real .unlinked entry would be shadowed by the synthetic one, which takes its place
+        let hide_unlinked = ino == FUSE_ROOT_ID && self.unlinked.is_some();
+
+        let mut entries = Vec::new();
+        let mut off = 0;
+        loop {
+            match dir.next(self.device.by_ref(), &self.sb, off) {
+                Ok((ino, offset, kind, name)) => {
+                    let hidden = hide_unlinked && name == Self::UNLINKED_NAME;
+                    if !hidden
+                        && !Self::skip_dirent(&self.sb, self.skip_unknown_types, ino, kind, &name)
+                    {
+                        entries.push((ino, kind, name));
+                    }
+                    off = offset;
+                }
+                Err(XfsError::NotFound) => break,
+                Err(e) => return Err(e),
+            }
+        }
+
+        let mut dirents = entries
+            .into_iter()
+            .map(|(ino, kind, name)| {
+                let nodeid = self.to_nodeid(ino);
+                let kind = Self::dirent_kind(
+                    &mut self.device,
+                    &self.sb,
+                    self.patches.as_ref(),
+                    self.open_files.get(&nodeid),
+                    ino,
+                    kind,
+                    &name,
+                )?;
+                Ok((nodeid, kind, name))
             })
+            .collect::<Result<Vec<_>, XfsError>>()?;
+        if hide_unlinked {
+            dirents.push((
+                Self::UNLINKED_ID,
+                FileType::Directory,
+                OsString::from(Self::UNLINKED_NAME),
+            ));
+        }
+        dirents.sort_unstable_by(|a, b| a.2.as_bytes().cmp(b.2.as_bytes()));
+        Ok(dirents)
+    }
+
+    /// List the synthetic directory of unlinked inodes
+    fn readdir_unlinked(&mut self, offset: i64, mut reply: ReplyDirectory) {
+        let unlinked = self.unlinked.as_ref().unwrap();
+        let dots = [
+            (Self::UNLINKED_ID, FileType::Directory, OsString::from(".")),
+            (FUSE_ROOT_ID, FileType::Directory, OsString::from("..")),
+        ];
+        let entries = unlinked
+            .iter()
+            .map(|(ino, kind)| (self.to_nodeid(*ino), *kind, OsString::from(ino.to_string())));
+        for (i, (nodeid, kind, name)) in dots
+            .into_iter()
+            .chain(entries)
+            .enumerate()
+            .skip(usize::try_from(offset).unwrap_or(usize::MAX))
+        {
+            if reply.add(nodeid, i as i64 + 1, kind, name) {
+                break;
+            }
+        }
+        reply.ok();
+    }
+
+    /// Get one of the pseudo-attributes named `system.xfs.*`, which expose internal metadata.
+    /// They live in the system namespace because it's the only one besides user that both
+    /// FreeBSD and Linux will pass through to FUSE, and XFS never stores attributes there.
+    fn get_meta_xattr(&mut self, nodeid: u64, name: &OsStr) -> Result<Vec<u8>, XfsError> {
+        match name.as_bytes() {
+            // One line per parent pointer, of the form "<parent inode> <entry name>".
+            b"parents" if self.sb.has_parent() => {
+                let oi = self.open_files.get_mut(&nodeid).unwrap();
+                self.device.set_bufsize(self.sb.geometry.blocksize);
+                let parents = match oi.dinode.get_attrs(self.device.by_ref(), &self.sb) {
+                    Some(attrs) => attrs.parents(self.device.by_ref(), &self.sb)?,
+                    None => Vec::new(),
+                };
+                let mut value = Vec::new();
+                for (ino, entry_name) in parents {
+                    // Absolute numbers match the parents' st_ino, so they include --ino-offset.
+                    let parent = if self.relative_ino {
+                        self.display_ino(ino)
+                    } else {
+                        self.to_nodeid(ino).to_string()
+                    };
+                    value.extend_from_slice(parent.as_bytes());
+                    value.push(b' ');
+                    value.extend_from_slice(&entry_name);
+                    value.push(b'\n');
+                }
+                Ok(value)
+            }
+            // The inode's change counter, in decimal.  FUSE has no way to report it as the NFSv4
+            // change attribute, so the kernel derives that from ctime instead.
+            b"changecount" => {
+                let oi = self.open_files.get(&nodeid).unwrap();
+                if oi.dinode.di_core.di_version >= 3 {
+                    Ok(oi.dinode.di_core.di_changecount.to_string().into_bytes())
+                } else {
+                    Err(XfsError::NoAttr)
+                }
+            }
+            // The allocation hints that a directory passes on to new files within it.
+            b"extsize_hint" => {
+                let oi = self.open_files.get(&nodeid).unwrap();
+                oi.dinode
+                    .di_core
+                    .extsize_hint()
+                    .map(String::into_bytes)
+                    .ok_or(XfsError::NoAttr)
+            }
+            // The data fork's extent count and how fragmented it is.
+            b"fragmentation" => {
+                let oi = self.open_files.get(&nodeid).unwrap();
+                self.device.set_bufsize(self.sb.geometry.blocksize);
+                let extents = oi.dinode.file_extents(self.device.by_ref(), &self.sb)?;
+                Ok(fragmentation(&extents, self.sb.sb_blocklog).into_bytes())
+            }
+            // The file system's label.  Only the root directory has it, and only if it's set.
+            b"label" if nodeid == FUSE_ROOT_ID && !self.sb.label().is_empty() => {
+                Ok(self.sb.label().to_vec())
+            }
+            _ => Err(XfsError::NoAttr),
+        }
+    }
+
+    /// List the names of the pseudo-attributes that the given inode has, in listxattr's format.
+    fn list_meta_xattrs(&mut self, nodeid: u64) -> Vec<u8> {
+        let mut list = Vec::new();
+        for name in META_XATTRS {
+            if self.get_meta_xattr(nodeid, OsStr::new(name)).is_ok() {
+                list.extend_from_slice(META_XATTR_PREFIX);
+                list.extend_from_slice(name.as_bytes());
+                list.push(0);
+            }
+        }
+        list
+    }
+
+    /// Return the complete extent map of the given on-disk inode's data fork
+    pub fn file_extents(&mut self, ino: XfsIno) -> Result<Vec<ExtentInfo>, XfsError> {
+        self.device.set_bufsize(self.sb.inode_size());
+        let dinode = Dinode::from(self.device.by_ref(), &self.sb, ino, self.patches.as_ref())?;
+        self.device.set_bufsize(self.sb.geometry.blocksize);
+        dinode.file_extents(self.device.by_ref(), &self.sb)
+    }
+
+    /// List the allocated inodes of one AG, from its inode B+tree.  Each AG's list is
+    /// independent, so they could be scanned in parallel.
+    fn ag_inodes(&mut self, agno: XfsAgnumber) -> Result<Vec<XfsIno>, XfsError> {
+        self.device.set_bufsize(self.sb.geometry.blocksize);
+        inobt::ag_inodes(self.device.by_ref(), &self.sb, agno)
+    }
+
+    /// Read from the given on-disk inode into `buf`, returning the number of bytes read.  The
+    /// inode is opened, as a lookup would, if it isn't open already.
+    pub fn read_into(
+        &mut self,
+        ino: XfsIno,
+        offset: i64,
+        buf: &mut [u8],
+    ) -> Result<usize, XfsError> {
+        let nodeid = self.to_nodeid(ino);
+        if !self.open_files.contains_key(&nodeid) {
+            self.open_inode(nodeid)?;
+        }
+        let oi = self.open_files.get_mut(&nodeid).unwrap();
+        self.device.set_bufsize(self.sb.geometry.blocksize);
+        if oi.dinode.di_core.is_realtime() {
+            // The data lives on the realtime device.  Without it, don't misinterpret its block
+            // numbers as belonging to the data device.
+            let Some(rtdev) = self.rtdev.as_mut() else {
+                return Err(XfsError::Unsupported(format!(
+                    "inode {} is on the realtime device",
+                    ino
+                )));
+            };
+            let file = oi.dinode.get_rt_file(self.device.by_ref(), &self.sb)?;
+            rtdev.set_bufsize(self.sb.geometry.blocksize);
+            return file.read_into(rtdev, offset, buf);
+        }
+        oi.dinode
+            .get_file(self.device.by_ref())
+            .and_then(|file| file.read_into(self.device.by_ref(), offset, buf))
+    }
+
+    /// Resolve a path, relative to the root directory, to an on-disk inode number.  Symbolic links
+    /// are followed in intermediate components, and also in the final component if `follow` is
+    /// set.
+    pub fn lookup_path(&mut self, path: &Path, follow: bool) -> Result<XfsIno, XfsError> {
+        let root = self.sb.sb_rootino;
+        path::resolve(self, root, path, follow, path::MAXSYMLINKS)
+    }
+
+    /// Print every extended attribute of every inode reachable from `top`, one per line, as
+    /// `<inode> <name>=<value>`.  Like getfattr(1), values are printed in double quotes if they
+    /// are text, or base64-encoded with a `0s` prefix otherwise.
+    pub fn xattr_dump<W: Write>(&mut self, top: XfsIno, out: &mut W) -> io::Result<()> {
+        let mut visited = HashSet::from([top]);
+        let mut pending = vec![top];
+        while let Some(ino) = pending.pop() {
+            self.device.set_bufsize(self.sb.inode_size());
+            let mut dinode =
+                match Dinode::from(self.device.by_ref(), &self.sb, ino, self.patches.as_ref()) {
+                    Ok(dinode) => dinode,
+                    Err(e) => {
+                        warn!("Cannot read inode {}: {}", ino, e);
+                        continue;
+                    }
+                };
+            self.dump_inode_xattrs(ino, &mut dinode, out)?;
+
+            if !dinode
+                .di_core
+                .stat(ino, &self.sb)
+                .is_ok_and(|attr| attr.kind == FileType::Directory)
+            {
+                continue;
+            }
+            self.device.set_bufsize(self.sb.geometry.dirblksize);
+            let dir = match dinode.get_dir(self.device.by_ref(), &self.sb) {
+                Ok(dir) => dir,
+                Err(e) => {
+                    warn!("Cannot read directory {}: {}", ino, e);
+                    continue;
+                }
+            };
+            let mut children = Vec::new();
+            let mut off = 0;
+            loop {
+                match dir.next(self.device.by_ref(), &self.sb, off) {
+                    Ok((child, offset, _, name)) => {
+                        if name != "." && name != ".." && visited.insert(child) {
+                            children.push(child);
+                        }
+                        off = offset;
+                    }
+                    Err(XfsError::NotFound) => break,
+                    Err(e) => {
+                        warn!("Cannot read directory {}: {}", ino, e);
+                        break;
+                    }
+                }
+            }
+            // Visit the children in directory order
+            pending.extend(children.into_iter().rev());
+        }
+        Ok(())
+    }
+
+    /// Like [`Volume::xattr_dump`], but for every allocated inode, as found from each AG's inode
+    /// B+tree, rather than for a directory tree.  The inodes are visited in inode number order.
+    /// Reserved inodes other than the root directory are skipped.
+    pub fn xattr_dump_all<W: Write>(&mut self, out: &mut W) -> io::Result<()> {
+        for agno in 0..self.sb.sb_agcount {
+            let inodes = match self.ag_inodes(agno) {
+                Ok(inodes) => inodes,
+                Err(e) => {
+                    warn!("Cannot list the inodes of AG {}: {}", agno, e);
+                    continue;
+                }
+            };
+            for ino in inodes {
+                if ino != self.sb.sb_rootino && self.sb.is_reserved_inode(ino) {
+                    continue;
+                }
+                self.device.set_bufsize(self.sb.inode_size());
+                let mut dinode = match Dinode::from(
+                    self.device.by_ref(),
+                    &self.sb,
+                    ino,
+                    self.patches.as_ref(),
+                ) {
+                    Ok(dinode) => dinode,
+                    Err(e) => {
+                        warn!("Cannot read inode {}: {}", ino, e);
+                        continue;
+                    }
+                };
+                self.dump_inode_xattrs(ino, &mut dinode, out)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn dump_inode_xattrs<W: Write>(
+        &mut self,
+        ino: XfsIno,
+        dinode: &mut Dinode,
+        out: &mut W,
+    ) -> io::Result<()> {
+        self.device.set_bufsize(self.sb.geometry.blocksize);
+        let Some(attrs) = dinode.get_attrs(self.device.by_ref(), &self.sb) else {
+            return Ok(());
+        };
+        let list = match attrs.list(self.device.by_ref(), &self.sb) {
+            Ok(list) => list,
+            Err(e) => {
+                warn!("Cannot list attributes of inode {}: {}", ino, e);
+                return Ok(());
+            }
+        };
+        for fullname in list.split(|c| *c == 0).filter(|n| !n.is_empty()) {
+            let mut nameparts = fullname.splitn(2, |c| *c == b'.');
+            let namespace = nameparts.next().unwrap();
+            let name = OsStr::from_bytes(nameparts.next().unwrap_or_default());
+            let ns = get_flags_from_namespace(namespace).unwrap();
+            let value = match attrs.get(self.device.by_ref(), &self.sb, ns, name) {
+                Ok(value) => value,
+                Err(e) => {
+                    warn!(
+                        "Cannot get attribute {:?} of inode {}: {}",
+                        OsStr::from_bytes(fullname),
+                        ino,
+                        e
+                    );
+                    continue;
+                }
+            };
+            write!(out, "{} ", self.display_ino(ino))?;
+            out.write_all(fullname)?;
+            if value
+                .iter()
+                .all(|c| matches!(c, b' '..=b'~') && *c != b'"' && *c != b'\\')
+            {
+                out.write_all(b"=\"")?;
+                out.write_all(&value)?;
+                out.write_all(b"\"\n")?;
+            } else {
+                writeln!(out, "=0s{}", BASE64.encode(&value))?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Print the usage and limits recorded in each of the user, group, and project quota inodes,
+    /// one ID per line, as `<type> <id> blocks=<n> soft=<n> hard=<n> inodes=<n> soft=<n>
+    /// hard=<n>`.  Block counts are in file system blocks.  IDs that have never been used are
+    /// omitted.
+    pub fn quota_dump<W: Write>(&mut self, out: &mut W) -> Result<(), XfsError> {
+        let qinodes = [
+            self.sb.sb_uquotino,
+            self.sb.sb_gquotino,
+            self.sb.sb_pquotino,
+        ];
+        for ino in qinodes {
+            // Before V5, an unused quota inode may be recorded as 0 instead of NULLFSINO
+            if ino == NULLFSINO || ino == 0 {
+                continue;
+            }
+            let extents = self.file_extents(ino)?;
+            for dq in quota::dquots(self.device.by_ref(), &self.sb, &extents)? {
+                writeln!(
+                    out,
+                    "{} {} blocks={} soft={} hard={} inodes={} soft={} hard={}",
+                    dq.kind(),
+                    dq.d_id,
+                    dq.d_bcount,
+                    dq.d_blk_softlimit,
+                    dq.d_blk_hardlimit,
+                    dq.d_icount,
+                    dq.d_ino_softlimit,
+                    dq.d_ino_hardlimit
+                )?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Check the parts of an open directory that reading it doesn't depend on.  Any corruption
+    /// found is only recorded, so the directory can still be read.
+    fn verify_dir(&mut self, nodeid: u64) {
+        let ino = self.to_ino(nodeid);
+        self.device.set_bufsize(self.sb.geometry.dirblksize);
+        let oi = self.open_files.get_mut(&nodeid).unwrap();
+        let res = oi
+            .dinode
+            .get_dir(self.device.by_ref(), &self.sb)
+            .and_then(|dir| dir.verify(self.device.by_ref(), &self.sb));
+        if let Err(e) = res {
+            report(&e, Implicated::Inode(ino));
+        }
+    }
+
+    fn open_inode(&mut self, nodeid: u64) -> Result<&mut OpenInode, XfsError> {
+        let ino = self.to_ino(nodeid);
+        match self.open_files.entry(nodeid) {
+            Entry::Occupied(e) => {
+                let oi = e.into_mut();
+                oi.count += 1;
+                Ok(oi)
+            }
+            Entry::Vacant(e) => {
+                self.device.set_bufsize(self.sb.inode_size());
+                let dinode =
+                    Dinode::from(self.device.by_ref(), &self.sb, ino, self.patches.as_ref())?;
+                Ok(e.insert(OpenInode { dinode, count: 1 }))
+            }
+        }
+    }
+}
+
+impl Namespace for Volume {
+    fn node(&mut self, ino: XfsIno) -> Result<PathNode, XfsError> {
+        self.device.set_bufsize(self.sb.inode_size());
+        let dinode = Dinode::from(self.device.by_ref(), &self.sb, ino, self.patches.as_ref())?;
+        match dinode.di_core.stat(ino, &self.sb)?.kind {
+            FileType::Directory => Ok(PathNode::Dir),
+            FileType::Symlink => {
+                self.device.set_bufsize(self.sb.geometry.blocksize);
+                let target = dinode.get_link_data(self.device.by_ref(), &self.sb)?;
+                Ok(PathNode::Symlink(
+                    OsStr::from_bytes(target.as_bytes()).to_owned(),
+                ))
+            }
+            _ => Ok(PathNode::Other),
+        }
+    }
+
+    fn lookup(&mut self, dir: XfsIno, name: &OsStr) -> Result<XfsIno, XfsError> {
+        self.device.set_bufsize(self.sb.inode_size());
+        let mut dinode = Dinode::from(self.device.by_ref(), &self.sb, dir, self.patches.as_ref())?;
+        self.device.set_bufsize(self.sb.geometry.dirblksize);
+        let dir = dinode.get_dir(self.device.by_ref(), &self.sb)?;
+        dir.lookup(self.device.by_ref(), &self.sb, name)
     }
 }
 
 impl Filesystem for Volume {
     fn lookup(&mut self, _req: &Request, parent: u64, name: &OsStr, reply: ReplyEntry) {
+        if self.unlinked.is_some() {
+            if parent == FUSE_ROOT_ID && name == Self::UNLINKED_NAME {
+                reply.entry(&Self::TTL, &self.unlinked_dir_attr(), 0);
+                return;
+            } else if parent == Self::UNLINKED_ID {
+                self.lookup_unlinked(name, reply);
+                return;
+            }
+        }
+        if self.show_meta && parent == FUSE_ROOT_ID {
+            if let Some(ino) = self.meta_inode(name) {
+                self.lookup_ino(ino, reply);
+                return;
+            }
+        }
         let parent_oi = &mut self.open_files.get_mut(&parent).unwrap();
-        let dirsize = self.sb.sb_blocksize << self.sb.sb_dirblklog;
-        self.device.set_bufsize(dirsize as usize);
-        let dir = parent_oi.dinode.get_dir(self.device.by_ref(), &self.sb);
-        match dir.lookup(self.device.by_ref(), &self.sb, name) {
-            Ok(ino) => {
-                let oi = self.open_inode(ino);
-                match oi.dinode.di_core.stat(ino) {
-                    Ok(attr) => {
-                        // We don't need to report the inode generation since this is a read-only
-                        // file system.  But we'll do it anyway.
-                        reply.entry(&Self::TTL, &attr, oi.dinode.di_core.di_gen.into())
-                    }
-                    Err(err) => reply.error(err),
-                }
+        self.device.set_bufsize(self.sb.geometry.dirblksize);
+        let dir = match parent_oi.dinode.get_dir(self.device.by_ref(), &self.sb) {
+            Ok(dir) => dir,
+            Err(e) => {
+                reply.error(errno(e, self.to_ino(parent)));
+                return;
             }
-            Err(err) => reply.error(err),
+        };
+        match dir.lookup(self.device.by_ref(), &self.sb, name) {
+            Ok(ino) => self.lookup_ino(ino, reply),
+            Err(e) => reply.error(errno(e, self.to_ino(parent))),
         }
     }
 
@@ -178,16 +1159,24 @@ impl Filesystem for Volume {
             return;
         };
 
-        let oi = &self.open_files.get(&ino).unwrap();
-        let file = oi.dinode.get_file(self.device.by_ref());
+        let xfs_ino = self.to_ino(ino);
+        let oi = self.open_files.get_mut(&ino).unwrap();
+        let file = match oi.dinode.get_file(self.device.by_ref()) {
+            Ok(file) => file,
+            Err(e) => {
+                reply.error(errno(e, xfs_ino));
+                return;
+            }
+        };
         if offset > file.size() {
             reply.error(libc::ENXIO);
             return;
         }
 
-        match file.lseek(self.device.by_ref(), uoffset, whence) {
+        match file.seek_hole_data(self.device.by_ref(), uoffset, whence) {
+            // The result is never beyond EOF, so it always fits
             Ok(ofs) => reply.offset(i64::try_from(ofs).unwrap()),
-            Err(e) => reply.error(e),
+            Err(e) => reply.error(errno(e, xfs_ino)),
         }
     }
 
@@ -197,6 +1186,10 @@ impl Filesystem for Volume {
             // inode, its FORGETs may be "unmatched"
             return;
         }
+        if ino == Self::UNLINKED_ID {
+            // The synthetic directory isn't cached, so there's nothing to forget
+            return;
+        }
         match self.open_files.get_mut(&ino) {
             Some(oi) => {
                 oi.count -= nlookup;
@@ -213,14 +1206,19 @@ impl Filesystem for Volume {
     }
 
     fn getattr(&mut self, _req: &Request, ino: u64, reply: ReplyAttr) {
-        let attr = self
+        if ino == Self::UNLINKED_ID {
+            reply.attr(&Self::TTL, &self.unlinked_dir_attr());
+            return;
+        }
+        let mut attr = self
             .open_files
             .get(&ino)
             .expect("getattr before lookup")
             .dinode
             .di_core
-            .stat(ino)
+            .stat(self.to_ino(ino), &self.sb)
             .expect("Unknown file type");
+        attr.ino = ino;
 
         reply.attr(&Self::TTL, &attr)
     }
@@ -229,33 +1227,57 @@ impl Filesystem for Volume {
         if config.add_capabilities(FUSE_NO_OPEN_SUPPORT).is_ok() {
             self.no_open = true;
         }
-        if config.add_capabilities(FUSE_NO_OPENDIR_SUPPORT).is_ok() {
+        // Sorted directories are buffered by opendir, so it can't be skipped.
+        if !self.sort_dents && config.add_capabilities(FUSE_NO_OPENDIR_SUPPORT).is_ok() {
             self.no_opendir = true;
         }
         let _ = config.add_capabilities(FUSE_ASYNC_READ | FUSE_EXPORT_SUPPORT);
+        if let Some(max_read) = self.max_read {
+            // There's no point in reading ahead more than we're willing to read at once.
+            let _ = config.set_max_readahead(max_read);
+        }
         Ok(())
     }
 
+    fn destroy(&mut self) {
+        print_repair_hint();
+    }
+
     fn readlink(&mut self, _req: &Request, ino: u64, reply: fuser::ReplyData) {
-        self.device.set_bufsize(self.sb.sb_blocksize as usize);
-        reply.data(
-            self.open_files
-                .get(&ino)
-                .expect("readlink before lookup")
-                .dinode
-                .get_link_data(self.device.by_ref(), &self.sb)
-                .as_bytes(),
-        );
+        self.device.set_bufsize(self.sb.geometry.blocksize);
+        match self
+            .open_files
+            .get(&ino)
+            .expect("readlink before lookup")
+            .dinode
+            .get_link_data(self.device.by_ref(), &self.sb)
+        {
+            Ok(target) => reply.data(target.as_bytes()),
+            Err(e) => reply.error(errno(e, self.to_ino(ino))),
+        }
     }
 
     fn open(&mut self, _req: &Request, _ino: u64, _flags: i32, reply: ReplyOpen) {
         if self.no_open {
             reply.error(libc::ENOSYS)
         } else {
+            // The file system is read-only, so cached pages never go stale.  Keeping them lets
+            // the kernel's readahead, which FUSE only negotiates at init time, serve sequential
+            // reads across opens of the same file.
             reply.opened(0, FOPEN_KEEP_CACHE)
         }
     }
 
+    // There is never anything to write back, but some programs treat ENOSYS from close(2) or
+    // fsync(2) as a failure.
+    fn flush(&mut self, _req: &Request, _ino: u64, _fh: u64, _lock_owner: u64, reply: ReplyEmpty) {
+        reply.ok()
+    }
+
+    fn fsync(&mut self, _req: &Request, _ino: u64, _fh: u64, _datasync: bool, reply: ReplyEmpty) {
+        reply.ok()
+    }
+
     fn read(
         &mut self,
         _req: &Request,
@@ -267,154 +1289,256 @@ impl Filesystem for Volume {
         _lock_owner: Option<u64>,
         reply: fuser::ReplyData,
     ) {
-        let oi = &self.open_files.get(&ino).unwrap();
-        self.device.set_bufsize(self.sb.sb_blocksize as usize);
-
-        let file = oi.dinode.get_file(self.device.by_ref());
-
-        match file.read(self.device.by_ref(), offset, size) {
-            Ok((v, ignore)) => reply.data(&v[ignore..]),
-            Err(e) => reply.error(e),
+        // Bound the size of our buffer.  FUSE permits short reads.
+        let size = self.max_read.map_or(size, |m| size.min(m));
+        let mut buf = mem::take(&mut self.read_buf);
+        buf.resize(usize::try_from(size).unwrap(), 0);
+        match self.read_into(self.to_ino(ino), offset, &mut buf) {
+            Ok(len) => reply.data(&buf[..len]),
+            Err(e) => reply.error(errno(e, self.to_ino(ino))),
         }
+        self.read_buf = buf;
     }
 
-    fn opendir(&mut self, _req: &Request, _ino: u64, _flags: i32, reply: ReplyOpen) {
+    fn opendir(&mut self, _req: &Request, ino: u64, _flags: i32, reply: ReplyOpen) {
         if self.no_opendir {
-            reply.error(libc::ENOSYS)
+            reply.error(libc::ENOSYS);
+            return;
+        }
+        if self.repair_hint && ino != Self::UNLINKED_ID {
+            self.verify_dir(ino);
+        }
+        if self.sort_dents && ino != Self::UNLINKED_ID {
+            match self.sorted_dirents(ino) {
+                Ok(dirents) => {
+                    let fh = self.next_fh;
+                    self.next_fh += 1;
+                    self.dirents.insert(fh, dirents);
+                    reply.opened(fh, FOPEN_CACHE_DIR)
+                }
+                Err(e) => reply.error(errno(e, self.to_ino(ino))),
+            }
         } else {
             reply.opened(0, FOPEN_CACHE_DIR)
         }
     }
 
+    fn releasedir(&mut self, _req: &Request, _ino: u64, fh: u64, _flags: i32, reply: ReplyEmpty) {
+        self.dirents.remove(&fh);
+        reply.ok()
+    }
+
+    fn fsyncdir(
+        &mut self,
+        _req: &Request,
+        _ino: u64,
+        _fh: u64,
+        _datasync: bool,
+        reply: ReplyEmpty,
+    ) {
+        reply.ok()
+    }
+
     fn readdir(
         &mut self,
         _req: &Request,
         ino: u64,
-        _fh: u64,
+        fh: u64,
         offset: i64,
         mut reply: ReplyDirectory,
     ) {
-        let dirsize = self.sb.sb_blocksize << self.sb.sb_dirblklog;
-        self.device.set_bufsize(dirsize as usize);
+        if ino == Self::UNLINKED_ID {
+            self.readdir_unlinked(offset, reply);
+            return;
+        }
+        if let Some(dirents) = self.dirents.get(&fh) {
+            // The offset of each sorted entry is its index plus one.  The synthetic .unlinked
+            // directory, if any, is already among them.
+            let start = usize::try_from(offset).unwrap_or(usize::MAX);
+            for (i, (nodeid, kind, name)) in dirents.iter().enumerate().skip(start) {
+                if reply.add(*nodeid, i as i64 + 1, *kind, name) {
+                    reply.ok();
+                    return;
+                }
+            }
+            reply.ok();
+            return;
+        }
+        let show_unlinked = ino == FUSE_ROOT_ID && self.unlinked.is_some();
+        if show_unlinked && offset == Self::UNLINKED_COOKIE {
+            reply.ok();
+            return;
+        }
+        self.device.set_bufsize(self.sb.geometry.dirblksize);
         let oi = &mut self.open_files.get_mut(&ino).unwrap();
+        if let Err(e) = oi.dinode.get_dir(self.device.by_ref(), &self.sb) {
+            reply.error(errno(e, self.to_ino(ino)));
+            return;
+        }
+        // Reborrow immutably, so other open inodes can be consulted below.
+        let dir = self.open_files[&ino].dinode.cached_dir().unwrap();
 
-        let dir = oi.dinode.get_dir(self.device.by_ref(), &self.sb);
-
+        // Entries are returned in on-disk order.  It isn't sorted, but each entry's offset is
+        // derived from its location, so the order is stable for a given image.
         let mut off = offset;
         loop {
             let res = dir.next(self.device.by_ref(), &self.sb, off);
             match res {
                 Ok((ino, offset, kind, name)) => {
-                    // FUSE requires the file system's root directory to have a
-                    // fixed inode number.
-                    let ino = if ino == self.sb.sb_rootino {
-                        FUSE_ROOT_ID
-                    } else {
-                        ino
-                    };
-                    let kind = match kind {
-                        Some(kind) => kind,
-                        None => {
-                            // This is very inefficient.  Frequently, getattr will be called for
-                            // every entry returned by readdir.  In such cases, this code will read
-                            // the inode twice.  The best solution is for everybody to use the
-                            // ftype option in their XFS format.
-                            self.device.set_bufsize(self.sb.inode_size());
-                            let dinode = Dinode::from(
-                                self.device.by_ref(),
-                                &self.sb,
-                                if ino == FUSE_ROOT_ID {
-                                    self.sb.sb_rootino
-                                } else {
-                                    ino as XfsIno
-                                },
-                            );
-                            match dinode.di_core.stat(ino) {
-                                Ok(attr) => attr.kind,
-                                Err(e) => {
-                                    reply.error(e);
-                                    return;
-                                }
-                            }
+                    // A real .unlinked entry would be shadowed by the synthetic one
+                    if (show_unlinked && name == Self::UNLINKED_NAME)
+                        || Self::skip_dirent(&self.sb, self.skip_unknown_types, ino, kind, &name)
+                    {
+                        off = offset;
+                        continue;
+                    }
+                    let nodeid = self.to_nodeid(ino);
+                    let kind = match Self::dirent_kind(
+                        &mut self.device,
+                        &self.sb,
+                        self.patches.as_ref(),
+                        self.open_files.get(&nodeid),
+                        ino,
+                        kind,
+                        &name,
+                    ) {
+                        Ok(kind) => kind,
+                        Err(e) => {
+                            reply.error(errno(e, ino));
+                            return;
                         }
                     };
-                    let res = reply.add(ino, offset, kind, name);
+                    let res = reply.add(nodeid, offset, kind, name);
                     if res {
                         reply.ok();
                         return;
                     }
                     off = offset;
                 }
-                // TODO: don't ignore errors other than ENOENT
-                Err(_) => {
+                Err(XfsError::NotFound) => {
+                    if show_unlinked {
+                        let _ = reply.add(
+                            Self::UNLINKED_ID,
+                            Self::UNLINKED_COOKIE,
+                            FileType::Directory,
+                            Self::UNLINKED_NAME,
+                        );
+                    }
                     reply.ok();
                     return;
                 }
+                Err(e) => {
+                    reply.error(errno(e, self.to_ino(ino)));
+                    return;
+                }
             }
         }
     }
 
     fn statfs(&mut self, _req: &Request, _ino: u64, reply: ReplyStatfs) {
+        let counters = self.counters;
+        let (files, ffree) = inode_counts(&self.sb, &counters);
         reply.statfs(
             self.sb.sb_dblocks - u64::from(self.sb.sb_logblocks),
-            self.sb.sb_fdblocks,
-            self.sb.sb_fdblocks,
-            self.sb.sb_icount,
-            self.sb.sb_ifree,
-            self.sb.sb_blocksize,
+            counters.fdblocks,
+            counters.fdblocks,
+            files,
+            ffree,
+            self.sb.iosize(),
             255,
             self.sb.sb_blocksize,
         )
     }
 
     fn getxattr(&mut self, _req: &Request, ino: u64, name: &OsStr, size: u32, reply: ReplyXattr) {
+        let meta_name = name.as_bytes().strip_prefix(META_XATTR_PREFIX);
         let mut nameparts = name.as_bytes().splitn(2, |c| *c == b'.');
-        let _namespace = nameparts.next().unwrap();
-        let name = OsStr::from_bytes(nameparts.next().unwrap());
+        let namespace = nameparts.next().unwrap();
+        let Some(name) = nameparts.next().map(OsStr::from_bytes) else {
+            reply.error(libc::ENOATTR);
+            return;
+        };
 
-        let oi = &mut self.open_files.get_mut(&ino).unwrap();
-        self.device.set_bufsize(self.sb.sb_blocksize as usize);
-        match oi.dinode.get_attrs(self.device.by_ref(), &self.sb) {
-            Some(attrs) => match attrs.get(self.device.by_ref(), &self.sb, name) {
-                Ok(value) => {
-                    let len: u32 = value.len().try_into().unwrap();
-                    if size == 0 {
-                        reply.size(len);
-                    } else if len > size {
-                        reply.error(ERANGE);
-                    } else {
-                        reply.data(value.as_slice())
-                    }
-                }
-                Err(e) => reply.error(e),
-            },
-            None => {
+        if ino == Self::UNLINKED_ID {
+            reply.error(libc::ENOATTR);
+            return;
+        }
+        let value = if let Some(meta_name) = meta_name.filter(|_| self.show_meta) {
+            self.get_meta_xattr(ino, OsStr::from_bytes(meta_name))
+        } else {
+            let Some(ns) = get_flags_from_namespace(namespace) else {
                 reply.error(libc::ENOATTR);
+                return;
+            };
+            let oi = &mut self.open_files.get_mut(&ino).unwrap();
+            self.device.set_bufsize(self.sb.geometry.blocksize);
+            match oi.dinode.get_attrs(self.device.by_ref(), &self.sb) {
+                Some(attrs) => attrs.get(self.device.by_ref(), &self.sb, ns, name),
+                None => Err(XfsError::NoAttr),
+            }
+        };
+        match value {
+            Ok(value) => {
+                let len: u32 = value.len().try_into().unwrap();
+                if size == 0 {
+                    reply.size(len);
+                } else if len > size {
+                    reply.error(ERANGE);
+                } else {
+                    reply.data(value.as_slice())
+                }
             }
+            Err(e) => reply.error(errno(e, self.to_ino(ino))),
         }
     }
 
     fn listxattr(&mut self, _req: &Request, ino: u64, size: u32, reply: ReplyXattr) {
+        if ino == Self::UNLINKED_ID {
+            if size == 0 {
+                reply.size(0);
+            } else {
+                reply.data(&[]);
+            }
+            return;
+        }
+        let meta = if self.show_meta {
+            self.list_meta_xattrs(ino)
+        } else {
+            Vec::new()
+        };
+        let meta_size = u32::try_from(meta.len()).unwrap();
         let oi = &mut self
             .open_files
             .get_mut(&ino)
             .expect("listxattr before lookup");
-        self.device.set_bufsize(self.sb.sb_blocksize as usize);
+        self.device.set_bufsize(self.sb.geometry.blocksize);
         match oi.dinode.get_attrs(self.device.by_ref(), &self.sb) {
             Some(ref mut attrs) => {
-                let attrs_size = attrs.get_total_size(self.device.by_ref(), &self.sb);
+                let attrs_size = match attrs.get_total_size(self.device.by_ref(), &self.sb) {
+                    Ok(attrs_size) => attrs_size,
+                    Err(e) => {
+                        reply.error(errno(e, self.to_ino(ino)));
+                        return;
+                    }
+                };
 
                 if size == 0 {
-                    reply.size(attrs_size);
+                    reply.size(attrs_size + meta_size);
                     return;
                 }
 
-                if attrs_size > size {
+                if attrs_size + meta_size > size {
                     reply.error(ERANGE);
                     return;
                 }
 
-                let list = attrs.list(self.device.by_ref(), &self.sb);
+                let mut list = match attrs.list(self.device.by_ref(), &self.sb) {
+                    Ok(list) => list,
+                    Err(e) => {
+                        reply.error(errno(e, self.to_ino(ino)));
+                        return;
+                    }
+                };
                 // Assert that we calculated the list size correctly.  This assertion is only
                 // safe since we're a read-only file system.
                 assert_eq!(
@@ -422,11 +1546,154 @@ impl Filesystem for Volume {
                     attrs_size as usize,
                     "size calculation was wrong!"
                 );
+                list.extend_from_slice(&meta);
                 reply.data(list.as_slice());
             }
             None => {
-                reply.size(0);
+                // No attribute fork is the same as an empty one
+                if size == 0 {
+                    reply.size(meta_size);
+                } else if meta_size > size {
+                    reply.error(ERANGE);
+                } else {
+                    reply.data(&meta);
+                }
             }
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use rstest::rstest;
+
+    use super::*;
+    use crate::libxfuse::{
+        definitions::XfsFsblock,
+        dir3_sf::{tests::mkdir, Dir2Sf},
+        sb::{self, tests::superblock},
+        utils::decode,
+    };
+
+    fn extent(file_block: u64, fsblock: XfsFsblock, blocks: u64) -> ExtentInfo {
+        ExtentInfo {
+            file_offset: file_block << 12,
+            fsblock,
+            length: blocks << 12,
+            unwritten: false,
+        }
+    }
+
+    /// With `--skip-unknown-types`, a directory entry with an invalid ftype is left out of the
+    /// listing, but the rest of the directory is still listed.  Without it, the entry is kept so
+    /// that its type can be read from its inode.
+    #[rstest]
+    #[case::skip(true, &[".", "..", "bcd"])]
+    #[case::keep(false, &[".", "..", "a", "bcd"])]
+    fn skip_unknown_types(#[case] skip: bool, #[case] expected: &[&str]) {
+        let sb = superblock();
+        let entries: [(&[u8], u16, XfsIno); 2] = [(b"a", 0x60, 131), (b"bcd", 0x70, 132)];
+        let mut raw = mkdir(false, 128, &entries);
+        // The first entry's ftype follows its namelen, offset, and 1-byte name
+        raw[10] = 9;
+        let (mut dir, _) = decode::<Dir2Sf>(&raw[..]).unwrap();
+        dir.set_ino(100);
+
+        let img = tempfile::NamedTempFile::new().unwrap();
+        let mut reader = BlockReader::open(img.path()).unwrap();
+        let mut offset = 0;
+        let mut names = Vec::new();
+        while let Ok((ino, next, kind, name)) = dir.next(&mut reader, sb, offset) {
+            if !Volume::skip_dirent(sb, skip, ino, kind, &name) {
+                names.push(name);
+            }
+            offset = next;
+        }
+        assert_eq!(names, expected);
+    }
+
+    /// Extents that are contiguous both logically and physically belong to the same fragment
+    #[rstest]
+    #[case::inline(&[], "extents=0 fragments=0 factor=0.00")]
+    #[case::large_extent(&[extent(0, 100, 4096)], "extents=1 fragments=1 factor=0.00")]
+    #[case::four_extents(
+        &[extent(0, 100, 1), extent(2, 102, 1), extent(4, 104, 1), extent(6, 106, 1)],
+        "extents=4 fragments=4 factor=75.00"
+    )]
+    #[case::split_unwritten(
+        &[extent(0, 100, 1), ExtentInfo { unwritten: true, ..extent(1, 101, 3) }],
+        "extents=2 fragments=1 factor=0.00"
+    )]
+    #[case::physical_gap(&[extent(0, 100, 1), extent(1, 200, 1)], "extents=2 fragments=2 factor=50.00")]
+    #[case::sparse(&[extent(0, 100, 1), extent(2, 101, 1)], "extents=2 fragments=2 factor=50.00")]
+    fn fragmentation_summary(#[case] extents: &[ExtentInfo], #[case] expected: &str) {
+        assert_eq!(fragmentation(extents, 12), expected);
+    }
+
+    /// The summary names every corrupt structure once, in a stable order
+    #[test]
+    fn repair_hint_summary() {
+        assert_eq!(repair_hint(&BTreeSet::new()), None);
+
+        // Inodes are named along with their block and AG
+        let sb = superblock();
+        let ino = (3 << (sb.sb_agblklog + sb.sb_inopblog)) | (17 << sb.sb_inopblog) | 1;
+        let found = BTreeSet::from([
+            (
+                Implicated::Inode(ino),
+                "attribute leaf block 3: bad magic".to_string(),
+            ),
+            (Implicated::Ag(2), "AGF magic number is invalid".to_string()),
+            (
+                Implicated::Unknown,
+                "Unknown file type (panicked at src/x.rs:1:1)".to_string(),
+            ),
+        ]);
+        let hint = repair_hint(&found).unwrap();
+        let lines = hint.lines().collect::<Vec<_>>();
+        assert_eq!(lines[0], "Found 3 corrupt structure(s) while mounted:");
+        assert_eq!(
+            lines[1],
+            "    Unknown structure: Unknown file type (panicked at src/x.rs:1:1)"
+        );
+        assert_eq!(lines[2], "    AG 2: AGF magic number is invalid");
+        assert_eq!(
+            lines[3],
+            format!(
+                "    AG 3, block {}, inode {}: attribute leaf block 3: bad magic",
+                (3 << sb.sb_agblklog) | 17,
+                ino
+            )
+        );
+        assert!(lines[4].contains("xfs_repair"));
+    }
+
+    /// The inode totals reported by statfs are estimates, but the number in use must be exact, and
+    /// never more than the total.
+    #[rstest]
+    #[case::unlimited(0, 64, 10, 1000, 8064)]
+    #[case::imax_pct(25, 64, 10, 60000, 131072)]
+    #[case::full(25, 64, 0, 0, 64)]
+    #[case::over_imax_pct(1, 6400, 100, 1000, 6400)]
+    fn statfs_inodes(
+        #[case] imax_pct: u8,
+        #[case] icount: u64,
+        #[case] ifree: u64,
+        #[case] fdblocks: u64,
+        #[case] expected: u64,
+    ) {
+        let sb = Sb::from(&mut io::Cursor::new(sb::tests::mksb(|buf| {
+            buf[127] = imax_pct
+        })))
+        .unwrap();
+        let counters = Counters {
+            fdblocks,
+            icount,
+            ifree,
+        };
+        let (files, ffree) = inode_counts(&sb, &counters);
+        assert_eq!(files, expected);
+        assert!(files >= icount - ifree);
+        assert_eq!(files - ffree, icount - ifree);
+    }
+}
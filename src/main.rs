@@ -25,32 +25,245 @@
  * OR TORT (INCLUDING NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE USE
  * OF THIS SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
  */
-use std::path::PathBuf;
+use std::{
+    fs,
+    io,
+    panic,
+    path::{Path, PathBuf},
+    process,
+    thread,
+};
 
-use clap::{crate_version, Parser};
+use clap::{crate_version, Parser, Subcommand, ValueEnum};
 use fuser::{mount2, MountOption};
-use libxfuse::volume::Volume;
+use libxfuse::{
+    patch::InodePatches,
+    volume::{self, Volume, VolumeOptions},
+};
+use nix::sys::signal::{SigSet, Signal};
 use tracing_subscriber::EnvFilter;
 
 mod libxfuse;
 
 #[derive(Parser, Clone, Debug)]
-#[clap(version = crate_version!())]
+#[clap(
+    version = crate_version!(),
+    args_conflicts_with_subcommands = true,
+    subcommand_negates_reqs = true
+)]
 struct App {
+    #[clap(subcommand)]
+    command:             Option<Command>,
     /// Mount options, comma delimited.
     #[clap(short = 'o', long, value_delimiter(','))]
-    options:    Vec<String>,
-    device:     PathBuf,
-    mountpoint: String,
+    options:             Vec<String>,
+    /// Add this constant to every reported inode number.
+    #[clap(long, default_value_t = 0)]
+    ino_offset:          u64,
+    /// Apply inode metadata overrides from this file.  Each line should be of
+    /// the form "<inode> <field> <value>".  The image is never modified.
+    #[clap(long)]
+    patch:               Option<PathBuf>,
+    /// Limit the size of a single read request, in bytes.
+    #[clap(long, value_parser = clap::value_parser!(u32).range(1..))]
+    max_read:            Option<u32>,
+    /// Show inodes that were unlinked while still open, in a synthetic
+    /// ".unlinked" directory beneath the root.
+    #[clap(long)]
+    show_deleted:        bool,
+    /// Expose internal metadata as extended attributes named "system.xfs.*".
+    #[clap(long)]
+    show_meta:           bool,
+    /// Pad every read of the device to a multiple of this many bytes.  Use it
+    /// when the device's logical sector size is larger than it reports.
+    #[clap(long, value_parser = parse_sectorsize)]
+    device_sector_size:  Option<u32>,
+    /// Have the kernel check file access against each file's mode, owner, and
+    /// group.  This is always enabled when run as root.
+    #[clap(long)]
+    default_permissions: bool,
+    /// The device that holds the file system's realtime section.  Without it,
+    /// files stored there can't be read.
+    #[clap(long)]
+    rtdev:               Option<PathBuf>,
+    /// Log the offset and length of every read from the device, regardless
+    /// of RUST_LOG.
+    #[clap(long)]
+    trace_reads:         bool,
+    /// Return directory entries in this order, rather than in the order
+    /// they're stored on disk.
+    #[clap(long, value_enum)]
+    sort_dents:          Option<DentOrder>,
+    /// Read the device through a memory mapping, rather than with read
+    /// syscalls.
+    #[clap(long)]
+    mmap:                bool,
+    /// At unmount, summarize any corruption that was found, with a hint to
+    /// run xfs_repair.  Also summarize it on SIGUSR1 or SIGINFO, or if the
+    /// daemon crashes.
+    #[clap(long)]
+    repair_hint:         bool,
+    /// Leave directory entries of unknown type out of directory listings,
+    /// rather than reading their types from their inodes.
+    #[clap(long)]
+    skip_unknown_types:  bool,
+    /// Display inode numbers in the system.xfs.parents attribute as an AG
+    /// number and AG-relative inode number, like xfs_db does.
+    #[clap(long, overrides_with = "absolute_ino")]
+    relative_ino:        bool,
+    /// Display inode numbers in the system.xfs.parents attribute as absolute
+    /// inode numbers.  This is the default.
+    #[clap(long)]
+    absolute_ino:        bool,
+    #[clap(required = true)]
+    device:              Option<PathBuf>,
+    #[clap(required = true)]
+    mountpoint:          Option<String>,
+}
+
+#[derive(Subcommand, Clone, Debug)]
+enum Command {
+    /// Print every extended attribute of every file, without mounting.  Each
+    /// line has the form "<inode> <name>=<value>".
+    XattrDump {
+        /// Follow the path if it is a symbolic link
+        #[clap(short = 'L', overrides_with = "physical")]
+        logical:      bool,
+        /// Don't follow the path if it is a symbolic link.  This is the
+        /// default.
+        #[clap(short = 'P')]
+        physical:     bool,
+        /// Print inode numbers as an AG number and AG-relative inode number,
+        /// like xfs_db does
+        #[clap(long, overrides_with = "absolute_ino")]
+        relative_ino: bool,
+        /// Print absolute inode numbers.  This is the default.
+        #[clap(long)]
+        absolute_ino: bool,
+        device:       PathBuf,
+        /// Only print the attributes of the files beneath this path, relative
+        /// to the file system's root
+        path:         Option<PathBuf>,
+    },
+    /// Print the usage and limits of every user, group, and project with
+    /// quotas, without mounting.  Each line has the form "<type> <id>
+    /// blocks=<n> soft=<n> hard=<n> inodes=<n> soft=<n> hard=<n>".
+    Quota { device: PathBuf },
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug)]
+enum DentOrder {
+    /// Sorted bytewise by name
+    Name,
+}
+
+fn parse_sectorsize(s: &str) -> Result<u32, String> {
+    let sectorsize: u32 = s.parse().map_err(|e| format!("{}", e))?;
+    if sectorsize.is_power_of_two() {
+        Ok(sectorsize)
+    } else {
+        Err(format!("{} is not a power of 2", sectorsize))
+    }
+}
+
+/// Open the file system on `device`, or exit with an error message if it can't be mounted.
+fn open_volume(device: &Path, opts: VolumeOptions) -> Volume {
+    Volume::from(device, opts).unwrap_or_else(|e| {
+        eprintln!("xfs-fuse: {}: {}", device.display(), e);
+        process::exit(1);
+    })
+}
+
+/// Summarize the corruption found by --repair-hint whenever SIGUSR1 or SIGINFO arrives, and if
+/// the daemon panics, rather than only at unmount.
+fn install_repair_hint() {
+    let default_hook = panic::take_hook();
+    panic::set_hook(Box::new(move |info| {
+        volume::record_panic(info);
+        default_hook(info);
+    }));
+
+    // Block the signals in this thread, and so in every thread that it starts, so that only the
+    // one that waits for them receives them.
+    let mut sigset = SigSet::empty();
+    sigset.add(Signal::SIGUSR1);
+    #[cfg(target_os = "freebsd")]
+    sigset.add(Signal::SIGINFO);
+    sigset.thread_block().unwrap();
+    thread::spawn(move || loop {
+        if sigset.wait().is_ok() {
+            volume::print_repair_hint();
+        }
+    });
 }
 
 fn main() {
+    let app = App::parse();
+
+    let mut filter = EnvFilter::from_default_env();
+    if app.trace_reads {
+        filter = filter.add_directive("xfs_fuse::libxfuse::block_reader=debug".parse().unwrap());
+    }
     tracing_subscriber::fmt()
         .pretty()
-        .with_env_filter(EnvFilter::from_default_env())
+        .with_env_filter(filter)
+        .with_writer(io::stderr)
         .init();
 
-    let app = App::parse();
+    match app.command {
+        Some(Command::XattrDump {
+            logical,
+            relative_ino,
+            device,
+            path,
+            ..
+        }) => {
+            let vol_opts = VolumeOptions {
+                relative_ino,
+                ..Default::default()
+            };
+            let mut vol = open_volume(&device, vol_opts);
+            let res = match path {
+                Some(path) => match vol.lookup_path(&path, logical) {
+                    Ok(ino) => vol.xattr_dump(ino, &mut io::stdout().lock()),
+                    Err(e) => {
+                        eprintln!("xattr-dump: {}: {}", path.display(), e);
+                        process::exit(1);
+                    }
+                },
+                None => vol.xattr_dump_all(&mut io::stdout().lock()),
+            };
+            if let Err(e) = res {
+                eprintln!("xattr-dump: {}", e);
+                process::exit(1);
+            }
+            return;
+        }
+        Some(Command::Quota { device }) => {
+            let mut vol = open_volume(&device, VolumeOptions::default());
+            if let Err(e) = vol.quota_dump(&mut io::stdout().lock()) {
+                eprintln!("quota: {}", e);
+                process::exit(1);
+            }
+            return;
+        }
+        None => (),
+    }
+
+    // Check the mountpoint before opening the device, because mount2's errors don't say which
+    // path was at fault.
+    let mountpoint = app.mountpoint.unwrap();
+    match fs::metadata(&mountpoint) {
+        Ok(md) if md.is_dir() => (),
+        Ok(_) => {
+            eprintln!("xfs-fuse: {}: Not a directory", mountpoint);
+            process::exit(1);
+        }
+        Err(e) => {
+            eprintln!("xfs-fuse: {}: {}", mountpoint, e);
+            process::exit(1);
+        }
+    }
 
     let mut opts = vec![
         MountOption::FSName("fusefs".to_string()),
@@ -58,8 +271,11 @@ fn main() {
         MountOption::RO,
     ];
     // geteuid is always safe
-    if unsafe { libc::geteuid() } == 0 {
+    let root = unsafe { libc::geteuid() } == 0;
+    if root {
         opts.push(MountOption::AllowOther);
+    }
+    if root || app.default_permissions {
         opts.push(MountOption::DefaultPermissions);
     }
     for o in app.options.iter() {
@@ -83,7 +299,39 @@ fn main() {
         });
     }
 
-    let vol = Volume::from(&app.device);
+    if let Some(max_read) = app.max_read {
+        opts.push(MountOption::CUSTOM(format!("max_read={}", max_read)));
+    }
+
+    let vol_opts = VolumeOptions {
+        ino_offset:         app.ino_offset,
+        patches:            app
+            .patch
+            .map(|p| InodePatches::from_file(&p).expect("Reading patch file")),
+        max_read:           app.max_read,
+        show_deleted:       app.show_deleted,
+        show_meta:          app.show_meta,
+        sectorsize:         app.device_sector_size,
+        rtdev:              app.rtdev,
+        trace_reads:        app.trace_reads,
+        sort_dents:         matches!(app.sort_dents, Some(DentOrder::Name)),
+        mmap:               app.mmap,
+        repair_hint:        app.repair_hint,
+        skip_unknown_types: app.skip_unknown_types,
+        relative_ino:       app.relative_ino,
+    };
+    let vol = open_volume(&app.device.unwrap(), vol_opts);
+    if app.ino_offset > vol.max_ino_offset() {
+        eprintln!(
+            "xfs-fuse: --ino-offset {} is too large for this file system; the maximum is {}",
+            app.ino_offset,
+            vol.max_ino_offset()
+        );
+        process::exit(1);
+    }
 
-    mount2(vol, app.mountpoint, &opts[..]).unwrap();
+    if app.repair_hint {
+        install_repair_hint();
+    }
+    mount2(vol, mountpoint, &opts[..]).unwrap();
 }
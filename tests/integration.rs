@@ -1,18 +1,20 @@
 use std::{
+    collections::HashSet,
     ffi::{OsStr, OsString},
     fs,
-    io::{self, ErrorKind, Read},
+    io::{self, BufRead, BufReader, ErrorKind, Read},
     os::{
         fd::AsRawFd,
         unix::{
             ffi::{OsStrExt, OsStringExt},
-            fs::{DirEntryExt, FileExt, MetadataExt, OpenOptionsExt},
+            fs::{DirEntryExt, FileExt, FileTypeExt, MetadataExt, OpenOptionsExt},
         },
     },
     path::{Path, PathBuf},
-    process::{Child, Command},
-    thread::sleep,
-    time::Duration,
+    process::{Child, Command, Stdio},
+    sync::mpsc::{self, Receiver},
+    thread::{self, sleep, JoinHandle},
+    time::{Duration, Instant},
 };
 
 use assert_cmd::cargo::CommandCargoExt;
@@ -124,9 +126,15 @@ struct Harness {
 }
 
 fn harness(img: &Path) -> Harness {
+    harness_with_args(img, &[])
+}
+
+/// Like `harness`, but pass extra command line arguments to the daemon
+fn harness_with_args(img: &Path, args: &[&str]) -> Harness {
     let d = tempdir().unwrap();
     let child = Command::cargo_bin("xfs-fuse")
         .unwrap()
+        .args(args)
         .arg(img)
         .arg(d.path())
         .spawn()
@@ -145,6 +153,128 @@ fn harness(img: &Path) -> Harness {
     }
 }
 
+/// Like `harness_with_args`, but capture the daemon's stderr, logging with the given RUST_LOG
+/// filter.  It's collected in the background, so a chatty daemon never blocks on a full pipe, and
+/// can be joined once the harness is dropped, because that unmounts the file system and the daemon
+/// exits.
+fn harness_with_stderr(img: &Path, args: &[&str], filter: &str) -> (Harness, JoinHandle<String>) {
+    let (harness, lines) = harness_with_stderr_lines(img, args, filter);
+    let stderr = thread::spawn(move || lines.iter().map(|line| line + "\n").collect());
+    (harness, stderr)
+}
+
+/// Like `harness_with_stderr`, but deliver the daemon's standard error one line at a time, as soon
+/// as it's written, for tests that need to see it before the daemon exits.
+fn harness_with_stderr_lines(
+    img: &Path,
+    args: &[&str],
+    filter: &str,
+) -> (Harness, Receiver<String>) {
+    let d = tempdir().unwrap();
+    let mut child = Command::cargo_bin("xfs-fuse")
+        .unwrap()
+        .env("RUST_LOG", filter)
+        .args(args)
+        .arg(img)
+        .arg(d.path())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    let stderr = child.stderr.take().unwrap();
+    let (tx, lines) = mpsc::channel();
+    thread::spawn(move || {
+        for line in BufReader::new(stderr).lines() {
+            if tx.send(line.unwrap()).is_err() {
+                break;
+            }
+        }
+    });
+
+    waitfor(Duration::from_secs(5), || {
+        let s = nix::sys::statfs::statfs(d.path()).unwrap();
+        s.filesystem_type_name() == "fusefs.xfs"
+    })
+    .unwrap();
+
+    let harness = Harness {
+        d,
+        child,
+        path: img.to_owned(),
+    };
+    (harness, lines)
+}
+
+/// Make a private copy of a golden image with `data` written at each byte offset, for tests that
+/// need metadata that the golden images lack.
+fn patched_image(img: &Path, patches: &[(u64, &[u8])]) -> (TempDir, PathBuf) {
+    let d = tempdir().unwrap();
+    let copy = d.path().join(img.file_name().unwrap());
+    fs::copy(img, &copy).unwrap();
+    let f = fs::OpenOptions::new().write(true).open(&copy).unwrap();
+    for (offset, data) in patches {
+        f.write_all_at(data, *offset).unwrap();
+    }
+    (d, copy)
+}
+
+fn read_superblock(img: &Path) -> [u8; 128] {
+    let mut sb = [0u8; 128];
+    fs::File::open(img)
+        .unwrap()
+        .read_exact_at(&mut sb, 0)
+        .unwrap();
+    sb
+}
+
+/// Read an image's superblock sector, rewrite it with `f`, and recompute its CRC.  The result is
+/// a patch for `patched_image` at offset 0.
+fn superblock_patch<F: FnOnce(&mut [u8])>(img: &Path, f: F) -> Vec<u8> {
+    let sectsize = u16::from_be_bytes(read_superblock(img)[102..104].try_into().unwrap());
+    let mut sector = vec![0u8; usize::from(sectsize)];
+    fs::File::open(img)
+        .unwrap()
+        .read_exact_at(&mut sector, 0)
+        .unwrap();
+    f(&mut sector);
+    sector[224..228].fill(0);
+    let crc = crc::Crc::<u32>::new(&crc::CRC_32_ISCSI).checksum(&sector);
+    sector[224..228].copy_from_slice(&crc.to_le_bytes());
+    sector
+}
+
+/// Encode a shortform attribute fork holding the given (flags, name, value) entries
+fn shortform_attrs(entries: &[(u8, &[u8], &[u8])]) -> Vec<u8> {
+    let mut fork = vec![0, 0, entries.len() as u8, 0];
+    for (flags, name, value) in entries {
+        fork.extend_from_slice(&[name.len() as u8, value.len() as u8, *flags]);
+        fork.extend_from_slice(name);
+        fork.extend_from_slice(value);
+    }
+    let totsize = fork.len() as u16;
+    fork[..2].copy_from_slice(&totsize.to_be_bytes());
+    fork
+}
+
+/// Find a file system block's byte offset within an image, using the geometry in its superblock
+fn fsb_offset(img: &Path, fsb: u64) -> u64 {
+    let sb = read_superblock(img);
+    let blocksize = u64::from(u32::from_be_bytes(sb[4..8].try_into().unwrap()));
+    let agblocks = u64::from(u32::from_be_bytes(sb[84..88].try_into().unwrap()));
+    let agblklog = sb[124];
+    let agno = fsb >> agblklog;
+    let agbno = fsb & ((1 << agblklog) - 1);
+    (agno * agblocks + agbno) * blocksize
+}
+
+/// Find an inode's byte offset within an image, using the geometry in its superblock
+fn inode_offset(img: &Path, ino: u64) -> u64 {
+    let sb = read_superblock(img);
+    let inodesize = u64::from(u16::from_be_bytes(sb[104..106].try_into().unwrap()));
+    let inopblog = sb[123];
+    let index = ino & ((1 << inopblog) - 1);
+    fsb_offset(img, ino >> inopblog) + index * inodesize
+}
+
 #[fixture]
 fn harness1k() -> Harness {
     harness(GOLDEN1K.as_path())
@@ -252,15 +382,20 @@ fn all_dir_types_shortnames(h: fn() -> Harness, d: &str) {}
 
 #[template]
 #[rstest]
+// Shortform attributes, stored in the inode
 #[case::local(harness4k, "xattrs/local")]
+// A single leaf block, in an extent list attribute fork
 #[case::extents(harness4k, "xattrs/extents")]
+// Node and leaf blocks, in a btree attribute fork
 #[case::btree2(harness1k, "xattrs/btree2")]
 #[case::btree2_5(harness1k, "xattrs/btree2.5")]
 #[case::btree3(harness1k, "xattrs/btree3")]
+// Shortform attributes on a directory with a btree data fork
 #[case::btree2_with_xattrs(harness1k, "btree2.with-xattrs")]
 #[case::v4_local(harnessv4, "xattrs/local")]
 #[case::v4_extents(harnessv4, "xattrs/extents")]
 #[case::four4kn_local(harness4kn, "xattrs/local")]
+// Node and leaf blocks, in an extent list attribute fork
 #[case::four4kn_extents(harness4kn, "xattrs/extents4")]
 fn all_xattr_fork_types(h: fn() -> Harness, d: &str) {}
 
@@ -274,6 +409,27 @@ fn all_xattr_fork_types(h: fn() -> Harness, d: &str) {}
 #[case::btree3(harness1k, "xattrs/btree3")]
 fn all_xattr_fork_types_with_none(h: fn() -> Harness, d: &str) {}
 
+mod access {
+    use super::*;
+
+    /// With --default-permissions, the kernel should enforce each file's mode even for an
+    /// unprivileged user.  Root always gets default_permissions, and bypasses most checks anyway.
+    #[named]
+    #[test]
+    fn default_permissions() {
+        require_fusefs!();
+        if nix::unistd::Uid::current().is_root() {
+            skip!("{} must be run as an unprivileged user", function_name!());
+        }
+
+        let harness = harness_with_args(GOLDEN4K.as_path(), &["--default-permissions"]);
+        // hello.txt has mode 01234 and is owned by 1234:5678
+        let path = harness.d.path().join("files").join("hello.txt");
+        access(&path, AccessFlags::R_OK).unwrap();
+        assert_eq!(access(&path, AccessFlags::X_OK).unwrap_err(), Errno::EACCES);
+    }
+}
+
 mod close {
     use super::*;
 
@@ -463,6 +619,31 @@ mod dev {
     }
 }
 
+mod fsync {
+    use super::*;
+
+    /// fsync is a no-op on a read-only file system, but it must succeed
+    #[named]
+    #[rstest]
+    fn file(harness4k: Harness) {
+        require_fusefs!();
+
+        let path = harness4k.d.path().join("files").join("hello.txt");
+        let f = fs::File::open(path).unwrap();
+        f.sync_all().unwrap();
+        f.sync_data().unwrap();
+    }
+
+    #[named]
+    #[rstest]
+    fn dir(harness4k: Harness) {
+        require_fusefs!();
+
+        let f = fs::File::open(harness4k.d.path().join("files")).unwrap();
+        f.sync_all().unwrap();
+    }
+}
+
 // TODO: xattr test on V4 file system
 mod getextattr {
     use super::*;
@@ -482,6 +663,30 @@ mod getextattr {
         }
     }
 
+    /// Every attribute that listxattr returns should be retrievable by getxattr, and together
+    /// they should yield exactly the expected names and values.
+    #[named]
+    #[apply(all_xattr_fork_types)]
+    fn round_trip(#[case] h: fn() -> Harness, #[case] d: &str) {
+        require_fusefs!();
+
+        let harness = h();
+        let p = harness.d.path().join(d);
+
+        let mut actual = xattr::list(&p)
+            .unwrap()
+            .map(|name| {
+                let value = xattr::get(&p, &name).unwrap().unwrap();
+                ExpectedXattr {
+                    name,
+                    value: OsString::from_vec(value),
+                }
+            })
+            .collect::<Vec<_>>();
+        actual.sort_unstable();
+        assert_eq!(actual, expected_xattrs_per_file(d).collect::<Vec<_>>());
+    }
+
     /// Try to get the value of an extended attribute that doesn't exist.
     // This test is freebsd-specific because the relevant syscall is.  It could
     // be implemented for Linux too, but I haven't done so.
@@ -542,6 +747,154 @@ mod getextattr {
             io::Error::last_os_error().raw_os_error().unwrap()
         );
     }
+
+    /// Overlayfs marks an opaque directory with `trusted.overlay.opaque`.  It must be returned
+    /// byte for byte, and an attribute of the same name in the user namespace must not be
+    /// confused with it.
+    #[named]
+    #[rstest]
+    fn trusted_overlay() {
+        require_fusefs!();
+        require_root!();
+
+        // Give the "xattrs" directory, inode 134 of a private copy of the 4k image, a local
+        // attribute fork.  di_forkoff is at byte 82 of the inode and di_aformat at byte 83.  A
+        // forkoff of 8 places the fork 64 bytes past the 176-byte v3 inode core, clear of the
+        // directory's own 34 bytes.  Inode checksums aren't verified.
+        let img = GOLDEN4K.as_path();
+        let ino = inode_offset(img, 134);
+        let fork = shortform_attrs(&[(0, b"overlay.opaque", b"n"), (2, b"overlay.opaque", b"y")]);
+        let (_d, img) = patched_image(img, &[(ino + 82, &[8, 1]), (ino + 240, &fork)]);
+        let harness = harness(&img);
+        let p = harness.d.path().join("xattrs");
+
+        assert_eq!(
+            xattr::get(&p, "user.overlay.opaque").unwrap().as_deref(),
+            Some(&b"n"[..])
+        );
+        // FreeBSD's fusefs can only name the user and system namespaces.
+        #[cfg(not(target_os = "freebsd"))]
+        assert_eq!(
+            xattr::get(&p, "trusted.overlay.opaque").unwrap().as_deref(),
+            Some(&b"y"[..])
+        );
+        #[cfg(target_os = "freebsd")]
+        assert_eq!(
+            xattr::list(&p).unwrap().collect::<Vec<_>>(),
+            vec![OsString::from("user.overlay.opaque")]
+        );
+    }
+
+    /// With --show-meta, the pseudo-attributes are reachable through the mount, in the system
+    /// namespace.  None of the golden images have parent pointers, so they lack xfs.parents.
+    #[named]
+    #[rstest]
+    #[case::v4(GOLDENV4.as_path())]
+    #[case::v5(GOLDEN4K.as_path())]
+    fn meta_parents(#[case] img: &Path) {
+        require_fusefs!();
+        require_root!();
+
+        let harness = harness_with_args(img, &["--show-meta"]);
+        let p = harness.d.path().join("files/hello.txt");
+        assert!(xattr::get(&p, "system.xfs.fragmentation")
+            .unwrap()
+            .is_some());
+        assert_eq!(xattr::get(&p, "system.xfs.parents").unwrap(), None);
+    }
+
+    /// The inode's change counter is reported through the mount.  Only version 5 file systems have
+    /// it.
+    #[named]
+    #[rstest]
+    #[case::v5(GOLDEN4K.as_path(), Some("10"))]
+    #[case::v4(GOLDENV4.as_path(), None)]
+    fn meta_changecount(#[case] img: &Path, #[case] expected: Option<&str>) {
+        require_fusefs!();
+        require_root!();
+
+        let harness = harness_with_args(img, &["--show-meta"]);
+        let p = harness.d.path().join("files/hello.txt");
+        let value = xattr::get(&p, "system.xfs.changecount").unwrap();
+        assert_eq!(value.as_deref(), expected.map(str::as_bytes));
+    }
+
+    /// Files' extent counts and fragmentation are reported through the mount
+    #[named]
+    #[rstest]
+    #[case::fragmented("files/four_extents.txt", "extents=4 fragments=4 factor=75.00")]
+    #[case::btree("files/btree2.txt", "extents=16 fragments=16 factor=93.75")]
+    #[case::contiguous("files/large_extent.txt", "extents=1 fragments=1 factor=0.00")]
+    #[case::local("sf", "extents=0 fragments=0 factor=0.00")]
+    fn meta_fragmentation(#[case] path: &str, #[case] expected: &str) {
+        require_fusefs!();
+        require_root!();
+
+        let harness = harness_with_args(GOLDEN4K.as_path(), &["--show-meta"]);
+        let p = harness.d.path().join(path);
+        let value = xattr::get(&p, "system.xfs.fragmentation").unwrap();
+        assert_eq!(value.as_deref(), Some(expected.as_bytes()));
+    }
+
+    /// A directory's extent size hint and inheritance flags are reported through the mount.  Files
+    /// don't have them.
+    #[named]
+    #[rstest]
+    #[case::none(0, 0, "extsize=0 inherit=none")]
+    #[case::extszinherit(16, 0x1000, "extsize=16 inherit=extszinherit")]
+    #[case::both(8, 0x1100, "extsize=8 inherit=rtinherit,extszinherit")]
+    fn meta_extsize_hint(#[case] extsize: u32, #[case] flags: u16, #[case] expected: &str) {
+        require_fusefs!();
+        require_root!();
+
+        // None of the golden images' directories have hints, so set them on the sf directory, inode
+        // 35, of a private copy of the v4 image, whose inodes have no checksums to update.
+        // di_extsize is at byte 72 of the inode, and di_flags at byte 90.
+        let ino = inode_offset(GOLDENV4.as_path(), 35);
+        let (_d, img) = patched_image(
+            GOLDENV4.as_path(),
+            &[
+                (ino + 72, &extsize.to_be_bytes()),
+                (ino + 90, &flags.to_be_bytes()),
+            ],
+        );
+        let harness = harness_with_args(&img, &["--show-meta"]);
+        let p = harness.d.path().join("sf");
+        let value = xattr::get(&p, "system.xfs.extsize_hint").unwrap();
+        assert_eq!(value.as_deref(), Some(expected.as_bytes()));
+        let p = harness.d.path().join("files/hello.txt");
+        assert_eq!(xattr::get(&p, "system.xfs.extsize_hint").unwrap(), None);
+    }
+
+    /// The file system's label is reported on the root directory, and only there
+    #[named]
+    #[rstest]
+    #[case::labeled(b"golden", Some(&b"golden"[..]))]
+    #[case::unlabeled(b"", None)]
+    fn meta_label(#[case] label: &[u8], #[case] expected: Option<&[u8]>) {
+        require_fusefs!();
+        require_root!();
+
+        // sb_fname is at byte 108 of the primary superblock.  Unlike a v5 superblock, a v4 one has
+        // no checksum that would need to be updated to match.
+        let (_d, img) = patched_image(GOLDENV4.as_path(), &[(108, label)]);
+        let harness = harness_with_args(&img, &["--show-meta"]);
+        let value = xattr::get(harness.d.path(), "system.xfs.label").unwrap();
+        assert_eq!(value.as_deref(), expected);
+        let files = harness.d.path().join("files");
+        assert_eq!(xattr::get(files, "system.xfs.label").unwrap(), None);
+    }
+
+    /// Without --show-meta, there are no pseudo-attributes
+    #[named]
+    #[rstest]
+    fn no_show_meta(harness4k: Harness) {
+        require_fusefs!();
+        require_root!();
+
+        let p = harness4k.d.path().join("files/hello.txt");
+        assert_eq!(xattr::get(&p, "system.xfs.fragmentation").unwrap(), None);
+    }
 }
 
 /// Lookup the size of an extended attribute without fetching it.
@@ -605,6 +958,44 @@ fn mount(#[case] h: fn() -> Harness) {
     drop(harness);
 }
 
+/// Walk the entire tree: stat everything, read every symlink, and read the start of every regular
+/// file.  This is a broad smoke test of the directory formats, symlinks, and read path together.
+#[named]
+#[rstest]
+#[case::fourk(harness4k)]
+#[case::onek(harness1k)]
+#[case::v4(harnessv4)]
+#[case::fourkn(harness4kn)]
+#[case::no_ftype(harness_noftype)]
+#[case::preallocated(harness_preallocated)]
+fn walk(#[case] h: fn() -> Harness) {
+    require_fusefs!();
+
+    const TIMEOUT: Duration = Duration::from_secs(120);
+
+    let harness = h();
+    let start = Instant::now();
+    let mut inodes = HashSet::new();
+    let mut buf = vec![0u8; 65536];
+    for entry in walkdir::WalkDir::new(harness.d.path()) {
+        let entry = entry.unwrap();
+        let path = entry.path();
+        let md = fs::symlink_metadata(path).unwrap();
+        if md.file_type().is_symlink() {
+            fs::read_link(path).unwrap();
+        } else if md.is_file() {
+            fs::File::open(path).unwrap().read(&mut buf).unwrap();
+        }
+        inodes.insert(md.ino());
+        assert!(
+            start.elapsed() < TIMEOUT,
+            "Walk took too long; gave up at {}",
+            path.display()
+        );
+    }
+    println!("Visited {} inodes in {:?}", inodes.len(), start.elapsed());
+}
+
 mod lookup {
     use super::*;
 
@@ -624,6 +1015,58 @@ mod lookup {
         }
     }
 
+    /// With --show-deleted, the root directory should contain a synthetic ".unlinked"
+    /// directory.  The golden images were cleanly unmounted, so it should be empty.
+    #[named]
+    #[test]
+    fn show_deleted() {
+        require_fusefs!();
+
+        let harness = harness_with_args(GOLDEN4K.as_path(), &["--show-deleted"]);
+        let root = std::fs::read_dir(harness.d.path())
+            .unwrap()
+            .map(|rent| rent.unwrap())
+            .filter(|ent| ent.file_name() == ".unlinked")
+            .collect::<Vec<_>>();
+        assert_eq!(root.len(), 1);
+        assert!(root[0].file_type().unwrap().is_dir());
+
+        let dpath = harness.d.path().join(".unlinked");
+        assert!(fs::metadata(&dpath).unwrap().is_dir());
+        assert_eq!(std::fs::read_dir(dpath).unwrap().count(), 0);
+    }
+
+    /// Without --show-deleted, there should be no ".unlinked" directory
+    #[named]
+    #[rstest]
+    fn no_show_deleted(harness4k: Harness) {
+        require_fusefs!();
+
+        let dpath = harness4k.d.path().join(".unlinked");
+        assert_eq!(fs::metadata(dpath).unwrap_err().kind(), ErrorKind::NotFound);
+    }
+
+    /// A directory entry whose inode number points into the AG headers should fail with EIO,
+    /// without disturbing its neighbors.
+    #[named]
+    #[test]
+    fn ino_in_ag_headers() {
+        require_fusefs!();
+
+        // The root directory's first shortform entry is "sf".  Its 4-byte inode number follows
+        // the 176-byte v3 inode core, the 6-byte header, and 6 bytes of namelen, offset, name,
+        // and ftype.
+        let img = GOLDEN4K.as_path();
+        let (_d, img) = patched_image(img, &[(inode_offset(img, 128) + 188, &7u32.to_be_bytes())]);
+        let harness = harness(&img);
+
+        let e = fs::metadata(harness.d.path().join("sf")).unwrap_err();
+        assert_eq!(e.raw_os_error(), Some(libc::EIO));
+        assert!(fs::metadata(harness.d.path().join("block"))
+            .unwrap()
+            .is_dir());
+    }
+
     /// A block directory with hash collisions
     #[rstest]
     #[named]
@@ -995,6 +1438,35 @@ mod lsextattr {
             panic!("{}", io::Error::last_os_error());
         }
     }
+
+    /// With --show-meta, the pseudo-attributes that each file has are listed in the system
+    /// namespace, and can all be retrieved.
+    #[named]
+    #[rstest]
+    #[case::file(GOLDEN4K.as_path(), "files/hello.txt", &["changecount", "fragmentation"])]
+    #[case::dir(GOLDEN4K.as_path(), "files", &["changecount", "extsize_hint", "fragmentation"])]
+    // Version 4 inodes have no change counter
+    #[case::v4_file(GOLDENV4.as_path(), "files/hello.txt", &["fragmentation"])]
+    fn meta(#[case] img: &Path, #[case] path: &str, #[case] expected: &[&str]) {
+        require_fusefs!();
+        require_root!();
+
+        let harness = harness_with_args(img, &["--show-meta"]);
+        let p = harness.d.path().join(path);
+        let mut names = xattr::list(&p)
+            .unwrap()
+            .filter(|name| name.as_bytes().starts_with(b"system."))
+            .collect::<Vec<_>>();
+        names.sort_unstable();
+        let expected = expected
+            .iter()
+            .map(|name| OsString::from(format!("system.xfs.{name}")))
+            .collect::<Vec<_>>();
+        assert_eq!(names, expected);
+        for name in names {
+            assert!(xattr::get(&p, &name).unwrap().is_some());
+        }
+    }
 }
 
 mod open {
@@ -1036,6 +1508,27 @@ mod open {
         // Ensure that daemon didn't crash
         access(&path_a, AccessFlags::F_OK).unwrap();
     }
+
+    /// A FIFO's I/O is handled by the kernel, like any other FIFO's.  With no writer, a
+    /// nonblocking read returns EOF, and the daemon is never asked for the FIFO's data.
+    #[named]
+    #[rstest]
+    fn fifo(harness4k: Harness) {
+        require_fusefs!();
+
+        let path = harness4k.d.path().join("files").join("fifo");
+        let mut f = fs::OpenOptions::new()
+            .read(true)
+            .custom_flags(libc::O_NONBLOCK)
+            .open(&path)
+            .unwrap();
+        let mut buf = [0u8; 16];
+        assert_eq!(f.read(&mut buf).unwrap(), 0);
+        drop(f);
+
+        // Ensure that daemon didn't crash
+        access(&path, AccessFlags::F_OK).unwrap();
+    }
 }
 
 mod pathconf {
@@ -1091,6 +1584,20 @@ mod read {
         assert_eq!(1, f.read_at(&mut buf[..], size as u64 - 1).unwrap());
     }
 
+    /// Reading an empty file should return no data
+    #[named]
+    #[rstest]
+    fn empty(harness4k: Harness) {
+        require_fusefs!();
+
+        let path = harness4k.d.path().join("files").join("executable");
+        let f = fs::File::open(path).unwrap();
+        assert_eq!(f.metadata().unwrap().len(), 0);
+        let mut buf = [0u8; 4096];
+        assert_eq!(0, f.read_at(&mut buf[..], 0).unwrap());
+        assert_eq!(0, f.read_at(&mut buf[..], 4096).unwrap());
+    }
+
     /// Read a whole file in a single syscall
     #[named]
     #[apply(all_files)]
@@ -1215,6 +1722,122 @@ mod read {
         f.read_exact(&mut buf[..]).unwrap();
         assert_eq!(&vec![0; 4096], &buf);
     }
+
+    /// An enormous read should be clamped to --max-read, not allocate a buffer of the
+    /// requested size.  The kernel should split it into several requests, none larger than the
+    /// limit, though without the limit it would send larger ones.
+    #[named]
+    #[test]
+    fn max_read() {
+        require_fusefs!();
+
+        const SIZE: usize = 8388608;
+        const MAX_READ: u32 = 65536;
+
+        // Read the whole file, and return the size of every FUSE read request that it took
+        let read_sizes = |args: &[&str]| {
+            let (harness, stderr) =
+                harness_with_stderr(GOLDEN4K.as_path(), args, "fuser::request=debug");
+            let path = harness.d.path().join("files").join("btree2.4.txt");
+            let mut buf = vec![0; SIZE];
+            let mut f = fs::File::open(path).unwrap();
+            f.read_exact(&mut buf[..]).unwrap();
+            let mut ofs = 0;
+            while ofs < SIZE {
+                let expected = format!("{:016x}", ofs);
+                assert_eq!(&buf[ofs..ofs + 16], expected.as_bytes());
+                ofs += 16;
+            }
+            drop(f);
+            drop(harness);
+
+            let log = stderr.join().unwrap();
+            log.lines()
+                .filter(|line| line.contains(" READ fh "))
+                .map(|line| {
+                    let size = line.rsplit("size ").next().unwrap();
+                    let digits = size.chars().take_while(char::is_ascii_digit);
+                    digits.collect::<String>().parse::<u32>().unwrap()
+                })
+                .collect::<Vec<_>>()
+        };
+
+        let unlimited = read_sizes(&[]);
+        assert!(
+            unlimited.iter().any(|size| *size > MAX_READ),
+            "The kernel never sent a read larger than {}: {:?}",
+            MAX_READ,
+            unlimited
+        );
+
+        let limited = read_sizes(&["--max-read", &MAX_READ.to_string()]);
+        assert!(limited.len() >= SIZE / MAX_READ as usize, "{:?}", limited);
+        assert!(
+            limited.iter().all(|size| *size <= MAX_READ),
+            "{:?}",
+            limited
+        );
+    }
+
+    /// Because open keeps the cache, the kernel's readahead turns a sequential read made of small
+    /// reads into fewer, larger FUSE requests.  With --trace-reads, the device reads that serve
+    /// them are logged, and they read the whole file a block at a time.
+    #[named]
+    #[test]
+    fn readahead() {
+        require_fusefs!();
+
+        const SIZE: usize = 8388608;
+        const BUFSIZE: usize = 4096;
+
+        let (harness, stderr) = harness_with_stderr(
+            GOLDEN4K.as_path(),
+            &["--trace-reads"],
+            "fuser::request=debug",
+        );
+        let path = harness.d.path().join("files").join("btree2.4.txt");
+        let mut f = fs::File::open(path).unwrap();
+        let mut buf = [0; BUFSIZE];
+        for ofs in (0..SIZE).step_by(BUFSIZE) {
+            f.read_exact(&mut buf[..]).unwrap();
+            let expected = format!("{:016x}", ofs);
+            assert_eq!(&buf[..16], expected.as_bytes());
+        }
+        drop(f);
+        drop(harness);
+
+        // Parse the decimal number that follows `key` in a log line
+        let number = |line: &str, key: &str| {
+            let s = line.rsplit(key).next().unwrap();
+            let digits = s.chars().take_while(char::is_ascii_digit);
+            digits.collect::<String>().parse::<usize>().unwrap()
+        };
+        let log = stderr.join().unwrap();
+        let requests = log
+            .lines()
+            .filter(|line| line.contains(" READ fh "))
+            .map(|line| number(line, "size "))
+            .collect::<Vec<_>>();
+        assert!(requests.len() < SIZE / BUFSIZE, "{:?}", requests);
+        assert!(
+            requests.iter().any(|size| *size > BUFSIZE),
+            "The kernel never sent a read larger than {}: {:?}",
+            BUFSIZE,
+            requests
+        );
+        assert!(requests.iter().sum::<usize>() >= SIZE, "{:?}", requests);
+
+        let reads = log
+            .lines()
+            .filter(|line| line.contains("data read at "))
+            .map(|line| number(line, ", "))
+            .collect::<Vec<_>>();
+        // Inodes are read in smaller pieces, but file data is read a block at a time
+        assert!(reads.iter().all(|size| *size <= 4096), "{:?}", reads);
+        let blocks = reads.iter().filter(|size| **size == 4096).count();
+        assert!(blocks >= SIZE / 4096, "{:?}", reads);
+    }
+
     // TODO: add a test case for reading with direct I/O where the image is on a
     // device, not a file
 
@@ -1280,6 +1903,64 @@ mod readdir {
         assert_eq!(count, ents_per_dir_shortnames(harness4k.path.as_path(), d));
     }
 
+    /// readdir reports each entry's ftype, while lookup reports the inode's own mode.  On a
+    /// consistent file system, they must agree.
+    #[rstest]
+    #[named]
+    fn ftype_matches_mode(harness4k: Harness) {
+        require_fusefs!();
+
+        let dpath = harness4k.d.path().join("files");
+        for rent in std::fs::read_dir(dpath).unwrap() {
+            let ent = rent.unwrap();
+            let md = fs::symlink_metadata(ent.path()).unwrap();
+            assert_eq!(
+                ent.file_type().unwrap(),
+                md.file_type(),
+                "type mismatch for {:?}",
+                ent.file_name()
+            );
+        }
+    }
+
+    /// On a corrupt file system, an entry's ftype may disagree with its inode's mode.  readdir
+    /// still reports the ftype, but lookup reports the inode's own mode, and readdir warns about
+    /// the mismatch once the inode is open.
+    #[named]
+    #[test]
+    fn ftype_mismatch() {
+        require_fusefs!();
+
+        // Turn hello.txt into a FIFO, leaving its directory entry's ftype alone
+        let patchdir = tempdir().unwrap();
+        let patchfile = patchdir.path().join("patch.txt");
+        fs::write(
+            &patchfile,
+            format!("142530 di_mode {}\n", libc::S_IFIFO | 0o644),
+        )
+        .unwrap();
+        let (harness, stderr) = harness_with_stderr(
+            GOLDEN4K.as_path(),
+            &["--patch", patchfile.to_str().unwrap()],
+            "warn",
+        );
+        let dpath = harness.d.path().join("files");
+        let md = fs::symlink_metadata(dpath.join("hello.txt")).unwrap();
+        assert!(md.file_type().is_fifo());
+        let ent = fs::read_dir(&dpath)
+            .unwrap()
+            .map(Result::unwrap)
+            .find(|ent| ent.file_name() == "hello.txt")
+            .unwrap();
+        assert!(ent.file_type().unwrap().is_file());
+        drop(harness);
+
+        let log = stderr.join().unwrap();
+        let expected =
+            "Directory entry \"hello.txt\" has type RegularFile, but inode 142530 is a NamedPipe";
+        assert!(log.contains(expected), "{}", log);
+    }
+
     /// A block directory with hash collisions
     #[rstest]
     #[named]
@@ -1306,8 +1987,103 @@ mod readdir {
         assert_eq!(count, 40);
     }
 
-    /// List a directory's contents with readdir
-    //
+    /// With --sort-dents name, entries should be returned sorted bytewise by name, rather than
+    /// in on-disk order.
+    #[named]
+    #[rstest]
+    #[case::sf("sf")]
+    #[case::all_name_lengths("all_name_lengths")]
+    #[case::hash_collisions("block-with-hash-collisions")]
+    #[case::files("files")]
+    fn sort_dents(#[case] d: &str) {
+        require_fusefs!();
+
+        let unsorted = std::fs::read_dir(harness4k().d.path().join(d))
+            .unwrap()
+            .map(|rent| rent.unwrap().file_name())
+            .collect::<Vec<_>>();
+
+        let harness = harness_with_args(GOLDEN4K.as_path(), &["--sort-dents", "name"]);
+        let names = std::fs::read_dir(harness.d.path().join(d))
+            .unwrap()
+            .map(|rent| rent.unwrap().file_name())
+            .collect::<Vec<_>>();
+        assert!(
+            names.windows(2).all(|w| w[0].as_bytes() < w[1].as_bytes()),
+            "{:?}",
+            names
+        );
+        let mut expected = unsorted;
+        expected.sort_unstable_by(|a, b| a.as_bytes().cmp(b.as_bytes()));
+        assert_eq!(names, expected);
+    }
+
+    /// With --show-deleted, a real ".unlinked" entry in the root directory is hidden by the
+    /// synthetic one, however the entries are ordered.  When sorted, the synthetic one takes its
+    /// place in order.
+    #[named]
+    #[rstest]
+    #[case::unsorted(&["--show-deleted"], false)]
+    #[case::sorted(&["--show-deleted", "--sort-dents", "name"], true)]
+    fn show_deleted_hides_real(#[case] args: &[&str], #[case] sorted: bool) {
+        require_fusefs!();
+
+        // Rename the root directory's "links" entry to ".unlinked".  The root is a shortform
+        // directory, whose entries are packed after the 176-byte v3 inode core as namelen,
+        // 2-byte offset, name, ftype, and a 4-byte inode number.
+        let img = GOLDEN4K.as_path();
+        let mut sb = [0u8; 64];
+        fs::File::open(img)
+            .unwrap()
+            .read_exact_at(&mut sb, 0)
+            .unwrap();
+        let rootino = u64::from_be_bytes(sb[56..64].try_into().unwrap());
+        let offset = inode_offset(img, rootino);
+        let mut inode = vec![0u8; 512];
+        fs::File::open(img)
+            .unwrap()
+            .read_exact_at(&mut inode, offset)
+            .unwrap();
+        let pos = inode[176..].windows(5).position(|w| w == b"links").unwrap() + 176;
+        assert_eq!(inode[pos - 3], 5);
+        inode[pos - 3] = 9;
+        inode.splice(pos..pos + 5, b".unlinked".iter().copied());
+        inode.truncate(512);
+        let size = u64::from_be_bytes(inode[56..64].try_into().unwrap()) + 4;
+        inode[56..64].copy_from_slice(&size.to_be_bytes());
+        let (_d, img) = patched_image(img, &[(offset, &inode)]);
+
+        // Without --show-deleted, the real entry is visible
+        let harness = harness(&img);
+        let real = fs::metadata(harness.d.path().join(".unlinked")).unwrap();
+        assert_ne!(real.ino(), 0);
+        drop(harness);
+
+        let harness = harness_with_args(&img, args);
+        let ents = std::fs::read_dir(harness.d.path())
+            .unwrap()
+            .map(|rent| rent.unwrap())
+            .collect::<Vec<_>>();
+        if sorted {
+            assert!(
+                ents.windows(2)
+                    .all(|w| w[0].file_name().as_bytes() < w[1].file_name().as_bytes()),
+                "{:?}",
+                ents
+            );
+        }
+        let unlinked = ents
+            .into_iter()
+            .filter(|ent| ent.file_name() == ".unlinked")
+            .collect::<Vec<_>>();
+        assert_eq!(unlinked.len(), 1);
+        assert_ne!(unlinked[0].ino(), real.ino());
+        let dpath = harness.d.path().join(".unlinked");
+        assert_eq!(std::fs::read_dir(dpath).unwrap().count(), 0);
+    }
+
+    /// List a directory's contents with readdir
+    //
     // The 1k blocksize formatted golden image uses a different naming convention than the 4k image
     #[named]
     #[apply(all_dir_types_longnames)]
@@ -1414,6 +2190,66 @@ mod readdir {
         assert_eq!("..", dotdot.file_name().to_str().unwrap());
         assert_eq!(root_md.ino(), dotdot.ino());
     }
+
+    /// Mount an image, stat every entry of every directory, and return whatever the daemon logged
+    /// at warn level or above.
+    fn walk_all(img: &Path) -> String {
+        let (harness, stderr) = harness_with_stderr(img, &[], "warn");
+        for entry in walkdir::WalkDir::new(harness.d.path()) {
+            let entry = entry.unwrap();
+            if entry.file_type().is_dir() {
+                for name in fs::read_dir(entry.path()).unwrap() {
+                    fs::symlink_metadata(name.unwrap().path()).unwrap();
+                }
+            }
+        }
+        // Unmount, so the daemon exits and closes its stderr
+        drop(harness);
+        stderr.join().unwrap()
+    }
+
+    /// Every directory block on a v5 file system records its directory's inode as its owner.
+    /// Walk every directory and check that the daemon never complained about a mismatch.
+    #[named]
+    #[rstest]
+    #[case::onek(GOLDEN1K.as_path())]
+    #[case::fourk(GOLDEN4K.as_path())]
+    #[case::fourkn(GOLDEN4KN.as_path())]
+    fn owner(#[case] img: &Path) {
+        require_fusefs!();
+
+        let errmsg = walk_all(img);
+        assert!(!errmsg.contains("belongs to inode"), "{}", errmsg);
+    }
+
+    /// The same walk does notice a directory block with the wrong owner.
+    #[named]
+    #[test]
+    fn owner_mismatch() {
+        require_fusefs!();
+
+        // "block" is a single-extent block directory.  Its extent record follows the 176-byte v3
+        // inode core, and the owner is the last field of the 48-byte data block header.
+        const INO: u64 = 65664;
+        let img = GOLDEN4K.as_path();
+        let mut rec = [0u8; 16];
+        fs::File::open(img)
+            .unwrap()
+            .read_exact_at(&mut rec, inode_offset(img, INO) + 176)
+            .unwrap();
+        let fsb = (u128::from_be_bytes(rec) >> 21) as u64 & ((1 << 52) - 1);
+        let bogus = (INO + 1).to_be_bytes();
+        let (_d, img) = patched_image(img, &[(fsb_offset(img, fsb) + 40, &bogus)]);
+
+        let errmsg = walk_all(&img);
+        let expected = format!(
+            "Directory block {} of inode {} belongs to inode {}",
+            fsb,
+            INO,
+            INO + 1
+        );
+        assert!(errmsg.contains(&expected), "{}", errmsg);
+    }
 }
 
 #[named]
@@ -1433,6 +2269,119 @@ fn readlink(
     assert_eq!(dest.as_os_str(), destname);
 }
 
+mod realtime {
+    use super::*;
+
+    /// The contents of files/hello.txt once it's moved to the realtime device
+    const RT_HELLO: &[u8] = b"realtime data\n";
+
+    /// Give a copy of the 4k golden image a 16-block realtime device, and move files/hello.txt
+    /// onto it.  Return the devices' directory, the data device and the realtime device.
+    pub(super) fn realtime_image() -> (TempDir, PathBuf, PathBuf) {
+        const RBLOCKS: u64 = 16;
+        const HELLO: u64 = 142530;
+        const HELLO_RTB: u64 = 5;
+        let img = GOLDEN4K.as_path();
+        // With an rextsize of 1, that's one bitmap block and a 4096-byte summary
+        let sb = superblock_patch(img, |sb| {
+            sb[16..24].copy_from_slice(&RBLOCKS.to_be_bytes());
+            sb[24..32].copy_from_slice(&RBLOCKS.to_be_bytes());
+            sb[92..96].copy_from_slice(&1u32.to_be_bytes());
+            sb[125] = RBLOCKS.ilog2() as u8;
+        });
+        let rsumino = u64::from_be_bytes(read_superblock(img)[72..80].try_into().unwrap());
+        let rsum_size = 4096i64.to_be_bytes();
+        let hello = inode_offset(img, HELLO);
+        let flags = 1u16.to_be_bytes(); // XFS_DIFLAG_REALTIME
+        let rec = (u128::from(HELLO_RTB) << 21 | 1).to_be_bytes();
+        let (d, img) = patched_image(
+            img,
+            &[
+                (0, &sb),
+                (inode_offset(img, rsumino) + 56, &rsum_size),
+                (hello + 90, &flags),
+                (hello + 176, &rec),
+            ],
+        );
+        let rtdev = d.path().join("rtdev");
+        let mut data = vec![0u8; RBLOCKS as usize * 4096];
+        data[HELLO_RTB as usize * 4096..][..RT_HELLO.len()].copy_from_slice(RT_HELLO);
+        fs::write(&rtdev, data).unwrap();
+        (d, img, rtdev)
+    }
+
+    /// A file on the realtime device is read from --rtdev
+    #[named]
+    #[test]
+    fn read() {
+        require_fusefs!();
+
+        let (_d, img, rtdev) = realtime_image();
+        let harness = harness_with_args(&img, &["--rtdev", rtdev.to_str().unwrap()]);
+        let path = harness.d.path().join("files/hello.txt");
+        assert_eq!(fs::read(path).unwrap(), RT_HELLO);
+    }
+
+    /// An image with reflink and rmapbt on a realtime device mounts without --rtdev.  Files on the
+    /// data device remain readable, and only the realtime file is refused.
+    #[named]
+    #[test]
+    fn rtreflink() {
+        require_fusefs!();
+
+        let (_d, img, _rtdev) = realtime_image();
+        // sb_features_ro_compat: finobt, rmapbt, reflink, and inobtcnt
+        let sb = superblock_patch(&img, |sb| {
+            sb[212..216].copy_from_slice(&0xfu32.to_be_bytes())
+        });
+        fs::OpenOptions::new()
+            .write(true)
+            .open(&img)
+            .unwrap()
+            .write_all_at(&sb, 0)
+            .unwrap();
+        let harness = harness(&img);
+
+        let data = fs::read(harness.d.path().join("files/four_extents.txt")).unwrap();
+        assert_eq!(data.len(), 16384);
+        for (i, chunk) in data.chunks(16).enumerate() {
+            assert_eq!(chunk, format!("{:016x}", i * 16).as_bytes());
+        }
+        let e = fs::read(harness.d.path().join("files/hello.txt")).unwrap_err();
+        assert_eq!(e.raw_os_error(), Some(libc::ENODEV));
+    }
+
+    /// A realtime device that doesn't match the superblock is refused before mounting
+    #[rstest]
+    #[case::none(false, "the file system has no realtime device")]
+    #[case::bad_rsum(true, "Realtime summary inode size is inconsistent")]
+    fn bad_geometry(#[case] realtime: bool, #[case] error: &str) {
+        let (d, img, rtdev) = realtime_image();
+        let img = if realtime {
+            // Grow the summary inode past what the geometry calls for
+            let rsumino = u64::from_be_bytes(read_superblock(&img)[72..80].try_into().unwrap());
+            let f = fs::OpenOptions::new().write(true).open(&img).unwrap();
+            f.write_all_at(&8192i64.to_be_bytes(), inode_offset(&img, rsumino) + 56)
+                .unwrap();
+            img
+        } else {
+            GOLDEN4K.to_path_buf()
+        };
+        let output = Command::cargo_bin("xfs-fuse")
+            .unwrap()
+            .arg("--rtdev")
+            .arg(&rtdev)
+            .arg(&img)
+            .arg(d.path())
+            .output()
+            .unwrap();
+        let stderr = String::from_utf8(output.stderr).unwrap();
+        assert_eq!(output.status.code(), Some(1), "{}", stderr);
+        assert!(stderr.contains(error), "{}", stderr);
+        assert!(!stderr.contains("panicked"), "{}", stderr);
+    }
+}
+
 mod stat {
     use super::*;
 
@@ -1440,9 +2389,9 @@ mod stat {
     // This may need to be updated whenever the golden images get rebuilt.
     #[named]
     #[rstest]
-    #[case::v4(harnessv4, 100551)]
-    #[case::v5(harness4k, 142530)]
-    fn file(#[case] h: fn() -> Harness, #[case] st_ino: libc::ino_t) {
+    #[case::v4(harnessv4, 100551, 1)]
+    #[case::v5(harness4k, 142530, 8)]
+    fn file(#[case] h: fn() -> Harness, #[case] st_ino: libc::ino_t, #[case] st_blocks: i64) {
         require_fusefs!();
 
         let harness = h();
@@ -1465,13 +2414,189 @@ mod stat {
         assert_eq!(stat.st_ino, st_ino);
         assert_eq!(stat.st_size, 14);
         assert_eq!(stat.st_blksize, 4096);
-        assert_eq!(stat.st_blocks, 1);
+        // One file system block, in 512-byte units
+        assert_eq!(stat.st_blocks, st_blocks);
         assert_eq!(stat.st_uid, 1234);
         assert_eq!(stat.st_gid, 5678);
         assert_eq!(stat.st_mode, libc::S_IFREG | 0o1234);
         assert_eq!(stat.st_nlink, 2);
     }
 
+    /// An empty file has no blocks at all
+    #[named]
+    #[rstest]
+    fn empty(harness4k: Harness) {
+        require_fusefs!();
+
+        let path = harness4k.d.path().join("files").join("executable");
+        let stat = nix::sys::stat::stat(&path).unwrap();
+        assert_eq!(stat.st_size, 0);
+        assert_eq!(stat.st_blocks, 0);
+        assert_eq!(stat.st_mode & libc::S_IFMT, libc::S_IFREG);
+    }
+
+    /// The block count includes the attribute fork's blocks, just like on the kernel.  This file
+    /// has no data at all, but 16 remote attribute values plus the attribute btree's blocks: 18
+    /// 1 KiB blocks, or 36 512-byte units.
+    #[named]
+    #[rstest]
+    fn attr_blocks(harness1k: Harness) {
+        require_fusefs!();
+
+        let path = harness1k.d.path().join("xattrs").join("btree2");
+        let stat = nix::sys::stat::stat(&path).unwrap();
+        assert_eq!(stat.st_size, 0);
+        assert_eq!(stat.st_blocks, 36);
+    }
+
+    /// A preallocated file's block count includes its unwritten blocks, in 512-byte units.
+    /// Shrinking its size with --patch leaves those blocks beyond EOF: the size shrinks, the
+    /// block count doesn't, and reads stop at the new EOF.
+    #[named]
+    #[rstest]
+    #[case::whole(None, 8388608)]
+    #[case::beyond_eof(Some(4096), 4096)]
+    fn preallocated(#[case] di_size: Option<u64>, #[case] st_size: i64) {
+        require_fusefs!();
+
+        let patchdir = tempdir().unwrap();
+        let patchfile = patchdir.path().join("patch.txt");
+        let mut args = vec![];
+        if let Some(di_size) = di_size {
+            fs::write(&patchfile, format!("11076 di_size {}\n", di_size)).unwrap();
+            args = vec!["--patch", patchfile.to_str().unwrap()];
+        }
+        let harness = harness_with_args(GOLDENPREALLOCATED.as_path(), &args);
+        let path = harness.d.path().join("files").join("preallocated");
+
+        let stat = nix::sys::stat::stat(&path).unwrap();
+        assert_eq!(stat.st_size, st_size);
+        // 2048 4 KiB blocks, or 8 MiB
+        assert_eq!(stat.st_blocks, 16384);
+
+        let data = fs::read(&path).unwrap();
+        assert_eq!(data.len() as i64, st_size);
+        assert!(data.iter().all(|b| *b == 0));
+    }
+
+    /// The mountpoint should report the root inode's own mode, which is always a directory.  The
+    /// root is never looked up, so this must work even as the first request after mounting.
+    #[named]
+    #[rstest]
+    #[case::v4(harnessv4)]
+    #[case::v5(harness4k)]
+    fn root(#[case] h: fn() -> Harness) {
+        require_fusefs!();
+
+        let harness = h();
+        let stat = nix::sys::stat::stat(harness.d.path()).unwrap();
+        assert!(fs::metadata(harness.d.path()).unwrap().is_dir());
+        assert_eq!(stat.st_ino, 1);
+        assert_eq!(stat.st_mode, libc::S_IFDIR | 0o755);
+        assert_eq!(stat.st_uid, 0);
+        assert_eq!(stat.st_gid, 0);
+    }
+
+    /// With --device-sector-size, inode reads are padded to a whole sector.  They should still
+    /// succeed, even for images with inodes smaller than that.
+    #[named]
+    #[rstest]
+    #[case::v4(GOLDENV4.as_path(), 100551)]
+    #[case::v5(GOLDEN4K.as_path(), 142530)]
+    fn device_sector_size(#[case] img: &Path, #[case] st_ino: libc::ino_t) {
+        require_fusefs!();
+
+        let harness = harness_with_args(img, &["--device-sector-size", "4096"]);
+        let path = harness.d.path().join("files").join("hello.txt");
+
+        let stat = nix::sys::stat::stat(&path).unwrap();
+        assert_eq!(stat.st_ino, st_ino);
+        assert_eq!(stat.st_size, 14);
+    }
+
+    /// With --ino-offset, every reported inode number should be shifted by a constant
+    #[named]
+    #[rstest]
+    fn ino_offset() {
+        require_fusefs!();
+
+        let harness = harness_with_args(GOLDEN4K.as_path(), &["--ino-offset", "1000000"]);
+        let path = harness.d.path().join("files").join("hello.txt");
+
+        let stat = nix::sys::stat::stat(&path).unwrap();
+        assert_eq!(stat.st_ino, 142530 + 1000000);
+
+        // readdir should agree with lookup
+        let dirent = fs::read_dir(harness.d.path().join("files"))
+            .unwrap()
+            .map(Result::unwrap)
+            .find(|e| e.file_name() == "hello.txt")
+            .unwrap();
+        assert_eq!(dirent.ino(), 142530 + 1000000);
+
+        // But the root directory's inode number is fixed by FUSE
+        let root_md = fs::metadata(harness.d.path()).unwrap();
+        assert_eq!(root_md.ino(), 1);
+    }
+
+    /// With --patch, overridden fields should be reported instead of the on-disk values, without
+    /// modifying the image.
+    #[named]
+    #[rstest]
+    fn patch() {
+        require_fusefs!();
+
+        let before = fs::read(GOLDEN4K.as_path()).unwrap();
+        let patchdir = tempdir().unwrap();
+        let patchfile = patchdir.path().join("patch.txt");
+        fs::write(&patchfile, "# Make hello.txt bigger\n142530 di_size 1000\n").unwrap();
+
+        let harness = harness_with_args(
+            GOLDEN4K.as_path(),
+            &["--patch", patchfile.to_str().unwrap()],
+        );
+        let path = harness.d.path().join("files").join("hello.txt");
+        let stat = nix::sys::stat::stat(&path).unwrap();
+        assert_eq!(stat.st_size, 1000);
+        drop(harness);
+
+        assert!(before == fs::read(GOLDEN4K.as_path()).unwrap());
+    }
+
+    /// With --show-meta, the realtime metadata inodes can be stat'ed through hidden names in the
+    /// root directory, even though the golden images have no realtime device.
+    #[named]
+    #[rstest]
+    #[case::rtbitmap(".xfs-rtbitmap")]
+    #[case::rtsummary(".xfs-rtsummary")]
+    fn show_meta_rt(#[case] name: &str) {
+        require_fusefs!();
+
+        let harness = harness_with_args(GOLDEN4K.as_path(), &["--show-meta"]);
+        let path = harness.d.path().join(name);
+        let stat = nix::sys::stat::stat(&path).unwrap();
+        assert_eq!(stat.st_mode & libc::S_IFMT, libc::S_IFREG);
+        assert_ne!(stat.st_ino, 1);
+
+        // The hidden names aren't listed
+        assert!(fs::read_dir(harness.d.path())
+            .unwrap()
+            .all(|e| e.unwrap().file_name() != name));
+    }
+
+    /// Without --show-meta, the realtime metadata inodes are inaccessible
+    #[named]
+    #[rstest]
+    fn no_show_meta_rt(harness4k: Harness) {
+        require_fusefs!();
+
+        let path = harness4k.d.path().join(".xfs-rtbitmap");
+        assert_eq!(
+            nix::sys::stat::stat(&path).unwrap_err(),
+            nix::errno::Errno::ENOENT
+        );
+    }
+
     /// Timestamps from before the Epoch should work
     #[named]
     #[rstest]
@@ -1515,6 +2640,53 @@ mod stat {
         assert_eq!(1, stat.st_nlink, "AT_SYMLINK_NOFOLLOW was ignored");
         assert_eq!(ino, stat.st_ino);
     }
+
+    /// A directory's st_size should be its di_size, which for block and larger
+    /// directories is the size of its data segment.
+    #[named]
+    #[rstest]
+    #[case::sf(harness4k, "sf", 44)]
+    #[case::block(harness4k, "block", 8192)]
+    #[case::leaf(harness4k, "leaf", 16384)]
+    #[case::node(harness1k, "node1", 147456)]
+    fn dir_size(#[case] h: fn() -> Harness, #[case] d: &str, #[case] size: i64) {
+        require_fusefs!();
+
+        let harness = h();
+        let path = harness.d.path().join(d);
+        let stat = nix::sys::stat::stat(&path).unwrap();
+        let mut di_size = [0u8; 8];
+        fs::File::open(&harness.path)
+            .unwrap()
+            .read_exact_at(&mut di_size, inode_offset(&harness.path, stat.st_ino) + 56)
+            .unwrap();
+        assert_eq!(stat.st_size, i64::from_be_bytes(di_size));
+        assert_eq!(stat.st_size, size);
+    }
+
+    /// Since st_size is the size of the directory's data, rather than a fixed value, it should
+    /// grow with the number of entries, through every directory format.
+    #[named]
+    #[rstest]
+    fn dir_size_grows(harnessv4: Harness) {
+        require_fusefs!();
+
+        // Each directory holds more entries than the last, in a roomier format
+        let dirs = [("sf", 2), ("block", 4), ("leaf", 128), ("node", 512)];
+        let sizes = dirs
+            .iter()
+            .map(|(d, nents)| {
+                let path = harnessv4.d.path().join(d);
+                assert_eq!(fs::read_dir(&path).unwrap().count(), *nents);
+                nix::sys::stat::stat(&path).unwrap().st_size
+            })
+            .collect::<Vec<_>>();
+        assert!(
+            sizes.windows(2).all(|w| w[0] < w[1]),
+            "Directory sizes don't grow with their entry counts: {:?}",
+            sizes
+        );
+    }
 }
 
 #[named]
@@ -1548,6 +2720,43 @@ fn statfs(harness4k: Harness) {
     // So ignore it.
 }
 
+/// The golden images use lazy superblock counters, so free space is summed from the AG headers.
+/// They were cleanly unmounted, so the sum should equal the superblock's copy.
+#[named]
+#[rstest]
+#[case::v4(harnessv4, 90624)]
+#[case::v5(harness4k, 16545)]
+fn statfs_lazycount(#[case] h: fn() -> Harness, #[case] bfree: u64) {
+    require_fusefs!();
+
+    let harness = h();
+    let sfs = nix::sys::statfs::statfs(harness.d.path()).unwrap();
+    assert_eq!(sfs.blocks_free(), bfree);
+}
+
+/// If an AGF is corrupt, statfs falls back to the superblock's counters rather than failing
+#[named]
+#[test]
+fn statfs_bad_agf() {
+    require_fusefs!();
+
+    // AG 1's AGF is in its second sector
+    let img = GOLDEN4K.as_path();
+    let agblklog = read_superblock(img)[124];
+    let (_d, img) = patched_image(img, &[(fsb_offset(img, 1 << agblklog) + 512, &[0; 4])]);
+    let (harness, stderr) = harness_with_stderr(&img, &[], "warn");
+    let sfs = nix::sys::statfs::statfs(harness.d.path()).unwrap();
+    assert_eq!(sfs.blocks_free(), 16545);
+    drop(harness);
+
+    let log = stderr.join().unwrap();
+    assert!(
+        log.contains("Cannot sum the AG counters, using the superblock's"),
+        "{}",
+        log
+    );
+}
+
 #[named]
 #[rstest]
 fn statvfs(harness4k: Harness) {
@@ -1578,3 +2787,499 @@ fn statvfs(harness4k: Harness) {
     // svfs.f_namemax is DONTCARE.  This information should be retrieved via
     // pathconf instead.
 }
+
+/// A mountpoint that isn't a directory is rejected with a clear error before mounting
+#[rstest]
+#[case::nonexistent(false, "No such file or directory")]
+#[case::not_dir(true, "Not a directory")]
+fn bad_mountpoint(#[case] create: bool, #[case] error: &str) {
+    let tempdir = tempdir().unwrap();
+    let mountpoint = tempdir.path().join("mnt");
+    if create {
+        fs::File::create(&mountpoint).unwrap();
+    }
+    let output = Command::cargo_bin("xfs-fuse")
+        .unwrap()
+        .arg(GOLDEN4K.as_path())
+        .arg(&mountpoint)
+        .output()
+        .unwrap();
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert_eq!(output.status.code(), Some(1), "{}", stderr);
+    assert!(stderr.contains(mountpoint.to_str().unwrap()), "{}", stderr);
+    assert!(stderr.contains(error), "{}", stderr);
+}
+
+/// An --ino-offset that would push the highest inode numbers past the largest node ID is rejected
+/// with a clear error before mounting
+#[test]
+fn bad_ino_offset() {
+    let tempdir = tempdir().unwrap();
+    let output = Command::cargo_bin("xfs-fuse")
+        .unwrap()
+        .arg("--ino-offset")
+        .arg(u64::MAX.to_string())
+        .arg(GOLDEN4K.as_path())
+        .arg(tempdir.path())
+        .output()
+        .unwrap();
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert_eq!(output.status.code(), Some(1), "{}", stderr);
+    let expected = format!("--ino-offset {} is too large", u64::MAX);
+    assert!(stderr.contains(&expected), "{}", stderr);
+    assert!(!stderr.contains("panicked"), "{}", stderr);
+}
+
+/// A superblock with invalid geometry is rejected with a clear error before mounting
+#[rstest]
+#[case::agcount(88, &[0, 0, 0, 0], "AG count 0 is invalid")]
+#[case::dirblklog(192, &[5], "sb_dirblklog 5 is invalid for block size 4096")]
+fn bad_superblock(#[case] offset: usize, #[case] value: &[u8], #[case] error: &str) {
+    let img = GOLDEN4K.as_path();
+    let sb = superblock_patch(img, |sb| {
+        sb[offset..offset + value.len()].copy_from_slice(value)
+    });
+    let (d, img) = patched_image(img, &[(0, &sb)]);
+    let output = Command::cargo_bin("xfs-fuse")
+        .unwrap()
+        .arg(&img)
+        .arg(d.path())
+        .output()
+        .unwrap();
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert_eq!(output.status.code(), Some(1), "{}", stderr);
+    assert!(stderr.contains(error), "{}", stderr);
+    assert!(!stderr.contains("panicked"), "{}", stderr);
+}
+
+/// A root inode that isn't a directory is rejected with a clear error before mounting
+#[test]
+fn bad_root() {
+    let tempdir = tempdir().unwrap();
+    let patchfile = tempdir.path().join("patch.txt");
+    fs::write(
+        &patchfile,
+        format!("128 di_mode {}\n", libc::S_IFREG | 0o644),
+    )
+    .unwrap();
+    let output = Command::cargo_bin("xfs-fuse")
+        .unwrap()
+        .arg("--patch")
+        .arg(&patchfile)
+        .arg(GOLDEN4K.as_path())
+        .arg(tempdir.path())
+        .output()
+        .unwrap();
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert_eq!(output.status.code(), Some(1), "{}", stderr);
+    assert!(
+        stderr.contains("Root inode 128 is not a directory"),
+        "{}",
+        stderr
+    );
+    assert!(!stderr.contains("panicked"), "{}", stderr);
+}
+
+/// With --repair-hint, corruption found while mounted is summarized when the file system is
+/// unmounted, naming the damaged structure.
+#[named]
+#[test]
+fn repair_hint() {
+    require_fusefs!();
+
+    // The target of links/max is stored in a single remote block.  Find that block from the
+    // inode's only extent record, which follows the 176-byte v3 inode core, and clobber its
+    // magic number.
+    let img = GOLDEN4K.as_path();
+    let mut rec = [0u8; 16];
+    fs::File::open(img)
+        .unwrap()
+        .read_exact_at(&mut rec, inode_offset(img, 65699) + 176)
+        .unwrap();
+    let fsb = (u128::from_be_bytes(rec) >> 21) as u64 & ((1 << 52) - 1);
+    let (_d, img) = patched_image(img, &[(fsb_offset(img, fsb), &[0; 4])]);
+
+    let (harness, stderr) = harness_with_stderr(&img, &["--repair-hint"], "warn");
+    let e = fs::read_link(harness.d.path().join("links/max")).unwrap_err();
+    assert_eq!(e.raw_os_error(), Some(libc::EIO));
+    // Unmount, so the daemon prints its summary and exits
+    drop(harness);
+
+    let errmsg = stderr.join().unwrap();
+    assert!(
+        errmsg.contains("Found 1 corrupt structure(s) while mounted:\n"),
+        "{}",
+        errmsg
+    );
+    let damaged = format!(
+        "    AG 1, block 8212, inode 65699: symlink block {} has bad magic 0x0\n",
+        fsb
+    );
+    assert!(errmsg.contains(&damaged), "{}", errmsg);
+    assert!(errmsg.contains("xfs_repair"), "{}", errmsg);
+}
+
+/// With --repair-hint, a node directory's free index is checked when the directory is opened.  An
+/// inconsistent index is summarized at unmount, but the directory can still be read.
+#[named]
+#[test]
+fn repair_hint_free_index() {
+    require_fusefs!();
+
+    // The free index of node1 is its block at directory offset 64 GiB.  Find it among the
+    // inode's extent records, which follow the 176-byte v3 inode core, and change the free space
+    // that it records for data block 0.
+    let img = GOLDEN1K.as_path();
+    let ioff = inode_offset(img, 524352);
+    let f = fs::File::open(img).unwrap();
+    let mut nextents = [0u8; 4];
+    f.read_exact_at(&mut nextents, ioff + 76).unwrap();
+    let free_offset = (64 << 30) >> 10;
+    let fsb = (0..u64::from(u32::from_be_bytes(nextents)))
+        .map(|i| {
+            let mut rec = [0u8; 16];
+            f.read_exact_at(&mut rec, ioff + 176 + 16 * i).unwrap();
+            u128::from_be_bytes(rec)
+        })
+        .find(|rec| (rec >> 73) as u64 & ((1 << 54) - 1) == free_offset)
+        .map(|rec| (rec >> 21) as u64 & ((1 << 52) - 1))
+        .unwrap();
+    // The bests array follows the 64-byte v3 free index header
+    let (_d, img) = patched_image(img, &[(fsb_offset(img, fsb) + 64, &[0, 8])]);
+
+    let (harness, stderr) = harness_with_stderr(&img, &["--repair-hint"], "warn");
+    let ents = fs::read_dir(harness.d.path().join("node1"))
+        .unwrap()
+        .count();
+    assert_eq!(ents, ents_per_dir_longnames(GOLDEN1K.as_path(), "node1"));
+    drop(harness);
+
+    let errmsg = stderr.join().unwrap();
+    assert!(
+        errmsg.contains("Found 1 corrupt structure(s) while mounted:\n"),
+        "{}",
+        errmsg
+    );
+    let damaged = "    AG 1, block 262176, inode 524352: Free index records 8 free bytes in data \
+                   block 0, but it has 192\n";
+    assert!(errmsg.contains(damaged), "{}", errmsg);
+}
+
+/// With --repair-hint, SIGUSR1 summarizes the corruption found so far, without unmounting.
+/// Corrupt AG headers are named by their AG.
+#[named]
+#[test]
+fn repair_hint_sigusr1() {
+    require_fusefs!();
+
+    // AG 1's AGF is in its second sector
+    let img = GOLDEN4K.as_path();
+    let agblklog = read_superblock(img)[124];
+    let (_d, img) = patched_image(img, &[(fsb_offset(img, 1 << agblklog) + 512, &[0; 4])]);
+    let (harness, lines) = harness_with_stderr_lines(&img, &["--repair-hint"], "warn");
+    let pid = nix::unistd::Pid::from_raw(harness.child.id() as i32);
+    nix::sys::signal::kill(pid, nix::sys::signal::Signal::SIGUSR1).unwrap();
+
+    let mut summary = Vec::new();
+    while !summary
+        .last()
+        .is_some_and(|l: &String| l.contains("xfs_repair"))
+    {
+        summary.push(lines.recv_timeout(Duration::from_secs(5)).unwrap());
+    }
+    let damaged = "    AG 1: AGF of AG 1: Other(\"AGF magic number is invalid\")";
+    assert!(summary.iter().any(|l| l == damaged), "{:?}", summary);
+    // The file system is still mounted
+    assert!(fs::metadata(harness.d.path().join("files"))
+        .unwrap()
+        .is_dir());
+}
+
+/// With --repair-hint, corruption that makes a decoder panic is still summarized, even though
+/// the daemon dies.
+#[named]
+#[test]
+fn repair_hint_panic() {
+    require_fusefs!();
+
+    // Clobber the magic number of the block directory's only block.  Its extent record follows
+    // the 176-byte v3 inode core.
+    let img = GOLDEN4K.as_path();
+    let mut rec = [0u8; 16];
+    fs::File::open(img)
+        .unwrap()
+        .read_exact_at(&mut rec, inode_offset(img, 65664) + 176)
+        .unwrap();
+    let fsb = (u128::from_be_bytes(rec) >> 21) as u64 & ((1 << 52) - 1);
+    let (_d, img) = patched_image(img, &[(fsb_offset(img, fsb), &[0; 4])]);
+
+    let (harness, stderr) = harness_with_stderr(&img, &["--repair-hint"], "warn");
+    let res = fs::read_dir(harness.d.path().join("block"))
+        .and_then(|dir| dir.collect::<Result<Vec<_>, _>>());
+    assert!(res.is_err());
+    drop(harness);
+
+    let errmsg = stderr.join().unwrap();
+    assert!(
+        errmsg.contains("Found 1 corrupt structure(s) while mounted:\n"),
+        "{}",
+        errmsg
+    );
+    let damaged =
+        "    Unknown structure: Unknown magic number for block directory 0x0 (panicked at ";
+    assert!(errmsg.contains(damaged), "{}", errmsg);
+}
+
+mod xattr_dump {
+    use super::*;
+
+    /// Every attribute of a known file should appear in the dump, attributed to its inode
+    // This may need to be updated whenever the golden images get rebuilt.
+    #[rstest]
+    #[case::local(GOLDEN4K.as_path(), "xattrs/local", 135)]
+    #[case::extents(GOLDEN4K.as_path(), "xattrs/extents", 136)]
+    #[case::btree2(GOLDEN1K.as_path(), "xattrs/btree2", 1180546)]
+    fn known_file(#[case] img: &Path, #[case] d: &str, #[case] ino: u64) {
+        let output = Command::cargo_bin("xfs-fuse")
+            .unwrap()
+            .arg("xattr-dump")
+            .arg(img)
+            .output()
+            .unwrap();
+        assert!(output.status.success());
+        let stdout = String::from_utf8(output.stdout).unwrap();
+        let prefix = format!("{} ", ino);
+        let mut actual = stdout
+            .lines()
+            .filter(|l| l.starts_with(&prefix))
+            .collect::<Vec<_>>();
+        actual.sort();
+        let expected = expected_xattrs_per_file(d)
+            .map(|x| {
+                format!(
+                    "{}{}=\"{}\"",
+                    prefix,
+                    x.name.to_str().unwrap(),
+                    x.value.to_str().unwrap()
+                )
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(actual, expected);
+    }
+
+    /// With a path, only the files beneath it are dumped
+    #[rstest]
+    #[case::relative("xattrs/local")]
+    #[case::absolute("/xattrs/local")]
+    #[case::dots("files/../xattrs/./local")]
+    fn path(#[case] top: &str) {
+        let output = Command::cargo_bin("xfs-fuse")
+            .unwrap()
+            .arg("xattr-dump")
+            .arg(GOLDEN4K.as_path())
+            .arg(top)
+            .output()
+            .unwrap();
+        assert!(output.status.success());
+        let stdout = String::from_utf8(output.stdout).unwrap();
+        assert_eq!(stdout.lines().count(), local_attrs_per_file("xattrs/local"));
+        assert!(stdout.lines().all(|l| l.starts_with("135 ")));
+    }
+
+    /// Without a path, every allocated inode is found from the inode B+trees rather than by
+    /// walking the directory tree.  On a cleanly unmounted image every inode is reachable, so the
+    /// results should be the same, though in a different order.
+    #[rstest]
+    #[case::v5(GOLDEN1K.as_path())]
+    #[case::v4(GOLDENV4.as_path())]
+    fn all_inodes(#[case] img: &Path) {
+        let dump = |args: &[&str]| {
+            let output = Command::cargo_bin("xfs-fuse")
+                .unwrap()
+                .arg("xattr-dump")
+                .arg(img)
+                .args(args)
+                .output()
+                .unwrap();
+            assert!(output.status.success());
+            let mut lines = String::from_utf8(output.stdout)
+                .unwrap()
+                .lines()
+                .map(String::from)
+                .collect::<Vec<_>>();
+            lines.sort();
+            lines
+        };
+        let all = dump(&[]);
+        assert!(!all.is_empty());
+        assert_eq!(all, dump(&["/"]));
+    }
+
+    /// Inode numbers may be printed either absolute or AG-relative
+    // This may need to be updated whenever the golden images get rebuilt.
+    #[rstest]
+    #[case::default(&[], "1180546 ")]
+    #[case::absolute(&["--absolute-ino"], "1180546 ")]
+    #[case::relative(&["--relative-ino"], "2/131970 ")]
+    #[case::last_wins(&["--relative-ino", "--absolute-ino"], "1180546 ")]
+    fn ino_format(#[case] args: &[&str], #[case] prefix: &str) {
+        let output = Command::cargo_bin("xfs-fuse")
+            .unwrap()
+            .arg("xattr-dump")
+            .args(args)
+            .arg(GOLDEN1K.as_path())
+            .arg("xattrs/btree2")
+            .output()
+            .unwrap();
+        assert!(output.status.success());
+        let stdout = String::from_utf8(output.stdout).unwrap();
+        assert_eq!(
+            stdout.lines().count(),
+            local_attrs_per_file("xattrs/btree2") + remote_attrs_per_file("xattrs/btree2")
+        );
+        assert!(stdout.lines().all(|l| l.starts_with(prefix)), "{}", stdout);
+    }
+
+    /// A path that can't be resolved fails with the reason.  A dangling symlink is only an error
+    /// when it must be followed.
+    #[rstest]
+    #[case::nonexistent(&[], "xattrs/nonexistent", Some("Not found"))]
+    #[case::not_dir(&[], "files/hello.txt/x", Some("Not a directory"))]
+    #[case::dangling_physical(&["-P"], "links/sf", None)]
+    #[case::dangling_logical(&["-L"], "links/sf", Some("Not found"))]
+    fn bad_path(#[case] args: &[&str], #[case] path: &str, #[case] error: Option<&str>) {
+        let output = Command::cargo_bin("xfs-fuse")
+            .unwrap()
+            .arg("xattr-dump")
+            .args(args)
+            .arg(GOLDEN4K.as_path())
+            .arg(path)
+            .output()
+            .unwrap();
+        let stderr = String::from_utf8(output.stderr).unwrap();
+        match error {
+            Some(error) => {
+                assert!(!output.status.success());
+                assert!(stderr.contains(error), "{}", stderr);
+            }
+            None => {
+                assert!(output.status.success(), "{}", stderr);
+                assert!(output.stdout.is_empty());
+            }
+        }
+    }
+
+    /// A value that isn't printable, or that contains a quote or backslash, is dumped in base64
+    /// with a `0s` prefix.  Anything else is quoted.
+    #[rstest]
+    #[case::text(b"plain", "=\"plain\"")]
+    #[case::binary(&[0, 1, 0xfe, 0xff], "=0sAAH+/w==")]
+    #[case::quote(b"say \"hi\"", "=0sc2F5ICJoaSI=")]
+    #[case::backslash(b"C:\\", "=0sQzpc")]
+    #[case::empty(b"", "=\"\"")]
+    fn encoding(#[case] value: &[u8], #[case] expected: &str) {
+        // Give the "xattrs" directory, inode 134 of the 4k image, a local attribute fork, as in
+        // getextattr::trusted_overlay.
+        let img = GOLDEN4K.as_path();
+        let ino = inode_offset(img, 134);
+        let fork = shortform_attrs(&[(0, b"value", value)]);
+        let (_d, img) = patched_image(img, &[(ino + 82, &[8, 1]), (ino + 240, &fork)]);
+        let output = Command::cargo_bin("xfs-fuse")
+            .unwrap()
+            .arg("xattr-dump")
+            .arg(&img)
+            .arg("xattrs")
+            .output()
+            .unwrap();
+        assert!(output.status.success());
+        let stdout = String::from_utf8(output.stdout).unwrap();
+        let line = stdout.lines().find(|l| l.starts_with("134 ")).unwrap();
+        assert_eq!(line, format!("134 user.value{}", expected));
+    }
+}
+
+mod quota {
+    use super::*;
+
+    /// The golden images don't enable quotas, so there's nothing to report
+    #[rstest]
+    #[case::v5(GOLDEN4K.as_path())]
+    #[case::v4(GOLDENV4.as_path())]
+    fn no_quotas(#[case] img: &Path) {
+        let output = Command::cargo_bin("xfs-fuse")
+            .unwrap()
+            .arg("quota")
+            .arg(img)
+            .output()
+            .unwrap();
+        assert!(output.status.success());
+        assert!(output.stdout.is_empty());
+    }
+
+    /// Build a V4 quota record, `xfs_dqblk`, for the given user ID, limits, and usage
+    fn dqblk(
+        id: u32,
+        blk_limits: (u64, u64),
+        ino_limits: (u64, u64),
+        bcount: u64,
+        icount: u64,
+    ) -> [u8; 136] {
+        let mut rec = [0u8; 136];
+        rec[0..2].copy_from_slice(b"DQ"); // d_magic
+        rec[2] = 1; // d_version
+        rec[3] = 1; // d_type: user
+        rec[4..8].copy_from_slice(&id.to_be_bytes());
+        rec[8..16].copy_from_slice(&blk_limits.1.to_be_bytes());
+        rec[16..24].copy_from_slice(&blk_limits.0.to_be_bytes());
+        rec[24..32].copy_from_slice(&ino_limits.1.to_be_bytes());
+        rec[32..40].copy_from_slice(&ino_limits.0.to_be_bytes());
+        rec[40..48].copy_from_slice(&bcount.to_be_bytes());
+        rec[48..56].copy_from_slice(&icount.to_be_bytes());
+        rec
+    }
+
+    /// Report the usage and limits of each user with quotas.  The golden images have none, so
+    /// turn a one-block file into a user quota inode.
+    #[test]
+    fn user_quotas() {
+        // The file's only extent record follows the 100-byte v2 inode core.  Its 512-byte block
+        // holds the records of users 0 through 2.
+        const INO: u64 = 100551;
+        let img = GOLDENV4.as_path();
+        let mut rec = [0u8; 16];
+        fs::File::open(img)
+            .unwrap()
+            .read_exact_at(&mut rec, inode_offset(img, INO) + 100)
+            .unwrap();
+        let fsb = (u128::from_be_bytes(rec) >> 21) as u64 & ((1 << 52) - 1);
+        let block = [
+            dqblk(0, (1000, 2000), (100, 200), 0, 0),
+            dqblk(1, (0, 0), (0, 0), 0, 0),
+            dqblk(2, (0, 0), (0, 0), 42, 7),
+        ]
+        .concat();
+        let versionnum = u16::from_be_bytes(read_superblock(img)[100..102].try_into().unwrap());
+        let (_d, img) = patched_image(
+            img,
+            &[
+                (100, &(versionnum | 0x40).to_be_bytes()), // XFS_SB_VERSION_QUOTABIT
+                (160, &INO.to_be_bytes()),                 // sb_uquotino
+                (176, &1u16.to_be_bytes()),                // sb_qflags: XFS_UQUOTA_ACCT
+                (fsb_offset(img, fsb), &block),
+            ],
+        );
+
+        let output = Command::cargo_bin("xfs-fuse")
+            .unwrap()
+            .arg("quota")
+            .arg(&img)
+            .output()
+            .unwrap();
+        assert!(output.status.success());
+        assert_eq!(
+            OsStr::from_bytes(&output.stdout),
+            "user 0 blocks=0 soft=1000 hard=2000 inodes=0 soft=100 hard=200\nuser 2 blocks=42 \
+             soft=0 hard=0 inodes=7 soft=0 hard=0\n"
+        );
+    }
+}